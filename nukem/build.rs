@@ -0,0 +1,84 @@
+//! Build script: records which binary is being built, so '--version' and the log of a run tell
+//! exactly which one produced them. It sets the git commit, the build date, the target triple,
+//! the profile, the platform features and the enabled Cargo features. Builds outside of a git
+//! checkout, such as from a source tarball, record the commit as 'unknown', and setting
+//! SOURCE_DATE_EPOCH pins the build date for reproducible builds.
+
+use std::env;
+use std::path::Path;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn main() {
+    let commit = git(&["rev-parse", "--short=12", "HEAD"]).unwrap_or_else(|| "unknown".into());
+    println!("cargo:rustc-env=NUKEM_GIT_COMMIT={}", commit);
+    println!("cargo:rustc-env=NUKEM_BUILD_DATE={}", build_date());
+    println!("cargo:rustc-env=NUKEM_TARGET={}", env::var("TARGET").unwrap_or_else(|_| "unknown".into()));
+    println!("cargo:rustc-env=NUKEM_PROFILE={}", env::var("PROFILE").unwrap_or_else(|_| "unknown".into()));
+
+    // /proc scanning backs '--check-open-handles', '--live-sockets' and '--skip-in-use'
+    let features = if env::var("CARGO_CFG_TARGET_OS").is_ok_and(|os| os == "linux") { "proc scanning, cgroups, user namespaces" } else { "none" };
+    println!("cargo:rustc-env=NUKEM_FEATURES={}", features);
+
+    // optional backends are Cargo features, which Cargo hands to build scripts as CARGO_FEATURE_<NAME>
+    let mut backends: Vec<String> = env::vars()
+        .filter_map(|(key, _)| key.strip_prefix("CARGO_FEATURE_").map(|name| name.to_lowercase()))
+        .filter(|name| name != "default")
+        .collect();
+    backends.sort();
+    println!("cargo:rustc-env=NUKEM_BACKENDS={}", if backends.is_empty() { "none".to_string() } else { backends.join(", ") });
+
+    // build again when HEAD moves to another commit
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    if let Some(git_dir) = git(&["rev-parse", "--git-dir"]) {
+        let git_dir = Path::new(&git_dir);
+        for file in ["HEAD", "refs/heads", "packed-refs"] {
+            if git_dir.join(file).exists() {
+                println!("cargo:rerun-if-changed={}", git_dir.join(file).display());
+            }
+        }
+    }
+}
+
+/// Runs git in the source directory.
+///
+/// # Arguments
+///
+/// * `args` - The arguments for git.
+///
+/// # Returns
+///
+/// * `Option<String>` - The trimmed output, or None if git isn't available or failed.
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).current_dir(env!("CARGO_MANIFEST_DIR")).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let text = String::from_utf8(output.stdout).ok()?.trim().to_string();
+    if text.is_empty() { None } else { Some(text) }
+}
+
+/// Formats the time of the build, from SOURCE_DATE_EPOCH if it is set.
+///
+/// # Returns
+///
+/// * `String` - The UTC time, e.g. '2024-08-02 14:03:11 UTC'.
+fn build_date() -> String {
+    let secs = env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.trim().parse::<u64>().ok())
+        .unwrap_or_else(|| SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs()));
+    // days since the epoch to a civil date, after Howard Hinnant's days_from_civil inverse
+    let days = (secs / 86_400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    let time = secs % 86_400;
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC", year, month, day, time / 3600, time / 60 % 60, time % 60)
+}
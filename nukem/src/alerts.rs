@@ -0,0 +1,180 @@
+//! This module fires alerts while a run is still in progress.
+//!
+//! Long runs can degrade hours before they finish. When the number of failed deletions or the
+//! elapsed time crosses a user-supplied threshold, an alert is fired by running a command
+//! ('--alert-exec') and/or POSTing a JSON document to a webhook ('--alert-webhook'). Each
+//! threshold fires at most once per run.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::process::Command;
+use tokio::sync::Mutex;
+use crate::args::Args;
+use crate::logger::Logger;
+
+/// The thresholds and actions configured for a run.
+#[derive(Clone)]
+pub struct Alerts {
+    max_failures: Option<u64>,
+    max_elapsed: Option<Duration>,
+    exec: Option<String>,
+    webhook: Option<String>,
+}
+
+impl Alerts {
+    // How often the thresholds are checked.
+    const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+    /// Creates the alert configuration from the command-line arguments.
+    ///
+    /// # Arguments
+    ///
+    /// * `args` - Command-line arguments.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<Self>` - The alert configuration, or None if no thresholds or actions were given.
+    pub fn from_args(args: &Args) -> Option<Self> {
+        let has_threshold = args.alert_failures.is_some() || args.alert_elapsed.is_some();
+        let has_action = args.alert_exec.is_some() || args.alert_webhook.is_some();
+        if !has_threshold || !has_action {
+            return None;
+        }
+        Some(Self {
+            max_failures: args.alert_failures,
+            max_elapsed: args.alert_elapsed,
+            exec: args.alert_exec.clone(),
+            webhook: args.alert_webhook.clone(),
+        })
+    }
+
+    /// Spawns the task that checks the thresholds for the remainder of the run.
+    ///
+    /// # Arguments
+    ///
+    /// * `failed_deletions` - The deleter's shared failure counter.
+    /// * `start` - The start time of the application.
+    /// * `logger` - An instance of the Logger.
+    pub fn spawn(self, failed_deletions: Arc<Mutex<u64>>, start: Instant, logger: Arc<Logger>) {
+        tokio::spawn(async move {
+            let mut failures_fired = false;
+            let mut elapsed_fired = false;
+            loop {
+                let failures = *failed_deletions.lock().await;
+                let elapsed = start.elapsed();
+
+                if let Some(max) = self.max_failures {
+                    if !failures_fired && failures >= max {
+                        failures_fired = true;
+                        self.fire("failures", failures, elapsed, &logger).await;
+                    }
+                }
+                if let Some(max) = self.max_elapsed {
+                    if !elapsed_fired && elapsed >= max {
+                        elapsed_fired = true;
+                        self.fire("elapsed", failures, elapsed, &logger).await;
+                    }
+                }
+                if (self.max_failures.is_none() || failures_fired) && (self.max_elapsed.is_none() || elapsed_fired) {
+                    break;
+                }
+                tokio::time::sleep(Self::CHECK_INTERVAL).await;
+            }
+        });
+    }
+
+    /// Fires an alert through every configured action.
+    ///
+    /// # Arguments
+    ///
+    /// * `alert` - The name of the threshold that was crossed.
+    /// * `failures` - The number of failed deletions so far.
+    /// * `elapsed` - The time elapsed since the application began.
+    /// * `logger` - An instance of the Logger.
+    async fn fire(&self, alert: &str, failures: u64, elapsed: Duration, logger: &Arc<Logger>) {
+        logger.log(&format!("Alert threshold crossed: {} (failures: {}, elapsed: {:?})", alert, failures, elapsed), true, true, false).await;
+
+        if let Some(ref command) = self.exec {
+            let status = Command::new("sh")
+                .arg("-c")
+                .arg(command)
+                .env("NUKEM_ALERT", alert)
+                .env("NUKEM_FAILURES", failures.to_string())
+                .env("NUKEM_ELAPSED_SECS", elapsed.as_secs().to_string())
+                .status()
+                .await;
+            match status {
+                Ok(status) if status.success() => {}
+                Ok(status) => logger.log(&format!("Alert command exited with {}", status), true, false, false).await,
+                Err(e) => logger.log(&format!("Failed to run alert command: {}", e), true, false, false).await,
+            }
+        }
+
+        if let Some(ref url) = self.webhook {
+            let body = format!(
+                "{{\"alert\":\"{}\",\"failures\":{},\"elapsed_secs\":{},\"pid\":{}}}",
+                alert, failures, elapsed.as_secs(), std::process::id()
+            );
+            if let Err(e) = post_json(url, &body).await {
+                logger.log(&format!("Failed to call alert webhook {}: {}", url, e), true, false, false).await;
+            }
+        }
+    }
+}
+
+/// Splits an `http://host[:port]/path` URL into its parts.
+///
+/// # Arguments
+///
+/// * `url` - The URL to split.
+///
+/// # Returns
+///
+/// * `Result<(String, u16, String), String>` - Ok with the host, port and path, Err with a message
+///   if the URL is not a plain HTTP URL.
+pub fn parse_http_url(url: &str) -> Result<(String, u16, String), String> {
+    let rest = url.strip_prefix("http://").ok_or_else(|| format!("Only http:// URLs are supported: {}", url))?;
+    let (authority, path) = match rest.find('/') {
+        Some(index) => (&rest[..index], &rest[index..]),
+        None => (rest, "/"),
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse().map_err(|_| format!("Invalid port in URL: {}", url))?),
+        None => (authority, 80),
+    };
+    if host.is_empty() {
+        return Err(format!("Missing host in URL: {}", url));
+    }
+    Ok((host.to_string(), port, path.to_string()))
+}
+
+/// POSTs a JSON document to an HTTP URL and checks for a successful status code.
+///
+/// # Arguments
+///
+/// * `url` - The URL to POST to.
+/// * `body` - The JSON document.
+///
+/// # Returns
+///
+/// * `Result<(), Box<dyn std::error::Error + Send + Sync>>` - Ok if the server answered with 2xx.
+async fn post_json(url: &str, body: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let (host, port, path) = parse_http_url(url)?;
+    let mut stream = TcpStream::connect((host.as_str(), port)).await?;
+    let request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        path, host, body.len(), body
+    );
+    stream.write_all(request.as_bytes()).await?;
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await?;
+    let response = String::from_utf8_lossy(&response);
+    let status_line = response.lines().next().unwrap_or_default();
+    match status_line.split_whitespace().nth(1) {
+        Some(code) if code.starts_with('2') => Ok(()),
+        _ => Err(format!("unexpected response '{}'", status_line).into()),
+    }
+}
@@ -0,0 +1,648 @@
+//! This module streams objects into a compressed tar archive for '--archive-to', so they can be
+//! deleted with a backup of them left behind.
+//!
+//! The archive is written by a thread of its own, which takes the objects from the deleter
+//! workers one at a time. A tar stream is sequential, so the workers line up for it, but the
+//! deletions themselves still run in parallel. Each object is added with everything within it at
+//! its absolute path (without the leading '/'), as a pax tar stream that GNU tar, bsdtar and
+//! Python's tarfile all read.
+//!
+//! The stream is compressed in batches by the system's zstd or gzip, which one is picked by the
+//! extension of the archive ('.tar.zst' or '.tar.gz'). A batch is sealed once nothing else is
+//! queued for a moment or it reached 64 MiB: the compressor is closed, its output synced to disk,
+//! and only then are the objects in the batch handed back to be deleted. The batches are
+//! complete frames (or gzip members) appended to one another, which the decompressors read as one
+//! stream, so nothing is ever deleted before its copy is on disk. A batch that fails is cut off
+//! the archive again, and its objects are kept. The end-of-archive marker is written last; an
+//! archive of a run that was cut short lacks it, but everything in it can still be extracted.
+//!
+//! Sockets, FIFOs and device nodes aren't archived, so a directory holding one is kept whole, and
+//! so is an object that changed while it was being archived.
+
+use std::fs::{self, File, Metadata, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::Duration;
+use tokio::sync::oneshot;
+use crate::args::Compressor;
+use crate::relocate;
+
+// A batch is sealed once it holds this many bytes, or nothing was queued for a moment.
+const BATCH_BYTES: u64 = 64 * 1024 * 1024;
+const BATCH_IDLE: Duration = Duration::from_millis(100);
+const BLOCK: usize = 512;
+
+/// An object that was written to the archive, and can be removed in the order of the list.
+#[derive(Debug)]
+pub struct Member {
+    pub path: PathBuf,
+    pub is_dir: bool,
+}
+
+/// What the archive holds once it is complete.
+#[derive(Debug)]
+pub struct Totals {
+    pub objects: u64,
+    pub bytes: u64,
+}
+
+/// A request to the thread writing the archive.
+enum Request {
+    Add(PathBuf, oneshot::Sender<io::Result<Vec<Member>>>),
+    Finish(oneshot::Sender<Result<Totals, String>>),
+}
+
+/// The archive of a run. Cloning it shares the writer, so every worker adds to the same archive.
+#[derive(Clone)]
+pub struct Archive {
+    path: PathBuf,
+    compressor: Compressor,
+    // None in a dry run, which writes nothing
+    sender: Option<mpsc::Sender<Request>>,
+}
+
+impl Archive {
+    /// Creates the archive and starts the thread that writes it.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The archive, which must not exist yet.
+    /// * `create` - A boolean indicating whether to create it. A dry run writes nothing.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, String>` - Ok with the archive, Err with a message if it can't be created.
+    pub fn create(path: &Path, create: bool) -> Result<Self, String> {
+        let compressor = compressor_for(path).ok_or_else(|| format!("--archive-to '{}' must end in .tar.zst or .tar.gz", path.display()))?;
+        if !create {
+            return Ok(Self { path: path.to_path_buf(), compressor, sender: None });
+        }
+        let file = OpenOptions::new().append(true).create_new(true).mode(0o600).open(path)
+            .map_err(|e| format!("Unable to create the archive {}: {}", path.display(), e))?;
+        let (sender, receiver) = mpsc::channel();
+        let writer = Writer { file, compressor, child: None, batch: Vec::new(), batch_start: 0, batch_bytes: 0, objects: 0 };
+        std::thread::Builder::new().name("archive".into()).spawn(move || writer.run(receiver))
+            .map_err(|e| format!("Unable to start writing the archive {}: {}", path.display(), e))?;
+        Ok(Self { path: path.to_path_buf(), compressor, sender: Some(sender) })
+    }
+
+    /// Retrieves the path of the archive.
+    ///
+    /// # Returns
+    ///
+    /// * `&Path` - The archive.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Retrieves the compressor of the archive.
+    ///
+    /// # Returns
+    ///
+    /// * `Compressor` - The compressor picked by the extension.
+    pub fn compressor(&self) -> Compressor {
+        self.compressor
+    }
+
+    /// Adds an object, with everything within it, to the archive and waits until its copy is on
+    /// disk.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The object.
+    ///
+    /// # Returns
+    ///
+    /// * `io::Result<Vec<Member>>` - Ok with what was archived, parents before their contents,
+    ///   Err if the object could not be archived and has to be kept.
+    pub async fn add(&self, path: &Path) -> io::Result<Vec<Member>> {
+        let (reply, archived) = oneshot::channel();
+        let sender = self.sender.as_ref().ok_or_else(|| io::Error::other("nothing is archived in a dry run"))?;
+        sender.send(Request::Add(path.to_path_buf(), reply)).map_err(|_| io::Error::other("the archive is closed"))?;
+        archived.await.map_err(|_| io::Error::other("the archive is closed"))?
+    }
+
+    /// Writes the end of the archive and makes sure it reached the disk.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Totals, String>` - Ok with what the archive holds, Err with a message if it
+    ///   couldn't be completed.
+    pub async fn finish(&self) -> Result<Totals, String> {
+        let (reply, finished) = oneshot::channel();
+        let closed = || format!("The archive {} was closed early", self.path.display());
+        let sender = self.sender.as_ref().ok_or("Nothing is archived in a dry run")?;
+        sender.send(Request::Finish(reply)).map_err(|_| closed())?;
+        finished.await.map_err(|_| closed())?
+    }
+}
+
+/// Finds the compressor for an archive by its extension.
+///
+/// # Arguments
+///
+/// * `path` - The archive.
+///
+/// # Returns
+///
+/// * `Option<Compressor>` - The compressor, or None if the extension isn't supported.
+pub fn compressor_for(path: &Path) -> Option<Compressor> {
+    let name = path.file_name()?.to_string_lossy().to_lowercase();
+    if name.ends_with(".tar.zst") || name.ends_with(".tzst") {
+        Some(Compressor::Zstd)
+    } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        Some(Compressor::Gzip)
+    } else {
+        None
+    }
+}
+
+/// Removes what was archived, contents before their directories. A directory that something was
+/// added to in the meantime is kept, along with what was added.
+///
+/// # Arguments
+///
+/// * `members` - What was archived, as returned by 'Archive::add'.
+///
+/// # Returns
+///
+/// * `io::Result<()>` - Ok if everything was removed, Err with the first failure otherwise.
+pub fn remove_members(members: &[Member]) -> io::Result<()> {
+    let mut result = Ok(());
+    for member in members.iter().rev() {
+        let removed = if member.is_dir { fs::remove_dir(&member.path) } else { fs::remove_file(&member.path) };
+        match removed {
+            Err(e) if e.kind() != io::ErrorKind::NotFound && result.is_ok() => result = Err(e),
+            _ => {}
+        }
+    }
+    result
+}
+
+// the objects being compressed, each with the members it added and who waits for them
+type Batch = Vec<(oneshot::Sender<io::Result<Vec<Member>>>, Vec<Member>)>;
+
+/// The thread writing the archive, and the batch it is compressing.
+struct Writer {
+    file: File,
+    compressor: Compressor,
+    child: Option<(Child, ChildStdin)>,
+    batch: Batch,
+    // the length of the archive before the batch, which it is cut back to if the batch fails
+    batch_start: u64,
+    batch_bytes: u64,
+    objects: u64,
+}
+
+impl Writer {
+    /// Writes objects until the archive is finished or every sender is gone.
+    ///
+    /// # Arguments
+    ///
+    /// * `receiver` - Where the requests come from.
+    fn run(mut self, receiver: mpsc::Receiver<Request>) {
+        loop {
+            let request = if self.batch.is_empty() {
+                receiver.recv().map_err(|_| RecvTimeoutError::Disconnected)
+            } else {
+                receiver.recv_timeout(BATCH_IDLE)
+            };
+            match request {
+                Ok(Request::Add(path, reply)) => {
+                    match self.add(&path) {
+                        Ok(members) => self.batch.push((reply, members)),
+                        Err(e) => {
+                            let _ = reply.send(Err(e));
+                        }
+                    }
+                    if self.batch_bytes >= BATCH_BYTES {
+                        self.seal();
+                    }
+                }
+                Ok(Request::Finish(reply)) => {
+                    let _ = reply.send(self.finish());
+                    return;
+                }
+                Err(RecvTimeoutError::Timeout) => self.seal(),
+                Err(RecvTimeoutError::Disconnected) => {
+                    self.seal();
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Writes an object and everything within it to the batch.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The object.
+    ///
+    /// # Returns
+    ///
+    /// * `io::Result<Vec<Member>>` - Ok with what was written, Err if the object can't be archived.
+    fn add(&mut self, path: &Path) -> io::Result<Vec<Member>> {
+        let root = relocate::absolute(path)?;
+        let mut entries = Vec::new();
+        collect(&root, fs::symlink_metadata(&root)?, &mut entries)?;
+        // the compressor is only started for something to write, so a broken one fails that object
+        if self.child.is_none() {
+            let mut child = Command::new(self.compressor.program()).args(["-c", "-q"])
+                .stdin(Stdio::piped()).stdout(self.file.try_clone()?).stderr(Stdio::null()).spawn()?;
+            let stdin = child.stdin.take().ok_or_else(|| io::Error::other("the compressor has no input"))?;
+            self.child = Some((child, stdin));
+        }
+        let mut members = Vec::with_capacity(entries.len());
+        let mut unreadable = None;
+        for (path, metadata) in entries {
+            let (_, stdin) = self.child.as_mut().ok_or_else(|| io::Error::other("the compressor is gone"))?;
+            match write_entry(stdin, &path, &metadata) {
+                Ok(bytes) => self.batch_bytes += bytes,
+                // the stream is intact, but the object has to be kept
+                Err(EntryError::Object(e)) => unreadable = unreadable.or(Some(e)),
+                Err(EntryError::Stream(e)) => {
+                    // the stream can't be trusted after a failed write, so the whole batch goes
+                    self.fail(&e);
+                    return Err(e);
+                }
+            }
+            members.push(Member { path, is_dir: metadata.is_dir() });
+        }
+        match unreadable {
+            Some(e) => Err(e),
+            None => Ok(members),
+        }
+    }
+
+    /// Closes the compressor of the batch, syncs its output and hands the objects back to be
+    /// deleted.
+    fn seal(&mut self) {
+        let Some((mut child, stdin)) = self.child.take() else { return };
+        drop(stdin);
+        let sealed = child.wait().and_then(|status| match status.success() {
+            true => self.file.sync_data(),
+            false => Err(io::Error::other(format!("{} exited with {}", self.compressor.program(), status))),
+        });
+        match sealed {
+            Ok(()) => {
+                for (reply, members) in self.batch.drain(..) {
+                    self.objects += members.len() as u64;
+                    let _ = reply.send(Ok(members));
+                }
+                self.batch_start = self.file.metadata().map(|metadata| metadata.len()).unwrap_or(self.batch_start);
+                self.batch_bytes = 0;
+            }
+            Err(e) => self.fail(&e),
+        }
+    }
+
+    /// Cuts a failed batch off the archive and keeps its objects.
+    ///
+    /// # Arguments
+    ///
+    /// * `error` - Why the batch failed.
+    fn fail(&mut self, error: &io::Error) {
+        if let Some((mut child, stdin)) = self.child.take() {
+            drop(stdin);
+            let _ = child.wait();
+        }
+        let _ = self.file.set_len(self.batch_start);
+        for (reply, _) in self.batch.drain(..) {
+            let _ = reply.send(Err(io::Error::new(error.kind(), format!("the archive batch failed: {}", error))));
+        }
+        self.batch_bytes = 0;
+    }
+
+    /// Seals the last batch and writes the end of the archive.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Totals, String>` - Ok with what the archive holds, Err with a message otherwise.
+    fn finish(&mut self) -> Result<Totals, String> {
+        self.seal();
+        let ended = Command::new(self.compressor.program()).args(["-c", "-q"])
+            .stdin(Stdio::piped()).stdout(self.file.try_clone().map_err(|e| e.to_string())?).stderr(Stdio::null()).spawn()
+            .and_then(|mut child| {
+                child.stdin.take().ok_or_else(|| io::Error::other("the compressor has no input"))?.write_all(&[0; 2 * BLOCK])?;
+                let status = child.wait()?;
+                if !status.success() {
+                    return Err(io::Error::other(format!("{} exited with {}", self.compressor.program(), status)));
+                }
+                self.file.sync_all()?;
+                self.file.metadata()
+            });
+        match ended {
+            Ok(metadata) => Ok(Totals { objects: self.objects, bytes: metadata.len() }),
+            Err(e) => {
+                let _ = self.file.set_len(self.batch_start);
+                Err(format!("The archive has {} objects, but its end couldn't be written: {}", self.objects, e))
+            }
+        }
+    }
+}
+
+/// Lists an object and everything within it, parents before their contents.
+///
+/// # Arguments
+///
+/// * `path` - The object.
+/// * `metadata` - Its metadata, read without following symlinks.
+/// * `entries` - Where the objects are listed.
+///
+/// # Returns
+///
+/// * `io::Result<()>` - Ok if everything can be archived, Err if something can't be read or
+///   isn't a file, symlink or directory.
+fn collect(path: &Path, metadata: Metadata, entries: &mut Vec<(PathBuf, Metadata)>) -> io::Result<()> {
+    let file_type = metadata.file_type();
+    if !(file_type.is_file() || file_type.is_symlink() || file_type.is_dir()) {
+        return Err(io::Error::other(format!("{} is a special file, which can't be archived", path.display())));
+    }
+    entries.push((path.to_path_buf(), metadata));
+    if file_type.is_dir() {
+        let mut children = fs::read_dir(path)?.map(|entry| entry.map(|entry| entry.path())).collect::<io::Result<Vec<_>>>()?;
+        children.sort();
+        for child in children {
+            let metadata = fs::symlink_metadata(&child)?;
+            collect(&child, metadata, entries)?;
+        }
+    }
+    Ok(())
+}
+
+/// Why an object couldn't be written to the tar stream.
+enum EntryError {
+    /// The object couldn't be read, or changed while it was read. The stream is intact: either
+    /// nothing was written, or the entry is complete, padded if the file shrank.
+    Object(io::Error),
+    /// Writing to the stream failed, so it can't be trusted anymore.
+    Stream(io::Error),
+}
+
+/// Writes one object to the tar stream: a pax header if the name, the link or a number doesn't
+/// fit the ustar header, the ustar header, and the contents of a file.
+///
+/// # Arguments
+///
+/// * `out` - The stream.
+/// * `path` - The absolute path of the object.
+/// * `metadata` - Its metadata, read without following symlinks.
+///
+/// # Returns
+///
+/// * `Result<u64, EntryError>` - Ok with the number of bytes written, Err otherwise.
+fn write_entry(out: &mut impl Write, path: &Path, metadata: &Metadata) -> Result<u64, EntryError> {
+    let mut name = path.as_os_str().as_bytes().strip_prefix(b"/").unwrap_or(path.as_os_str().as_bytes()).to_vec();
+    let (kind, size, link) = if metadata.is_dir() {
+        name.push(b'/');
+        (b'5', 0, Vec::new())
+    } else if metadata.file_type().is_symlink() {
+        (b'2', 0, fs::read_link(path).map_err(EntryError::Object)?.into_os_string().as_bytes().to_vec())
+    } else {
+        (b'0', metadata.len(), Vec::new())
+    };
+    // open the file first, so one that can't be read doesn't leave a header without contents
+    let file = if kind == b'0' { Some(File::open(path).map_err(EntryError::Object)?) } else { None };
+
+    let records = pax_records(&name, &link, size, metadata.uid() as u64, metadata.gid() as u64);
+    let mut written = 0;
+    if !records.is_empty() {
+        let mut pax_name = b"PaxHeaders/".to_vec();
+        pax_name.extend_from_slice(&name[name.len().saturating_sub(80)..]);
+        let mut entry = header(&pax_name, b'x', records.len() as u64, 0o644, 0, 0, metadata.mtime(), b"").to_vec();
+        entry.extend_from_slice(&records);
+        entry.resize(entry.len() + padding(records.len() as u64), 0);
+        out.write_all(&entry).map_err(EntryError::Stream)?;
+        written += entry.len() as u64;
+    }
+    out.write_all(&header(&name, kind, size, metadata.mode() & 0o7777, metadata.uid() as u64, metadata.gid() as u64, metadata.mtime(), &link))
+        .map_err(EntryError::Stream)?;
+    written += BLOCK as u64;
+
+    let Some(mut file) = file else { return Ok(written) };
+    let mut buffer = vec![0u8; 64 * 1024];
+    let mut copied = 0;
+    let mut unreadable = None;
+    while copied < size {
+        let wanted = buffer.len().min((size - copied) as usize);
+        match file.read(&mut buffer[..wanted]) {
+            Ok(0) => break,
+            Ok(read) => {
+                out.write_all(&buffer[..read]).map_err(EntryError::Stream)?;
+                copied += read as u64;
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                unreadable = Some(e);
+                break;
+            }
+        }
+    }
+    // a file that shrank or couldn't be read to the end is padded, so the entry still has the
+    // size of its header
+    io::copy(&mut io::repeat(0).take(size - copied), out).map_err(EntryError::Stream)?;
+    out.write_all(&vec![0; padding(size)]).map_err(EntryError::Stream)?;
+    written += size + padding(size) as u64;
+    if let Some(e) = unreadable {
+        return Err(EntryError::Object(e));
+    }
+    let after = file.metadata().map_err(EntryError::Object)?;
+    if copied != size || after.len() != size || after.mtime() != metadata.mtime() || after.mtime_nsec() != metadata.mtime_nsec() {
+        return Err(EntryError::Object(io::Error::other(format!("{} changed while it was being archived", path.display()))));
+    }
+    Ok(written)
+}
+
+/// Builds a ustar header. Numbers that don't fit are left at zero, for a pax header to override.
+///
+/// # Arguments
+///
+/// * `name` - The name of the entry, cut to 100 bytes.
+/// * `kind` - The type flag.
+/// * `size` - The size of the contents.
+/// * `mode` - The permission bits.
+/// * `uid` - The owner.
+/// * `gid` - The group.
+/// * `mtime` - The modification time in seconds since the epoch.
+/// * `link` - The target of a symlink, cut to 100 bytes.
+///
+/// # Returns
+///
+/// * `[u8; BLOCK]` - The header.
+#[allow(clippy::too_many_arguments)]
+fn header(name: &[u8], kind: u8, size: u64, mode: u32, uid: u64, gid: u64, mtime: i64, link: &[u8]) -> [u8; BLOCK] {
+    let mut block = [0u8; BLOCK];
+    let name = &name[..name.len().min(100)];
+    block[..name.len()].copy_from_slice(name);
+    octal(&mut block[100..108], mode as u64);
+    octal(&mut block[108..116], uid);
+    octal(&mut block[116..124], gid);
+    octal(&mut block[124..136], size);
+    octal(&mut block[136..148], mtime.max(0) as u64);
+    block[156] = kind;
+    let link = &link[..link.len().min(100)];
+    block[157..157 + link.len()].copy_from_slice(link);
+    block[257..263].copy_from_slice(b"ustar\0");
+    block[263..265].copy_from_slice(b"00");
+    // the checksum is summed with its own field taken as spaces
+    block[148..156].fill(b' ');
+    let sum: u32 = block.iter().map(|&byte| byte as u32).sum();
+    block[148..156].copy_from_slice(format!("{:06o}\0 ", sum).as_bytes());
+    block
+}
+
+/// Writes a number into an octal field of a header, ending in a NUL, or leaves it at zero if it
+/// doesn't fit.
+///
+/// # Arguments
+///
+/// * `field` - The field.
+/// * `value` - The number.
+fn octal(field: &mut [u8], value: u64) {
+    let digits = format!("{:0width$o}", if fits(value, field.len()) { value } else { 0 }, width = field.len() - 1);
+    field[..digits.len()].copy_from_slice(digits.as_bytes());
+}
+
+/// Checks whether a number fits an octal field of a header.
+///
+/// # Arguments
+///
+/// * `value` - The number.
+/// * `width` - The width of the field, including the NUL.
+///
+/// # Returns
+///
+/// * `bool` - True if it fits.
+fn fits(value: u64, width: usize) -> bool {
+    value < 1u64 << (3 * (width - 1))
+}
+
+/// Builds the pax records an entry needs for the name, the link and the numbers that don't fit
+/// its ustar header.
+///
+/// # Arguments
+///
+/// * `name` - The name of the entry.
+/// * `link` - The target of a symlink, or nothing.
+/// * `size` - The size of the contents.
+/// * `uid` - The owner.
+/// * `gid` - The group.
+///
+/// # Returns
+///
+/// * `Vec<u8>` - The records, or nothing if the ustar header holds everything.
+fn pax_records(name: &[u8], link: &[u8], size: u64, uid: u64, gid: u64) -> Vec<u8> {
+    let mut records = Vec::new();
+    if name.len() > 100 {
+        records.push(pax_record("path", name));
+    }
+    if link.len() > 100 {
+        records.push(pax_record("linkpath", link));
+    }
+    for (key, value, width) in [("size", size, 12), ("uid", uid, 8), ("gid", gid, 8)] {
+        if !fits(value, width) {
+            records.push(pax_record(key, value.to_string().as_bytes()));
+        }
+    }
+    records.concat()
+}
+
+/// Builds a pax record, whose length counts its own digits.
+///
+/// # Arguments
+///
+/// * `key` - The key.
+/// * `value` - The value.
+///
+/// # Returns
+///
+/// * `Vec<u8>` - The record.
+fn pax_record(key: &str, value: &[u8]) -> Vec<u8> {
+    let rest = key.len() + value.len() + 3;
+    let mut length = rest + rest.to_string().len();
+    if length.to_string().len() != rest.to_string().len() {
+        length += 1;
+    }
+    let mut record = format!("{} {}=", length, key).into_bytes();
+    record.extend_from_slice(value);
+    record.push(b'\n');
+    record
+}
+
+/// Finds how many bytes fill up the last block of the contents of an entry.
+///
+/// # Arguments
+///
+/// * `size` - The size of the contents.
+///
+/// # Returns
+///
+/// * `usize` - The padding.
+fn padding(size: u64) -> usize {
+    (BLOCK - (size % BLOCK as u64) as usize) % BLOCK
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BLOCK, header, pax_record, pax_records, padding};
+
+    #[test]
+    fn checksums_the_ustar_header() {
+        let block = header(b"a.txt", b'0', 5, 0o644, 1000, 1000, 1_700_000_000, b"");
+        assert_eq!(&block[..6], b"a.txt\0");
+        assert_eq!(&block[100..108], b"0000644\0");
+        assert_eq!(&block[108..116], b"0001750\0");
+        assert_eq!(&block[124..136], b"00000000005\0");
+        assert_eq!(&block[136..148], b"14524770400\0");
+        assert_eq!(&block[148..156], b"007015\0 ");
+        assert_eq!(block[156], b'0');
+        assert_eq!(&block[257..265], b"ustar\x0000");
+        // the checksum is the sum of the header with the checksum field as spaces
+        let mut spaced = block;
+        spaced[148..156].fill(b' ');
+        let sum: u32 = spaced.iter().map(|&byte| byte as u32).sum();
+        assert_eq!(sum, 0o7015);
+    }
+
+    #[test]
+    fn moves_numbers_that_overflow_their_field_to_pax_records() {
+        // 11 octal digits hold sizes below 8 GiB
+        assert!(pax_records(b"a", b"", (1 << 33) - 1, 0, 0).is_empty());
+        assert_eq!(pax_records(b"a", b"", 1 << 33, 0, 0), b"19 size=8589934592\n");
+        assert_eq!(&header(b"a", b'0', 1 << 33, 0o644, 0, 0, 0, b"")[124..136], b"00000000000\0");
+        assert_eq!(pax_records(b"a", b"", 0, 1 << 21, 0), b"15 uid=2097152\n");
+        assert!(pax_records(b"a", b"", 0, (1 << 21) - 1, (1 << 21) - 1).is_empty());
+
+        let name = vec![b'n'; 101];
+        let records = pax_records(&name, b"", 0, 0, 0);
+        assert_eq!(records.len(), 111);
+        assert!(records.starts_with(b"111 path=nnn"));
+        assert!(pax_records(&name[..100], &name[..100], 0, 0, 0).is_empty());
+    }
+
+    #[test]
+    fn counts_the_digits_of_the_length_in_pax_records() {
+        assert_eq!(pax_record("k", b"vvvv"), b"9 k=vvvv\n");
+        // one more byte takes the length to two digits, which takes it to 11
+        assert_eq!(pax_record("k", b"vvvvv"), b"11 k=vvvvv\n");
+        let record = pax_record("k", &[b'v'; 93]);
+        assert!(record.starts_with(b"99 k="));
+        assert_eq!(record.len(), 99);
+        let record = pax_record("k", &[b'v'; 94]);
+        assert!(record.starts_with(b"101 k="));
+        assert_eq!(record.len(), 101);
+    }
+
+    #[test]
+    fn pads_contents_to_whole_blocks() {
+        assert_eq!(padding(0), 0);
+        assert_eq!(padding(1), 511);
+        assert_eq!(padding(511), 1);
+        assert_eq!(padding(512), 0);
+        assert_eq!(padding(BLOCK as u64 * 3 + 2), 510);
+    }
+}
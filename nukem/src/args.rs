@@ -1,18 +1,28 @@
 //! This module defines the command-line arguments for the application.
 
-use clap::Parser;
-use chrono::Local;
+use clap::{Parser, Subcommand, ValueEnum};
+use chrono::{Local, NaiveDate, NaiveDateTime, TimeZone};
 use std::path::PathBuf;
+use std::time::Duration;
+use crate::retry::RetrySet;
+use crate::filters::{PermFilter, TypeSet};
+use crate::cgroup::{self, IoLimits};
+use crate::content::{Magic, MimePattern};
+use crate::syslog::{self, Target};
+use crate::confirm::Threshold;
+use crate::tiers::{Tier, TierAction};
 
 #[derive(Parser, Debug, Clone)]
 #[command(
     author,
     version,
+    long_version = crate::environment::LONG_VERSION,
     about = "A multi-threaded tool for the massive deletion of files and directories.",
     long_about = "Nukem is a command-line tool designed to help you delete massive amounts of files and \
                   directories efficiently. It supports various options such as dry-run mode, specifying \
                   number of threads and buffers, and verbose logging to the console or to a logfile. \
                   You can provide multiple paths, and it will process each according to the specified options.",
+    subcommand_negates_reqs = true,
     help_template = "\
 --------------------------
 {bin} {version}
@@ -24,21 +34,64 @@ Usage: {usage}
 
 {all-args}"
 )]
-
 pub struct Args {
+    /// Subcommand to run instead of deleting paths
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     /// Full path(s) to the file(s) or directory(s) that will be deleted. This is the only required
-    /// field.
-    #[clap(required = true)]
+    /// field, unless '--retry-failed' is used. Patterns may use '*', '?', '[0-9]', '**' and '{a,b}'.
+    #[clap(required_unless_present_any = ["retry_failed", "explain_threads"], conflicts_with = "retry_failed")]
     pub paths: Vec<PathBuf>,
 
+    /// Retry only the paths that could not be deleted in a previous run, given by its run ID (see
+    /// 'nukem history') or by a file with one path per line
+    #[clap(long = "retry-failed", value_name = "RUN_ID|FILE")]
+    pub retry_failed: Option<String>,
+
+    /// The failures loaded for '--retry-failed'
+    #[clap(skip)]
+    pub retry: Option<RetrySet>,
+
+    /// The file 'nukem plan' writes its plan to
+    #[clap(skip)]
+    pub plan: Option<PathBuf>,
+
     /// Full path of the directory for the logfile
     #[clap(short = 'l', long = "logfile_path")]
     pub logfile_path: Option<PathBuf>,
 
+    /// Also send every log message to this syslog server, as tcp://HOST[:PORT] (514 by default)
+    /// or tls://HOST[:PORT] (6514 by default)
+    #[clap(long = "syslog", value_parser = syslog::parse_target, value_name = "URL")]
+    pub syslog: Option<Target>,
+
+    /// CA certificates (PEM) to verify a tls:// syslog server with, instead of the system trust store
+    #[clap(long = "syslog-ca", value_name = "FILE", requires = "syslog")]
+    pub syslog_ca: Option<PathBuf>,
+
+    /// Facility of the messages sent to the syslog server
+    #[clap(long = "syslog-facility", value_enum, default_value = "user", ignore_case = true, requires = "syslog")]
+    pub syslog_facility: SyslogFacility,
+
     /// Number of threads to use for file and directory deletion. It cannot be zero or greater than 64.
     #[clap(short = 't', long = "threads")]
     pub threads: Option<usize>,
 
+    /// Number of threads per CPU core when '-t' isn't given. Defaults to 10; it cannot be zero or
+    /// greater than 64.
+    #[clap(long = "thread-ratio", value_name = "N", conflicts_with = "threads")]
+    pub thread_ratio: Option<usize>,
+
+    /// Number of threads added to the count for the logger. It cannot be greater than 8.
+    #[clap(long = "logger-threads", value_name = "N", default_value_t = 1)]
+    pub logger_threads: usize,
+
+    /// Print how the number of threads is derived from the CPU cores, '-t', '--thread-ratio' and
+    /// '--logger-threads', and exit without deleting anything
+    #[clap(long = "explain-threads")]
+    pub explain_threads: bool,
+
     /// Enable verbose logging
     #[clap(short = 'v', long = "verbose")]
     pub verbose: bool,
@@ -50,6 +103,735 @@ pub struct Args {
     /// Perform a dry run without deleting any files or directories
     #[clap(short = 'd', long = "dry-run")]
     pub dry_run: bool,
+
+    /// Ask before deleting each file, symlink and directory, like 'rm -i'. A directory is only
+    /// asked about once everything within it was confirmed, and is kept if anything was declined
+    #[clap(short = 'i', long = "interactive", conflicts_with_all = ["interactive_top", "dashboard"])]
+    pub interactive: bool,
+
+    /// Ask once before deleting each match of the target patterns, a directory along with
+    /// everything within it
+    #[clap(short = 'I', long = "interactive-top", conflicts_with = "dashboard")]
+    pub interactive_top: bool,
+
+    /// Estimate the number of objects and their size from a random sample instead of deleting
+    #[clap(long = "estimate")]
+    pub estimate: bool,
+
+    /// After the dry run's scan, refine the excludes and filters at an interactive prompt and
+    /// turn the approved plan into a real run
+    #[clap(long = "refine", requires = "dry_run")]
+    pub refine: bool,
+
+    /// Percentage of subtrees walked by '--estimate' (e.g. 1%, 0.5%)
+    #[clap(long = "sample", value_parser = parse_percent, default_value = "1%", requires = "estimate")]
+    pub sample: f64,
+
+    /// Show a full-screen live view of the run. Falls back to periodic progress lines when the
+    /// output is not a terminal.
+    #[clap(long = "dashboard")]
+    pub dashboard: bool,
+
+    /// Only delete files and symlinks of at least this size (e.g. 10M, 2GiB). Directories are
+    /// only deleted if everything within them matches and their own size does too.
+    #[clap(long = "min-size", value_parser = parse_size)]
+    pub min_size: Option<u64>,
+
+    /// Only delete files and symlinks of at most this size (e.g. 10M, 2GiB). Directories are
+    /// only deleted if everything within them matches and their own size does too.
+    #[clap(long = "max-size", value_parser = parse_size)]
+    pub max_size: Option<u64>,
+
+    /// Only delete files and directories whose name matches this regular expression. Can be given
+    /// more than once, a name has to match any of them. A matching directory is deleted with
+    /// everything in it.
+    #[clap(long = "name-regex", value_name = "REGEX")]
+    pub name_regex: Vec<String>,
+
+    /// Only delete objects, directories included, that match this expression, e.g.
+    /// 'size > 100M && mtime > 90d && name ~ "*.bak"'. See the README for the fields and operators.
+    #[clap(long = "where", value_name = "EXPRESSION")]
+    pub where_expr: Option<String>,
+
+    /// Only delete regular files whose contents are of one of these MIME types, found from their
+    /// first bytes (e.g. 'image/*,video/*'). Can be combined with '--magic'
+    #[clap(long = "mime", value_delimiter = ',', value_name = "TYPES", value_parser = MimePattern::parse, conflicts_with = "refine")]
+    pub mime: Vec<MimePattern>,
+
+    /// Only delete regular files that start with these bytes, in hex and optionally at a decimal
+    /// offset (e.g. '89504e47' or '257:7573746172'). Can be given more than once
+    #[clap(long = "magic", value_name = "HEX", value_parser = Magic::parse, conflicts_with = "refine")]
+    pub magic: Vec<Magic>,
+
+    /// Whether files have to meet all of the name, size, extension, '--where' and content
+    /// criteria to be deleted, or any of them
+    #[clap(long = "match", value_enum, default_value = "all", ignore_case = true)]
+    pub match_mode: MatchMode,
+
+    /// Delete what the name, size, extension, '--where' and content criteria don't select
+    #[clap(long = "not")]
+    pub negate: bool,
+
+    /// Largest number of files read at the same time for '--mime' and '--magic'
+    #[clap(long = "sniff-readers", value_name = "N", default_value_t = 16)]
+    pub sniff_readers: usize,
+
+    /// Only delete files with one of these extensions (e.g. log,tmp,core). Directories are kept.
+    #[clap(long = "ext", value_delimiter = ',', value_name = "EXTENSIONS")]
+    pub ext: Vec<String>,
+
+    /// Only delete empty (zero-byte) regular files. Everything else, including directories, is kept.
+    #[clap(long = "empty-files-only")]
+    pub empty_files_only: bool,
+
+    /// Report the number and size of deleted files per owner
+    #[clap(long = "owner-report")]
+    pub owner_report: bool,
+
+    /// Count symlinks separately from files in the reports, as valid or dangling
+    #[clap(long = "symlink-report")]
+    pub symlink_report: bool,
+
+    /// Mail each owner a summary of their deleted files through the local sendmail. Not done in a dry run
+    #[clap(long = "mail-owners", requires = "owner_report")]
+    pub mail_owners: bool,
+
+    /// Only remove directories that contain no files, bottom-up. Files and everything holding them are kept.
+    #[clap(long = "prune-empty-dirs", conflicts_with_all = ["min_size", "max_size", "ext", "empty_files_only", "uid", "gid", "user", "group", "only_mine", "perm", "file_type", "not_accessed_in", "created_before", "min_age", "hidden_only", "skip_hardlinked", "mime", "magic", "negate"])]
+    pub prune_empty_dirs: bool,
+
+    /// Only delete objects owned by this user ID, directories included. Can be given more than
+    /// once.
+    #[clap(long = "uid", value_name = "UID")]
+    pub uid: Vec<u32>,
+
+    /// Only delete objects owned by this group ID, directories included. Can be given more than
+    /// once.
+    #[clap(long = "gid", value_name = "GID")]
+    pub gid: Vec<u32>,
+
+    /// Only delete objects owned by this user, directories included. Can be given more than
+    /// once.
+    #[clap(long = "user", value_name = "NAME")]
+    pub user: Vec<String>,
+
+    /// Only delete objects owned by this group, directories included. Can be given more than
+    /// once.
+    #[clap(long = "group", value_name = "NAME")]
+    pub group: Vec<String>,
+
+    /// Only delete objects owned by the user running nukem, directories included, and keep
+    /// everything of other users, even when running as root. With sudo, the user who ran sudo
+    #[clap(long = "only-mine")]
+    pub only_mine: bool,
+
+    /// Only delete regular files and directories whose permissions match, like find's '-perm': an
+    /// exact mode (0777), all of the bits (-0002) or any of them (/o+w). Modes are octal or
+    /// symbolic.
+    #[clap(long = "perm", value_name = "MODE", value_parser = PermFilter::parse, allow_hyphen_values = true)]
+    pub perm: Option<PermFilter>,
+
+    /// Only delete objects that haven't been read for this long, by their access time.
+    /// A plain number is a number of days; units are s, m, h, d or w (e.g. 30, 12h, 2w)
+    #[clap(long = "not-accessed-in", value_name = "AGE", value_parser = parse_age)]
+    pub not_accessed_in: Option<Duration>,
+
+    /// Only delete objects created before this time, by their birth time. Either a date and time
+    /// in local time (2024-05-01, '2024-05-01 13:30') or an age like '--not-accessed-in'
+    #[clap(long = "created-before", value_name = "TIME", value_parser = parse_time)]
+    pub created_before: Option<i64>,
+
+    /// Never delete objects modified or changed more recently than this, by the newer of their
+    /// modification and change times, e.g. 1h. Units are those of '--not-accessed-in'
+    #[clap(long = "min-age", value_name = "AGE", value_parser = parse_age)]
+    pub min_age: Option<Duration>,
+
+    /// Keep a ledger of everything deleted in the state directory, and don't delete a path again
+    /// within this time after a run deleted it or a directory above it, e.g. 1d. Units are those
+    /// of '--not-accessed-in'
+    #[clap(long = "cooldown", value_name = "AGE", value_parser = parse_age)]
+    pub cooldown: Option<Duration>,
+
+    /// Only delete objects of these types, like find's '-type': f (regular file), d (directory)
+    /// and l (symlink), e.g. 'l' or 'f,l'. Directories are only deleted when everything within them is
+    #[clap(long = "type", value_name = "TYPES", value_parser = TypeSet::parse)]
+    pub file_type: Option<TypeSet>,
+
+    /// Keep files that have more than one hard link, since deleting one name doesn't free their space
+    #[clap(long = "skip-hardlinked")]
+    pub skip_hardlinked: bool,
+
+    /// Keep hidden files and directories (names starting with '.', or with the hidden attribute on
+    /// FAT, NTFS and SMB) found within the targets. Hidden directories are not even read
+    #[clap(long = "skip-hidden", conflicts_with = "hidden_only")]
+    pub skip_hidden: bool,
+
+    /// Only delete hidden files and directories (names starting with '.', or with the hidden
+    /// attribute on FAT, NTFS and SMB), along with everything within a hidden directory.
+    /// Everything else is kept
+    #[clap(long = "hidden-only")]
+    pub hidden_only: bool,
+
+    /// Glob pattern for the only paths to delete (e.g. '*.o'), written like '--exclude' patterns.
+    /// Can be given more than once. Included directories go with their contents, and excludes
+    /// take precedence
+    #[clap(long = "include", value_name = "PATTERN")]
+    pub include: Vec<String>,
+
+    /// Glob pattern for paths that must not be deleted (e.g. '**/.git/**'). Can be given more than
+    /// once. A pattern without a '/' is matched against basenames.
+    #[clap(long = "exclude", value_name = "PATTERN")]
+    pub exclude: Vec<String>,
+
+    /// File of gitignore-style rules for paths that must not be deleted, including '!' to bring
+    /// paths back. Can be given more than once.
+    #[clap(long = "exclude-from", value_name = "FILE")]
+    pub exclude_from: Vec<PathBuf>,
+
+    /// Path that must never be deleted, on top of the built-in list of system directories ('/',
+    /// '/etc', '/usr' and the like). A target that is or contains a protected path is refused
+    /// before anything is deleted. Can be given more than once.
+    #[clap(long = "protect", value_name = "PATH")]
+    pub protect: Vec<PathBuf>,
+
+    /// Allow a target that is the root of a mounted filesystem or the home directory of the user
+    /// running nukem. Those are refused otherwise, even when a wildcard such as '/mnt/*' matched
+    /// them. The protected paths stay protected.
+    #[clap(long = "i-know-what-i-am-doing")]
+    pub i_know_what_i_am_doing: bool,
+
+    /// Scan the targets first and, if the run would delete more than this many objects or more
+    /// than this size (e.g. 100000, 500G), have the operator type each target path back before
+    /// anything is deleted. Can be given once for objects and once for bytes
+    #[clap(long = "confirm-over", value_name = "N|SIZE", value_parser = Threshold::parse)]
+    pub confirm_over: Vec<Threshold>,
+
+    /// What a run over '--confirm-over' does: 'ask' for the target paths, which needs a
+    /// terminal, or 'abort' without deleting anything
+    #[clap(long = "over-threshold", value_enum, default_value = "ask", ignore_case = true)]
+    pub over_threshold: OverThreshold,
+
+    /// Go on without the typed confirmation of '--confirm-over', for runs that were reviewed
+    /// otherwise. A run that goes over the threshold logs that it wasn't confirmed
+    #[clap(long = "skip-confirmation")]
+    pub skip_confirmation: bool,
+
+    /// Stop deleting once the run deleted this many objects or this size (e.g. 10000, 50G), as a
+    /// limit on the damage of a scripted run gone wrong. What is left is kept, and the run exits
+    /// with an error. Can be given once for objects and once for bytes
+    #[clap(long = "max-delete", value_name = "N|SIZE", value_parser = Threshold::parse)]
+    pub max_delete: Vec<Threshold>,
+
+    /// What a run that reaches '--max-delete' does: 'abort' and keep the rest, or 'ask' whether
+    /// to go on with the same budget once more, which needs a terminal
+    #[clap(long = "over-budget", value_enum, default_value = "abort", ignore_case = true)]
+    pub over_budget: OverBudget,
+
+    /// Confine the run with Landlock (Linux 5.13 or later), so it can only remove objects below
+    /// the root of each target pattern, the directory above its first wildcard, and only write to
+    /// the directories of its logfile, state, reports and action, whatever goes wrong while
+    /// crawling. A kernel without Landlock fails the run
+    #[clap(long = "sandbox")]
+    pub sandbox: bool,
+
+    /// Stay on the filesystem of each target, like 'rm --one-file-system': a directory or file
+    /// within it that is on another filesystem, such as a mount point, is kept along with the
+    /// directories holding it
+    #[clap(long = "one-file-system")]
+    pub one_file_system: bool,
+
+    /// What to do with the matched files. 'compress' compresses each file in place instead of
+    /// deleting it, and 'move' moves it under '--move-to'. Both keep every directory.
+    #[clap(long = "action", value_enum, default_value = "delete", ignore_case = true)]
+    pub action: Action,
+
+    /// Program used to compress files with '--action compress' and compress tiers
+    #[clap(long = "compressor", value_enum, default_value = "gzip", ignore_case = true)]
+    pub compressor: Compressor,
+
+    /// Directory that files and symlinks are moved to with '--action move' and move tiers. Each
+    /// one ends up at its absolute path below it.
+    #[clap(long = "move-to", value_name = "DIR", required_if_eq("action", "move"))]
+    pub move_to: Option<PathBuf>,
+
+    /// Move files, symlinks and directories to the desktop trash (the freedesktop.org home
+    /// trash) instead of deleting them, so they can be restored from a file manager.
+    #[clap(long = "trash", conflicts_with_all = ["action", "move_to", "prune_empty_dirs"])]
+    pub trash: bool,
+
+    /// Stage everything that is deleted in the undo directory instead of removing it, and record
+    /// it in a manifest, so the run can be undone with 'nukem restore <MANIFEST>'.
+    #[clap(long = "undoable", conflicts_with_all = ["action", "move_to", "prune_empty_dirs", "trash"])]
+    pub undoable: bool,
+
+    /// Directory that '--undoable' stages deleted objects and writes its manifests in. Defaults
+    /// to the 'undo' folder of the state directory.
+    #[clap(long = "undo-dir", value_name = "DIR", requires = "undoable")]
+    pub undo_dir: Option<PathBuf>,
+
+    /// Add everything to a compressed tar archive before deleting it, so the run leaves a backup.
+    /// The extension picks the compressor: '.tar.zst' for zstd, '.tar.gz' for gzip.
+    #[clap(long = "archive-to", value_name = "FILE", conflicts_with_all = ["action", "move_to", "prune_empty_dirs", "trash", "undoable"])]
+    pub archive_to: Option<PathBuf>,
+
+    /// Act on files by their age in one run, e.g. '--tier 30d=compress --tier 90d=trash --tier
+    /// 180d=delete': each file goes to the oldest tier its modification time reaches, and files
+    /// younger than every tier are kept. Actions are compress, move (to '--move-to'), trash and
+    /// delete. Ages have the units of '--not-accessed-in'. Directories are kept. Can be given more
+    /// than once
+    #[clap(long = "tier", value_name = "AGE=ACTION", value_parser = Tier::parse, conflicts_with_all = ["action", "trash", "undoable", "archive_to", "prune_empty_dirs"])]
+    pub tier: Vec<Tier>,
+
+    /// Resident memory limit (e.g. 512MiB, 4G). When memory use approaches the limit, discovery
+    /// is throttled until the deleters have drained the queues.
+    #[clap(long = "memory-limit", value_parser = parse_size)]
+    pub memory_limit: Option<u64>,
+
+    /// Before deleting, report processes that hold files open within the targets (Linux only).
+    /// Also reports the responsible processes when a deletion fails because a path is busy.
+    #[clap(long = "check-open-handles")]
+    pub check_open_handles: bool,
+
+    /// What to do with directories that contain a Unix socket bound by a running process:
+    /// 'warn' deletes them with a warning, 'skip' leaves them in place (Linux only)
+    #[clap(long = "live-sockets", value_enum, ignore_case = true)]
+    pub live_sockets: Option<LiveSocketPolicy>,
+
+    /// Keep files that a running process has open or mapped, and directories holding such a
+    /// file, and list them at the end (Linux only)
+    #[clap(long = "skip-in-use")]
+    pub skip_in_use: bool,
+
+    /// Take the immutable and append-only attributes ('chattr +i', '+a') off the objects being
+    /// deleted instead of keeping them. Needs root (CAP_LINUX_IMMUTABLE, Linux only)
+    #[clap(long = "clear-immutable")]
+    pub clear_immutable: bool,
+
+    /// Fire an alert once this many deletions have failed
+    #[clap(long = "alert-failures")]
+    pub alert_failures: Option<u64>,
+
+    /// Fire an alert once the run has taken longer than this (e.g. 90s, 30m, 2h)
+    #[clap(long = "alert-elapsed", value_parser = parse_duration)]
+    pub alert_elapsed: Option<Duration>,
+
+    /// Command run through 'sh -c' when an alert fires. NUKEM_ALERT, NUKEM_FAILURES and
+    /// NUKEM_ELAPSED_SECS are set in its environment.
+    #[clap(long = "alert-exec")]
+    pub alert_exec: Option<String>,
+
+    /// http:// URL that receives a JSON POST when an alert fires
+    #[clap(long = "alert-webhook")]
+    pub alert_webhook: Option<String>,
+
+    /// Run in an unprivileged user namespace that maps the subordinate UIDs and GIDs of
+    /// /etc/subuid and /etc/subgid, to clean up the storage of rootless containers
+    #[clap(long = "userns")]
+    pub userns: bool,
+
+    /// Run in a transient cgroup (cgroup v2, Linux only), so the kernel enforces the
+    /// '--cgroup-cpu', '--cgroup-memory' and '--cgroup-io' limits
+    #[clap(long = "cgroup")]
+    pub cgroup: bool,
+
+    /// CPU time the run may use, as a share of one CPU (e.g. 50%, or 200% for two CPUs)
+    #[clap(long = "cgroup-cpu", value_name = "PERCENT", value_parser = cgroup::parse_cpu, requires = "cgroup")]
+    pub cgroup_cpu: Option<f64>,
+
+    /// Memory the run may use before the kernel reclaims from it (e.g. 512MiB, 4G)
+    #[clap(long = "cgroup-memory", value_name = "SIZE", value_parser = parse_size, requires = "cgroup")]
+    pub cgroup_memory: Option<u64>,
+
+    /// I/O limits for the disks holding the targets, as comma-separated rbps, wbps, riops and
+    /// wiops values (e.g. 'wiops=500,wbps=50M')
+    #[clap(long = "cgroup-io", value_name = "LIMITS", value_parser = IoLimits::parse, requires = "cgroup")]
+    pub cgroup_io: Option<IoLimits>,
+
+    /// Lockfile on a shared filesystem that only one run (on any host) may hold at a time. The run
+    /// exits with an error if another run holds it.
+    #[clap(long = "lock-file")]
+    pub lock_file: Option<PathBuf>,
+
+    /// How long to wait for '--lock-file' to be released before giving up (e.g. 30s, 10m)
+    #[clap(long = "lock-timeout", value_parser = parse_duration, default_value = "0s", requires = "lock_file")]
+    pub lock_timeout: Duration,
+
+    /// Number of slowest individual operations to list in the report (default 10, 0 disables the
+    /// list). The final report only lists them with '--verbose' or when this is given
+    #[clap(long = "slowest")]
+    pub slowest: Option<usize>,
+
+    /// Give up on a stat, opendir or unlink that takes longer than this (e.g. 30s, 2m), such as
+    /// one hung on an unresponsive NFS server. The path is kept, and listed at the end as stuck
+    #[clap(long = "op-timeout", value_parser = parse_duration, value_name = "DURATION")]
+    pub op_timeout: Option<Duration>,
+
+    /// How long a run that is sent SIGTERM, such as by a batch scheduler at the end of the
+    /// walltime, may take to stop and write its reports and run history (e.g. 30s). 0 leaves
+    /// SIGTERM to end the run at once
+    #[clap(long = "term-grace", value_parser = parse_duration, value_name = "DURATION", default_value = "10s")]
+    pub term_grace: Duration,
+
+    /// Stop the run once this many deletions failed, e.g. because a whole subtree is on a
+    /// read-only mount. No more objects are queued, the deleters finish what they are working
+    /// on, and the run writes its reports and exits with an error
+    #[clap(long = "max-errors", value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    pub max_errors: Option<u64>,
+
+    /// Write a JSON report of the run, including the results of every crawled directory, to this file
+    #[clap(long = "json-report")]
+    pub json_report: Option<PathBuf>,
+
+    /// Write the totals of the run as one line of JSON to this file descriptor, opened by the
+    /// calling script, e.g. '--summary-fd 3 3>summary.json'
+    #[clap(long = "summary-fd", value_name = "FD")]
+    pub summary_fd: Option<i32>,
+
+    /// Write a binary journal of every object removed, for indexers and backup catalogs to
+    /// invalidate their records from, to this file
+    #[clap(long = "event-journal", value_name = "FILE")]
+    pub event_journal: Option<PathBuf>,
+
+    /// Directory where run history is kept. Defaults to $XDG_STATE_HOME/nukem or ~/.local/state/nukem
+    #[clap(long = "state-dir")]
+    pub state_dir: Option<PathBuf>,
+
+    /// Don't record this run in the run history
+    #[clap(long = "no-history")]
+    pub no_history: bool,
+
+    /// Match wildcard patterns, --exclude and --include patterns, --name-regex and --ext
+    /// case-insensitively
+    #[clap(long = "ignore-case")]
+    pub ignore_case: bool,
+
+    /// Unit used for all human-readable sizes in reports. 'auto' picks the largest unit that
+    /// keeps the value at or above one.
+    #[clap(long = "size-unit", value_enum, default_value = "MiB", ignore_case = true)]
+    pub size_unit: SizeUnit,
+}
+
+/// Subcommands that inspect the application's state instead of deleting paths.
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// List past runs recorded in the state directory
+    History,
+    /// Display the stored report of a past run
+    Show {
+        /// ID of the run, as listed by 'nukem history'
+        run_id: String,
+    },
+    /// Measure the metadata rates of a filesystem, used to forecast the duration of '--estimate'
+    Bench {
+        /// Directory on the filesystem to measure. A scratch tree is created in it and removed again
+        dir: PathBuf,
+        /// Number of empty files in the scratch tree
+        #[clap(long = "files", default_value_t = 20000)]
+        files: usize,
+    },
+    /// Delete the objects listed in a manifest, with an audit log and a signed completion certificate
+    Purge {
+        /// CSV file with one path per line, relative to the root, optionally preceded by an identifier
+        #[clap(long = "manifest")]
+        manifest: PathBuf,
+        /// Directory the manifest's paths are relative to. Nothing outside it is deleted
+        #[clap(long = "root")]
+        root: PathBuf,
+        /// File with the key that signs the certificate. Defaults to 'purge.key' in the state directory
+        #[clap(long = "key-file")]
+        key_file: Option<PathBuf>,
+    },
+    /// Check the signature of a purge certificate and that its audit log is unchanged
+    VerifyCertificate {
+        /// The certificate, as written by 'nukem purge'
+        certificate: PathBuf,
+        /// File with the key the certificate was signed with
+        #[clap(long = "key-file")]
+        key_file: Option<PathBuf>,
+    },
+    /// Write a plan of everything deleting the paths would delete, for review before 'nukem apply'.
+    /// Nothing is deleted. Options such as filters go before 'plan'
+    Plan {
+        /// File to write the plan to. It must not exist yet
+        #[clap(short = 'o', long = "output")]
+        output: PathBuf,
+        /// Full path(s) to the file(s) or directory(s) to plan the deletion of, as for a run
+        #[clap(required = true)]
+        paths: Vec<PathBuf>,
+    },
+    /// Delete exactly the objects listed in a plan, as long as they haven't changed since it was made
+    Apply {
+        /// The plan, as written by 'nukem plan'
+        plan: PathBuf,
+    },
+    /// Put back everything an '--undoable' run deleted
+    Restore {
+        /// The manifest of the run, as logged at its end
+        manifest: PathBuf,
+    },
+    /// Work with the audit logs written by 'nukem purge'
+    Audit {
+        #[command(subcommand)]
+        command: AuditCommand,
+    },
+}
+
+/// Subcommands of 'nukem audit'.
+#[derive(Subcommand, Debug, Clone)]
+pub enum AuditCommand {
+    /// Check that no record of an audit log was changed, removed or inserted
+    Verify {
+        /// The audit log
+        file: PathBuf,
+        /// File with the key the records were signed with. Only needed for signed logs
+        #[clap(long = "key-file")]
+        key_file: Option<PathBuf>,
+    },
+}
+
+/// Units available for displaying sizes in reports. All multiples are binary (powers of 1024).
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SizeUnit {
+    #[value(name = "B")]
+    Bytes,
+    #[value(name = "KiB")]
+    KiB,
+    #[value(name = "MiB")]
+    MiB,
+    #[value(name = "GiB")]
+    GiB,
+    #[value(name = "auto")]
+    Auto,
+}
+
+/// What a run over '--confirm-over' does.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverThreshold {
+    /// Ask for the target paths to be typed back.
+    Ask,
+    /// Stop without deleting anything.
+    Abort,
+}
+
+/// What a run that reaches '--max-delete' does.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverBudget {
+    /// Ask whether to go on with the same budget once more.
+    Ask,
+    /// Stop deleting and keep what is left.
+    Abort,
+}
+
+/// Policies for directories that contain live Unix sockets.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LiveSocketPolicy {
+    /// Delete the directory, but log which sockets were removed.
+    Warn,
+    /// Leave the directory (and its parents) in place.
+    Skip,
+}
+
+/// How the criteria that select files are combined.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MatchMode {
+    /// A file has to meet every criterion.
+    #[default]
+    All,
+    /// A file has to meet at least one criterion.
+    Any,
+}
+
+/// What is done with the files and directories that are found.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Delete them.
+    Delete,
+    /// Compress files in place, removing the originals, and keep directories.
+    Compress,
+    /// Move files and symlinks to another location, and keep directories.
+    Move,
+}
+
+/// Programs that can compress files for '--action compress'.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compressor {
+    /// gzip, producing '.gz' files.
+    Gzip,
+    /// zstd, producing '.zst' files.
+    Zstd,
+}
+
+/// Syslog facilities that messages can be sent with for '--syslog'.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyslogFacility {
+    User,
+    Daemon,
+    Auth,
+    Local0,
+    Local1,
+    Local2,
+    Local3,
+    Local4,
+    Local5,
+    Local6,
+    Local7,
+}
+
+impl SyslogFacility {
+    /// Retrieves the numerical code of the facility.
+    ///
+    /// # Returns
+    ///
+    /// * `u8` - The code, as defined by RFC 5424.
+    pub fn code(self) -> u8 {
+        match self {
+            SyslogFacility::User => 1,
+            SyslogFacility::Daemon => 3,
+            SyslogFacility::Auth => 4,
+            SyslogFacility::Local0 => 16,
+            SyslogFacility::Local1 => 17,
+            SyslogFacility::Local2 => 18,
+            SyslogFacility::Local3 => 19,
+            SyslogFacility::Local4 => 20,
+            SyslogFacility::Local5 => 21,
+            SyslogFacility::Local6 => 22,
+            SyslogFacility::Local7 => 23,
+        }
+    }
+}
+
+/// Parses a human-readable size such as `100`, `10K`, `10M`, `2GiB` or `1.5TB` into bytes.
+/// All multiples are binary, so `K`, `KB` and `KiB` all mean 1024 bytes. Only sizes with a unit
+/// larger than a byte can have a fraction.
+///
+/// # Arguments
+///
+/// * `value` - The size string supplied on the command line.
+///
+/// # Returns
+///
+/// * `Result<u64, String>` - Ok with the number of bytes, Err with a message if the value is invalid.
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    // split the value into its numeric part and its unit suffix
+    let split = value.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number.parse().map_err(|_| format!("Invalid size '{}'", value))?;
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" | "kib" => 1 << 10,
+        "m" | "mb" | "mib" => 1 << 20,
+        "g" | "gb" | "gib" => 1 << 30,
+        "t" | "tb" | "tib" => 1 << 40,
+        _ => return Err(format!("Invalid size unit in '{}'. Use B, K, M, G or T", value)),
+    };
+    if multiplier == 1 && number.fract() != 0.0 {
+        return Err(format!("Invalid size '{}'. A number of bytes can't have a fraction", value));
+    }
+    // sizes beyond what fits saturate at the largest one
+    Ok((number * multiplier as f64) as u64)
+}
+
+/// Parses a percentage such as `1%`, `0.5%` or `10` into a fraction between 0 and 1.
+///
+/// # Arguments
+///
+/// * `value` - The percentage string supplied on the command line.
+///
+/// # Returns
+///
+/// * `Result<f64, String>` - Ok with the fraction, Err with a message if the value is invalid.
+pub fn parse_percent(value: &str) -> Result<f64, String> {
+    let value = value.trim();
+    let percent: f64 = value.trim_end_matches('%').trim().parse().map_err(|_| format!("Invalid percentage '{}'", value))?;
+    if !(percent > 0.0 && percent <= 100.0) {
+        return Err(format!("Invalid percentage '{}'. It must be greater than 0% and at most 100%", value));
+    }
+    Ok(percent / 100.0)
+}
+
+/// Parses a human-readable duration such as `90`, `90s`, `30m`, `2h`, `7d` or `2w`. A number
+/// without a unit is taken as seconds.
+///
+/// # Arguments
+///
+/// * `value` - The duration string supplied on the command line.
+///
+/// # Returns
+///
+/// * `Result<Duration, String>` - Ok with the duration, Err with a message if the value is invalid.
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    parse_span(value, 1.0)
+}
+
+/// Parses the age given to '--not-accessed-in'. A plain number is a number of days, anything
+/// else is read like `parse_duration`.
+///
+/// # Arguments
+///
+/// * `value` - The age string supplied on the command line.
+///
+/// # Returns
+///
+/// * `Result<Duration, String>` - Ok with the age, Err with a message if the value is invalid.
+pub fn parse_age(value: &str) -> Result<Duration, String> {
+    parse_span(value, 86_400.0)
+}
+
+/// Parses a duration for `parse_duration` and `parse_age`, which only differ in the unit of a
+/// plain number.
+///
+/// # Arguments
+///
+/// * `value` - The duration string supplied on the command line.
+/// * `bare` - The number of seconds a plain number counts for.
+///
+/// # Returns
+///
+/// * `Result<Duration, String>` - Ok with the duration, Err with a message if the value is invalid
+///   or too long to represent.
+fn parse_span(value: &str, bare: f64) -> Result<Duration, String> {
+    let value = value.trim();
+    let split = value.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number.parse().map_err(|_| format!("Invalid duration '{}'", value))?;
+    let seconds: f64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" => bare,
+        "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        "d" => 86_400.0,
+        "w" => 604_800.0,
+        _ => return Err(format!("Invalid duration unit in '{}'. Use s, m, h, d or w", value)),
+    };
+    Duration::try_from_secs_f64(number * seconds).map_err(|_| format!("Invalid duration '{}'. It is too long", value))
+}
+
+/// Parses the time given to '--created-before': a local date, a local date and time, or an age
+/// that is counted back from now.
+///
+/// # Arguments
+///
+/// * `value` - The time string supplied on the command line.
+///
+/// # Returns
+///
+/// * `Result<i64, String>` - Ok with the time in seconds since the epoch, Err with a message if
+///   the value is invalid.
+pub fn parse_time(value: &str) -> Result<i64, String> {
+    let value = value.trim();
+    let date_time = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+        .or_else(|| NaiveDate::parse_from_str(value, "%Y-%m-%d").ok().and_then(|date| date.and_hms_opt(0, 0, 0)));
+    if let Some(date_time) = date_time {
+        return match Local.from_local_datetime(&date_time).earliest() {
+            Some(time) => Ok(time.timestamp()),
+            None => Err(format!("Invalid time '{}': it doesn't exist in the local time zone", value)),
+        };
+    }
+    let age = parse_age(value).map_err(|_| format!("Invalid time '{}'. Use a date (2024-05-01), a date and time (2024-05-01 13:30) or an age (30d)", value))?;
+    Ok(crate::filters::access_cutoff(age))
 }
 
 impl Args {
@@ -68,4 +850,110 @@ impl Args {
             None
         }
     }
+
+    /// Checks whether files are compressed, by '--action compress' or a compress tier.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if files may be compressed.
+    pub fn compresses(&self) -> bool {
+        self.action == Action::Compress || self.tier.iter().any(|tier| tier.action == TierAction::Compress)
+    }
+
+    /// Checks whether files are moved under '--move-to', by '--action move' or a move tier.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if files may be moved.
+    pub fn moves(&self) -> bool {
+        self.action == Action::Move || self.tier.iter().any(|tier| tier.action == TierAction::Move)
+    }
+
+    /// Checks whether objects go to the trash, by '--trash' or a trash tier.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if objects may be trashed.
+    pub fn trashes(&self) -> bool {
+        self.trash || self.tier.iter().any(|tier| tier.action == TierAction::Trash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::{parse_age, parse_duration, parse_size};
+
+    #[test]
+    fn parses_durations_with_units() {
+        assert_eq!(parse_duration("90s"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("30m"), Ok(Duration::from_secs(1800)));
+        assert_eq!(parse_duration("2H"), Ok(Duration::from_secs(7200)));
+        assert_eq!(parse_duration("7d"), Ok(Duration::from_secs(604_800)));
+        assert_eq!(parse_duration("2w"), Ok(Duration::from_secs(1_209_600)));
+        assert_eq!(parse_duration("1.5m"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration(" 10 s "), Ok(Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn takes_a_bare_number_as_seconds() {
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("0"), Ok(Duration::ZERO));
+    }
+
+    #[test]
+    fn rejects_invalid_durations() {
+        assert_eq!(parse_duration("10y"), Err("Invalid duration unit in '10y'. Use s, m, h, d or w".into()));
+        assert_eq!(parse_duration("d"), Err("Invalid duration 'd'".into()));
+        assert_eq!(parse_duration(""), Err("Invalid duration ''".into()));
+        assert_eq!(parse_duration("-5s"), Err("Invalid duration '-5s'".into()));
+        assert_eq!(parse_duration("1.2.3s"), Err("Invalid duration '1.2.3s'".into()));
+    }
+
+    #[test]
+    fn rejects_durations_too_long_to_represent() {
+        assert_eq!(parse_duration("99999999999999999999"), Err("Invalid duration '99999999999999999999'. It is too long".into()));
+        assert_eq!(parse_duration("99999999999999999w"), Err("Invalid duration '99999999999999999w'. It is too long".into()));
+        assert!(parse_duration("99999999999d").is_ok());
+        // '--min-age' and the other age options read durations the same way
+        assert_eq!(parse_age("99999999999999999999"), Err("Invalid duration '99999999999999999999'. It is too long".into()));
+    }
+
+    #[test]
+    fn takes_a_bare_age_as_days_but_a_bare_duration_as_seconds() {
+        assert_eq!(parse_age("7"), parse_age("7d"));
+        assert_eq!(parse_age("7"), Ok(Duration::from_secs(7 * 86_400)));
+        assert_eq!(parse_duration("7"), parse_duration("7s"));
+        assert_eq!(parse_duration("7"), Ok(Duration::from_secs(7)));
+        // with a unit, both read the same
+        assert_eq!(parse_age("12h"), parse_duration("12h"));
+        assert_eq!(parse_age("1.5"), Ok(Duration::from_secs(129_600)));
+    }
+
+    #[test]
+    fn parses_sizes_with_binary_units() {
+        assert_eq!(parse_size("1.5K"), Ok(1536));
+        assert_eq!(parse_size("10MiB"), Ok(10 << 20));
+        assert_eq!(parse_size("2gb"), Ok(2 << 30));
+        assert_eq!(parse_size("1T"), Ok(1 << 40));
+        assert_eq!(parse_size("512 B"), Ok(512));
+        assert_eq!(parse_size("0"), Ok(0));
+        assert_eq!(parse_size("4096"), Ok(4096));
+    }
+
+    #[test]
+    fn rejects_invalid_sizes() {
+        assert_eq!(parse_size("K"), Err("Invalid size 'K'".into()));
+        assert_eq!(parse_size(""), Err("Invalid size ''".into()));
+        assert_eq!(parse_size("10X"), Err("Invalid size unit in '10X'. Use B, K, M, G or T".into()));
+        assert_eq!(parse_size("10PB"), Err("Invalid size unit in '10PB'. Use B, K, M, G or T".into()));
+        assert_eq!(parse_size("1.5"), Err("Invalid size '1.5'. A number of bytes can't have a fraction".into()));
+        assert_eq!(parse_size("1.5B"), Err("Invalid size '1.5B'. A number of bytes can't have a fraction".into()));
+    }
+
+    #[test]
+    fn saturates_sizes_too_large_to_represent() {
+        assert_eq!(parse_size("99999999999999999999"), Ok(u64::MAX));
+        assert_eq!(parse_size("99999999999T"), Ok(u64::MAX));
+    }
 }
@@ -0,0 +1,159 @@
+//! This module writes and checks tamper-evident audit logs, as kept by the `purge` subcommand.
+//!
+//! An audit log has one tab-separated record per line: a timestamp, the action, the identifier of
+//! the manifest entry, the path and a detail. Each record ends with a hash over the previous
+//! record's hash and its own fields, so editing, removing or inserting a record breaks every hash
+//! after it. When a signing key is given, the hashes are HMAC-SHA256 signatures, so the chain
+//! can't be rebuilt after an edit without the key either. The `audit verify` subcommand walks the
+//! chain and reports the first record that doesn't match. Cutting records off the end leaves the
+//! chain intact, which is why a finished log ends with an END record and a purge certificate
+//! records the digest of the whole log.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use chrono::Local;
+use crate::sha256::{self, Sha256};
+
+// The hash the first record of a log is chained to.
+const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// An append-only, hash-chained audit log. Every record is written through at once, so the log
+/// survives a crash part way through.
+pub struct AuditLog {
+    pub path: PathBuf,
+    file: File,
+    key: Option<Vec<u8>>,
+    previous: String,
+}
+
+impl AuditLog {
+    /// Creates the audit log. An existing log is never overwritten.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the log.
+    /// * `key` - The key the records are signed with, or None to only chain them with SHA-256.
+    ///
+    /// # Returns
+    ///
+    /// * `io::Result<Self>` - Ok with the log if it could be created.
+    pub fn create(path: PathBuf, key: Option<Vec<u8>>) -> io::Result<Self> {
+        let file = OpenOptions::new().write(true).create_new(true).mode(0o600).open(&path)?;
+        Ok(Self { path, file, key, previous: GENESIS.to_string() })
+    }
+
+    /// Appends a record.
+    ///
+    /// # Arguments
+    ///
+    /// * `action` - What was done.
+    /// * `identifier` - The identifier of the manifest entry, if any.
+    /// * `path` - The object the record is about.
+    /// * `detail` - Further information, such as a size or an error.
+    ///
+    /// # Returns
+    ///
+    /// * `io::Result<()>` - Ok if the record was written.
+    pub fn record(&mut self, action: &str, identifier: Option<&str>, path: &Path, detail: &str) -> io::Result<()> {
+        let fields = [
+            Local::now().to_rfc3339(), action.to_string(), identifier.unwrap_or("-").to_string(),
+            path.display().to_string(), detail.to_string(),
+        ];
+        let body = fields.iter().map(|field| escape(field)).collect::<Vec<_>>().join("\t");
+        let link = chain(self.key.as_deref(), &self.previous, &body);
+        writeln!(self.file, "{}\t{}", body, link)?;
+        self.previous = link;
+        Ok(())
+    }
+
+    /// Flushes the log to disk.
+    ///
+    /// # Returns
+    ///
+    /// * `io::Result<()>` - Ok if the log is on disk.
+    pub fn sync(&self) -> io::Result<()> {
+        self.file.sync_all()
+    }
+}
+
+/// What the check of an audit log found.
+#[derive(Debug, Clone, Default)]
+pub struct Verification {
+    pub records: usize,
+    pub signed: bool,
+    pub finished: bool,
+}
+
+/// Checks the hash chain of an audit log.
+///
+/// # Arguments
+///
+/// * `path` - The audit log.
+/// * `key` - The key the records were signed with, needed only if they were signed.
+///
+/// # Returns
+///
+/// * `Result<Verification, String>` - Ok with what was checked if the chain is intact, Err with the
+///   first record that doesn't match otherwise.
+pub fn verify(path: &Path, key: Option<&[u8]>) -> Result<Verification, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("Unable to read the audit log {}: {}", path.display(), e))?;
+    let mut verification = Verification::default();
+    let mut previous = GENESIS.to_string();
+    for (index, line) in text.lines().enumerate() {
+        let (body, link) = line.rsplit_once('\t').ok_or_else(|| format!("Record {} is not chained", index + 1))?;
+        let signed = link.starts_with("hmac-sha256:");
+        if signed && key.is_none() {
+            return Err("The audit log is signed, so checking it needs the signing key".into());
+        }
+        if index > 0 && signed != verification.signed {
+            return Err(format!("Record {} is {} unlike the records before it", index + 1, if signed { "signed" } else { "unsigned" }));
+        }
+        verification.signed = signed;
+        let expected = chain(key.filter(|_| signed), &previous, body);
+        if link != expected {
+            return Err(format!("Record {} does not match its hash: it or a record before it was changed, removed or inserted", index + 1));
+        }
+        verification.records += 1;
+        verification.finished = body.split('\t').nth(1) == Some("END");
+        previous = expected;
+    }
+    Ok(verification)
+}
+
+/// Works out the hash that links a record to the one before it.
+///
+/// # Arguments
+///
+/// * `key` - The signing key, or None for a plain SHA-256 hash.
+/// * `previous` - The hash of the previous record.
+/// * `body` - The fields of the record, as written.
+///
+/// # Returns
+///
+/// * `String` - The scheme and hash, e.g. 'sha256:<hex>'.
+fn chain(key: Option<&[u8]>, previous: &str, body: &str) -> String {
+    let message = format!("{}\n{}", previous, body);
+    match key {
+        Some(key) => format!("hmac-sha256:{}", sha256::to_hex(&sha256::hmac(key, message.as_bytes()))),
+        None => {
+            let mut digest = Sha256::new();
+            digest.update(message.as_bytes());
+            format!("sha256:{}", sha256::to_hex(&digest.finish()))
+        }
+    }
+}
+
+/// Escapes the characters that separate fields and records.
+///
+/// # Arguments
+///
+/// * `field` - The field.
+///
+/// # Returns
+///
+/// * `String` - The field with backslashes, tabs and newlines escaped.
+fn escape(field: &str) -> String {
+    field.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n")
+}
@@ -0,0 +1,142 @@
+//! This module caps how much a single run may delete, as a limit on the damage of a scripted
+//! invocation gone wrong.
+//!
+//! With '--max-delete', the deleter workers share a budget of objects or bytes, or both, and
+//! take from it before every file, symlink or directory they remove. Once the next object would
+//! go over it, the run stops deleting: 'abort', the default, logs it as an error, and 'ask' pauses
+//! the other workers and asks whether to go on with the same budget once more. Whatever is still
+//! queued after that is kept and counted as "over --max-delete", and the run exits with an error
+//! once its report is written. The crawl goes on, so the report accounts for everything that was
+//! found. A directory takes itself and everything still within it, which it would take along, so
+//! a tree that doesn't fit is kept as a whole. Files compressed or moved by '--action' take from
+//! it too, since the original goes away, each with its size before it was compressed or moved.
+//! Empty directories pruned by '--prune-empty-dirs' hold nothing, so they don't. Dry runs take
+//! from the budget too, so they stop where a real run would.
+
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use crate::args::{Args, OverBudget, SizeUnit};
+use crate::confirm::Threshold;
+use crate::logger::Logger;
+use crate::utility::format_size;
+
+/// What was taken from the budget so far.
+#[derive(Default)]
+struct Spent {
+    objects: u64,
+    bytes: u64,
+    // how many times the budget was granted, once more for every 'y' at the prompt
+    rounds: u64,
+    exhausted: bool,
+}
+
+/// The budget of '--max-delete'. Cloning it shares what was spent, so every worker takes from
+/// the same budget.
+#[derive(Clone)]
+pub struct Budget {
+    limits: Vec<Threshold>,
+    ask: bool,
+    logger: Arc<Logger>,
+    spent: Arc<Mutex<Spent>>,
+    size_unit: SizeUnit,
+}
+
+impl Budget {
+    /// Creates the budget.
+    ///
+    /// # Arguments
+    ///
+    /// * `args` - The command-line arguments.
+    /// * `logger` - An instance of the Logger, which asks whether to go on.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<Self>` - The budget, or None if '--max-delete' wasn't specified.
+    pub fn from_args(args: &Args, logger: &Arc<Logger>) -> Option<Self> {
+        if args.max_delete.is_empty() {
+            return None;
+        }
+        Some(Self {
+            limits: args.max_delete.clone(),
+            ask: args.over_budget == OverBudget::Ask,
+            logger: Arc::clone(logger),
+            spent: Arc::new(Mutex::new(Spent { rounds: 1, ..Spent::default() })),
+            size_unit: args.size_unit,
+        })
+    }
+
+    /// Takes an object from the budget before it is removed. With 'ask', the other workers wait
+    /// while the question is on the console.
+    ///
+    /// # Arguments
+    ///
+    /// * `objects` - The number of objects removed with it, counting itself.
+    /// * `bytes` - The size they free.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if the object may be removed, false if the budget is exhausted.
+    pub async fn take(&self, objects: u64, bytes: u64) -> bool {
+        let mut spent = self.spent.lock().await;
+        if spent.exhausted {
+            return false;
+        }
+        while self.exceeded(&spent, objects, bytes) {
+            let totals = self.totals(&spent);
+            if self.ask {
+                let answer = self.logger.prompt(&format!("nukem: {}. Go on with the same budget once more? [y/N]", totals)).await;
+                if matches!(answer.to_ascii_lowercase().as_str(), "y" | "yes") {
+                    spent.rounds += 1;
+                    continue;
+                }
+            }
+            spent.exhausted = true;
+            self.logger.log(&format!("{}. Stopping, everything that is left is kept", totals), true, false, false).await;
+            return false;
+        }
+        spent.objects += objects;
+        spent.bytes += bytes;
+        true
+    }
+
+    /// Tells whether the run stopped at the budget, and how far it got.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<String>` - What was deleted before the run stopped, or None if it didn't stop.
+    pub async fn exhausted(&self) -> Option<String> {
+        let spent = self.spent.lock().await;
+        spent.exhausted.then(|| self.totals(&spent))
+    }
+
+    /// Checks whether more objects would go over the budget.
+    ///
+    /// # Arguments
+    ///
+    /// * `spent` - What was taken so far.
+    /// * `objects` - The number of objects.
+    /// * `bytes` - Their size.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if they don't fit.
+    fn exceeded(&self, spent: &Spent, objects: u64, bytes: u64) -> bool {
+        self.limits.iter().any(|limit| match *limit {
+            Threshold::Objects(limit) => spent.objects + objects > limit.saturating_mul(spent.rounds),
+            Threshold::Bytes(limit) => spent.bytes + bytes > limit.saturating_mul(spent.rounds),
+        })
+    }
+
+    /// Describes what was taken from the budget.
+    ///
+    /// # Arguments
+    ///
+    /// * `spent` - What was taken so far.
+    ///
+    /// # Returns
+    ///
+    /// * `String` - The description.
+    fn totals(&self, spent: &Spent) -> String {
+        format!("--max-delete reached after {} objects ({})", spent.objects, format_size(spent.bytes, self.size_unit))
+    }
+}
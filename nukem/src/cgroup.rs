@@ -0,0 +1,323 @@
+//! This module runs nukem in a transient cgroup, so the kernel holds a heavy run to the CPU,
+//! memory and I/O limits given with '--cgroup-cpu', '--cgroup-memory' and '--cgroup-io'.
+//!
+//! Only the unified cgroup v2 hierarchy is supported. The cgroup is created next to the one nukem
+//! was started in, since a cgroup that hands controllers down to its children can't hold
+//! processes itself, and the parent has to have the controllers enabled for its children already
+//! or let nukem enable them. Under systemd that is the case for root in system.slice and for
+//! users within their own user@.service. The whole process, with all its threads, is moved into
+//! the cgroup, and moved back and the cgroup removed when the run ends.
+
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use crate::args::{parse_size, Args};
+
+// Period of the CPU quota, in microseconds.
+const CPU_PERIOD: u64 = 100_000;
+
+/// The limits of the cgroup.
+#[derive(Debug, Clone, Default)]
+pub struct Limits {
+    pub cpu: Option<f64>,
+    pub memory: Option<u64>,
+    pub io: Option<IoLimits>,
+}
+
+impl Limits {
+    /// Collects the limits from the command line.
+    ///
+    /// # Arguments
+    ///
+    /// * `args` - Command-line arguments.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The limits requested by the user.
+    pub fn from_args(args: &Args) -> Self {
+        Self { cpu: args.cgroup_cpu, memory: args.cgroup_memory, io: args.cgroup_io.clone() }
+    }
+
+    /// Lists the controllers that the limits need.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<&'static str>` - The controllers to enable.
+    fn controllers(&self) -> Vec<&'static str> {
+        let mut controllers = Vec::new();
+        if self.cpu.is_some() {
+            controllers.push("cpu");
+        }
+        if self.memory.is_some() {
+            controllers.push("memory");
+        }
+        if self.io.is_some() {
+            controllers.push("io");
+        }
+        controllers
+    }
+}
+
+/// The per-device limits written to io.max, such as 'wiops=500,wbps=50M'.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IoLimits {
+    limits: Vec<(String, u64)>,
+}
+
+impl IoLimits {
+    /// Parses '--cgroup-io'.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - Comma-separated rbps, wbps, riops and wiops limits.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, String>` - Ok with the limits, Err with a message if they are invalid.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut limits = Vec::new();
+        for limit in text.split(',').map(str::trim).filter(|limit| !limit.is_empty()) {
+            let (key, value) = limit.split_once('=').ok_or_else(|| format!("Invalid I/O limit '{}': expected e.g. wiops=500 or wbps=50M", limit))?;
+            let value = match key {
+                "rbps" | "wbps" => parse_size(value)?,
+                "riops" | "wiops" => value.parse().map_err(|_| format!("Invalid I/O limit '{}': {} is a number of operations per second", limit, key))?,
+                _ => return Err(format!("Invalid I/O limit '{}': expected rbps, wbps, riops or wiops", limit)),
+            };
+            if value == 0 {
+                return Err(format!("Invalid I/O limit '{}': it must be greater than 0", limit));
+            }
+            limits.push((key.to_string(), value));
+        }
+        if limits.is_empty() {
+            return Err("--cgroup-io needs at least one of rbps, wbps, riops or wiops".into());
+        }
+        Ok(Self { limits })
+    }
+
+    /// Formats the limits the way io.max takes them.
+    ///
+    /// # Returns
+    ///
+    /// * `String` - The limits, e.g. 'wiops=500 wbps=52428800'.
+    fn to_io_max(&self) -> String {
+        self.limits.iter().map(|(key, value)| format!("{}={}", key, value)).collect::<Vec<_>>().join(" ")
+    }
+}
+
+/// Parses '--cgroup-cpu'.
+///
+/// # Arguments
+///
+/// * `value` - The share of one CPU, such as '50%' or '200' for two CPUs.
+///
+/// # Returns
+///
+/// * `Result<f64, String>` - Ok with the percentage, Err with a message if it is invalid.
+pub fn parse_cpu(value: &str) -> Result<f64, String> {
+    let percent: f64 = value.trim().trim_end_matches('%').trim().parse().map_err(|_| format!("Invalid CPU limit '{}'", value))?;
+    if !(percent >= 1.0 && percent.is_finite()) {
+        return Err(format!("Invalid CPU limit '{}'. It must be at least 1% of one CPU", value));
+    }
+    Ok(percent)
+}
+
+/// The transient cgroup the run is contained in. Dropping it moves the process back to the
+/// cgroup it came from and removes it.
+#[derive(Debug)]
+pub struct Cgroup {
+    pub path: PathBuf,
+    pub io_devices: Vec<String>,
+    original: PathBuf,
+}
+
+impl Cgroup {
+    /// Creates the cgroup, sets its limits and moves the process into it.
+    ///
+    /// # Arguments
+    ///
+    /// * `limits` - The limits of the cgroup.
+    /// * `targets` - The paths the run works on, whose block devices get the I/O limits.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, String>` - Ok with the cgroup the process is in, Err with a message if it
+    ///   could not be set up. Nothing is left behind on an error.
+    pub fn enter(limits: &Limits, targets: &[PathBuf]) -> Result<Self, String> {
+        let root = unified_root()?;
+        let current = fs::read_to_string("/proc/self/cgroup")
+            .map_err(|e| format!("Unable to read /proc/self/cgroup: {}", e))?
+            .lines()
+            .find_map(|line| line.strip_prefix("0::").map(|path| path.trim().trim_start_matches('/').to_string()))
+            .ok_or("--cgroup needs the unified cgroup v2 hierarchy, and this process isn't in it")?;
+        let original = root.join(&current);
+        let parent = if current.is_empty() { root.clone() } else { original.parent().unwrap_or(&root).to_path_buf() };
+
+        // the devices are looked up first, so a run that can't be limited as asked doesn't start
+        let io_devices = match limits.io {
+            Some(_) => {
+                let devices = block_devices(targets);
+                if devices.is_empty() {
+                    return Err("--cgroup-io: none of the targets is on a block device that can be limited".into());
+                }
+                devices
+            }
+            None => Vec::new(),
+        };
+        enable_controllers(&parent, &limits.controllers())?;
+
+        let path = parent.join(format!("nukem-{}", std::process::id()));
+        fs::create_dir(&path).map_err(|e| format!("Unable to create the cgroup {}: {}", path.display(), e))?;
+        let cgroup = Self { path, io_devices, original };
+        if let Some(cpu) = limits.cpu {
+            cgroup.write("cpu.max", &format!("{} {}", (cpu / 100.0 * CPU_PERIOD as f64) as u64, CPU_PERIOD))?;
+        }
+        if let Some(memory) = limits.memory {
+            cgroup.write("memory.max", &memory.to_string())?;
+        }
+        if let Some(ref io) = limits.io {
+            for device in &cgroup.io_devices {
+                cgroup.write("io.max", &format!("{} {}", device, io.to_io_max()))?;
+            }
+        }
+        cgroup.write("cgroup.procs", &std::process::id().to_string())?;
+        Ok(cgroup)
+    }
+
+    /// Writes an interface file of the cgroup.
+    ///
+    /// # Arguments
+    ///
+    /// * `file` - The name of the file, such as 'memory.max'.
+    /// * `value` - What to write.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), String>` - Ok if the kernel accepted the value, Err with a message otherwise.
+    fn write(&self, file: &str, value: &str) -> Result<(), String> {
+        fs::write(self.path.join(file), value).map_err(|e| format!("Unable to set {} of {} to '{}': {}", file, self.path.display(), value, e))
+    }
+}
+
+impl Drop for Cgroup {
+    fn drop(&mut self) {
+        // a cgroup can only be removed once no process is left in it
+        let _ = fs::write(self.original.join("cgroup.procs"), std::process::id().to_string());
+        let _ = fs::remove_dir(&self.path);
+    }
+}
+
+/// Finds where the unified cgroup v2 hierarchy is mounted.
+///
+/// # Returns
+///
+/// * `Result<PathBuf, String>` - Ok with the mount point, Err with a message if there is none.
+fn unified_root() -> Result<PathBuf, String> {
+    let mounts = fs::read_to_string("/proc/self/mountinfo").map_err(|e| format!("Unable to read /proc/self/mountinfo: {}", e))?;
+    mounts.lines()
+        .find_map(|line| {
+            // the filesystem type follows the ' - ' separator
+            let (fields, rest) = line.split_once(" - ")?;
+            if rest.split_whitespace().next() != Some("cgroup2") {
+                return None;
+            }
+            fields.split_whitespace().nth(4).map(|mount_point| PathBuf::from(mount_point.replace("\\040", " ")))
+        })
+        .ok_or_else(|| "--cgroup needs the unified cgroup v2 hierarchy, which is not mounted".into())
+}
+
+/// Makes sure the children of a cgroup get the controllers the limits need.
+///
+/// # Arguments
+///
+/// * `parent` - The cgroup the new cgroup is created in.
+/// * `controllers` - The controllers that have to be enabled.
+///
+/// # Returns
+///
+/// * `Result<(), String>` - Ok if they are enabled, Err with a message otherwise.
+fn enable_controllers(parent: &Path, controllers: &[&str]) -> Result<(), String> {
+    let available = parent.join("cgroup.controllers");
+    let available = fs::read_to_string(&available).map_err(|e| format!("Unable to read {}: {}", available.display(), e))?;
+    let available: Vec<&str> = available.split_whitespace().collect();
+    let control = parent.join("cgroup.subtree_control");
+    let enabled = fs::read_to_string(&control).map_err(|e| format!("Unable to read {}: {}", control.display(), e))?;
+    let enabled: Vec<&str> = enabled.split_whitespace().collect();
+    for controller in controllers.iter().filter(|controller| !enabled.contains(controller)) {
+        // a controller that is still bound to a cgroup v1 hierarchy isn't offered in v2
+        if !available.contains(controller) {
+            return Err(format!("The {} controller is not available in {}. It may still be in use by a cgroup v1 hierarchy", controller, parent.display()));
+        }
+        fs::write(&control, format!("+{}", controller)).map_err(|e| format!(
+            "The {} controller is not enabled for the children of {} and could not be enabled: {}. Run in a cgroup that has it delegated, e.g. with 'systemd-run --scope -p Delegate=yes'",
+            controller, parent.display(), e
+        ))?;
+    }
+    Ok(())
+}
+
+/// Looks up the block devices that hold the targets, as io.max takes them. Partitions are
+/// replaced by their disk, since limits can only be set per disk. Targets on filesystems without
+/// a block device of their own, such as tmpfs, NFS or btrfs subvolumes, are left out.
+///
+/// # Arguments
+///
+/// * `targets` - The paths the run works on.
+///
+/// # Returns
+///
+/// * `Vec<String>` - The devices as 'major:minor', without duplicates.
+fn block_devices(targets: &[PathBuf]) -> Vec<String> {
+    let mut devices: Vec<String> = Vec::new();
+    for target in targets {
+        let dev = match fs::symlink_metadata(target) {
+            Ok(metadata) => metadata.dev(),
+            Err(_) => continue,
+        };
+        let device = device_number(dev);
+        let sysfs = Path::new("/sys/dev/block").join(&device);
+        if !sysfs.exists() {
+            continue;
+        }
+        let disk = if sysfs.join("partition").exists() {
+            match fs::read_to_string(sysfs.join("../dev")) {
+                Ok(disk) => disk.trim().to_string(),
+                Err(_) => continue,
+            }
+        } else {
+            device
+        };
+        if !devices.contains(&disk) {
+            devices.push(disk);
+        }
+    }
+    devices
+}
+
+/// Splits a device number into its major and minor parts, the way glibc encodes them, so the
+/// result doesn't depend on which libc release provides 'major' and 'minor'.
+///
+/// # Arguments
+///
+/// * `dev` - The device number.
+///
+/// # Returns
+///
+/// * `String` - The device as 'major:minor'.
+fn device_number(dev: u64) -> String {
+    let major = ((dev >> 32) & 0xffff_f000) | ((dev >> 8) & 0x0000_0fff);
+    let minor = ((dev >> 12) & 0xffff_ff00) | (dev & 0x0000_00ff);
+    format!("{}:{}", major, minor)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::device_number;
+
+    #[test]
+    fn splits_device_numbers_like_glibc() {
+        assert_eq!(device_number(0x0801), "8:1");
+        assert_eq!(device_number(0x1_0303), "259:3");
+        assert_eq!(device_number(0x0001_2000_6783_459a), "74565:424090");
+        assert_eq!(device_number(libc::makedev(253, 17)), "253:17");
+    }
+}
@@ -0,0 +1,106 @@
+//! This module tells the deleters when a crawler pass won't send them anything more.
+//!
+//! Each pass (files and directories) hands out a token for every root it crawls. The token is
+//! shared by the tasks crawling the subdirectories of the root, so it is only released once all
+//! of them have stopped, whether they finished, failed or were aborted. A pass is complete when
+//! its patterns are expanded and every token is released. The deleter workers drain their queue
+//! once the passes feeding it are complete and then stop, instead of waiting for every clone of
+//! a channel sender to be dropped, which a crawler cut short by an error couldn't guarantee.
+
+use std::sync::{Arc, Mutex};
+use tokio::sync::watch;
+
+/// The roots a pass is still crawling, and whether it is still expanding patterns.
+struct State {
+    roots: usize,
+    expanding: bool,
+}
+
+/// What the tokens and the guard of a pass share.
+struct Shared {
+    state: Mutex<State>,
+    done: watch::Sender<bool>,
+}
+
+impl Shared {
+    /// Changes the state and announces the completion of the pass once nothing is left.
+    ///
+    /// # Arguments
+    ///
+    /// * `change` - The change to the state.
+    fn update(&self, change: impl FnOnce(&mut State)) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        change(&mut state);
+        if state.roots == 0 && !state.expanding {
+            self.done.send_replace(true);
+        }
+    }
+}
+
+/// The completion of one crawler pass. Cloning it shares the same pass.
+#[derive(Clone)]
+pub struct Completion {
+    shared: Arc<Shared>,
+}
+
+impl Completion {
+    /// Creates the completion of a pass that hasn't started yet.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - A new instance of Completion.
+    pub fn new() -> Self {
+        let (done, _) = watch::channel(false);
+        Self { shared: Arc::new(Shared { state: Mutex::new(State { roots: 0, expanding: true }), done }) }
+    }
+
+    /// Starts the expansion of the patterns of the pass. The pass can't complete while the guard
+    /// is held, and dropping it, which also happens when the pass fails or panics, ends the
+    /// expansion.
+    ///
+    /// # Returns
+    ///
+    /// * `ExpansionGuard` - The guard.
+    pub fn expanding(&self) -> ExpansionGuard {
+        ExpansionGuard { shared: Arc::clone(&self.shared) }
+    }
+
+    /// Hands out the token for a root of the pass.
+    ///
+    /// # Returns
+    ///
+    /// * `Arc<RootToken>` - The token, to be shared with every task crawling within the root.
+    pub fn root(&self) -> Arc<RootToken> {
+        self.shared.update(|state| state.roots += 1);
+        Arc::new(RootToken { shared: Arc::clone(&self.shared) })
+    }
+
+    /// Waits until the pass is complete.
+    pub async fn wait(&self) {
+        let mut done = self.shared.done.subscribe();
+        // the sender lives in the shared state, so it can't be dropped while it is waited on
+        let _ = done.wait_for(|done| *done).await;
+    }
+}
+
+/// Keeps a pass from completing while its patterns are expanded.
+pub struct ExpansionGuard {
+    shared: Arc<Shared>,
+}
+
+impl Drop for ExpansionGuard {
+    fn drop(&mut self) {
+        self.shared.update(|state| state.expanding = false);
+    }
+}
+
+/// Keeps a pass from completing while a root is crawled.
+pub struct RootToken {
+    shared: Arc<Shared>,
+}
+
+impl Drop for RootToken {
+    fn drop(&mut self) {
+        self.shared.update(|state| state.roots -= 1);
+    }
+}
@@ -0,0 +1,184 @@
+//! This module compresses files in place for '--action compress'.
+//!
+//! Each file is compressed by the system's gzip or zstd into a temporary file next to it. The
+//! temporary file is synced and given the permissions, owner and times of the original, and only
+//! then moved into place (without ever replacing an existing file) and the original removed. If
+//! the original changed while it was being compressed, the compressed copy is thrown away and the
+//! original is left alone. So is a file whose compressed copy wouldn't be smaller, such as one
+//! that holds compressed data already, so compressing never takes more space than before.
+
+use std::fs::{self, File, FileTimes};
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use crate::args::Compressor;
+
+impl Compressor {
+    /// Returns the name of the program that does the compression.
+    ///
+    /// # Returns
+    ///
+    /// * `&'static str` - The program name.
+    pub fn program(&self) -> &'static str {
+        match self {
+            Compressor::Gzip => "gzip",
+            Compressor::Zstd => "zstd",
+        }
+    }
+
+    /// Returns the extension of the compressed files.
+    ///
+    /// # Returns
+    ///
+    /// * `&'static str` - The extension, without the dot.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Compressor::Gzip => "gz",
+            Compressor::Zstd => "zst",
+        }
+    }
+
+    /// Checks that the program is installed by asking it for its version.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), String>` - Ok if the program runs, Err with a message otherwise.
+    pub fn check_available(&self) -> Result<(), String> {
+        match Command::new(self.program()).arg("--version").stdout(Stdio::null()).stderr(Stdio::null()).status() {
+            Ok(status) if status.success() => Ok(()),
+            _ => Err(format!("--action compress needs '{}', which could not be run", self.program())),
+        }
+    }
+}
+
+/// What happened to a file that was handed to the compressor.
+#[derive(Debug)]
+pub enum Outcome {
+    /// The file was compressed, and the compressed copy has this size in bytes.
+    Compressed(u64),
+    /// The file was left alone for the given reason.
+    Unsuitable(&'static str),
+}
+
+/// Compresses a file in place, removing the original once the compressed copy is in place. The
+/// original is kept if the compressed copy isn't smaller.
+///
+/// # Arguments
+///
+/// * `path` - The file to compress.
+/// * `compressor` - The compressor to use.
+///
+/// # Returns
+///
+/// * `io::Result<Outcome>` - Ok with the outcome, Err if the file could not be compressed.
+pub fn compress_file(path: &Path, compressor: Compressor) -> io::Result<Outcome> {
+    let before = fs::symlink_metadata(path)?;
+    if let Some(reason) = unsuitable(path, &before, compressor) {
+        return Ok(Outcome::Unsuitable(reason));
+    }
+    let target = with_suffix(path, &format!(".{}", compressor.extension()));
+    if fs::symlink_metadata(&target).is_ok() {
+        return Ok(Outcome::Unsuitable("compressed file already exists"));
+    }
+    let partial = with_suffix(path, &format!(".{}.nukem-partial", compressor.extension()));
+    let output = File::create_new(&partial)?;
+    match write_compressed(path, &partial, output, &before, compressor) {
+        Ok(size) if size >= before.len() => {
+            fs::remove_file(&partial)?;
+            Ok(Outcome::Unsuitable("compressed copy isn't smaller"))
+        }
+        Ok(size) => {
+            // a hard link never replaces an existing file, unlike a rename
+            let placed = fs::hard_link(&partial, &target);
+            let _ = fs::remove_file(&partial);
+            placed?;
+            fs::remove_file(path)?;
+            Ok(Outcome::Compressed(size))
+        }
+        Err(e) => {
+            let _ = fs::remove_file(&partial);
+            Err(e)
+        }
+    }
+}
+
+/// Checks whether a file should be left uncompressed.
+///
+/// # Arguments
+///
+/// * `path` - The file.
+/// * `metadata` - The metadata of the file, read without following symlinks.
+/// * `compressor` - The compressor that would be used.
+///
+/// # Returns
+///
+/// * `Option<&'static str>` - The reason to leave the file alone, or None if it can be compressed.
+pub fn unsuitable(path: &Path, metadata: &fs::Metadata, compressor: Compressor) -> Option<&'static str> {
+    if !metadata.is_file() {
+        return Some("not a regular file");
+    }
+    // compressing one name of a hard-linked file would silently duplicate its contents
+    if metadata.nlink() > 1 {
+        return Some("file has hard links");
+    }
+    let name = path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+    if name.ends_with(&format!(".{}", compressor.extension())) || name.ends_with(".nukem-partial") {
+        return Some("already compressed");
+    }
+    None
+}
+
+/// Compresses a file into the partial output and gives the output the attributes of the
+/// original.
+///
+/// # Arguments
+///
+/// * `path` - The file to compress.
+/// * `partial` - The path of the partial output.
+/// * `output` - The partial output, opened for writing.
+/// * `before` - The metadata of the file before it was compressed.
+/// * `compressor` - The compressor to use.
+///
+/// # Returns
+///
+/// * `io::Result<u64>` - Ok with the size of the compressed copy, Err if anything failed.
+fn write_compressed(path: &Path, partial: &Path, output: File, before: &fs::Metadata, compressor: Compressor) -> io::Result<u64> {
+    let status = Command::new(compressor.program())
+        .args(["-c", "-q", "--"])
+        .arg(path)
+        .stdin(Stdio::null())
+        .stdout(output.try_clone()?)
+        .status()?;
+    if !status.success() {
+        return Err(io::Error::other(format!("{} exited with {}", compressor.program(), status)));
+    }
+
+    let after = fs::symlink_metadata(path)?;
+    if after.len() != before.len() || after.mtime() != before.mtime() || after.mtime_nsec() != before.mtime_nsec() {
+        return Err(io::Error::other("the file changed while it was being compressed"));
+    }
+
+    // only root can hand the file to another owner, so a failure here is not an error
+    let _ = std::os::unix::fs::chown(partial, Some(before.uid()), Some(before.gid()));
+    output.set_permissions(before.permissions())?;
+    output.set_times(FileTimes::new().set_accessed(before.accessed()?).set_modified(before.modified()?))?;
+    output.sync_all()?;
+    Ok(output.metadata()?.len())
+}
+
+/// Appends a suffix to the file name of a path.
+///
+/// # Arguments
+///
+/// * `path` - The path.
+/// * `suffix` - The suffix to append.
+///
+/// # Returns
+///
+/// * `PathBuf` - The path with the suffix.
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(suffix);
+    PathBuf::from(name)
+}
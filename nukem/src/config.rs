@@ -1,15 +1,18 @@
 //! This module provides utility functions that are configuration-related
 
-use crate::args::Args;
+use crate::args::{Args, Command};
 use crate::threads::ThreadInfo;
 use clap::Parser;
 use crate::validator::Validator;
+use crate::history;
+use crate::retry::RetrySet;
 
 /// Determines the total number of threads to use for application execution. These threads
 /// are used by the Crawler, the Deleter, and the Logger
 ///
-/// Once the number of initial threads is determined by the threads module, one additional
-/// thread is added to the total. This thread will be dedicated to the logger functionality.
+/// Once the number of initial threads is determined by the threads module, the threads given by
+/// '--logger-threads' (one by default) are added to the total. They are dedicated to the logger
+/// functionality.
 ///
 /// # Arguments
 ///
@@ -19,9 +22,9 @@ use crate::validator::Validator;
 ///
 /// * `Result<ThreadInfo, Box<dyn std::error::Error + Send + Sync>>` - Ok with ThreadInfo if successful.
 pub fn define_threads(args: &Args) -> Result<ThreadInfo, Box<dyn std::error::Error + Send + Sync>> {
-    // take number of threads returned by ThreadInfo and add one extra thread for the logger
+    // take number of threads returned by ThreadInfo and add the threads for the logger
     let mut info = ThreadInfo::compute_thread_count(args)?;
-    info.total_thread_count += 1;
+    info.add_logger_threads(args.logger_threads);
     Ok(info)
 }
 
@@ -33,6 +36,24 @@ pub fn define_threads(args: &Args) -> Result<ThreadInfo, Box<dyn std::error::Err
 pub fn initialize_arguments() -> Result<Args, Box<dyn std::error::Error + Send + Sync>> {
     // parse arguments
     let mut args = Args::parse();
+    // a plan is a dry run of its paths that records everything it would delete
+    if let Some(Command::Plan { output, paths }) = args.command.clone() {
+        let cwd = std::env::current_dir()?;
+        args.paths = paths.iter().map(|path| cwd.join(path)).collect();
+        args.plan = Some(output);
+        args.dry_run = true;
+        args.command = None;
+    }
+    // subcommands don't delete anything, so there is nothing to validate
+    if args.command.is_some() {
+        return Ok(args);
+    }
+    // the failures of a previous run become the targets of this one
+    if let Some(ref source) = args.retry_failed {
+        let retry = RetrySet::load(history::state_dir(args.state_dir.as_ref()).as_deref(), source)?;
+        args.paths = retry.patterns();
+        args.retry = Some(retry);
+    }
     // validate paths, logfile, thread count, and number of buffers
     Validator::validate(&mut args)?;
     Ok(args)
@@ -0,0 +1,107 @@
+//! This module stops large deletions until the operator confirms them by typing the target back.
+//!
+//! A wrong pattern or a target one level too high can wipe far more than intended, and a 'y' is
+//! typed out of habit. With '--confirm-over', the targets are scanned before anything is deleted,
+//! the same way '--refine' does, and a run that would delete more objects or more bytes than a
+//! threshold stops and asks for each target path to be typed back, like the deletion of a
+//! repository on GitHub. Anything but the exact path ends the run without deleting anything.
+//! Unattended runs can abort instead ('--over-threshold abort'), and only '--skip-confirmation'
+//! goes on without the confirmation, which is logged as an error so it stands out.
+
+use std::io::{self, IsTerminal};
+use std::sync::Arc;
+use crate::args::{parse_size, Args, OverThreshold};
+use crate::logger::Logger;
+use crate::refine;
+use crate::utility::format_size;
+
+/// A limit of '--confirm-over'.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Threshold {
+    /// A number of files, symlinks and directories.
+    Objects(u64),
+    /// A number of bytes.
+    Bytes(u64),
+}
+
+impl Threshold {
+    /// Parses a '--confirm-over' value.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - A plain number of objects, or a size with a unit such as 500G.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, String>` - Ok with the threshold, Err with a message if the value is invalid.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let text = text.trim();
+        if !text.is_empty() && text.chars().all(|c| c.is_ascii_digit()) {
+            return text.parse().map(Threshold::Objects).map_err(|e| format!("'{}' is not a number of objects: {}", text, e));
+        }
+        parse_size(text).map(Threshold::Bytes)
+    }
+
+    /// Tells whether a run goes over the threshold.
+    ///
+    /// # Arguments
+    ///
+    /// * `objects` - The number of objects the run would delete.
+    /// * `bytes` - Their size.
+    /// * `args` - Command-line arguments, for the size unit.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<String>` - How the run goes over it, or None if it stays within.
+    fn exceeded(self, objects: u64, bytes: u64, args: &Args) -> Option<String> {
+        match self {
+            Threshold::Objects(limit) if objects > limit => Some(format!("more than {} objects", limit)),
+            Threshold::Bytes(limit) if bytes > limit => Some(format!("more than {}", format_size(limit, args.size_unit))),
+            _ => None,
+        }
+    }
+}
+
+/// Scans the targets and, if the run would go over '--confirm-over', asks for the target paths
+/// to be typed back.
+///
+/// # Arguments
+///
+/// * `args` - Command-line arguments.
+/// * `logger` - An instance of the Logger, which asks the questions.
+///
+/// # Returns
+///
+/// * `Result<(), Box<dyn std::error::Error + Send + Sync>>` - Ok if the run may go on, Err with
+///   a message if it is over the threshold and wasn't confirmed.
+pub async fn check(args: &Args, logger: &Arc<Logger>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    logger.log("Scanning the targets for --confirm-over...", false, false, false).await;
+    let (objects, bytes) = refine::prescan(args).await?;
+    let exceeded: Vec<String> = args.confirm_over.iter().filter_map(|threshold| threshold.exceeded(objects, bytes, args)).collect();
+    let totals = format!("The run would delete {} objects ({})", objects, format_size(bytes, args.size_unit));
+    if exceeded.is_empty() {
+        logger.log(&format!("{}, within --confirm-over", totals), false, false, false).await;
+        return Ok(());
+    }
+    let totals = format!("{}, {}", totals, exceeded.join(" and "));
+    if args.skip_confirmation {
+        logger.log(&format!("{}. Going on without confirmation because of --skip-confirmation", totals), true, false, false).await;
+        return Ok(());
+    }
+    if args.over_threshold == OverThreshold::Abort {
+        return Err(format!("{}. Nothing was deleted, since --over-threshold is abort", totals).into());
+    }
+    if !io::stdin().is_terminal() {
+        return Err(format!("{}, and there is no terminal to confirm on. Nothing was deleted; use --skip-confirmation to go on without", totals).into());
+    }
+    logger.log(&totals, false, false, false).await;
+    for target in &args.paths {
+        let target = target.to_string_lossy();
+        let answer = logger.prompt(&format!("To confirm, type the target path '{}':", target)).await;
+        if answer != target {
+            return Err(format!("The confirmation didn't match '{}'. Nothing was deleted", target).into());
+        }
+    }
+    logger.log("Confirmed, deleting", false, false, false).await;
+    Ok(())
+}
@@ -0,0 +1,137 @@
+//! This module coordinates everything written to the terminal while nukem runs.
+//!
+//! The logger, the live dashboard ('--dashboard') and the refinement prompt ('--refine') share the
+//! terminal. Each of them writes through the `Console`, which holds a lock while a line is
+//! written, so lines from different tasks never run into each other and an error on stderr never
+//! lands in the middle of a line on stdout. A view that draws the whole screen or reads from it
+//! takes the console over: while its `Takeover` is held, only the view writes to the terminal, and
+//! the lines of the logger are kept off it (they still go to the logfile and the syslog server).
+//! Anything that brings a view of its own, such as a front end embedding the pipeline, takes the
+//! console over from the logger the same way instead of printing directly.
+
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+/// The stream a line is written to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// The coordinator of the terminal output. The lock is held while anything is written, and
+/// tells whether a view has taken the console over.
+#[derive(Default)]
+pub struct Console {
+    taken: Mutex<bool>,
+}
+
+impl Console {
+    /// Creates the console, owned by the logger until a view takes it over.
+    ///
+    /// # Returns
+    ///
+    /// * `Arc<Self>` - A pointer to the console.
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    /// Writes a line, unless a view has taken the console over.
+    ///
+    /// # Arguments
+    ///
+    /// * `stream` - The stream to write to.
+    /// * `line` - The line, without its newline.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if the line was written, false if it was kept off the terminal.
+    pub fn line(&self, stream: Stream, line: &str) -> bool {
+        let taken = self.taken.lock().unwrap();
+        if *taken {
+            return false;
+        }
+        write(stream, &format!("{}\n", line));
+        true
+    }
+
+    /// Writes text that doesn't end the line, such as a question, unless a view has taken the
+    /// console over.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The text.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if the text was written.
+    pub fn text(&self, text: &str) -> bool {
+        let taken = self.taken.lock().unwrap();
+        if *taken {
+            return false;
+        }
+        write(Stream::Stdout, text);
+        true
+    }
+
+    /// Takes the console over for a view. Only one view can hold it at a time.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<Takeover>` - The hold on the console, which gives it back when dropped, or None
+    ///   if another view has it.
+    pub fn take_over(self: &Arc<Self>) -> Option<Takeover> {
+        let mut taken = self.taken.lock().unwrap();
+        if *taken {
+            return None;
+        }
+        *taken = true;
+        Some(Takeover { console: Arc::clone(self) })
+    }
+}
+
+/// The hold of a view on the console. Everything the view shows is written through it.
+pub struct Takeover {
+    console: Arc<Console>,
+}
+
+impl Takeover {
+    /// Writes text to stdout as it is, such as a frame or escape sequences.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The text.
+    pub fn text(&self, text: &str) {
+        let _taken = self.console.taken.lock().unwrap();
+        write(Stream::Stdout, text);
+    }
+
+    /// Writes a line to stdout.
+    ///
+    /// # Arguments
+    ///
+    /// * `line` - The line, without its newline.
+    pub fn line(&self, line: &str) {
+        self.text(&format!("{}\n", line));
+    }
+}
+
+impl Drop for Takeover {
+    fn drop(&mut self) {
+        *self.console.taken.lock().unwrap() = false;
+    }
+}
+
+/// Writes text to a stream and flushes it. A terminal that went away isn't worth failing over.
+///
+/// # Arguments
+///
+/// * `stream` - The stream.
+/// * `text` - The text.
+fn write(stream: Stream, text: &str) {
+    let mut out: Box<dyn Write> = match stream {
+        Stream::Stdout => Box::new(io::stdout().lock()),
+        Stream::Stderr => Box::new(io::stderr().lock()),
+    };
+    let _ = out.write_all(text.as_bytes()).and_then(|()| out.flush());
+}
@@ -0,0 +1,281 @@
+//! This module classifies files by their contents for '--mime' and '--magic'.
+//!
+//! Names and extensions can't be trusted in a dump of mixed files, so the first bytes of every
+//! candidate are read and compared with the signatures of common formats, much like file(1)
+//! does. '--mime' selects files by the MIME type found that way ('image/*', 'video/mp4'), and
+//! '--magic' by raw leading bytes given in hex, optionally at an offset ('25504446' for '%PDF',
+//! '257:7573746172' for tar). A file that matches any of them is deleted, as long as it meets the
+//! other filters as well.
+//!
+//! Only regular files are read. Symlinks never match, since their contents belong to another
+//! file. The crawler only reads a file once it has passed every other filter, and at most
+//! '--sniff-readers' files are read at the same time, so content checks don't swamp the disk
+//! while the deleters are working on it.
+
+use std::fs::{File, Metadata, OpenOptions};
+use std::io::Read;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tokio::task;
+
+// Number of bytes read from the start of each file.
+const SNIFF_LEN: usize = 512;
+
+/// A '--mime' pattern, such as 'image/*' or 'application/pdf'.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MimePattern {
+    kind: String,
+    subtype: String,
+}
+
+impl MimePattern {
+    /// Parses a '--mime' value.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The pattern, a type and a subtype either of which may be '*'.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, String>` - Ok with the pattern, Err with a message if it isn't one.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let text = text.trim().to_ascii_lowercase();
+        match text.split_once('/') {
+            Some((kind, subtype)) if !kind.is_empty() && !subtype.is_empty() && !subtype.contains('/') => {
+                Ok(Self { kind: kind.to_string(), subtype: subtype.to_string() })
+            }
+            _ => Err(format!("Invalid MIME type '{}': expected e.g. image/png or image/*", text)),
+        }
+    }
+
+    /// Checks whether a MIME type matches the pattern.
+    ///
+    /// # Arguments
+    ///
+    /// * `mime` - The MIME type of a file.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if it matches.
+    fn matches(&self, mime: &str) -> bool {
+        let (kind, subtype) = mime.split_once('/').unwrap_or((mime, ""));
+        (self.kind == "*" || self.kind == kind) && (self.subtype == "*" || self.subtype == subtype)
+    }
+}
+
+/// A '--magic' signature: bytes a file has to hold at an offset.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Magic {
+    offset: usize,
+    bytes: Vec<u8>,
+}
+
+impl Magic {
+    /// Parses a '--magic' value.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The bytes in hex, optionally preceded by a decimal offset and a colon, such as
+    ///   '89504e47' or '257:7573746172'.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, String>` - Ok with the signature, Err with a message if it is invalid.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let (offset, hex) = match text.split_once(':') {
+            Some((offset, hex)) => (offset.trim().parse::<usize>().map_err(|_| format!("Invalid magic '{}': the offset must be a number", text))?, hex),
+            None => (0, text),
+        };
+        let hex: String = hex.chars().filter(|c| !c.is_whitespace()).collect();
+        if hex.is_empty() || !hex.len().is_multiple_of(2) || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!("Invalid magic '{}': expected an even number of hex digits, e.g. 89504e47", text));
+        }
+        let bytes: Vec<u8> = (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap_or(0)).collect();
+        if offset + bytes.len() > SNIFF_LEN {
+            return Err(format!("Invalid magic '{}': only the first {} bytes of a file are read", text, SNIFF_LEN));
+        }
+        Ok(Self { offset, bytes })
+    }
+
+    /// Checks whether the start of a file holds the signature.
+    ///
+    /// # Arguments
+    ///
+    /// * `head` - The first bytes of the file.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if it does.
+    fn matches(&self, head: &[u8]) -> bool {
+        head.get(self.offset..self.offset + self.bytes.len()) == Some(&self.bytes[..])
+    }
+}
+
+/// The '--mime' and '--magic' filters, and the permits that limit how many files are read at once.
+#[derive(Debug, Clone)]
+pub struct ContentFilter {
+    mimes: Vec<MimePattern>,
+    magics: Vec<Magic>,
+    readers: Arc<Semaphore>,
+}
+
+impl ContentFilter {
+    /// Creates the filter.
+    ///
+    /// # Arguments
+    ///
+    /// * `mimes` - The '--mime' patterns.
+    /// * `magics` - The '--magic' signatures.
+    /// * `readers` - The largest number of files read at the same time.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<Self>` - The filter, or None if neither '--mime' nor '--magic' was given.
+    pub fn new(mimes: &[MimePattern], magics: &[Magic], readers: usize) -> Option<Self> {
+        if mimes.is_empty() && magics.is_empty() {
+            return None;
+        }
+        Some(Self { mimes: mimes.to_vec(), magics: magics.to_vec(), readers: Arc::new(Semaphore::new(readers.max(1))) })
+    }
+
+    /// Reads the start of a file and checks it against the filter.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the file.
+    /// * `metadata` - The metadata of the file, read without following symlinks.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if the file matches any of the MIME types or signatures. Files that can't
+    ///   be read don't match.
+    pub async fn matches(&self, path: &Path, metadata: &Metadata) -> bool {
+        if !metadata.is_file() {
+            return false;
+        }
+        let head = {
+            let _permit = match self.readers.acquire().await {
+                Ok(permit) => permit,
+                Err(_) => return false,
+            };
+            let file = path.to_path_buf();
+            match task::spawn_blocking(move || read_head(&file)).await {
+                Ok(Some(head)) => head,
+                _ => return false,
+            }
+        };
+        let mime = sniff(&head);
+        self.mimes.iter().any(|pattern| pattern.matches(mime)) || self.magics.iter().any(|magic| magic.matches(&head))
+    }
+}
+
+/// Reads the first bytes of a file.
+///
+/// # Arguments
+///
+/// * `path` - The path of the file.
+///
+/// # Returns
+///
+/// * `Option<Vec<u8>>` - Up to the first 512 bytes, or None if the file can't be read.
+fn read_head(path: &Path) -> Option<Vec<u8>> {
+    // reading would make the file look recently used to '--not-accessed-in', unless the access
+    // time is left alone, which the kernel only allows the owner of the file (or root)
+    let file = OpenOptions::new().read(true).custom_flags(libc::O_NOATIME).open(path).or_else(|_| File::open(path)).ok()?;
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    file.take(SNIFF_LEN as u64).read_to_end(&mut head).ok()?;
+    Some(head)
+}
+
+/// Determines the MIME type of a file from its first bytes.
+///
+/// # Arguments
+///
+/// * `head` - The first bytes of the file.
+///
+/// # Returns
+///
+/// * `&'static str` - The MIME type, 'text/plain' for text without a known signature and
+///   'application/octet-stream' for anything else.
+pub fn sniff(head: &[u8]) -> &'static str {
+    let at = |offset: usize, signature: &[u8]| head.get(offset..offset + signature.len()) == Some(signature);
+    // RIFF and ISO media files name their format after a common header
+    if at(0, b"RIFF") {
+        return match head.get(8..12) {
+            Some(b"WEBP") => "image/webp",
+            Some(b"WAVE") => "audio/wav",
+            Some(b"AVI ") => "video/x-msvideo",
+            _ => "application/octet-stream",
+        };
+    }
+    if at(4, b"ftyp") {
+        return match head.get(8..12) {
+            Some(b"qt  ") => "video/quicktime",
+            Some(b"M4A ") | Some(b"M4B ") => "audio/mp4",
+            Some(b"heic") | Some(b"heix") | Some(b"mif1") => "image/heic",
+            Some(b"avif") => "image/avif",
+            Some(b"3gp4") | Some(b"3gp5") | Some(b"3g2a") => "video/3gpp",
+            _ => "video/mp4",
+        };
+    }
+    let signatures: [(usize, &[u8], &'static str); 26] = [
+        (0, b"\xff\xd8\xff", "image/jpeg"),
+        (0, b"\x89PNG\r\n\x1a\n", "image/png"),
+        (0, b"GIF87a", "image/gif"),
+        (0, b"GIF89a", "image/gif"),
+        (0, b"II*\x00", "image/tiff"),
+        (0, b"MM\x00*", "image/tiff"),
+        (0, b"BM", "image/bmp"),
+        (0, b"\x00\x00\x01\x00", "image/vnd.microsoft.icon"),
+        (0, b"8BPS", "image/vnd.adobe.photoshop"),
+        (0, b"ID3", "audio/mpeg"),
+        (0, b"fLaC", "audio/flac"),
+        (0, b"OggS", "audio/ogg"),
+        (0, b"\x1a\x45\xdf\xa3", "video/x-matroska"),
+        (0, b"\x00\x00\x01\xba", "video/mpeg"),
+        (0, b"%PDF-", "application/pdf"),
+        (0, b"PK\x03\x04", "application/zip"),
+        (0, b"\x1f\x8b", "application/gzip"),
+        (0, b"BZh", "application/x-bzip2"),
+        (0, b"\xfd7zXZ\x00", "application/x-xz"),
+        (0, b"\x28\xb5\x2f\xfd", "application/zstd"),
+        (0, b"7z\xbc\xaf\x27\x1c", "application/x-7z-compressed"),
+        (0, b"Rar!\x1a\x07", "application/vnd.rar"),
+        (257, b"ustar", "application/x-tar"),
+        (0, b"\x7fELF", "application/x-executable"),
+        (0, b"SQLite format 3\x00", "application/vnd.sqlite3"),
+        (0, b"\xd0\xcf\x11\xe0\xa1\xb1\x1a\xe1", "application/x-ole-storage"),
+    ];
+    if let Some((_, _, mime)) = signatures.iter().find(|(offset, signature, _)| at(*offset, signature)) {
+        return mime;
+    }
+    // MPEG audio frames start with 11 set bits
+    if head.len() >= 2 && head[0] == 0xff && head[1] & 0xe0 == 0xe0 {
+        return "audio/mpeg";
+    }
+    if head.is_empty() {
+        return "application/x-empty";
+    }
+    if is_text(head) { "text/plain" } else { "application/octet-stream" }
+}
+
+/// Checks whether the start of a file looks like text: UTF-8 without control characters other
+/// than whitespace. A multibyte character cut off at the end of the read doesn't count against it.
+///
+/// # Arguments
+///
+/// * `head` - The first bytes of the file.
+///
+/// # Returns
+///
+/// * `bool` - True if it is text.
+fn is_text(head: &[u8]) -> bool {
+    let text = match std::str::from_utf8(head) {
+        Ok(text) => text,
+        Err(e) if e.error_len().is_none() => std::str::from_utf8(&head[..e.valid_up_to()]).unwrap_or(""),
+        Err(_) => return false,
+    };
+    text.chars().all(|c| !c.is_control() || c.is_whitespace() || c == '\u{1b}')
+}
@@ -2,28 +2,115 @@
 //! on all objects including files, directories, and symlinks. Symlinks are not followed.
 
 use std::sync::Arc;
-use std::path::PathBuf;
+use std::fs::{FileType, Metadata};
+use std::path::{Path, PathBuf};
+use std::collections::{HashMap, HashSet};
+use std::os::unix::fs::MetadataExt;
+use std::time::Instant;
 use tokio::fs as async_fs;
-use tokio::sync::{mpsc::Sender, Mutex};
-use tokio::task;
-use glob::glob;
+use tokio::sync::{mpsc, mpsc::Sender, Mutex, Semaphore};
+use tokio::task::{self, JoinSet};
+use glob::glob_with;
+use crate::utility::{glob_options, shared_size, Counters};
 use crate::logger::Logger;
+use crate::skipped::{SkipCounter, SkipReason};
+use crate::watchdog::Watchdog;
+use crate::termination::Termination;
+use crate::report::DirReport;
+use crate::slowops::{OpKind, SlowOps};
+use crate::filters::Filters;
+use crate::excludes::{self, Excludes};
+use crate::phases::{Phase, PhaseTimes};
+use crate::symlinks::SymlinkCounts;
+use crate::patterns;
+use crate::safefs::Anchors;
+use crate::identity::Found;
+use crate::prompt::Prompter;
+use crate::completion::{Completion, RootToken};
 use futures::future::BoxFuture;
 
 /// This structure represents the file and directory crawler.
 #[derive(Clone)]
 pub struct Crawler {
     logger: Arc<Logger>,
-    file_sender: Sender<PathBuf>,
-    dir_sender: Sender<PathBuf>,
+    file_sender: Sender<Found>,
+    dir_sender: Sender<Found>,
     total_files_symlinks: Arc<Mutex<usize>>,
     total_directories: Arc<Mutex<usize>>,
     total_crawling_ops: Arc<Mutex<usize>>,
     total_stat_ops: Arc<Mutex<usize>>,
     verbose: bool,
+    memory_pressure: Option<Arc<Mutex<bool>>>,
+    skipped: SkipCounter,
+    dir_permits: Arc<Semaphore>,
+    widest_dirs: Arc<Mutex<Vec<(usize, PathBuf)>>>,
+    ignore_case: bool,
+    dir_report: Option<DirReport>,
+    slow_ops: SlowOps,
+    filters: Arc<Filters>,
+    excludes: Arc<Excludes>,
+    phases: PhaseTimes,
+    symlink_counts: Option<SymlinkCounts>,
+    prompter: Option<Prompter>,
+    termination: Option<Termination>,
+    one_file_system: bool,
+    anchors: Arc<Anchors>,
+    kept_roots: Arc<Vec<PathBuf>>,
+    // the device of the root being crawled, with '--one-file-system'
+    root_device: Option<u64>,
+    // the completion token of the root being crawled, shared by every task crawling within it
+    _root_token: Option<Arc<RootToken>>,
+}
+
+/// What a Crawler sends on to the deleters and how, handed to `Crawler::new`.
+pub struct CrawlerOptions {
+    /// Whether to enable verbose logging.
+    pub verbose: bool,
+    /// The watchdog's pressure flag, if '--memory-limit' was specified.
+    pub memory_pressure: Option<Arc<Mutex<bool>>>,
+    /// A shared counter for objects that are skipped instead of deleted.
+    pub skipped: SkipCounter,
+    /// Whether wildcards match case-insensitively.
+    pub ignore_case: bool,
+    /// The per-directory results, if '--json-report' was specified.
+    pub dir_report: Option<DirReport>,
+    /// A shared list of the slowest operations.
+    pub slow_ops: SlowOps,
+    /// The criteria objects have to meet to be deleted.
+    pub filters: Arc<Filters>,
+    /// The paths that must not be deleted.
+    pub excludes: Arc<Excludes>,
+    /// The shared record of the phases of the run.
+    pub phases: PhaseTimes,
+    /// The symlink counts, if '--symlink-report' was specified.
+    pub symlink_counts: Option<SymlinkCounts>,
+    /// Asks for confirmation, if '-i' or '-I' was specified.
+    pub prompter: Option<Prompter>,
+    /// Whether the run has to stop, on SIGTERM or at '--max-errors'.
+    pub termination: Option<Termination>,
+    /// Whether to stay on the filesystem of each target.
+    pub one_file_system: bool,
+    /// The anchors of the target patterns, below which no symlink is followed.
+    pub anchors: Arc<Anchors>,
+    /// The roots of the target patterns that are kept if a pattern matches them, since
+    /// '--sandbox' only lets the run remove what is within them.
+    pub kept_roots: Arc<Vec<PathBuf>>,
 }
 
 impl Crawler {
+    // Directories with more entries than this stream their files straight to the file deleters.
+    const WIDE_DIRECTORY_THRESHOLD: usize = 10_000;
+    // Maximum number of subdirectories crawled concurrently across the whole run.
+    const MAX_CONCURRENT_DIRS: usize = 64;
+    // Number of widest directories listed in the crawler summary.
+    const WIDEST_REPORTED: usize = 5;
+    // Maximum number of pattern matches (top-level paths) processed concurrently per pass.
+    const MAX_CONCURRENT_ROOTS: usize = 256;
+    // Number of pattern matches buffered between the glob expansion and the crawler.
+    const GLOB_BUFFER: usize = 1024;
+    // Number of pattern matches between progress messages while a pattern is expanded.
+    const GLOB_PROGRESS_INTERVAL: usize = 100_000;
+
     /// Creates a new instance of the Crawler.
     ///
     /// # Arguments
@@ -31,38 +118,66 @@ impl Crawler {
     /// * `logger` - An instance of the Logger.
     /// * `file_sender` - A channel sender for file paths.
     /// * `dir_sender` - A channel sender for directory paths.
-    /// * `total_files_symlinks` - A shared counter for the total number of files and symlinks.
-    /// * `total_directories` - A shared counter for the total number of directories.
-    /// * `total_crawling_ops` - A shared counter for the total number of crawling operations.
-    /// * `total_stat_ops` - A shared counter for the total number of stat operations.
-    /// * `verbose` - A boolean indicating whether to enable verbose logging.
+    /// * `counters` - The shared totals of the run, of which the crawler counts what it finds.
+    /// * `options` - What the crawler sends on and how.
     pub fn new(
         logger: Arc<Logger>,
-        file_sender: Sender<PathBuf>,
-        dir_sender: Sender<PathBuf>,
-        total_files_symlinks: Arc<Mutex<usize>>,
-        total_directories: Arc<Mutex<usize>>,
-        total_crawling_ops: Arc<Mutex<usize>>,
-        total_stat_ops: Arc<Mutex<usize>>,
-        verbose: bool,
+        file_sender: Sender<Found>,
+        dir_sender: Sender<Found>,
+        counters: &Counters,
+        options: CrawlerOptions,
     ) -> Self {
+        let CrawlerOptions {
+            verbose, memory_pressure, skipped, ignore_case, dir_report, slow_ops, filters, excludes, phases,
+            symlink_counts, prompter, termination, one_file_system, anchors, kept_roots,
+        } = options;
         Self {
             logger,
             file_sender,
             dir_sender,
-            total_files_symlinks,
-            total_directories,
-            total_crawling_ops,
-            total_stat_ops,
+            total_files_symlinks: Arc::clone(&counters.files_symlinks),
+            total_directories: Arc::clone(&counters.directories),
+            total_crawling_ops: Arc::clone(&counters.crawling_ops),
+            total_stat_ops: Arc::clone(&counters.stat_ops),
             verbose,
+            memory_pressure,
+            skipped,
+            // the questions of '-i' and '-I' follow the tree when every directory is crawled inline
+            dir_permits: Arc::new(Semaphore::new(if prompter.is_some() { 0 } else { Self::MAX_CONCURRENT_DIRS })),
+            widest_dirs: Arc::new(Mutex::new(Vec::new())),
+            ignore_case,
+            dir_report,
+            slow_ops,
+            filters,
+            excludes,
+            phases,
+            symlink_counts,
+            prompter,
+            termination,
+            one_file_system,
+            anchors,
+            kept_roots,
+            root_device: None,
+            _root_token: None,
         }
     }
 
+    /// Retrieves the list of widest directories, widest first. The list is shared, so it keeps
+    /// filling up as the crawl progresses without holding on to the Crawler (and its channels).
+    ///
+    /// # Returns
+    ///
+    /// * `Arc<Mutex<Vec<(usize, PathBuf)>>>` - The number of entries and path of each directory.
+    pub fn widest_dirs(&self) -> Arc<Mutex<Vec<(usize, PathBuf)>>> {
+        Arc::clone(&self.widest_dirs)
+    }
+
     /// Runs crawlers to collect metadata on files.
     ///
     /// # Arguments
     ///
     /// * `patterns` - A vector of file path patterns to crawl.
+    /// * `completion` - The completion of the pass, announced to the deleters.
     ///
     /// # Returns
     ///
@@ -71,8 +186,9 @@ impl Crawler {
     pub async fn run_crawlers_files(
         self,
         patterns: Vec<PathBuf>,
+        completion: Completion,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        self.run_crawlers(patterns, true).await
+        self.run_crawlers(patterns, true, completion).await
     }
 
     /// Runs crawlers to collect metadata on directories.
@@ -80,6 +196,7 @@ impl Crawler {
     /// # Arguments
     ///
     /// * `patterns` - A vector of directory path patterns to crawl.
+    /// * `completion` - The completion of the pass, announced to the deleters.
     ///
     /// # Returns
     ///
@@ -88,16 +205,22 @@ impl Crawler {
     pub async fn run_crawlers_dirs(
         self,
         patterns: Vec<PathBuf>,
+        completion: Completion,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        self.run_crawlers(patterns, false).await
+        self.run_crawlers(patterns, false, completion).await
     }
 
-    /// Internal function to run crawlers.
+    /// Internal function to run crawlers. Each pattern is expanded on a blocking thread and its
+    /// matches are streamed in as they are produced, so crawling starts before a large pattern
+    /// has been fully expanded. At most `MAX_CONCURRENT_ROOTS` matches are processed at a time.
+    /// Every match is crawled with a completion token of its own, and the pass completes once
+    /// the patterns are expanded and the tokens released, even if it ends in an error.
     ///
     /// # Arguments
     ///
     /// * `patterns` - A vector of path patterns to crawl.
     /// * `is_file` - A boolean indicating whether to crawl files.
+    /// * `completion` - The completion of the pass.
     ///
     /// # Returns
     ///
@@ -107,92 +230,717 @@ impl Crawler {
         self,
         patterns: Vec<PathBuf>,
         is_file: bool,
+        completion: Completion,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        // make a tasks vector
-        let mut tasks = Vec::new();
-        for pattern in patterns {
-            let paths = glob(pattern.to_str().unwrap()).expect("Failed to read glob pattern").filter_map(Result::ok);
-            for path in paths {
-                let logger = self.logger.clone();
-                let sender = if is_file { self.file_sender.clone() } else { self.dir_sender.clone() };
-                let total_files_symlinks = self.total_files_symlinks.clone();
-                let total_directories = self.total_directories.clone();
-                let total_crawling_ops = self.total_crawling_ops.clone();
-                let total_stat_ops = self.total_stat_ops.clone();
-                let verbose = self.verbose;
-                tasks.push(task::spawn(async move {
+        let _expanding = completion.expanding();
+        let mut tasks = JoinSet::new();
+        let started = Instant::now();
+        // directories matched so far by device and inode, to catch the same directory matched
+        // under two names, such as through a bind mount
+        let mut directories: HashMap<(u64, u64), PathBuf> = HashMap::new();
+        let max_roots = if self.prompter.is_some() { 1 } else { Self::MAX_CONCURRENT_ROOTS };
+        // braces are expanded up front, and each alternative is crawled as a pattern of its own
+        for pattern in patterns::expand_all(&patterns) {
+            // both passes expand the same patterns, so only the file pass reports progress
+            if is_file {
+                self.logger.log(&format!("Expanding pattern '{}'...", pattern), false, false, false).await;
+            }
+            let (match_sender, mut matches) = mpsc::channel(Self::GLOB_BUFFER);
+            let expansion = {
+                let pattern = pattern.clone();
+                let options = glob_options(self.ignore_case);
+                task::spawn_blocking(move || -> Result<(), glob::PatternError> {
+                    for path in glob_with(&pattern, options)?.filter_map(Result::ok) {
+                        if match_sender.blocking_send(path).is_err() {
+                            break;
+                        }
+                    }
+                    Ok(())
+                })
+            };
+
+            // matches found by following a symlink out of the pattern's root are never crawled
+            let root = match Self::literal_root(&pattern) {
+                Some(root) => async_fs::canonicalize(&root).await.ok(),
+                None => None,
+            };
+
+            // like a shell, '--skip-hidden' keeps a wildcard from matching hidden names, unless the
+            // pattern itself asks for a leading dot
+            let wildcard_name = Path::new(&pattern).file_name()
+                .is_some_and(|name| { let name = name.to_string_lossy(); patterns::has_wildcard(&name) && !name.starts_with('.') });
+            let skip_hidden = self.filters.skips_hidden() && wildcard_name;
+
+            // '**' matches a directory as well as everything within it, and crawling the directory
+            // already takes care of its subdirectories
+            let mut recursive_matches = if !is_file && pattern.contains("**") { Some(HashSet::new()) } else { None };
+
+            let mut matched = 0;
+            while let Some(path) = matches.recv().await {
+                matched += 1;
+                if let Some(ref mut recursive_matches) = recursive_matches {
+                    if path.ancestors().skip(1).any(|ancestor| recursive_matches.contains(ancestor)) {
+                        continue;
+                    }
+                    recursive_matches.insert(path.clone());
+                }
+                if skip_hidden && self.filters.is_hidden(&path) {
                     if is_file {
-                        Crawler::process_path(path, sender, logger, total_files_symlinks, total_crawling_ops, total_stat_ops, verbose, true).await
-                    } else {
-                        Crawler::process_path(path, sender, logger, total_directories, total_crawling_ops, total_stat_ops, verbose, false).await
+                        *self.total_files_symlinks.lock().await += 1;
+                        self.count_symlink(&path, None).await;
+                        self.skip_hidden(&path).await;
+                    }
+                    continue;
+                }
+                if let Some(ref root) = root {
+                    if !Self::is_contained(&path, root).await {
+                        if is_file {
+                            *self.total_files_symlinks.lock().await += 1;
+                            self.count_symlink(&path, None).await;
+                            self.skipped.add(SkipReason::OutsideRoot).await;
+                            self.logger.log(&format!("Skipped {:?}: it resolves outside {:?}", path, root), false, false, false).await;
+                        }
+                        continue;
+                    }
+                }
+                if let Some(first) = Self::first_match(&mut directories, &path).await {
+                    if is_file {
+                        let message = if first == path {
+                            format!("Warning: {:?} was given more than once, it is only crawled once", path)
+                        } else {
+                            format!("Warning: {:?} is the same directory as {:?} (an alias or bind mount), it is only crawled as {:?}", path, first, first)
+                        };
+                        self.logger.log(&message, false, false, false).await;
+                    }
+                    continue;
+                }
+                if is_file && matched % Self::GLOB_PROGRESS_INTERVAL == 0 {
+                    self.logger.log(&format!("Expanding pattern '{}'... {} matches so far", pattern, matched), false, false, false).await;
+                }
+                if tasks.len() >= max_roots {
+                    if let Some(result) = tasks.join_next().await {
+                        self.log_root_error(result?).await;
                     }
-                }));
+                }
+                let crawler = Crawler { _root_token: Some(completion.root()), ..self.clone() };
+                let sender = if is_file { self.file_sender.clone() } else { self.dir_sender.clone() };
+                let counter = if is_file { self.total_files_symlinks.clone() } else { self.total_directories.clone() };
+                tasks.spawn(crawler.process_path(path, sender, counter, is_file, false, true));
+            }
+            expansion.await??;
+            self.phases.record_until_now(Phase::Expansion, started).await;
+            if is_file {
+                self.logger.log(&format!("Pattern '{}' matched {} paths", pattern, matched), false, false, false).await;
             }
         }
-        for task in tasks {
-            task.await??;
+        while let Some(result) = tasks.join_next().await {
+            self.log_root_error(result?).await;
         }
         Ok(())
     }
 
-    /// Processes paths and sends them through the provided channel.
+    /// Records a directory matched by a pattern, unless the same directory was matched before.
+    ///
+    /// # Arguments
+    ///
+    /// * `directories` - The directories matched so far, by device and inode.
+    /// * `path` - The match.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<PathBuf>` - The earlier match of the same directory, or None if there is none or
+    ///   the match isn't a directory.
+    async fn first_match(directories: &mut HashMap<(u64, u64), PathBuf>, path: &Path) -> Option<PathBuf> {
+        let metadata = async_fs::symlink_metadata(path).await.ok().filter(|metadata| metadata.is_dir())?;
+        match directories.get(&(metadata.dev(), metadata.ino())) {
+            Some(first) => Some(first.clone()),
+            None => {
+                directories.insert((metadata.dev(), metadata.ino()), path.to_path_buf());
+                None
+            }
+        }
+    }
+
+    /// Finds the literal part of a pattern that precedes its first wildcard component. Only
+    /// patterns with a wildcard before their last component can match through a symlink that
+    /// leads somewhere else, so other patterns have no root to check against.
+    ///
+    /// # Arguments
+    ///
+    /// * `pattern` - The path pattern.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<PathBuf>` - The literal root, or None if the pattern needs no containment check.
+    fn literal_root(pattern: &str) -> Option<PathBuf> {
+        let components: Vec<_> = Path::new(pattern).components().collect();
+        let is_wildcard = |c: &std::path::Component| patterns::has_wildcard(&c.as_os_str().to_string_lossy());
+        let first = components.iter().position(is_wildcard)?;
+        if first + 1 == components.len() {
+            return None;
+        }
+        let root: PathBuf = components[..first].iter().collect();
+        Some(if root.as_os_str().is_empty() { PathBuf::from(".") } else { root })
+    }
+
+    /// Checks that the directory holding a pattern match resolves to a location within the
+    /// pattern's root. Matches that can't be resolved are let through, since the crawler will
+    /// report them as vanished or failed anyway.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The pattern match.
+    /// * `root` - The canonicalized root of the pattern.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if the match is within the root.
+    async fn is_contained(path: &Path, root: &Path) -> bool {
+        match path.parent() {
+            Some(parent) => async_fs::canonicalize(parent).await.map_or(true, |parent| parent.starts_with(root)),
+            None => true,
+        }
+    }
+
+    /// Logs the failure of a single pattern match without stopping the others, as each match is
+    /// crawled independently.
+    ///
+    /// # Arguments
+    ///
+    /// * `result` - The result of crawling one pattern match.
+    async fn log_root_error(&self, result: Result<bool, Box<dyn std::error::Error + Send + Sync>>) {
+        if let Err(e) = result {
+            self.logger.log(&format!("Crawler error: {}", e), true, false, false).await;
+        }
+    }
+
+    /// Processes paths and sends them through the provided channel. With filters, only the
+    /// files and symlinks that match are sent, and a directory is only sent if everything within
+    /// it was.
     ///
     /// # Arguments
     ///
     /// * `path` - The path to process.
     /// * `sender` - A channel sender for paths.
-    /// * `logger` - An instance of the Logger.
     /// * `counter` - A shared counter for the total number of objects.
-    /// * `total_crawling_ops` - A shared counter for the total number of crawling operations.
-    /// * `total_stat_ops` - A shared counter for the total number of stat operations.
-    /// * `verbose` - A boolean indicating whether to enable verbose logging.
     /// * `is_file` - A boolean indicating whether to process files or directories.
+    /// * `within_match` - True if a parent directory matched the name filters.
+    /// * `is_root` - True if the path is a pattern match rather than something found within one.
     ///
     /// # Returns
     ///
-    /// * `Result<(), Box<dyn std::error::Error + Send + Sync>>`
-    ///   - Ok if successful, Err otherwise.
+    /// * `Result<bool, Box<dyn std::error::Error + Send + Sync>>`
+    ///   - Ok with true if the path was sent for deletion in full, or is left to its parent
+    ///     directory when pruning empty directories, Err otherwise.
     fn process_path(
+        mut self,
         path: PathBuf,
-        sender: Sender<PathBuf>,
-        logger: Arc<Logger>,
+        sender: Sender<Found>,
         counter: Arc<Mutex<usize>>,
-        total_crawling_ops: Arc<Mutex<usize>>,
-        total_stat_ops: Arc<Mutex<usize>>,
-        verbose: bool,
         is_file: bool,
-    ) -> BoxFuture<'static, Result<(), Box<dyn std::error::Error + Send + Sync>>> {
+        within_match: bool,
+        is_root: bool,
+    ) -> BoxFuture<'static, Result<bool, Box<dyn std::error::Error + Send + Sync>>> {
         Box::pin(async move {
-            *total_crawling_ops.lock().await += 1;
-            let metadata = async_fs::metadata(&path).await?;
-            *total_stat_ops.lock().await += 1;
+            // hold off on discovering new objects while the memory watchdog reports pressure
+            if let Some(ref pressure) = self.memory_pressure {
+                Watchdog::wait_for_headroom(pressure).await;
+            }
+            // a run that is terminated discovers nothing more, and keeps what it hasn't reached
+            if self.termination.as_ref().is_some_and(Termination::requested) {
+                return Ok(false);
+            }
 
-            if verbose {
-                logger.log(&format!("Found object: {:?}", path), false, false, true).await;
+            *self.total_crawling_ops.lock().await += 1;
+            // never follow symlinks, so a link to a directory is deleted as a link instead of
+            // crawling (and deleting) the directory it points to
+            let started = Instant::now();
+            let metadata = match self.slow_ops.limit(OpKind::Stat, &path, async_fs::symlink_metadata(&path)).await {
+                Some(Ok(metadata)) => {
+                    self.slow_ops.record(OpKind::Stat, &path, started.elapsed()).await;
+                    metadata
+                }
+                // the other pass may already have deleted a top-level match before it was counted here
+                Some(Err(e)) if e.kind() == std::io::ErrorKind::NotFound => return Ok(true),
+                Some(Err(e)) => return Err(e.into()),
+                None => {
+                    // both passes look at a top-level match, so only the file pass counts it
+                    if is_file || !is_root {
+                        *counter.lock().await += 1;
+                        self.skip_stuck(&path, OpKind::Stat).await;
+                    }
+                    return Ok(false);
+                }
+            };
+            *self.total_stat_ops.lock().await += 1;
+            // everything within a target is compared with the filesystem the target is on
+            if is_root && self.one_file_system {
+                self.root_device = Some(metadata.dev());
             }
 
+            if self.verbose {
+                self.logger.log(&format!("Found object: {:?}", path), false, false, true).await;
+            }
+
+            let name_matched = within_match || self.filters.matches_name(&path);
             if metadata.is_file() || metadata.file_type().is_symlink() {
                 if is_file {
                     *counter.lock().await += 1;
-                    sender.send(path).await.map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
-                } else {
-                    Ok(())
+                    self.count_symlink(&path, Some(metadata.file_type().is_symlink())).await;
+                    if self.excludes.is_excluded(&path, false) {
+                        self.skip_excluded(&path).await;
+                        return Ok(false);
+                    }
+                    if let Some(reason) = self.filters.rejects(&metadata) {
+                        self.skipped.add(reason).await;
+                        return Ok(false);
+                    }
+                    if !self.filters.selects(&path, &metadata, name_matched).await {
+                        self.skip_filtered(&path).await;
+                        return Ok(false);
+                    }
+                    if let Some(ref prompter) = self.prompter {
+                        if !prompter.confirm_file(&path, is_root).await {
+                            self.skip_declined(&path).await;
+                            return Ok(false);
+                        }
+                    }
+                    let tier = self.filters.tiers().pick(&metadata);
+                    sender.send(Found::new(path, &metadata).in_tier(tier)).await.map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)?;
                 }
+                Ok(true)
             } else if metadata.is_dir() {
                 if !is_file {
                     *counter.lock().await += 1;
-                    let mut entries = async_fs::read_dir(&path).await?;
-                    while let Some(entry) = entries.next_entry().await? {
-                        let entry_path = entry.path();
-                        Crawler::process_path(entry_path, sender.clone(), logger.clone(), counter.clone(), total_crawling_ops.clone(), total_stat_ops.clone(), verbose, is_file).await?;
+                    // a directory on another filesystem, such as a mount point, isn't even read
+                    if self.root_device.is_some_and(|device| device != metadata.dev()) {
+                        self.skip_other_filesystem(&path).await;
+                        return Ok(false);
                     }
-                    sender.send(path).await.map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
-                } else {
-                    Ok(())
+                    // excluded directories are left alone without even being read
+                    if self.excludes.is_excluded(&path, true) {
+                        self.skip_excluded(&path).await;
+                        return Ok(false);
+                    }
+                    // so are hidden directories within the targets with '--skip-hidden'
+                    if !is_root && self.filters.skips_hidden() && self.filters.is_hidden(&path) {
+                        self.skip_hidden(&path).await;
+                        return Ok(false);
+                    }
+                    if let Some(ref prompter) = self.prompter {
+                        if !prompter.confirm_tree(&path, is_root).await {
+                            self.skip_declined(&path).await;
+                            return Ok(false);
+                        }
+                    }
+                    let kept_root = is_root && self.is_kept_root(&path).await;
+                    // the directory is judged before it is crawled, since removing what is within it
+                    // changes its times
+                    let selected = !kept_root && self.filters.selects_dir(&path, &metadata, name_matched) && self.filters.rejects_dir(&metadata).is_none();
+                    // a '.nukemignore' file adds its rules for everything below the directory
+                    let complete = match self.read_ignore_file(&path).await {
+                        Ok(Some(excludes)) => Crawler { excludes: Arc::new(excludes), ..self.clone() }.crawl_directory(&path, &sender, &counter, name_matched, selected).await?,
+                        Ok(None) => self.crawl_directory(&path, &sender, &counter, name_matched, selected).await?,
+                        Err(e) => {
+                            self.logger.log(&format!("Keeping directory {:?}: {}", path, e), true, false, false).await;
+                            self.skip_excluded(&path).await;
+                            return Ok(false);
+                        }
+                    };
+                    if !complete || !self.filters.selects_dir(&path, &metadata, name_matched) || self.filters.keeps_directories() {
+                        // the directory didn't match, some of its contents didn't or are excluded, or
+                        // only files are acted on, so it has to stay
+                        if self.filters.is_active() {
+                            self.skip_filtered(&path).await;
+                        } else if self.filters.keeps_directories() {
+                            self.skipped.add(SkipReason::KeptDirectory).await;
+                        } else if self.prompter.is_some() && self.excludes.is_empty() {
+                            self.skip_declined(&path).await;
+                        } else if self.root_device.is_some() && self.excludes.is_empty() {
+                            // it holds another filesystem
+                            self.skipped.add(SkipReason::OtherFilesystem).await;
+                        } else {
+                            self.skip_excluded(&path).await;
+                        }
+                        return Ok(false);
+                    }
+                    // a directory of another owner stays, even once everything selected within it is gone
+                    if let Some(reason) = self.filters.rejects_dir(&metadata) {
+                        self.skipped.add(reason).await;
+                        return Ok(false);
+                    }
+                    // the sandbox lets the run empty the root of a pattern, but not remove it
+                    if kept_root {
+                        self.skipped.add(SkipReason::SandboxedTarget).await;
+                        return Ok(false);
+                    }
+                    if let Some(ref prompter) = self.prompter {
+                        if !prompter.confirm_dir(&path).await {
+                            self.skip_declined(&path).await;
+                            return Ok(false);
+                        }
+                    }
+                    // an empty directory within an empty directory is removed along with it, and a
+                    // directory within a trashed or staged directory moves along with it, so only
+                    // the top of each subtree is sent
+                    if (self.filters.prunes_empty() || self.filters.moves_whole_trees()) && !is_root {
+                        return Ok(true);
+                    }
+                    sender.send(Found::new(path, &metadata)).await.map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)?;
                 }
+                Ok(true)
             } else {
-                Ok(())
+                // sockets, FIFOs and device nodes are never deleted on their own
+                if is_file {
+                    *counter.lock().await += 1;
+                    self.skipped.add(SkipReason::SpecialFile).await;
+                }
+                Ok(false)
             }
         })
     }
+
+    /// Reads the entries of a directory as they arrive. Subdirectories are crawled concurrently
+    /// while permits are available and inline otherwise, and once a directory grows past the
+    /// wide-directory threshold its files and symlinks are streamed to the file deleters instead
+    /// of waiting for the directory itself to be removed. With filters or excludes, every file and
+    /// symlink is checked and the matching ones are streamed right away. Returns once every subdirectory has
+    /// been crawled, so the directory is always sent after its children. When pruning empty
+    /// directories, a directory that has to stay sends its empty subdirectories instead.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The directory to read.
+    /// * `sender` - A channel sender for directory paths.
+    /// * `counter` - A shared counter for the total number of directories.
+    /// * `name_matched` - True if the directory, or one of its parents, matched the name filters.
+    /// * `selected` - True if the directory meets the filters itself, so it may be removed once
+    ///   everything within it matched.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<bool, Box<dyn std::error::Error + Send + Sync>>`
+    ///   - Ok with true if everything within the directory matched the filters, Err otherwise.
+    async fn crawl_directory(
+        &self,
+        path: &Path,
+        sender: &Sender<Found>,
+        counter: &Arc<Mutex<usize>>,
+        name_matched: bool,
+        selected: bool,
+    ) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        let started = Instant::now();
+        let mut entries = match self.slow_ops.limit(OpKind::OpenDir, path, self.anchors.read_dir(path)).await {
+            Some(entries) => entries?,
+            None => {
+                self.skip_stuck(path, OpKind::OpenDir).await;
+                return Ok(false);
+            }
+        };
+        self.slow_ops.record(OpKind::OpenDir, path, started.elapsed()).await;
+        let mut width = 0;
+        let mut subdirs = 0;
+        let mut bytes = 0;
+        let mut subdir_tasks = Vec::new();
+        // '-i' asks about every file, so each one is sent on its own
+        let filtering = self.filters.applies_within(name_matched) || !self.excludes.is_empty()
+            || self.prompter.as_ref().is_some_and(|prompter| prompter.confirms_each());
+        let mut complete = true;
+        let mut complete_subdirs = Vec::new();
+        // the files that go along with the directory, which are held back with '--one-file-system'
+        // in case the directory has to stay, and for a directory that stays anyway
+        let mut held_files = Vec::new();
+
+        while let Some(entry) = entries.next_entry().await? {
+            width += 1;
+            // the entries are listed through the descriptor, so their paths are made from the name
+            let entry_path = path.join(entry.file_name());
+            // the entry type comes from the directory listing and does not follow symlinks
+            let file_type = entry.file_type().await?;
+
+            if file_type.is_dir() {
+                subdirs += 1;
+                // a complete subdirectory may be sent from here, as the directory that was listed
+                let listed = if self.filters.prunes_empty() || self.filters.moves_whole_trees() {
+                    entry.metadata().await.ok().map(|metadata| Found::new(entry_path.clone(), &metadata))
+                } else {
+                    None
+                };
+                let subdir = self.clone().process_path(entry_path, sender.clone(), counter.clone(), false, name_matched, false);
+                match Arc::clone(&self.dir_permits).try_acquire_owned() {
+                    Ok(permit) => subdir_tasks.push((listed, task::spawn(async move {
+                        let result = subdir.await;
+                        drop(permit);
+                        result
+                    }))),
+                    Err(_) => {
+                        let removable = subdir.await?;
+                        if let Some(listed) = listed.filter(|_| removable) {
+                            complete_subdirs.push(listed);
+                        }
+                        complete &= removable;
+                    }
+                }
+            } else {
+                if self.verbose {
+                    self.logger.log(&format!("Found object: {:?}", entry_path), false, false, true).await;
+                }
+                let metadata = if self.dir_report.is_some() || filtering { entry.metadata().await.ok() } else { None };
+                bytes += metadata.as_ref().map_or(0, shared_size);
+                if filtering {
+                    complete &= self.filter_entry(entry_path, file_type, metadata, name_matched).await?;
+                } else if width > Self::WIDE_DIRECTORY_THRESHOLD && !self.filters.moves_whole_trees() && (file_type.is_file() || file_type.is_symlink()) {
+                    *self.total_files_symlinks.lock().await += 1;
+                    self.count_symlink(&entry_path, Some(file_type.is_symlink())).await;
+                    // the deleter checks that the file is still the one listed here
+                    let metadata = match metadata {
+                        Some(metadata) => Some(metadata),
+                        None => entry.metadata().await.ok(),
+                    };
+                    match metadata {
+                        Some(ref metadata) => self.file_sender.send(Found::new(entry_path, metadata)).await.map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)?,
+                        None => self.skipped.add(SkipReason::Vanished).await,
+                    }
+                } else if (self.root_device.is_some() || !selected) && (file_type.is_file() || file_type.is_symlink()) {
+                    let metadata = match metadata {
+                        Some(metadata) => Some(metadata),
+                        None => entry.metadata().await.ok(),
+                    };
+                    if let Some(ref metadata) = metadata {
+                        held_files.push(Found::new(entry_path, metadata));
+                    }
+                }
+            }
+        }
+
+        for (listed, subdir_task) in subdir_tasks {
+            let removable = subdir_task.await??;
+            if let Some(listed) = listed.filter(|_| removable) {
+                complete_subdirs.push(listed);
+            }
+            complete &= removable;
+        }
+        // a directory that stays, such as one holding another filesystem, still has its files deleted
+        if !complete || !selected {
+            for file in held_files {
+                *self.total_files_symlinks.lock().await += 1;
+                self.count_symlink(&file.path, None).await;
+                self.file_sender.send(file).await.map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)?;
+            }
+        }
+        // the files were judged one by one and the matching ones are moved on their own, so the
+        // directory stays instead of being moved while they are still on their way
+        if filtering && self.filters.moves_whole_trees() && width > subdirs {
+            complete = false;
+        }
+        // the complete subdirectories weren't sent yet, and won't go along with this directory
+        if (self.filters.prunes_empty() || self.filters.moves_whole_trees()) && !(complete && selected) {
+            for subdir in complete_subdirs {
+                sender.send(subdir).await.map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)?;
+            }
+        }
+        self.record_width(path, width).await;
+        if let Some(ref dir_report) = self.dir_report {
+            dir_report.record_crawl(path, (width - subdirs) as u64, subdirs as u64, bytes, started.elapsed()).await;
+        }
+        Ok(complete)
+    }
+
+    /// Sends a file or symlink found within a directory to the file deleters if it matches the
+    /// filters and isn't excluded. Special files never match, since they are only removed along with their directory.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the entry.
+    /// * `file_type` - The type of the entry, from the directory listing.
+    /// * `metadata` - The metadata of the entry, or None if it could not be read.
+    /// * `within_match` - True if the directory holding the entry matched the name filters.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<bool, Box<dyn std::error::Error + Send + Sync>>`
+    ///   - Ok with true if the entry was sent for deletion, Err otherwise.
+    async fn filter_entry(&self, path: PathBuf, file_type: FileType, metadata: Option<Metadata>, within_match: bool) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        *self.total_files_symlinks.lock().await += 1;
+        self.count_symlink(&path, Some(file_type.is_symlink())).await;
+        if self.excludes.is_excluded(&path, file_type.is_dir()) {
+            self.skip_excluded(&path).await;
+            return Ok(false);
+        }
+        if self.filters.skips_hidden() && self.filters.is_hidden(&path) {
+            self.skip_hidden(&path).await;
+            return Ok(false);
+        }
+        if !(file_type.is_file() || file_type.is_symlink()) {
+            self.skipped.add(SkipReason::SpecialFile).await;
+            return Ok(false);
+        }
+        *self.total_stat_ops.lock().await += 1;
+        // a file can be bind-mounted from another filesystem as well
+        if metadata.as_ref().is_some_and(|metadata| self.root_device.is_some_and(|device| device != metadata.dev())) {
+            self.skip_other_filesystem(&path).await;
+            return Ok(false);
+        }
+        if let Some(reason) = metadata.as_ref().and_then(|metadata| self.filters.rejects(metadata)) {
+            self.skipped.add(reason).await;
+            return Ok(false);
+        }
+        match metadata {
+            Some(ref metadata) if self.filters.selects(&path, metadata, within_match || self.filters.matches_name(&path)).await => {
+                if let Some(ref prompter) = self.prompter {
+                    if !prompter.confirm_file(&path, false).await {
+                        self.skip_declined(&path).await;
+                        return Ok(false);
+                    }
+                }
+                let tier = self.filters.tiers().pick(metadata);
+                self.file_sender.send(Found::new(path, metadata).in_tier(tier)).await.map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)?;
+                Ok(true)
+            }
+            Some(_) => {
+                self.skip_filtered(&path).await;
+                Ok(false)
+            }
+            // the entry disappeared while the directory was being read
+            None => {
+                self.skipped.add(SkipReason::Vanished).await;
+                Ok(true)
+            }
+        }
+    }
+
+    /// Reads the '.nukemignore' file of a directory, if it has one.
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - The directory.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<Excludes>, String>` - Ok with the excludes layered with its rules, or None
+    ///   if there is no such file. Err with a message if it can't be read or parsed.
+    async fn read_ignore_file(&self, dir: &Path) -> Result<Option<Excludes>, String> {
+        let file = dir.join(excludes::IGNORE_FILE);
+        match async_fs::read_to_string(&file).await {
+            Ok(text) => self.excludes.layer(dir, &text).map(Some),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(format!("unable to read {:?}: {}", file, e)),
+        }
+    }
+
+    /// Counts a symlink among the files and symlinks found, for '--symlink-report'.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the object.
+    /// * `is_symlink` - Whether the object is a symlink, or None to look it up.
+    async fn count_symlink(&self, path: &Path, is_symlink: Option<bool>) {
+        if let Some(ref symlink_counts) = self.symlink_counts {
+            let is_symlink = match is_symlink {
+                Some(is_symlink) => is_symlink,
+                None => async_fs::symlink_metadata(path).await.is_ok_and(|metadata| metadata.file_type().is_symlink()),
+            };
+            if is_symlink {
+                symlink_counts.record_found(path).await;
+            }
+        }
+    }
+
+    /// Checks whether a target is the root of a pattern, which '--sandbox' doesn't let the run remove.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the target.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if the target has to stay.
+    async fn is_kept_root(&self, path: &Path) -> bool {
+        !self.kept_roots.is_empty() && async_fs::canonicalize(path).await.is_ok_and(|path| self.kept_roots.contains(&path))
+    }
+
+    /// Counts an object that matched an exclude pattern.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the object.
+    async fn skip_excluded(&self, path: &Path) {
+        self.skipped.add(SkipReason::Excluded).await;
+        if self.verbose {
+            self.logger.log(&format!("Kept excluded object: {:?}", path), false, false, true).await;
+        }
+    }
+
+    /// Counts a hidden object that is kept because of '--skip-hidden'.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the object.
+    async fn skip_hidden(&self, path: &Path) {
+        self.skipped.add(SkipReason::Hidden).await;
+        if self.verbose {
+            self.logger.log(&format!("Kept hidden object: {:?}", path), false, false, true).await;
+        }
+    }
+
+    /// Counts an object that is kept because it (or something within it) didn't match the filters.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the object.
+    async fn skip_filtered(&self, path: &Path) {
+        self.skipped.add(SkipReason::Filtered).await;
+        if self.verbose {
+            self.logger.log(&format!("Kept object that didn't match the filters: {:?}", path), false, false, true).await;
+        }
+    }
+
+    /// Counts an object that is kept because it is on another filesystem than its target, with
+    /// '--one-file-system'.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the object.
+    async fn skip_other_filesystem(&self, path: &Path) {
+        self.skipped.add(SkipReason::OtherFilesystem).await;
+        self.logger.log(&format!("Kept {:?}: it is on another filesystem than its target", path), false, false, false).await;
+    }
+
+    /// Counts an object that is kept because an operation on it took longer than '--op-timeout'.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the object.
+    /// * `kind` - The operation that got stuck.
+    async fn skip_stuck(&self, path: &Path, kind: OpKind) {
+        self.skipped.add(SkipReason::Stuck).await;
+        self.logger.log(&format!("Gave up on {} of {:?}: it took longer than the operation timeout", kind.label(), path), true, false, false).await;
+    }
+
+    /// Counts an object that is kept because deleting it was declined at the prompt.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the object.
+    async fn skip_declined(&self, path: &Path) {
+        self.skipped.add(SkipReason::Declined).await;
+        if self.verbose {
+            self.logger.log(&format!("Kept object that was declined: {:?}", path), false, false, true).await;
+        }
+    }
+
+    /// Keeps track of the widest directories encountered for the crawler summary.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The directory that was read.
+    /// * `width` - The number of entries in the directory.
+    async fn record_width(&self, path: &Path, width: usize) {
+        let mut widest = self.widest_dirs.lock().await;
+        if widest.len() < Self::WIDEST_REPORTED || widest.last().is_some_and(|(w, _)| width > *w) {
+            widest.push((width, path.to_path_buf()));
+            widest.sort_by_key(|(w, _)| std::cmp::Reverse(*w));
+            widest.truncate(Self::WIDEST_REPORTED);
+        }
+    }
 }
@@ -0,0 +1,204 @@
+//! This module renders a live view of a run while it executes.
+//!
+//! With '--dashboard', the terminal switches to its alternate screen and a full-screen view of the
+//! discovery and deletion counters, queue depths, rates, the longest-running deletions and the
+//! most recent errors is redrawn twice per second. Console logging is paused meanwhile, and the
+//! normal screen (followed by the final report) comes back when the run ends. When the output is
+//! not a terminal, a plain progress line is logged every few seconds instead.
+
+use std::collections::HashMap;
+use std::io::IsTerminal;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::WeakSender;
+use tokio::sync::{oneshot, Mutex};
+use tokio::task::JoinHandle;
+use crate::deleter::Deleter;
+use crate::console::Takeover;
+use crate::logger::Logger;
+use crate::identity::Found;
+
+/// Paths the deletion workers are working on right now, with the time each one was picked up.
+pub type InFlight = Arc<Mutex<HashMap<PathBuf, Instant>>>;
+
+/// The shared state the dashboard reads from.
+pub struct DashboardSources {
+    pub total_files_symlinks: Arc<Mutex<usize>>,
+    pub total_directories: Arc<Mutex<usize>>,
+    pub deleter: Deleter,
+    pub file_queue: WeakSender<Found>,
+    pub dir_queue: WeakSender<Found>,
+    pub queue_capacity: usize,
+    pub workers: usize,
+}
+
+/// A running dashboard. It keeps drawing until it is stopped.
+pub struct Dashboard {
+    stop: oneshot::Sender<()>,
+    task: JoinHandle<()>,
+}
+
+/// A point-in-time reading of the counters.
+struct Snapshot {
+    found_files: usize,
+    found_dirs: usize,
+    deleted: u64,
+    failed: u64,
+    skipped: u64,
+    file_queue: usize,
+    dir_queue: usize,
+}
+
+impl Dashboard {
+    // How often the full-screen view is redrawn.
+    const REDRAW_INTERVAL: Duration = Duration::from_millis(500);
+    // How often a progress line is logged when the output is not a terminal.
+    const PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+    // Number of in-flight deletions shown.
+    const ACTIVE_SHOWN: usize = 8;
+
+    /// Starts the dashboard, or the plain progress fallback if stdout is not a terminal.
+    ///
+    /// # Arguments
+    ///
+    /// * `sources` - The shared state to display.
+    /// * `logger` - An instance of the Logger.
+    /// * `start` - The start time of the application.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The running dashboard.
+    pub async fn spawn(sources: DashboardSources, logger: Arc<Logger>, start: Instant) -> Self {
+        let (stop, mut stopped) = oneshot::channel();
+        let takeover = if std::io::stdout().is_terminal() {
+            // print everything queued so far before taking over the screen
+            logger.flush().await;
+            logger.console().take_over()
+        } else {
+            None
+        };
+        let interactive = takeover.is_some();
+        if let Some(ref takeover) = takeover {
+            takeover.text("\x1b[?1049h\x1b[?25l");
+        }
+
+        let task = tokio::spawn(async move {
+            let interval = if interactive { Self::REDRAW_INTERVAL } else { Self::PROGRESS_INTERVAL };
+            let mut previous = (Instant::now(), 0);
+            loop {
+                tokio::select! {
+                    _ = &mut stopped => break,
+                    _ = tokio::time::sleep(interval) => {}
+                }
+                let snapshot = Self::snapshot(&sources).await;
+                let now = Instant::now();
+                let rate = (snapshot.deleted.saturating_sub(previous.1)) as f64 / now.duration_since(previous.0).as_secs_f64();
+                previous = (now, snapshot.deleted);
+                if let Some(ref takeover) = takeover {
+                    Self::draw(&sources, &snapshot, rate, start.elapsed(), &logger, takeover).await;
+                } else {
+                    logger.log(&format!(
+                        "Progress: found {}, deleted {}, failed {}, skipped {}, {:.0} deletions/s",
+                        snapshot.found_files + snapshot.found_dirs, snapshot.deleted, snapshot.failed, snapshot.skipped, rate
+                    ), false, true, false).await;
+                }
+            }
+            if let Some(takeover) = takeover {
+                takeover.text("\x1b[?25h\x1b[?1049l");
+            }
+        });
+        Self { stop, task }
+    }
+
+    /// Stops the dashboard and gives the terminal back.
+    pub async fn stop(self) {
+        let _ = self.stop.send(());
+        let _ = self.task.await;
+    }
+
+    /// Reads the counters.
+    ///
+    /// # Arguments
+    ///
+    /// * `sources` - The shared state to read.
+    ///
+    /// # Returns
+    ///
+    /// * `Snapshot` - The current values.
+    async fn snapshot(sources: &DashboardSources) -> Snapshot {
+        // the queues are only held weakly so the dashboard never keeps a channel open
+        let queued = |queue: &WeakSender<Found>| queue.upgrade().map_or(0, |sender| sources.queue_capacity - sender.capacity());
+        Snapshot {
+            found_files: *sources.total_files_symlinks.lock().await,
+            found_dirs: *sources.total_directories.lock().await,
+            deleted: sources.deleter.get_deleted_files().await + sources.deleter.get_deleted_dirs().await,
+            failed: sources.deleter.get_failed_deletions().await,
+            skipped: sources.deleter.get_skipped().await,
+            file_queue: queued(&sources.file_queue),
+            dir_queue: queued(&sources.dir_queue),
+        }
+    }
+
+    /// Redraws the full-screen view.
+    ///
+    /// # Arguments
+    ///
+    /// * `sources` - The shared state to display.
+    /// * `snapshot` - The current counter values.
+    /// * `rate` - Deletions per second since the previous frame.
+    /// * `elapsed` - The time since the application began.
+    /// * `logger` - An instance of the Logger, for the recent errors.
+    /// * `takeover` - The hold on the console the view is drawn through.
+    async fn draw(sources: &DashboardSources, snapshot: &Snapshot, rate: f64, elapsed: Duration, logger: &Arc<Logger>, takeover: &Takeover) {
+        let width = terminal_width();
+        let mut active: Vec<(Duration, PathBuf)> = match sources.deleter.in_flight {
+            Some(ref in_flight) => in_flight.lock().await.iter().map(|(path, since)| (since.elapsed(), path.clone())).collect(),
+            None => Vec::new(),
+        };
+        active.sort_by_key(|(age, _)| std::cmp::Reverse(*age));
+
+        let mut lines = vec![
+            format!("nukem {} - elapsed {:.0?}", env!("CARGO_PKG_VERSION"), elapsed),
+            String::new(),
+            format!("Found:    {} directories, {} files and symlinks", snapshot.found_dirs, snapshot.found_files),
+            format!("Deleted:  {}    Failed: {}    Skipped: {}", snapshot.deleted, snapshot.failed, snapshot.skipped),
+            format!("Rate:     {:.0} deletions/s", rate),
+            format!("Queues:   files {}/{}    directories {}/{}", snapshot.file_queue, sources.queue_capacity, snapshot.dir_queue, sources.queue_capacity),
+            String::new(),
+            format!("Workers busy: {}/{}", active.len(), sources.workers),
+        ];
+        for (age, path) in active.iter().take(Self::ACTIVE_SHOWN) {
+            lines.push(format!("  {:>8.1?}  {}", age, path.display()));
+        }
+        lines.push(String::new());
+        lines.push("Recent errors:".into());
+        for error in logger.recent_errors() {
+            lines.push(format!("  {}", error));
+        }
+
+        let mut frame = String::from("\x1b[H\x1b[2J");
+        for line in lines {
+            frame.extend(line.chars().take(width));
+            frame.push_str("\r\n");
+        }
+        takeover.text(&frame);
+    }
+}
+
+/// Reads the width of the terminal.
+///
+/// # Returns
+///
+/// * `usize` - The number of columns, or 80 if it can't be determined.
+fn terminal_width() -> usize {
+    // SAFETY: winsize is plain data and TIOCGWINSZ only writes into it
+    unsafe {
+        let mut size: libc::winsize = std::mem::zeroed();
+        if libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) == 0 && size.ws_col > 0 {
+            size.ws_col as usize
+        } else {
+            80
+        }
+    }
+}
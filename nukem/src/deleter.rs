@@ -1,18 +1,188 @@
 //! The deleter module provides functionality to delete files and directories
-//! based on the paths received from crawlers.
+//! based on the paths received from crawlers. With '--action compress' the same workers compress
+//! files in place instead, and with '--action move' they move them to another location. With
+//! '--tier', each file is compressed, moved, trashed or deleted by the tier the crawler picked.
 
 use std::sync::Arc;
 use tokio::sync::{mpsc, Mutex};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use tokio::task;
 use tokio::fs;
 use crate::logger::Logger;
+use crate::skipped::{SkipCounter, SkipReason};
+use crate::handles;
+use crate::args::LiveSocketPolicy;
+use crate::sockets::LiveSockets;
+use crate::report::DirReport;
+use crate::slowops::{OpKind, SlowOps};
+use crate::dashboard::InFlight;
+use crate::filters::{self, ExtensionCounts, TypeSet};
+use crate::owners::OwnerCounts;
+use crate::safefs::Anchors;
+use crate::identity::{Found, Identity};
+use crate::excludes::Excludes;
+use crate::args::Compressor;
+use crate::compress;
+use crate::relocate;
+use crate::trash::Trash;
+use crate::undo::Undo;
+use crate::archive::{self, Archive};
+use crate::termination::{self, Termination};
+use crate::symlinks::SymlinkCounts;
+use crate::inuse::InUse;
+use crate::journal::{EventKind, Journal};
+use crate::plan::Plan;
+use crate::ledger::Ledger;
+use crate::budget::Budget;
+use crate::tiers::{TierAction, TierCounts};
+use crate::immutable::{self, Immutables};
+use crate::failures::{self, Failure, Failures, OpError};
+use crate::utility::{freed_size, shared_size};
+use crate::completion::Completion;
+use std::collections::HashMap;
+use std::time::Instant;
+use std::os::unix::fs::MetadataExt;
+
+// per inode with several links: the link count when it was first seen, and how many were deleted
+type HardLinks = HashMap<(u64, u64), (u64, u64)>;
+
+/// A deletion worker, along with the path it is processing, so that its supervisor can count
+/// the path as failed if the worker panics.
+struct WorkerSlot {
+    index: usize,
+    current: Mutex<Option<PathBuf>>,
+}
 
 /// The Deleter struct is responsible for deleting files and directories.
+#[derive(Clone)]
 pub struct Deleter {
     pub failed_deletions: Arc<Mutex<u64>>,
+    pub deleted_files: Arc<Mutex<u64>>,
+    pub deleted_dirs: Arc<Mutex<u64>>,
+    pub skipped: SkipCounter,
     pub total_size: Arc<Mutex<u64>>,
+    pub worker_restarts: Arc<Mutex<u64>>,
+    pub dry_run: bool,
+    pub check_open_handles: bool,
+    pub live_sockets: Option<Arc<LiveSockets>>,
+    pub dir_report: Option<DirReport>,
+    pub slow_ops: SlowOps,
+    pub in_flight: Option<InFlight>,
+    pub failures: Failures,
+    pub ext_counts: Option<ExtensionCounts>,
+    pub owner_counts: Option<OwnerCounts>,
+    pub excludes: Arc<Excludes>,
+    pub compressor: Option<Compressor>,
+    pub compressed_size: Arc<Mutex<u64>>,
+    pub move_to: Option<PathBuf>,
+    pub copied_files: Arc<Mutex<u64>>,
+    pub tier_counts: Option<TierCounts>,
+    pub empty_files_only: bool,
+    pub prune_empty_dirs: bool,
+    pub types: Option<TypeSet>,
+    pub accessed_before: Option<i64>,
+    pub skip_hardlinked: bool,
+    pub hard_links: Arc<Mutex<HardLinks>>,
+    pub symlink_counts: Option<SymlinkCounts>,
+    pub in_use: Option<Arc<InUse>>,
+    pub journal: Option<Journal>,
+    pub immutables: Immutables,
+    pub trash: Option<Trash>,
+    pub undo: Option<Undo>,
+    pub archive: Option<Archive>,
+    pub termination: Option<Termination>,
+    pub one_file_system: bool,
+    pub anchors: Arc<Anchors>,
+    pub changed_before: Option<i64>,
+    pub plan: Option<Plan>,
+    pub ledger: Option<Ledger>,
+    pub budget: Option<Budget>,
+    pub max_errors: Option<u64>,
+    pub only_mine: Option<u32>,
+}
+
+/// What a Deleter does with the paths it receives, handed to `Deleter::new`.
+pub struct DeleterOptions {
+    /// Whether to perform a dry run.
     pub dry_run: bool,
+    /// Whether to report processes holding busy paths.
+    pub check_open_handles: bool,
+    /// The live sockets to look out for, if '--live-sockets' was specified.
+    pub live_sockets: Option<Arc<LiveSockets>>,
+    /// The per-directory results, if '--json-report' was specified.
+    pub dir_report: Option<DirReport>,
+    /// The number of slowest operations to keep for the report.
+    pub slowest: usize,
+    /// How long a stat or unlink may take, if '--op-timeout' was specified.
+    pub op_timeout: Option<std::time::Duration>,
+    /// Whether to keep track of the paths being worked on.
+    pub track_activity: bool,
+    /// The per-extension counts, if '--ext' was specified.
+    pub ext_counts: Option<ExtensionCounts>,
+    /// The per-owner counts, if '--owner-report' was specified.
+    pub owner_counts: Option<OwnerCounts>,
+    /// The paths that must not be deleted.
+    pub excludes: Arc<Excludes>,
+    /// The compressor to use, if files are compressed instead of deleted.
+    pub compressor: Option<Compressor>,
+    /// The directory to move files to, if they are moved instead of deleted.
+    pub move_to: Option<PathBuf>,
+    /// The tiers and the files acted on per tier, if '--tier' was specified.
+    pub tier_counts: Option<TierCounts>,
+    /// Whether only empty files are deleted.
+    pub empty_files_only: bool,
+    /// Whether only empty directories are removed.
+    pub prune_empty_dirs: bool,
+    /// The object types that may be deleted, if '--type' was specified.
+    pub types: Option<TypeSet>,
+    /// The access time files must be older than, if '--not-accessed-in' was specified.
+    pub accessed_before: Option<i64>,
+    /// Whether to keep files with more than one hard link.
+    pub skip_hardlinked: bool,
+    /// The symlink counts, if '--symlink-report' was specified.
+    pub symlink_counts: Option<SymlinkCounts>,
+    /// The files open in other processes, if '--skip-in-use' was specified.
+    pub in_use: Option<Arc<InUse>>,
+    /// The event journal, if '--event-journal' was specified.
+    pub journal: Option<Journal>,
+    /// Whether to clear the immutable and append-only attributes.
+    pub clear_immutable: bool,
+    /// The trash to move objects to, if '--trash' was specified.
+    pub trash: Option<Trash>,
+    /// The staging folder to move objects to, if '--undoable' was specified.
+    pub undo: Option<Undo>,
+    /// The archive to add objects to before deleting them, if '--archive-to' was specified.
+    pub archive: Option<Archive>,
+    /// Whether the run has to stop, on SIGTERM or at '--max-errors'.
+    pub termination: Option<Termination>,
+    /// Whether removing a directory tree stays on its filesystem.
+    pub one_file_system: bool,
+    /// The anchors of the target patterns, below which no symlink is followed.
+    pub anchors: Arc<Anchors>,
+    /// The time files must not have been modified or changed after, if '--min-age' was specified.
+    pub changed_before: Option<i64>,
+    /// The plan to record what the dry run would delete in, for 'nukem plan'.
+    pub plan: Option<Plan>,
+    /// The ledger of recent deletions, if '--cooldown' was specified.
+    pub ledger: Option<Ledger>,
+    /// What the run may still delete, if '--max-delete' was specified.
+    pub budget: Option<Budget>,
+    /// How many deletions may fail before the run stops, if '--max-errors' was specified.
+    pub max_errors: Option<u64>,
+    /// The user whose objects may be deleted, if '--only-mine' was specified.
+    pub only_mine: Option<u32>,
+}
+
+/// What happened to the directories of an empty subtree that was pruned.
+#[derive(Default)]
+struct Pruned {
+    removed: u64,
+    // directories that something was added to after they were crawled
+    kept: u64,
+    vanished: u64,
+    failed: Vec<(PathBuf, OpKind, std::io::Error)>,
+    // the directories that were actually removed, for the event journal
+    removed_dirs: Vec<(PathBuf, std::fs::Metadata)>,
 }
 
 impl Deleter {
@@ -20,16 +190,62 @@ impl Deleter {
     ///
     /// # Arguments
     ///
-    /// * dry_run - A boolean indicating whether to perform a dry run.
+    /// * options - What the deleter does with the paths it receives.
     ///
     /// # Returns
     ///
     /// * 'Self' - A new instance of the Deleter.
-    pub fn new(dry_run: bool) -> Self {
+    pub fn new(options: DeleterOptions) -> Self {
+        let DeleterOptions {
+            dry_run, check_open_handles, live_sockets, dir_report, slowest, op_timeout, track_activity, ext_counts,
+            owner_counts, excludes, compressor, move_to, tier_counts, empty_files_only, prune_empty_dirs, types, accessed_before,
+            skip_hardlinked, symlink_counts, in_use, journal, clear_immutable, trash, undo, archive, termination,
+            one_file_system, anchors, changed_before, plan, ledger, budget, max_errors, only_mine,
+        } = options;
         Self {
             failed_deletions: Arc::new(Mutex::new(0)),
+            deleted_files: Arc::new(Mutex::new(0)),
+            deleted_dirs: Arc::new(Mutex::new(0)),
+            skipped: SkipCounter::new(),
             total_size: Arc::new(Mutex::new(0)),
+            worker_restarts: Arc::new(Mutex::new(0)),
             dry_run,
+            check_open_handles,
+            live_sockets,
+            dir_report,
+            slow_ops: SlowOps::new(slowest, op_timeout),
+            in_flight: if track_activity { Some(Arc::new(Mutex::new(HashMap::new()))) } else { None },
+            failures: Failures::new(),
+            ext_counts,
+            owner_counts,
+            excludes,
+            compressor,
+            compressed_size: Arc::new(Mutex::new(0)),
+            move_to,
+            copied_files: Arc::new(Mutex::new(0)),
+            tier_counts,
+            empty_files_only,
+            prune_empty_dirs,
+            types,
+            accessed_before,
+            skip_hardlinked,
+            hard_links: Arc::new(Mutex::new(HashMap::new())),
+            symlink_counts,
+            in_use,
+            journal,
+            immutables: Immutables::new(clear_immutable),
+            trash,
+            undo,
+            archive,
+            termination,
+            one_file_system,
+            anchors,
+            changed_before,
+            plan,
+            ledger,
+            budget,
+            max_errors,
+            only_mine,
         }
     }
 
@@ -42,6 +258,24 @@ impl Deleter {
         *self.total_size.lock().await
     }
 
+    /// Retrieves the total size of the compressed copies of compressed files.
+    ///
+    /// # Returns
+    ///
+    /// * 'u64' - The total size of the compressed copies in bytes.
+    pub async fn get_compressed_size(&self) -> u64 {
+        *self.compressed_size.lock().await
+    }
+
+    /// Retrieves the number of moved files and symlinks that had to be copied to another filesystem.
+    ///
+    /// # Returns
+    ///
+    /// * 'u64' - The number of copied files and symlinks.
+    pub async fn get_copied_files(&self) -> u64 {
+        *self.copied_files.lock().await
+    }
+
     /// Retrieves the total number of failed deletions.
     ///
     /// # Returns
@@ -51,7 +285,160 @@ impl Deleter {
         *self.failed_deletions.lock().await
     }
 
-    /// Deletes all paths received through the channel.
+    /// Retrieves the number of files and symlinks deleted (or that would be deleted in a dry run).
+    ///
+    /// # Returns
+    ///
+    /// * 'u64' - The total number of deleted files and symlinks.
+    pub async fn get_deleted_files(&self) -> u64 {
+        *self.deleted_files.lock().await
+    }
+
+    /// Retrieves the number of directories deleted (or that would be deleted in a dry run).
+    ///
+    /// # Returns
+    ///
+    /// * 'u64' - The total number of deleted directories.
+    pub async fn get_deleted_dirs(&self) -> u64 {
+        *self.deleted_dirs.lock().await
+    }
+
+    /// Retrieves the number of paths that were skipped for any reason. The breakdown per reason
+    /// is available through the shared `skipped` counter.
+    ///
+    /// # Returns
+    ///
+    /// * 'u64' - The total number of skipped paths.
+    pub async fn get_skipped(&self) -> u64 {
+        self.skipped.total().await
+    }
+
+    /// Retrieves the paths that could not be deleted.
+    ///
+    /// # Returns
+    ///
+    /// * 'Vec<PathBuf>' - The failed paths in path order.
+    pub async fn get_failed_paths(&self) -> Vec<PathBuf> {
+        self.failures.paths().await
+    }
+
+    /// Retrieves the failures with the operation and the error code of each.
+    ///
+    /// # Returns
+    ///
+    /// * 'Vec<Failure>' - The failures in the order they happened.
+    pub async fn get_failures(&self) -> Vec<Failure> {
+        self.failures.all().await
+    }
+
+    /// Retrieves the directories that could not be removed completely and still exist, with the
+    /// number of entries left inside each of them.
+    ///
+    /// # Returns
+    ///
+    /// * 'Vec<(PathBuf, u64)>' - The partially deleted directories in path order.
+    pub async fn get_partial_dirs(&self) -> Vec<(PathBuf, u64)> {
+        let failed_dirs = self.get_failed_paths().await;
+        task::spawn_blocking(move || {
+            failed_dirs.into_iter()
+                .filter(|dir| std::fs::symlink_metadata(dir).is_ok_and(|metadata| metadata.is_dir()))
+                .map(|dir| {
+                    let remaining = Deleter::count_entries(&dir);
+                    (dir, remaining)
+                })
+                .collect()
+        }).await.unwrap_or_default()
+    }
+
+    /// Counts everything left below a directory, without following symlinks.
+    ///
+    /// # Arguments
+    ///
+    /// * path - The directory.
+    ///
+    /// # Returns
+    ///
+    /// * 'u64' - The number of files, symlinks and directories within it.
+    fn count_entries(path: &Path) -> u64 {
+        let mut count = 0;
+        let mut pending = vec![path.to_path_buf()];
+        while let Some(dir) = pending.pop() {
+            let entries = match std::fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+            for entry in entries.filter_map(Result::ok) {
+                count += 1;
+                if entry.file_type().is_ok_and(|file_type| file_type.is_dir()) {
+                    pending.push(entry.path());
+                }
+            }
+        }
+        count
+    }
+
+    /// Measures a directory with everything left below it, without following symlinks.
+    ///
+    /// # Arguments
+    ///
+    /// * path - The directory.
+    ///
+    /// # Returns
+    ///
+    /// * '(u64, u64)' - The number of objects, counting the directory, and the space they free.
+    fn measure_tree(path: &Path) -> (u64, u64) {
+        let (mut objects, mut bytes) = (1, 0);
+        let mut pending = vec![path.to_path_buf()];
+        while let Some(dir) = pending.pop() {
+            for entry in std::fs::read_dir(&dir).into_iter().flatten().filter_map(Result::ok) {
+                let Ok(metadata) = entry.metadata() else {
+                    continue;
+                };
+                objects += 1;
+                if metadata.is_dir() {
+                    pending.push(entry.path());
+                } else {
+                    bytes += freed_size(&metadata, metadata.nlink());
+                }
+            }
+        }
+        (objects, bytes)
+    }
+
+    /// Counts the directories below a directory, without following symlinks.
+    ///
+    /// # Arguments
+    ///
+    /// * path - The directory.
+    ///
+    /// # Returns
+    ///
+    /// * 'u64' - The number of directories within it.
+    fn count_dirs(path: &Path) -> u64 {
+        let mut count = 0;
+        let mut pending = vec![path.to_path_buf()];
+        while let Some(dir) = pending.pop() {
+            for entry in std::fs::read_dir(&dir).into_iter().flatten().filter_map(Result::ok) {
+                if entry.file_type().is_ok_and(|file_type| file_type.is_dir()) {
+                    count += 1;
+                    pending.push(entry.path());
+                }
+            }
+        }
+        count
+    }
+
+    /// Retrieves the number of workers that were restarted after a panic.
+    ///
+    /// # Returns
+    ///
+    /// * 'u64' - The total number of worker restarts.
+    pub async fn get_worker_restarts(&self) -> u64 {
+        *self.worker_restarts.lock().await
+    }
+
+    /// Deletes all paths received through the channel, until the crawler passes that send to it
+    /// are complete and it is drained.
     ///
     /// # Arguments
     ///
@@ -60,57 +447,63 @@ impl Deleter {
     /// * logger - An instance of the Logger.
     /// * verbose - A boolean indicating whether to enable verbose logging.
     /// * total_deletion_ops - A shared counter for the total number of deletion operations.
-    /// * total_directories - A shared counter for the total number of directories.
+    /// * passes - The crawler passes that send paths to the channel.
     ///
     /// # Returns
     ///
     /// * 'Result<(), Box<dyn std::error::Error + Send + Sync>>' - Ok if successful, Err otherwise.
     pub async fn delete_all(
         &self,
-        receiver: Arc<Mutex<mpsc::Receiver<PathBuf>>>,
+        receiver: Arc<Mutex<mpsc::Receiver<Found>>>,
         worker_tasks_count: usize,
         logger: Arc<Logger>,
         verbose: bool,
         total_deletion_ops: Arc<Mutex<usize>>,
-        total_directories: Arc<Mutex<usize>>,
+        passes: Vec<Completion>,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         // vector to hold metadata from crawlers
         let mut handles = vec![];
 
         for i in 0..worker_tasks_count {
+            let deleter = self.clone();
             let logger = logger.clone();
-            let failed_deletions = self.failed_deletions.clone();
-            let total_size = self.total_size.clone();
             let receiver = receiver.clone();
             let total_deletion_ops = total_deletion_ops.clone();
-            let total_directories = total_directories.clone();
-            let dry_run = self.dry_run;
+            let passes = passes.clone();
 
-            // push object to vector
+            // push a supervisor to the vector. If its worker panics, the supervisor logs it, counts
+            // the path the worker held as failed and starts a replacement so the queue never loses
+            // a consumer.
             handles.push(task::spawn(async move {
-                while let Some(path) = receiver.lock().await.recv().await {
-                    if verbose {
-                        logger.log(&format!("Worker {} picked up path: {:?}", i, &path), false, true, true).await;
-                    }
-
-                    if let Err(e) = Deleter::process_path(
-                        &path,
+                let slot = Arc::new(WorkerSlot { index: i, current: Mutex::new(None) });
+                loop {
+                    let worker = task::spawn(deleter.clone().run_worker(
+                        slot.clone(),
+                        receiver.clone(),
                         logger.clone(),
                         verbose,
-                        total_size.clone(),
-                        failed_deletions.clone(),
                         total_deletion_ops.clone(),
-                        total_directories.clone(),
-                        dry_run,
-                    ).await {
-                        logger.log(&format!("[ERROR] Worker {} failed to process path {:?}: {:?}", i, &path, e), true, false, false).await;
-                        // Increment failed_deletions count
-                        *failed_deletions.lock().await += 1;
+                        passes.clone(),
+                    ));
+                    match worker.await {
+                        Ok(()) => break,
+                        Err(e) if e.is_panic() => {
+                            logger.log(&format!("Worker {} panicked, starting a replacement: {:?}", i, e), true, true, false).await;
+                            if let Some(path) = slot.current.lock().await.take() {
+                                if let Some(ref in_flight) = deleter.in_flight {
+                                    in_flight.lock().await.remove(&path);
+                                }
+                                let error = std::io::Error::other(format!("the worker panicked: {}", e));
+                                deleter.record_failure(i, &path, &error, &logger).await;
+                            }
+                            *deleter.worker_restarts.lock().await += 1;
+                        }
+                        Err(e) => {
+                            logger.log(&format!("Worker {} was cancelled: {:?}", i, e), true, true, false).await;
+                            break;
+                        }
                     }
                 }
-                if verbose {
-                    logger.log(&format!("Worker {} finished processing paths", i), false, true, true).await;
-                }
             }));
         }
 
@@ -129,54 +522,244 @@ impl Deleter {
         Ok(())
     }
 
+    /// Consumes paths from the channel until the crawler passes are complete and the channel is
+    /// drained.
+    ///
+    /// # Arguments
+    ///
+    /// * slot - The number of the worker, used in log messages, and where it keeps the path it is
+    ///   processing.
+    /// * receiver - A receiver for paths to delete.
+    /// * logger - An instance of the Logger.
+    /// * verbose - A boolean indicating whether to enable verbose logging.
+    /// * total_deletion_ops - A shared counter for the total number of deletion operations.
+    /// * passes - The crawler passes that send paths to the channel.
+    async fn run_worker(
+        self,
+        slot: Arc<WorkerSlot>,
+        receiver: Arc<Mutex<mpsc::Receiver<Found>>>,
+        logger: Arc<Logger>,
+        verbose: bool,
+        total_deletion_ops: Arc<Mutex<usize>>,
+        passes: Vec<Completion>,
+    ) {
+        let i = slot.index;
+        let terminated = termination::wait(self.termination.as_ref());
+        tokio::pin!(terminated);
+        loop {
+            // a terminated run leaves what is still queued alone
+            if self.termination.as_ref().is_some_and(Termination::requested) {
+                break;
+            }
+            // only hold the receiver lock while waiting for the next path, so the other
+            // workers can pick up paths while this one is deleting
+            let next = {
+                let mut receiver = receiver.lock().await;
+                tokio::select! {
+                    path = receiver.recv() => path,
+                    // nothing more can arrive, so whatever is still queued is drained
+                    _ = futures::future::join_all(passes.iter().map(Completion::wait)) => receiver.try_recv().ok(),
+                    _ = &mut terminated => None,
+                }
+            };
+            let Found { path, identity, tier } = match next {
+                Some(found) => found,
+                None => break,
+            };
+
+            if verbose {
+                logger.log(&format!("Worker {} picked up path: {:?}", i, &path), false, true, true).await;
+            }
+
+            *slot.current.lock().await = Some(path.clone());
+            if let Some(ref in_flight) = self.in_flight {
+                in_flight.lock().await.insert(path.clone(), std::time::Instant::now());
+            }
+            let result = self.process_path(&path, identity, tier, &logger, verbose, &total_deletion_ops).await;
+            if let Some(ref in_flight) = self.in_flight {
+                in_flight.lock().await.remove(&path);
+            }
+            *slot.current.lock().await = None;
+            if let Err(e) = result {
+                self.record_failure(i, &path, e.as_ref(), &logger).await;
+            }
+        }
+        if verbose {
+            logger.log(&format!("Worker {} finished processing paths", i), false, true, true).await;
+        }
+    }
+
+    /// Counts a path that could not be processed as failed, logs why and remembers it so it can
+    /// be retried.
+    ///
+    /// # Arguments
+    ///
+    /// * i - The number of the worker, used in log messages.
+    /// * path - The path that failed.
+    /// * e - The error processing it returned.
+    /// * logger - An instance of the Logger.
+    async fn record_failure(&self, i: usize, path: &Path, e: &(dyn std::error::Error + Send + Sync + 'static), logger: &Arc<Logger>) {
+        let failure = self.failures.record(path, e).await;
+        logger.log(&format!("Worker {} failed to {}", i, failure.describe()), true, false, false).await;
+        if self.check_open_handles && Deleter::is_busy(e) {
+            self.report_busy(path, logger).await;
+        }
+        self.count_failure(logger).await;
+        if let Some(ref dir_report) = self.dir_report {
+            dir_report.record_failure(path).await;
+        }
+    }
+
     /// Processes a path and deletes it if it's a file or recursively deletes if it's a directory.
+    /// In a dry run nothing is removed, but the path is counted as if it had been deleted.
     ///
     /// # Arguments
     ///
     /// * path - The path to process.
+    /// * identity - The device and inode the object had when it was crawled.
+    /// * tier - The tier the crawler picked for the object, if '--tier' was specified.
     /// * logger - An instance of the Logger.
     /// * verbose - A boolean indicating whether to enable verbose logging.
-    /// * total_size - A shared counter for the total size of deleted files.
-    /// * failed_deletions - A shared counter for the number of failed deletions.
     /// * total_deletion_ops - A shared counter for the total number of deletion operations.
-    /// * total_directories - A shared counter for the total number of directories.
-    /// * dry_run - A boolean indicating whether to perform a dry run.
     ///
     /// # Returns
     ///
     /// * 'Result<(), Box<dyn std::error::Error + Send + Sync>>' - Ok if successful, Err otherwise.
     async fn process_path(
+        &self,
         path: &PathBuf,
-        logger: Arc<Logger>,
+        identity: Identity,
+        tier: Option<usize>,
+        logger: &Arc<Logger>,
         verbose: bool,
-        total_size: Arc<Mutex<u64>>,
-        failed_deletions: Arc<Mutex<u64>>,
-        total_deletion_ops: Arc<Mutex<usize>>,
-        total_directories: Arc<Mutex<usize>>,
-        dry_run: bool,
+        total_deletion_ops: &Arc<Mutex<usize>>,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        let metadata = fs::metadata(path).await?;
-        if metadata.is_file() || metadata.file_type().is_symlink() {
-            if !dry_run {
-                if let Err(e) = fs::remove_file(path).await {
-                    *failed_deletions.lock().await += 1;
-                    return Err(Box::new(e));
-                }
-                *total_deletion_ops.lock().await += 1;
-                *total_size.lock().await += metadata.len();
+        // never follow symlinks, a link is removed on its own and its target is left alone
+        let started = Instant::now();
+        let metadata = match self.slow_ops.limit(OpKind::Stat, path, fs::symlink_metadata(path)).await {
+            Some(Ok(metadata)) => {
+                self.slow_ops.record(OpKind::Stat, path, started.elapsed()).await;
+                metadata
+            }
+            Some(Err(e)) if e.kind() == std::io::ErrorKind::NotFound => return self.skip_vanished(path, logger, verbose).await,
+            Some(Err(e)) => return Err(OpError::boxed(OpKind::Stat, e)),
+            None => return self.skip_stuck(path, OpKind::Stat, logger).await,
+        };
+        // something else may have been put in its place since the crawl, such as a symlink
+        if Identity::of(&metadata) != identity {
+            self.skipped.add(SkipReason::Replaced).await;
+            logger.log(&format!("Refused to delete {:?}: it was replaced since it was crawled ({} then, {} now)", path, identity, Identity::of(&metadata)), true, false, false).await;
+            return Ok(());
+        }
+        // it may have been given to another user since the crawl
+        if self.only_mine.is_some_and(|uid| metadata.uid() != uid) {
+            self.skipped.add(SkipReason::Owner).await;
+            if verbose {
+                logger.log(&format!("Kept {:?}: it is owned by user {} now", path, metadata.uid()), false, true, true).await;
+            }
+            return Ok(());
+        }
+        // an overlapping run deleted it within the cooldown, so it was re-created since
+        if let Some((deleted, entry)) = self.ledger.as_ref().and_then(|ledger| ledger.deleted_within(path)) {
+            self.skipped.add(SkipReason::Cooldown).await;
+            if verbose {
+                logger.log(&format!("Kept {:?}: {:?} was deleted within the cooldown ({})", path, deleted, entry), false, true, true).await;
+            }
+            return Ok(());
+        }
+        // the crawler only sends objects of the selected types, but one may have been replaced since
+        if self.types.is_some_and(|types| !types.allows(&metadata)) {
+            self.skipped.add(SkipReason::Filtered).await;
+            if verbose {
+                logger.log(&format!("Kept object of another type: {:?}", path), false, true, true).await;
+            }
+            return Ok(());
+        }
+        // a file that was read since it was crawled isn't stale anymore
+        if !metadata.is_dir() && self.accessed_before.is_some_and(|cutoff| metadata.atime() >= cutoff) {
+            self.skipped.add(SkipReason::RecentlyAccessed).await;
+            if verbose {
+                logger.log(&format!("Kept file that was read since it was crawled: {:?}", path), false, true, true).await;
+            }
+            return Ok(());
+        }
+        // a file written to within the minimum age may still be on its way
+        if !metadata.is_dir() && self.changed_before.is_some_and(|cutoff| filters::last_change(&metadata) >= cutoff) {
+            self.skipped.add(SkipReason::MinAge).await;
+            if verbose {
+                logger.log(&format!("Kept file that changed within the minimum age: {:?}", path), false, true, true).await;
             }
+            return Ok(());
+        }
+        // a link may have been added since the crawl
+        if self.skip_hardlinked && !metadata.is_dir() && metadata.nlink() > 1 {
+            self.skipped.add(SkipReason::Hardlinked).await;
             if verbose {
-                logger.log(&format!("Deleted file/symlink: {:?}", path), false, true, true).await;
+                logger.log(&format!("Kept file that is hard linked elsewhere: {:?}", path), false, true, true).await;
             }
+            return Ok(());
+        }
+        // a service may still be writing to it
+        if !metadata.is_dir() && !self.check_in_use(path, &metadata, logger, verbose).await {
+            self.skipped.add(SkipReason::InUse).await;
+            return Ok(());
+        }
+        if let Some(ref tier_counts) = self.tier_counts {
+            return self.tier_path(path, &metadata, tier_counts, tier, logger, verbose, total_deletion_ops).await;
+        }
+        if let Some(compressor) = self.compressor {
+            return self.compress_path(path, &metadata, compressor, logger, verbose, total_deletion_ops).await.map(|_| ());
+        }
+        if let Some(ref dest) = self.move_to {
+            return self.move_path(path, &metadata, dest, logger, verbose, total_deletion_ops).await.map(|_| ());
+        }
+        if self.trash.is_some() || self.undo.is_some() || self.archive.is_some() {
+            return self.stash_path(path, &metadata, logger, verbose, total_deletion_ops).await;
+        }
+        if metadata.is_file() || metadata.file_type().is_symlink() {
+            self.delete_file(path, &metadata, logger, verbose, total_deletion_ops).await?;
+        } else if metadata.is_dir() && self.prune_empty_dirs {
+            return self.prune_path(path, logger, verbose, total_deletion_ops).await;
         } else if metadata.is_dir() {
-            if !dry_run {
-                if let Err(e) = fs::remove_dir_all(path).await {
-                    *failed_deletions.lock().await += 1;
-                    return Err(Box::new(e));
+            if !self.check_live_sockets(path, logger).await {
+                self.skipped.add(SkipReason::LiveSocket).await;
+                return Ok(());
+            }
+            if !self.check_in_use(path, &metadata, logger, verbose).await {
+                self.skipped.add(SkipReason::InUse).await;
+                return Ok(());
+            }
+            if !self.check_excludes(path, logger).await {
+                self.skipped.add(SkipReason::Excluded).await;
+                return Ok(());
+            }
+            if !self.take_budget(path, &metadata, logger, verbose).await {
+                return Ok(());
+            }
+            if !self.dry_run {
+                let started = Instant::now();
+                let mut removed = self.remove_tree(path, metadata.dev()).await;
+                if is_not_permitted(&removed) {
+                    match self.check_immutable(path, true, logger, verbose).await {
+                        immutable::Outcome::Kept => return Ok(()),
+                        immutable::Outcome::Cleared => removed = self.remove_tree(path, metadata.dev()).await,
+                        immutable::Outcome::Unprotected => {}
+                    }
+                }
+                match removed {
+                    Ok(()) => self.slow_ops.record(OpKind::RemoveDir, path, started.elapsed()).await,
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => return self.skip_vanished(path, logger, verbose).await,
+                    Err(e) => return Err(OpError::boxed(OpKind::RemoveDir, e)),
                 }
                 *total_deletion_ops.lock().await += 1;
-                *total_directories.lock().await += 1;
+                self.record_event(path, &metadata).await;
+                self.record_deletion(path, &metadata).await;
+                if let Some(ref dir_report) = self.dir_report {
+                    dir_report.record_delete(path, started.elapsed()).await;
+                }
             }
+            *self.deleted_dirs.lock().await += 1;
+            self.record_plan(path, &metadata).await;
             if verbose {
                 logger.log(&format!("Deleted directory: {:?}", path), false, true, true).await;
             }
@@ -184,8 +767,1003 @@ impl Deleter {
         Ok(())
     }
 
-    /// Shuts down the deleter, performing any necessary cleanup.
-    pub async fn shutdown(&self) {
-        // Perform any necessary cleanup here.
+    /// Deletes a file or symlink. In a dry run nothing is removed, but the object is counted as
+    /// if it had been.
+    ///
+    /// # Arguments
+    ///
+    /// * path - The path to process.
+    /// * metadata - The metadata of the path, read without following symlinks.
+    /// * logger - An instance of the Logger.
+    /// * verbose - A boolean indicating whether to enable verbose logging.
+    /// * total_deletion_ops - A shared counter for the total number of deletion operations.
+    ///
+    /// # Returns
+    ///
+    /// * 'Result<bool, Box<dyn std::error::Error + Send + Sync>>' - Ok with true if the object
+    ///   was deleted (or would be in a dry run), false if it was kept or vanished, Err otherwise.
+    async fn delete_file(
+        &self,
+        path: &PathBuf,
+        metadata: &std::fs::Metadata,
+        logger: &Arc<Logger>,
+        verbose: bool,
+        total_deletion_ops: &Arc<Mutex<usize>>,
+    ) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        if !self.take_budget(path, metadata, logger, verbose).await {
+            return Ok(false);
+        }
+        // whether a link dangles can only be told before it is gone
+        let valid_link = self.check_symlink(path, metadata).await;
+        if !self.dry_run {
+            let started = Instant::now();
+            let mut removed = match self.slow_ops.limit(OpKind::RemoveFile, path, self.anchors.remove_file(path)).await {
+                Some(removed) => removed,
+                None => return self.skip_stuck(path, OpKind::RemoveFile, logger).await.map(|()| false),
+            };
+            if is_not_permitted(&removed) {
+                match self.check_immutable(path, false, logger, verbose).await {
+                    immutable::Outcome::Kept => return Ok(false),
+                    immutable::Outcome::Cleared => removed = match self.slow_ops.limit(OpKind::RemoveFile, path, self.anchors.remove_file(path)).await {
+                        Some(removed) => removed,
+                        None => return self.skip_stuck(path, OpKind::RemoveFile, logger).await.map(|()| false),
+                    },
+                    immutable::Outcome::Unprotected => {}
+                }
+            }
+            match removed {
+                Ok(()) => self.slow_ops.record(OpKind::RemoveFile, path, started.elapsed()).await,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => return self.skip_vanished(path, logger, verbose).await.map(|()| false),
+                Err(e) => return Err(OpError::boxed(OpKind::RemoveFile, e)),
+            }
+            *total_deletion_ops.lock().await += 1;
+            self.record_event(path, metadata).await;
+            self.record_deletion(path, metadata).await;
+        }
+        *self.deleted_files.lock().await += 1;
+        self.record_reclaimed(metadata).await;
+        self.record_symlink(valid_link).await;
+        self.record_plan(path, metadata).await;
+        if let Some(ref ext_counts) = self.ext_counts {
+            ext_counts.record(path).await;
+        }
+        if verbose {
+            logger.log(&format!("Deleted file/symlink: {:?}", path), false, true, true).await;
+        }
+        Ok(true)
+    }
+
+    /// Acts on a file with the action of the tier the crawler picked for it, for '--tier', and
+    /// counts it for that tier. Directories are kept, since they could hold files of several
+    /// tiers.
+    ///
+    /// # Arguments
+    ///
+    /// * path - The path to process.
+    /// * metadata - The metadata of the path, read without following symlinks.
+    /// * tier_counts - The tiers and the files acted on per tier.
+    /// * tier - The tier the crawler picked for the path.
+    /// * logger - An instance of the Logger.
+    /// * verbose - A boolean indicating whether to enable verbose logging.
+    /// * total_deletion_ops - A shared counter for the total number of deletion operations.
+    ///
+    /// # Returns
+    ///
+    /// * 'Result<(), Box<dyn std::error::Error + Send + Sync>>' - Ok if successful, Err otherwise.
+    #[allow(clippy::too_many_arguments)]
+    async fn tier_path(
+        &self,
+        path: &PathBuf,
+        metadata: &std::fs::Metadata,
+        tier_counts: &TierCounts,
+        tier: Option<usize>,
+        logger: &Arc<Logger>,
+        verbose: bool,
+        total_deletion_ops: &Arc<Mutex<usize>>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let Some(index) = tier.filter(|_| !metadata.is_dir()) else {
+            self.skipped.add(SkipReason::KeptDirectory).await;
+            return Ok(());
+        };
+        // a file written to since the crawl has become younger, and may have left its tier
+        if tier_counts.tiers().pick(metadata) != Some(index) {
+            self.skipped.add(SkipReason::TierChanged).await;
+            if verbose {
+                logger.log(&format!("Kept file that changed tier since it was crawled: {:?}", path), false, true, true).await;
+            }
+            return Ok(());
+        }
+        let acted = match (tier_counts.tiers().get(index).map(|tier| tier.action), self.compressor, &self.move_to) {
+            (Some(TierAction::Compress), Some(compressor), _) => self.compress_path(path, metadata, compressor, logger, verbose, total_deletion_ops).await?,
+            (Some(TierAction::Move), _, Some(dest)) => self.move_path(path, metadata, dest, logger, verbose, total_deletion_ops).await?,
+            (Some(TierAction::Trash), _, _) => self.stash_object(path, metadata, logger, verbose, total_deletion_ops).await?,
+            (Some(TierAction::Delete), _, _) => self.delete_file(path, metadata, logger, verbose, total_deletion_ops).await?,
+            // the compressor and the destination are set up for every tier that needs them
+            _ => false,
+        };
+        if acted {
+            tier_counts.record(index, metadata.len()).await;
+        }
+        Ok(())
+    }
+
+    /// Removes a directory tree with everything within it, through descriptors opened below its
+    /// anchor. With '--one-file-system', a directory within the tree that is on another
+    /// filesystem, such as a mount point that appeared since the crawl, stops the removal instead
+    /// of being emptied.
+    ///
+    /// # Arguments
+    ///
+    /// * path - The directory.
+    /// * device - The device the directory is on.
+    ///
+    /// # Returns
+    ///
+    /// * 'std::io::Result<()>' - Ok if the directory was removed, Err otherwise.
+    async fn remove_tree(&self, path: &Path, device: u64) -> std::io::Result<()> {
+        let (device, owner) = (self.one_file_system.then_some(device), self.only_mine);
+        let (anchors, dir) = (Arc::clone(&self.anchors), path.to_path_buf());
+        let (result, removed) = task::spawn_blocking(move || {
+            let mut removed = Vec::new();
+            (anchors.remove_tree(&dir, &mut removed, device, owner), removed)
+        }).await?;
+        // files and symlinks unlinked here rather than by the file deleters
+        if let Some(ref owner_counts) = self.owner_counts {
+            for (uid, bytes) in removed {
+                owner_counts.record(uid, bytes).await;
+            }
+        }
+        result
+    }
+
+    /// Looks into an object whose removal wasn't permitted, in case it is immutable or
+    /// append-only. A protected object is counted as skipped unless its attributes were cleared.
+    ///
+    /// # Arguments
+    ///
+    /// * path - The object.
+    /// * is_dir - Whether the object is a directory tree.
+    /// * logger - An instance of the Logger.
+    /// * verbose - A boolean indicating whether to enable verbose logging.
+    ///
+    /// # Returns
+    ///
+    /// * 'immutable::Outcome' - Whether the object was kept, cleared, or isn't protected at all.
+    async fn check_immutable(&self, path: &Path, is_dir: bool, logger: &Arc<Logger>, verbose: bool) -> immutable::Outcome {
+        let (outcome, error) = self.immutables.handle(path, is_dir).await;
+        if let Some(error) = error {
+            logger.log(&error, true, false, false).await;
+        }
+        match outcome {
+            immutable::Outcome::Kept => {
+                self.skipped.add(SkipReason::Immutable).await;
+                if verbose {
+                    logger.log(&format!("Kept immutable or append-only object: {:?}", path), false, true, true).await;
+                }
+            }
+            immutable::Outcome::Cleared if verbose => {
+                logger.log(&format!("Cleared the immutable and append-only attributes of {:?}", path), false, true, true).await;
+            }
+            _ => {}
+        }
+        outcome
     }
+
+    /// Removes an empty subtree bottom-up for '--prune-empty-dirs'. Each directory is removed with
+    /// 'remove_dir', which refuses anything that isn't empty, so a file that appeared since the
+    /// crawl keeps its directory and every directory above it. In a dry run nothing is removed,
+    /// but the directories are counted as if they had been.
+    ///
+    /// # Arguments
+    ///
+    /// * path - The top of the empty subtree.
+    /// * logger - An instance of the Logger.
+    /// * verbose - A boolean indicating whether to enable verbose logging.
+    /// * total_deletion_ops - A shared counter for the total number of deletion operations.
+    ///
+    /// # Returns
+    ///
+    /// * 'Result<(), Box<dyn std::error::Error + Send + Sync>>' - Ok if successful, Err otherwise.
+    async fn prune_path(
+        &self,
+        path: &PathBuf,
+        logger: &Arc<Logger>,
+        verbose: bool,
+        total_deletion_ops: &Arc<Mutex<usize>>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let started = Instant::now();
+        let (dir, dry_run, excludes, anchors) = (path.clone(), self.dry_run, Arc::clone(&self.excludes), Arc::clone(&self.anchors));
+        let pruned = task::spawn_blocking(move || {
+            let mut pruned = Pruned::default();
+            Deleter::prune_tree(&dir, dry_run, &excludes, &anchors, &mut pruned);
+            pruned
+        }).await?;
+        if !self.dry_run && pruned.removed > 0 {
+            self.slow_ops.record(OpKind::RemoveDir, path, started.elapsed()).await;
+            *total_deletion_ops.lock().await += pruned.removed as usize;
+            if let Some(ref dir_report) = self.dir_report {
+                dir_report.record_delete(path, started.elapsed()).await;
+            }
+        }
+        *self.deleted_dirs.lock().await += pruned.removed;
+        for (dir, metadata) in &pruned.removed_dirs {
+            self.record_event(dir, metadata).await;
+        }
+        for _ in 0..pruned.kept {
+            self.skipped.add(SkipReason::NoLongerEmpty).await;
+        }
+        for _ in 0..pruned.vanished {
+            self.skipped.add(SkipReason::Vanished).await;
+        }
+        for (dir, kind, e) in pruned.failed {
+            let failure = self.failures.record_io(&dir, kind, &e).await;
+            logger.log(&format!("Failed to remove empty directory: {}", failure.describe()), true, false, false).await;
+            self.count_failure(logger).await;
+            if let Some(ref dir_report) = self.dir_report {
+                dir_report.record_failure(&dir).await;
+            }
+        }
+        if verbose {
+            logger.log(&format!("Pruned {} empty directories at {:?}", pruned.removed, path), false, true, true).await;
+        }
+        Ok(())
+    }
+
+    /// Removes the directories of a subtree that are still empty, deepest first.
+    ///
+    /// # Arguments
+    ///
+    /// * path - The directory at the top of the subtree.
+    /// * dry_run - A boolean indicating whether to only count the directories.
+    /// * excludes - The paths that must not be deleted.
+    /// * anchors - The anchors of the target patterns.
+    /// * pruned - Where the outcome for each directory is added up.
+    ///
+    /// # Returns
+    ///
+    /// * 'bool' - True if the directory was removed.
+    fn prune_tree(path: &Path, dry_run: bool, excludes: &Excludes, anchors: &Anchors, pruned: &mut Pruned) -> bool {
+        let entries = match std::fs::read_dir(path) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                pruned.vanished += 1;
+                return false;
+            }
+            Err(e) => {
+                pruned.failed.push((path.to_path_buf(), OpKind::OpenDir, e));
+                return false;
+            }
+        };
+        let mut empty = true;
+        for entry in entries {
+            // anything but a directory, or a directory that must be kept, means this one stays
+            empty &= match entry {
+                Ok(entry) if entry.file_type().is_ok_and(|file_type| file_type.is_dir()) && !excludes.is_excluded(&entry.path(), true) => {
+                    Deleter::prune_tree(&entry.path(), dry_run, excludes, anchors, pruned)
+                }
+                _ => false,
+            };
+        }
+        if !empty {
+            pruned.kept += 1;
+            return false;
+        }
+        if dry_run {
+            pruned.removed += 1;
+            return true;
+        }
+        let metadata = std::fs::symlink_metadata(path);
+        match anchors.remove_dir(path) {
+            Ok(()) => {
+                pruned.removed += 1;
+                if let Ok(metadata) = metadata {
+                    pruned.removed_dirs.push((path.to_path_buf(), metadata));
+                }
+                true
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::DirectoryNotEmpty => {
+                pruned.kept += 1;
+                false
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                pruned.vanished += 1;
+                false
+            }
+            Err(e) => {
+                pruned.failed.push((path.to_path_buf(), OpKind::RemoveDir, e));
+                false
+            }
+        }
+    }
+
+    /// Compresses a file in place. Directories and files that can't be compressed are skipped.
+    /// In a dry run nothing is compressed, but the file is counted as if it had been.
+    ///
+    /// # Arguments
+    ///
+    /// * path - The path to process.
+    /// * metadata - The metadata of the path, read without following symlinks.
+    /// * compressor - The compressor to use.
+    /// * logger - An instance of the Logger.
+    /// * verbose - A boolean indicating whether to enable verbose logging.
+    /// * total_deletion_ops - A shared counter for the total number of deletion operations.
+    ///
+    /// # Returns
+    ///
+    /// * 'Result<bool, Box<dyn std::error::Error + Send + Sync>>' - Ok with true if the file
+    ///   was compressed (or would be in a dry run), false if it was kept or vanished, Err otherwise.
+    async fn compress_path(
+        &self,
+        path: &PathBuf,
+        metadata: &std::fs::Metadata,
+        compressor: Compressor,
+        logger: &Arc<Logger>,
+        verbose: bool,
+        total_deletion_ops: &Arc<Mutex<usize>>,
+    ) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        if metadata.is_dir() {
+            self.skipped.add(SkipReason::KeptDirectory).await;
+            return Ok(false);
+        }
+        if !self.take_budget(path, metadata, logger, verbose).await {
+            return Ok(false);
+        }
+        let compressed_size = if self.dry_run {
+            if let Some(reason) = compress::unsuitable(path, metadata, compressor) {
+                return self.skip_not_compressible(path, reason, logger, verbose).await.map(|()| false);
+            }
+            None
+        } else {
+            let started = Instant::now();
+            let file = path.clone();
+            match task::spawn_blocking(move || compress::compress_file(&file, compressor)).await? {
+                Ok(compress::Outcome::Compressed(size)) => {
+                    self.slow_ops.record(OpKind::Compress, path, started.elapsed()).await;
+                    *total_deletion_ops.lock().await += 1;
+                    self.record_event(path, metadata).await;
+                    Some(size)
+                }
+                Ok(compress::Outcome::Unsuitable(reason)) => return self.skip_not_compressible(path, reason, logger, verbose).await.map(|()| false),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => return self.skip_vanished(path, logger, verbose).await.map(|()| false),
+                Err(e) => return Err(OpError::boxed(OpKind::Compress, e)),
+            }
+        };
+        *self.deleted_files.lock().await += 1;
+        self.record_reclaimed(metadata).await;
+        *self.compressed_size.lock().await += compressed_size.unwrap_or(0);
+        if let Some(ref ext_counts) = self.ext_counts {
+            ext_counts.record(path).await;
+        }
+        if verbose {
+            match compressed_size {
+                Some(size) => logger.log(&format!("Compressed file: {:?} ({} -> {} bytes)", path, metadata.len(), size), false, true, true).await,
+                None => logger.log(&format!("Compressed file: {:?}", path), false, true, true).await,
+            }
+        }
+        Ok(true)
+    }
+
+    /// Moves a file or symlink under the destination directory. Directories are skipped. In a dry
+    /// run nothing is moved, but the object is counted as if it had been.
+    ///
+    /// # Arguments
+    ///
+    /// * path - The path to process.
+    /// * metadata - The metadata of the path, read without following symlinks.
+    /// * dest - The destination directory.
+    /// * logger - An instance of the Logger.
+    /// * verbose - A boolean indicating whether to enable verbose logging.
+    /// * total_deletion_ops - A shared counter for the total number of deletion operations.
+    ///
+    /// # Returns
+    ///
+    /// * 'Result<bool, Box<dyn std::error::Error + Send + Sync>>' - Ok with true if the object
+    ///   was moved (or would be in a dry run), false if it was kept or vanished, Err otherwise.
+    async fn move_path(
+        &self,
+        path: &PathBuf,
+        metadata: &std::fs::Metadata,
+        dest: &Path,
+        logger: &Arc<Logger>,
+        verbose: bool,
+        total_deletion_ops: &Arc<Mutex<usize>>,
+    ) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        if metadata.is_dir() {
+            self.skipped.add(SkipReason::KeptDirectory).await;
+            return Ok(false);
+        }
+        if !self.take_budget(path, metadata, logger, verbose).await {
+            return Ok(false);
+        }
+        let target = relocate::destination(dest, path);
+        let valid_link = self.check_symlink(path, metadata).await;
+        let outcome = if self.dry_run {
+            if fs::symlink_metadata(&target).await.is_ok() {
+                relocate::Outcome::DestinationExists
+            } else {
+                relocate::Outcome::Moved(false)
+            }
+        } else {
+            let started = Instant::now();
+            let (file, dest) = (path.clone(), dest.to_path_buf());
+            match task::spawn_blocking(move || relocate::move_path(&file, &dest)).await? {
+                Ok(outcome) => {
+                    self.slow_ops.record(OpKind::Move, path, started.elapsed()).await;
+                    outcome
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound && fs::symlink_metadata(path).await.is_err() => {
+                    return self.skip_vanished(path, logger, verbose).await.map(|()| false);
+                }
+                Err(e) => return Err(OpError::boxed(OpKind::Move, e)),
+            }
+        };
+        match outcome {
+            relocate::Outcome::Moved(copied) => {
+                if !self.dry_run {
+                    *total_deletion_ops.lock().await += 1;
+                    self.record_event(path, metadata).await;
+                }
+                if copied {
+                    *self.copied_files.lock().await += 1;
+                }
+                *self.deleted_files.lock().await += 1;
+                self.record_reclaimed(metadata).await;
+                self.record_symlink(valid_link).await;
+                if let Some(ref ext_counts) = self.ext_counts {
+                    ext_counts.record(path).await;
+                }
+                if verbose {
+                    logger.log(&format!("Moved {:?} to {:?}", path, target), false, true, true).await;
+                }
+            }
+            relocate::Outcome::DestinationExists => {
+                self.skipped.add(SkipReason::DestinationExists).await;
+                if verbose {
+                    logger.log(&format!("Skipped {:?}: {:?} already exists", path, target), false, true, true).await;
+                }
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Moves a file, symlink or directory tree to the trash or the staging folder of '--undoable',
+    /// or archives and removes it for '--archive-to'.
+    ///
+    /// # Arguments
+    ///
+    /// * path - The path of the object.
+    /// * metadata - The metadata of the object, read without following symlinks.
+    /// * logger - The logger.
+    /// * verbose - A boolean indicating whether to log verbose output.
+    /// * total_deletion_ops - The number of objects removed so far.
+    ///
+    /// # Returns
+    ///
+    /// * 'Result<(), Box<dyn std::error::Error + Send + Sync>>' - Ok if successful, Err otherwise.
+    async fn stash_path(
+        &self,
+        path: &PathBuf,
+        metadata: &std::fs::Metadata,
+        logger: &Arc<Logger>,
+        verbose: bool,
+        total_deletion_ops: &Arc<Mutex<usize>>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        // the crawler found every directory within a tree but only sent the top one, so the
+        // directories within share its fate
+        let nested_dirs = if metadata.is_dir() {
+            let tree = path.clone();
+            task::spawn_blocking(move || Deleter::count_dirs(&tree)).await?
+        } else {
+            0
+        };
+        let stashed = self.stash_object(path, metadata, logger, verbose, total_deletion_ops).await;
+        if nested_dirs > 0 {
+            match stashed {
+                Ok(true) => *self.deleted_dirs.lock().await += nested_dirs,
+                _ => self.skipped.add_many(SkipReason::KeptDirectory, nested_dirs).await,
+            }
+        }
+        stashed.map(|_| ())
+    }
+
+    /// Moves a file, symlink or the top of a directory tree to the trash or the staging folder,
+    /// or archives and removes it, unless it has to be kept.
+    ///
+    /// # Arguments
+    ///
+    /// * path - The path of the object.
+    /// * metadata - The metadata of the object, read without following symlinks.
+    /// * logger - The logger.
+    /// * verbose - A boolean indicating whether to log verbose output.
+    /// * total_deletion_ops - The number of objects removed so far.
+    ///
+    /// # Returns
+    ///
+    /// * 'Result<bool, Box<dyn std::error::Error + Send + Sync>>' - Ok with true if the object
+    ///   was moved (or would be in a dry run), false if it was kept or vanished, Err otherwise.
+    async fn stash_object(
+        &self,
+        path: &PathBuf,
+        metadata: &std::fs::Metadata,
+        logger: &Arc<Logger>,
+        verbose: bool,
+        total_deletion_ops: &Arc<Mutex<usize>>,
+    ) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        if metadata.is_dir() {
+            if !self.check_live_sockets(path, logger).await {
+                self.skipped.add(SkipReason::LiveSocket).await;
+                return Ok(false);
+            }
+            if !self.check_in_use(path, metadata, logger, verbose).await {
+                self.skipped.add(SkipReason::InUse).await;
+                return Ok(false);
+            }
+            if !self.check_excludes(path, logger).await {
+                self.skipped.add(SkipReason::Excluded).await;
+                return Ok(false);
+            }
+        }
+        if !self.take_budget(path, metadata, logger, verbose).await {
+            return Ok(false);
+        }
+        let valid_link = self.check_symlink(path, metadata).await;
+        if !self.dry_run {
+            let started = Instant::now();
+            let (moved, kind) = match (&self.archive, &self.undo, &self.trash) {
+                (Some(archive), _, _) => (Self::archive_object(path, archive).await?, OpKind::Archive),
+                (None, Some(undo), _) => (undo.stage(path, metadata).await, OpKind::Stage),
+                (None, None, Some(trash)) => {
+                    let (object, trash) = (path.clone(), trash.clone());
+                    (task::spawn_blocking(move || trash.put(&object)).await?, OpKind::Trash)
+                }
+                (None, None, None) => return Ok(false),
+            };
+            let copied = match moved {
+                Ok(copied) => copied,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound && fs::symlink_metadata(path).await.is_err() => {
+                    return self.skip_vanished(path, logger, verbose).await.map(|()| false);
+                }
+                Err(e) => return Err(OpError::boxed(kind, e)),
+            };
+            self.slow_ops.record(kind, path, started.elapsed()).await;
+            *total_deletion_ops.lock().await += 1;
+            self.record_event(path, metadata).await;
+            if copied {
+                *self.copied_files.lock().await += 1;
+            }
+            if metadata.is_dir() {
+                if let Some(ref dir_report) = self.dir_report {
+                    dir_report.record_delete(path, started.elapsed()).await;
+                }
+            }
+        }
+        if metadata.is_dir() {
+            *self.deleted_dirs.lock().await += 1;
+        } else {
+            *self.deleted_files.lock().await += 1;
+            self.record_reclaimed(metadata).await;
+            self.record_symlink(valid_link).await;
+            if let Some(ref ext_counts) = self.ext_counts {
+                ext_counts.record(path).await;
+            }
+        }
+        if verbose {
+            let moved = match (&self.archive, &self.undo) {
+                (Some(_), _) => "Archived and deleted",
+                (None, Some(_)) => "Staged for undo",
+                (None, None) => "Moved to the trash",
+            };
+            logger.log(&format!("{}: {:?}", moved, path), false, true, true).await;
+        }
+        Ok(true)
+    }
+
+    /// Adds an object to the archive and, once its copy is on disk, removes what was archived.
+    ///
+    /// # Arguments
+    ///
+    /// * path - The path of the object.
+    /// * archive - The archive.
+    ///
+    /// # Returns
+    ///
+    /// * 'Result<std::io::Result<bool>, tokio::task::JoinError>' - The outcome like that of a
+    ///   move, which never copies anything, or Err if the removal panicked.
+    async fn archive_object(path: &Path, archive: &Archive) -> Result<std::io::Result<bool>, tokio::task::JoinError> {
+        let members = match archive.add(path).await {
+            Ok(members) => members,
+            Err(e) => return Ok(Err(e)),
+        };
+        task::spawn_blocking(move || archive::remove_members(&members).map(|()| false)).await
+    }
+
+    /// Counts a failed deletion, and stops the run once '--max-errors' of them failed.
+    ///
+    /// # Arguments
+    ///
+    /// * logger - An instance of the Logger.
+    async fn count_failure(&self, logger: &Arc<Logger>) {
+        let failed = {
+            let mut failed_deletions = self.failed_deletions.lock().await;
+            *failed_deletions += 1;
+            *failed_deletions
+        };
+        if self.max_errors != Some(failed) {
+            return;
+        }
+        logger.log(&format!("{} deletions failed, which is --max-errors. Stopping, everything that is left is kept", failed), true, false, false).await;
+        if let Some(ref termination) = self.termination {
+            termination.stop();
+        }
+    }
+
+    /// Takes an object from the budget of '--max-delete' before it is removed, compressed or
+    /// moved, and keeps it if the budget is exhausted.
+    ///
+    /// # Arguments
+    ///
+    /// * path - The path of the object.
+    /// * metadata - The metadata of the object, read without following symlinks.
+    /// * logger - An instance of the Logger.
+    /// * verbose - A boolean indicating whether to enable verbose logging.
+    ///
+    /// # Returns
+    ///
+    /// * 'bool' - True if the object may be removed, false if it was kept.
+    async fn take_budget(&self, path: &Path, metadata: &std::fs::Metadata, logger: &Arc<Logger>, verbose: bool) -> bool {
+        let Some(ref budget) = self.budget else {
+            return true;
+        };
+        // a directory takes whatever is still within it along
+        let (objects, bytes) = if metadata.is_dir() {
+            let tree = path.to_path_buf();
+            task::spawn_blocking(move || Deleter::measure_tree(&tree)).await.unwrap_or_default()
+        } else {
+            (1, freed_size(metadata, metadata.nlink()))
+        };
+        if budget.take(objects, bytes).await {
+            return true;
+        }
+        self.skipped.add(SkipReason::OverBudget).await;
+        if verbose {
+            logger.log(&format!("Kept {:?}: the run reached --max-delete", path), false, true, true).await;
+        }
+        false
+    }
+
+    /// Records an object that was removed in the event journal, if '--event-journal' was specified.
+    ///
+    /// # Arguments
+    ///
+    /// * path - The path of the object.
+    /// * metadata - The metadata of the object, read before it was removed.
+    async fn record_event(&self, path: &Path, metadata: &std::fs::Metadata) {
+        if let Some(ref journal) = self.journal {
+            journal.record(EventKind::of(metadata), path, metadata).await;
+        }
+    }
+
+    /// Records an object that was deleted in the ledger, if '--cooldown' was specified.
+    ///
+    /// # Arguments
+    ///
+    /// * path - The object.
+    /// * metadata - The metadata of the object, read before it was deleted.
+    async fn record_deletion(&self, path: &Path, metadata: &std::fs::Metadata) {
+        if let Some(ref ledger) = self.ledger {
+            ledger.record(path, metadata).await;
+        }
+    }
+
+    /// Records an object the dry run would delete in the plan, if this is 'nukem plan'.
+    ///
+    /// # Arguments
+    ///
+    /// * path - The object.
+    /// * metadata - The metadata of the object, read without following symlinks.
+    async fn record_plan(&self, path: &Path, metadata: &std::fs::Metadata) {
+        if let Some(ref plan) = self.plan {
+            plan.record(path, metadata).await;
+        }
+    }
+
+    /// Checks whether a symlink is valid, for '--symlink-report'.
+    ///
+    /// # Arguments
+    ///
+    /// * path - The path to check.
+    /// * metadata - The metadata of the path, read without following symlinks.
+    ///
+    /// # Returns
+    ///
+    /// * 'Option<bool>' - Whether the target of the link exists, or None if the path isn't a
+    ///   symlink or symlinks aren't counted.
+    async fn check_symlink(&self, path: &Path, metadata: &std::fs::Metadata) -> Option<bool> {
+        match self.symlink_counts {
+            Some(_) if metadata.file_type().is_symlink() => Some(SymlinkCounts::is_valid(path).await),
+            _ => None,
+        }
+    }
+
+    /// Counts a deleted or moved symlink for '--symlink-report'.
+    ///
+    /// # Arguments
+    ///
+    /// * valid_link - What `check_symlink` returned for the object.
+    async fn record_symlink(&self, valid_link: Option<bool>) {
+        if let (Some(ref symlink_counts), Some(valid)) = (&self.symlink_counts, valid_link) {
+            symlink_counts.record_deleted(valid).await;
+        }
+    }
+
+    /// Counts a file that was left uncompressed.
+    ///
+    /// # Arguments
+    ///
+    /// * path - The path of the file.
+    /// * reason - Why it can't be compressed.
+    /// * logger - An instance of the Logger.
+    /// * verbose - A boolean indicating whether to enable verbose logging.
+    ///
+    /// # Returns
+    ///
+    /// * 'Result<(), Box<dyn std::error::Error + Send + Sync>>' - Always Ok.
+    async fn skip_not_compressible(
+        &self,
+        path: &PathBuf,
+        reason: &str,
+        logger: &Arc<Logger>,
+        verbose: bool,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.skipped.add(SkipReason::NotCompressible).await;
+        if verbose {
+            logger.log(&format!("Skipped {:?}: {}", path, reason), false, true, true).await;
+        }
+        Ok(())
+    }
+
+    /// Checks whether an error was caused by a path being busy (EBUSY).
+    ///
+    /// # Arguments
+    ///
+    /// * e - The error returned while processing a path.
+    ///
+    /// # Returns
+    ///
+    /// * 'bool' - True if the path was busy.
+    fn is_busy(e: &(dyn std::error::Error + Send + Sync + 'static)) -> bool {
+        failures::io_error(e).is_some_and(|e| e.kind() == std::io::ErrorKind::ResourceBusy)
+    }
+
+    /// Logs the processes that hold files open below a path that could not be deleted.
+    ///
+    /// # Arguments
+    ///
+    /// * path - The path that was busy.
+    /// * logger - An instance of the Logger.
+    async fn report_busy(&self, path: &Path, logger: &Arc<Logger>) {
+        let roots = handles::canonical_roots(&[path.to_path_buf()]);
+        let open_handles = task::spawn_blocking(move || handles::find_open_handles(&roots)).await.unwrap_or_default();
+        for handle in open_handles {
+            logger.log(&format!("Path {:?} is held open by pid {} ({}): {}", path, handle.pid, handle.command, handle.path.display()), true, false, false).await;
+        }
+    }
+
+    /// Looks for live sockets within a directory that is about to be deleted and logs them.
+    ///
+    /// # Arguments
+    ///
+    /// * path - The directory.
+    /// * logger - An instance of the Logger.
+    ///
+    /// # Returns
+    ///
+    /// * 'bool' - True if the directory may be deleted, false if it has to be skipped.
+    async fn check_live_sockets(&self, path: &Path, logger: &Arc<Logger>) -> bool {
+        let live_sockets = match self.live_sockets {
+            Some(ref live_sockets) if !live_sockets.is_empty() => live_sockets,
+            _ => return true,
+        };
+        let dir = match fs::canonicalize(path).await {
+            Ok(dir) => dir,
+            Err(_) => return true,
+        };
+        let found = live_sockets.within(&dir);
+        if found.is_empty() {
+            return true;
+        }
+        let action = match live_sockets.policy {
+            LiveSocketPolicy::Warn => "deleting anyway",
+            LiveSocketPolicy::Skip => "skipping",
+        };
+        for socket in found {
+            let state = if socket.listening { "listening" } else { "connected" };
+            logger.log(&format!("Directory {:?} contains a live {} socket {}, {}", path, state, socket.path.display(), action), true, false, false).await;
+        }
+        live_sockets.policy == LiveSocketPolicy::Warn
+    }
+
+    /// Makes sure nothing is deleted that another process has open, for '--skip-in-use'. A
+    /// directory is kept if any file within it is open.
+    ///
+    /// # Arguments
+    ///
+    /// * path - The file or directory.
+    /// * metadata - The metadata of the path, read without following symlinks.
+    /// * logger - An instance of the Logger.
+    /// * verbose - A boolean indicating whether to enable verbose logging.
+    ///
+    /// # Returns
+    ///
+    /// * 'bool' - True if the path may be deleted, false if it has to be kept.
+    async fn check_in_use(&self, path: &Path, metadata: &std::fs::Metadata, logger: &Arc<Logger>, verbose: bool) -> bool {
+        let in_use = match self.in_use {
+            Some(ref in_use) => in_use,
+            None => return true,
+        };
+        let open = if metadata.is_dir() {
+            match fs::canonicalize(path).await {
+                Ok(dir) => in_use.open_within(&dir).await,
+                Err(_) => None,
+            }
+        } else if in_use.is_open(metadata).await {
+            Some(path.to_path_buf())
+        } else {
+            None
+        };
+        let open = match open {
+            Some(open) => open,
+            None => return true,
+        };
+        in_use.record(path).await;
+        if verbose {
+            if metadata.is_dir() {
+                logger.log(&format!("Kept directory with a file that is in use: {:?} ({})", path, open.display()), false, true, true).await;
+            } else {
+                logger.log(&format!("Kept file that is in use: {:?}", path), false, true, true).await;
+            }
+        }
+        false
+    }
+
+    /// Makes sure a directory tree doesn't contain anything excluded before it is removed. The
+    /// crawler already keeps such directories, so this only catches objects that were created
+    /// or renamed after the directory was crawled. A dry run checks too, so it keeps the same
+    /// directories a real run would.
+    ///
+    /// # Arguments
+    ///
+    /// * path - The directory.
+    /// * logger - An instance of the Logger.
+    ///
+    /// # Returns
+    ///
+    /// * 'bool' - True if the directory may be removed, false if it has to be kept.
+    async fn check_excludes(&self, path: &Path, logger: &Arc<Logger>) -> bool {
+        if self.excludes.is_empty() {
+            return true;
+        }
+        let excludes = Arc::clone(&self.excludes);
+        let dir = path.to_path_buf();
+        match task::spawn_blocking(move || excludes.find_within(&dir)).await.unwrap_or_default() {
+            Some(excluded) => {
+                logger.log(&format!("Refusing to remove directory {:?}: it contains excluded path {:?}", path, excluded), true, false, false).await;
+                false
+            }
+            None => true,
+        }
+    }
+
+    /// Adds the space a file reclaims to the total size and to the counts per owner. Deleting,
+    /// compressing, moving and stashing a file all go through here, so hard links are counted
+    /// the same way whatever the action.
+    ///
+    /// # Arguments
+    ///
+    /// * metadata - The metadata of the file, read right before it was acted on.
+    async fn record_reclaimed(&self, metadata: &std::fs::Metadata) {
+        let size = self.reclaimed_size(metadata).await;
+        *self.total_size.lock().await += size;
+        if let Some(ref owner_counts) = self.owner_counts {
+            owner_counts.record(metadata.uid(), size).await;
+        }
+    }
+
+    /// Works out how much space deleting, compressing, moving or stashing a file reclaims. A file
+    /// with several hard links is only freed along with its last link, so the earlier ones count
+    /// for nothing. Workers may read the link count of two links before either is gone, so the
+    /// links acted on so far are counted per inode. A dry run leaves every link in place, so each
+    /// one counts for its share of the file.
+    ///
+    /// # Arguments
+    ///
+    /// * metadata - The metadata of the file, read right before it was acted on.
+    ///
+    /// # Returns
+    ///
+    /// * 'u64' - The number of bytes reclaimed.
+    async fn reclaimed_size(&self, metadata: &std::fs::Metadata) -> u64 {
+        if self.dry_run {
+            return shared_size(metadata);
+        }
+        let mut hard_links = self.hard_links.lock().await;
+        let inode = (metadata.dev(), metadata.ino());
+        match hard_links.get_mut(&inode) {
+            Some((links, deleted)) => {
+                *deleted += 1;
+                if *deleted < *links {
+                    return 0;
+                }
+                hard_links.remove(&inode);
+                freed_size(metadata, 1)
+            }
+            None if metadata.nlink() > 1 => {
+                hard_links.insert(inode, (metadata.nlink(), 1));
+                0
+            }
+            None => freed_size(metadata, metadata.nlink()),
+        }
+    }
+
+    /// Counts a path that disappeared before it could be deleted, most likely because it was
+    /// removed along with a parent directory by another worker.
+    ///
+    /// # Arguments
+    ///
+    /// * path - The path that vanished.
+    /// * logger - An instance of the Logger.
+    /// * verbose - A boolean indicating whether to enable verbose logging.
+    ///
+    /// # Returns
+    ///
+    /// * 'Result<(), Box<dyn std::error::Error + Send + Sync>>' - Always Ok.
+    async fn skip_vanished(
+        &self,
+        path: &PathBuf,
+        logger: &Arc<Logger>,
+        verbose: bool,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.skipped.add(SkipReason::Vanished).await;
+        if verbose {
+            logger.log(&format!("Skipped vanished path: {:?}", path), false, true, true).await;
+        }
+        Ok(())
+    }
+
+    /// Counts an object that is kept because an operation on it took longer than '--op-timeout'.
+    ///
+    /// # Arguments
+    ///
+    /// * path - The file or directory.
+    /// * kind - The operation that got stuck.
+    /// * logger - An instance of the Logger.
+    ///
+    /// # Returns
+    ///
+    /// * 'Result<(), Box<dyn std::error::Error + Send + Sync>>' - Always Ok, the worker moves on.
+    async fn skip_stuck(&self, path: &Path, kind: OpKind, logger: &Arc<Logger>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.skipped.add(SkipReason::Stuck).await;
+        logger.log(&format!("Gave up on {} of {:?}: it took longer than the operation timeout", kind.label(), path), true, false, false).await;
+        Ok(())
+    }
+
+    /// Shuts down the deleter, performing any necessary cleanup.
+    pub async fn shutdown(&self) {
+        // Perform any necessary cleanup here.
+    }
+}
+
+/// Checks whether an operation failed because it wasn't permitted, which is how the kernel refuses
+/// to remove immutable and append-only objects.
+///
+/// # Arguments
+///
+/// * result - The result of the operation.
+///
+/// # Returns
+///
+/// * 'bool' - True if it failed with EPERM.
+fn is_not_permitted(result: &std::io::Result<()>) -> bool {
+    result.as_ref().is_err_and(|e| e.raw_os_error() == Some(libc::EPERM))
 }
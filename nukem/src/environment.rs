@@ -0,0 +1,158 @@
+//! This module describes the environment a run happens in, for the header of the log.
+//!
+//! How fast nukem deletes depends as much on the machine as on nukem itself: the kernel, the
+//! filesystems of the targets, the limit on open files and whether it runs as root. The header
+//! records them, along with the exact build, so a reported performance problem can be reproduced.
+
+use std::ffi::CStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::forecast;
+use crate::owners;
+use crate::patterns;
+
+/// The version of nukem.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// The git commit nukem was built from, or 'unknown'.
+pub const GIT_COMMIT: &str = env!("NUKEM_GIT_COMMIT");
+
+/// What '--version' prints: the version, followed by the details of the build.
+pub const LONG_VERSION: &str = concat!(
+    env!("CARGO_PKG_VERSION"),
+    "\ncommit:   ", env!("NUKEM_GIT_COMMIT"),
+    "\nbuilt:    ", env!("NUKEM_BUILD_DATE"),
+    "\ntarget:   ", env!("NUKEM_TARGET"),
+    "\nprofile:  ", env!("NUKEM_PROFILE"),
+    "\nfeatures: ", env!("NUKEM_FEATURES"),
+    "\nbackends: ", env!("NUKEM_BACKENDS"),
+);
+
+/// Describes the build.
+///
+/// # Returns
+///
+/// * `String` - E.g. 'nukem 0.26.0-alpha (commit 0132f64a1b2c, release build for x86_64-unknown-linux-gnu)'.
+pub fn build() -> String {
+    format!("nukem {} (commit {}, {} build for {})", VERSION, GIT_COMMIT, env!("NUKEM_PROFILE"), env!("NUKEM_TARGET"))
+}
+
+/// Lists the platform features and optional backends compiled into this build.
+///
+/// # Returns
+///
+/// * `Vec<&'static str>` - The features, empty if nukem was built without any of them.
+pub fn features() -> Vec<&'static str> {
+    [env!("NUKEM_FEATURES"), env!("NUKEM_BACKENDS")].iter()
+        .flat_map(|list| list.split(", "))
+        .filter(|feature| *feature != "none")
+        .collect()
+}
+
+/// Reads the name of the host.
+///
+/// # Returns
+///
+/// * `String` - The host name, or '?' if it can't be read.
+pub fn hostname() -> String {
+    let mut buffer = [0 as libc::c_char; 256];
+    if unsafe { libc::gethostname(buffer.as_mut_ptr(), buffer.len()) } != 0 {
+        return "?".into();
+    }
+    unsafe { CStr::from_ptr(buffer.as_ptr()) }.to_string_lossy().into_owned()
+}
+
+/// Describes the operating system and the kernel.
+///
+/// # Returns
+///
+/// * `String` - E.g. 'Debian GNU/Linux 12 (bookworm), Linux 6.1.0-18-amd64 x86_64'.
+pub fn system() -> String {
+    let mut uname: libc::utsname = unsafe { std::mem::zeroed() };
+    let kernel = if unsafe { libc::uname(&mut uname) } == 0 {
+        let field = |field: &[libc::c_char]| unsafe { CStr::from_ptr(field.as_ptr()) }.to_string_lossy().into_owned();
+        format!("{} {} {}", field(&uname.sysname), field(&uname.release), field(&uname.machine))
+    } else {
+        std::env::consts::OS.to_string()
+    };
+    match os_name() {
+        Some(os) => format!("{}, {}", os, kernel),
+        None => kernel,
+    }
+}
+
+/// Reads the name of the distribution from os-release.
+///
+/// # Returns
+///
+/// * `Option<String>` - The pretty name, or None if there is no os-release.
+fn os_name() -> Option<String> {
+    let text = fs::read_to_string("/etc/os-release").or_else(|_| fs::read_to_string("/usr/lib/os-release")).ok()?;
+    text.lines()
+        .find_map(|line| line.strip_prefix("PRETTY_NAME="))
+        .map(|name| name.trim_matches('"').to_string())
+}
+
+/// Describes the user nukem runs as.
+///
+/// # Returns
+///
+/// * `String` - E.g. '0 (root)'.
+pub fn effective_user() -> String {
+    let uid = unsafe { libc::geteuid() };
+    match owners::user_name(uid) {
+        Some(name) => format!("{} ({})", uid, name),
+        None => uid.to_string(),
+    }
+}
+
+/// Describes the resource limits that matter for a run.
+///
+/// # Returns
+///
+/// * `Vec<String>` - The soft and hard limits, e.g. 'open files 1024/524288'.
+pub fn rlimits() -> Vec<String> {
+    let limits = [
+        ("open files", libc::RLIMIT_NOFILE),
+        ("processes", libc::RLIMIT_NPROC),
+        ("address space", libc::RLIMIT_AS),
+        ("file size", libc::RLIMIT_FSIZE),
+    ];
+    let show = |value: libc::rlim_t| if value == libc::RLIM_INFINITY { "unlimited".to_string() } else { value.to_string() };
+    limits.iter()
+        .filter_map(|(name, resource)| {
+            let mut limit: libc::rlimit = unsafe { std::mem::zeroed() };
+            if unsafe { libc::getrlimit(*resource, &mut limit) } != 0 {
+                return None;
+            }
+            Some(format!("{} {}/{}", name, show(limit.rlim_cur), show(limit.rlim_max)))
+        })
+        .collect()
+}
+
+/// Finds the filesystems the targets are on. The part of each pattern before its first wildcard
+/// is looked up, so a pattern matching many paths is only looked up once.
+///
+/// # Arguments
+///
+/// * `targets` - The patterns supplied by the user.
+///
+/// # Returns
+///
+/// * `Vec<(PathBuf, String)>` - The mount points and filesystem types, without duplicates.
+pub fn target_filesystems(targets: &[PathBuf]) -> Vec<(PathBuf, String)> {
+    let mut filesystems: Vec<(PathBuf, String)> = Vec::new();
+    for pattern in patterns::expand_all(targets) {
+        let base: PathBuf = Path::new(&pattern)
+            .components()
+            .take_while(|component| !patterns::has_wildcard(&component.as_os_str().to_string_lossy()))
+            .collect();
+        let base = if base.as_os_str().is_empty() { PathBuf::from(".") } else { base };
+        if let Some(filesystem) = forecast::filesystem_of(&base) {
+            if !filesystems.contains(&filesystem) {
+                filesystems.push(filesystem);
+            }
+        }
+    }
+    filesystems
+}
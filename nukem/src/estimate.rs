@@ -0,0 +1,291 @@
+//! This module estimates the size of a purge from a random sample instead of crawling everything.
+//!
+//! With '--estimate', the top levels of each target are read completely until there are enough
+//! subtrees to sample from. A random sample of those subtrees ('--sample', 1% by default) is then
+//! walked to the bottom, and the totals are extrapolated to all subtrees. The spread between the
+//! sampled subtrees gives an approximate 95% confidence interval. Trees where a few subtrees hold
+//! most of the data give wide intervals, and a larger sample narrows them. Nothing is deleted.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use crate::args::SizeUnit;
+use crate::forecast::{self, Objects};
+use crate::logger::Logger;
+use crate::utility::{format_size, shared_size};
+
+/// Counts for a part of the tree.
+#[derive(Debug, Clone, Copy, Default)]
+struct Tally {
+    files: u64,
+    dirs: u64,
+    bytes: u64,
+}
+
+impl Tally {
+    /// Adds another tally to this one.
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - The tally to add.
+    fn add(&mut self, other: &Tally) {
+        self.files += other.files;
+        self.dirs += other.dirs;
+        self.bytes += other.bytes;
+    }
+}
+
+/// An extrapolated total with its confidence interval.
+#[derive(Debug, Clone, Copy)]
+struct Estimate {
+    total: f64,
+    low: f64,
+    high: f64,
+}
+
+/// The outcome of an estimation run.
+#[derive(Debug)]
+pub struct EstimateResult {
+    files: Estimate,
+    dirs: Estimate,
+    bytes: Estimate,
+    exact_dirs: u64,
+    subtrees: usize,
+    sampled: usize,
+}
+
+// Minimum number of subtrees that should end up in the sample.
+const MIN_SAMPLED: f64 = 30.0;
+// Maximum number of levels read completely while looking for enough subtrees to sample from.
+const MAX_EXACT_LEVELS: usize = 4;
+// Two-sided 95% quantile of the normal distribution.
+const Z_95: f64 = 1.96;
+
+/// Estimates the totals below a set of paths.
+///
+/// # Arguments
+///
+/// * `roots` - The target paths, with patterns already expanded.
+/// * `fraction` - The fraction of subtrees to sample, between 0 and 1.
+///
+/// # Returns
+///
+/// * `EstimateResult` - The extrapolated totals.
+pub fn estimate(roots: &[PathBuf], fraction: f64) -> EstimateResult {
+    // read the top levels completely until there are enough subtrees to sample from
+    let mut exact = Tally::default();
+    let mut frontier = Vec::new();
+    for root in roots {
+        match fs::symlink_metadata(root) {
+            Ok(metadata) if metadata.is_dir() => {
+                exact.dirs += 1;
+                let (tally, subdirs) = scan_dir(root);
+                exact.add(&tally);
+                frontier.extend(subdirs);
+            }
+            Ok(metadata) => {
+                exact.files += 1;
+                exact.bytes += shared_size(&metadata);
+            }
+            Err(_) => {}
+        }
+    }
+    let mut levels = 0;
+    while !frontier.is_empty() && (frontier.len() as f64) * fraction < MIN_SAMPLED && levels < MAX_EXACT_LEVELS {
+        let mut next = Vec::new();
+        for dir in &frontier {
+            exact.dirs += 1;
+            let (tally, subdirs) = scan_dir(dir);
+            exact.add(&tally);
+            next.extend(subdirs);
+        }
+        frontier = next;
+        levels += 1;
+    }
+
+    // walk a simple random sample of the remaining subtrees
+    let population = frontier.len();
+    let sampled = ((population as f64 * fraction).ceil() as usize).max(MIN_SAMPLED as usize).min(population);
+    let mut rng = Rng::seeded();
+    for i in 0..sampled {
+        let j = i + rng.below(population - i);
+        frontier.swap(i, j);
+    }
+    let tallies: Vec<Tally> = frontier[..sampled].iter().map(|dir| walk(dir)).collect();
+
+    EstimateResult {
+        files: extrapolate(exact.files, tallies.iter().map(|t| t.files), population),
+        dirs: extrapolate(exact.dirs, tallies.iter().map(|t| t.dirs), population),
+        bytes: extrapolate(exact.bytes, tallies.iter().map(|t| t.bytes), population),
+        exact_dirs: exact.dirs,
+        subtrees: population,
+        sampled,
+    }
+}
+
+/// Extrapolates one metric from the sampled subtrees to all of them, using the standard
+/// estimator for a simple random sample without replacement. The interval never goes below
+/// what was actually counted.
+///
+/// # Arguments
+///
+/// * `exact` - The part of the total that was counted completely.
+/// * `samples` - The values of the sampled subtrees.
+/// * `population` - The number of subtrees the sample was drawn from.
+///
+/// # Returns
+///
+/// * `Estimate` - The extrapolated total and its confidence interval.
+fn extrapolate(exact: u64, samples: impl Iterator<Item = u64>, population: usize) -> Estimate {
+    let samples: Vec<f64> = samples.map(|value| value as f64).collect();
+    let n = samples.len() as f64;
+    let exact = exact as f64;
+    if samples.is_empty() {
+        return Estimate { total: exact, low: exact, high: exact };
+    }
+    let big_n = population as f64;
+    let sum: f64 = samples.iter().sum();
+    let mean = sum / n;
+    let variance = if n > 1.0 { samples.iter().map(|value| (value - mean).powi(2)).sum::<f64>() / (n - 1.0) } else { 0.0 };
+    let standard_error = big_n * ((1.0 - n / big_n) * variance / n).sqrt();
+    let total = exact + big_n * mean;
+    Estimate {
+        total,
+        low: (total - Z_95 * standard_error).max(exact + sum),
+        high: total + Z_95 * standard_error,
+    }
+}
+
+/// Reads the direct entries of a directory.
+///
+/// # Arguments
+///
+/// * `path` - The directory to read.
+///
+/// # Returns
+///
+/// * `(Tally, Vec<PathBuf>)` - The counts of the non-directory entries and the subdirectories.
+fn scan_dir(path: &Path) -> (Tally, Vec<PathBuf>) {
+    let mut tally = Tally::default();
+    let mut subdirs = Vec::new();
+    let entries = match fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(_) => return (tally, subdirs),
+    };
+    for entry in entries.filter_map(Result::ok) {
+        match entry.file_type() {
+            Ok(file_type) if file_type.is_dir() => subdirs.push(entry.path()),
+            Ok(_) => {
+                tally.files += 1;
+                tally.bytes += entry.metadata().map_or(0, |metadata| shared_size(&metadata));
+            }
+            Err(_) => {}
+        }
+    }
+    (tally, subdirs)
+}
+
+/// Counts everything within a subtree, including the subtree's directory itself.
+///
+/// # Arguments
+///
+/// * `path` - The directory at the top of the subtree.
+///
+/// # Returns
+///
+/// * `Tally` - The counts for the whole subtree.
+fn walk(path: &Path) -> Tally {
+    let mut total = Tally::default();
+    let mut pending = vec![path.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        total.dirs += 1;
+        let (tally, subdirs) = scan_dir(&dir);
+        total.add(&tally);
+        pending.extend(subdirs);
+    }
+    total
+}
+
+/// Logs the outcome of an estimation run.
+///
+/// # Arguments
+///
+/// * `result` - The extrapolated totals.
+/// * `elapsed` - The time the estimation took.
+/// * `logger` - An instance of the Logger.
+/// * `size_unit` - The unit used to display sizes.
+pub async fn print_estimate(result: &EstimateResult, elapsed: std::time::Duration, logger: &Arc<Logger>, size_unit: SizeUnit) {
+    let count = |e: &Estimate| format!("~{:.0} (95% interval {:.0} to {:.0})", e.total, e.low, e.high);
+    let size = |e: &Estimate| format!(
+        "~{} (95% interval {} to {})",
+        format_size(e.total as u64, size_unit), format_size(e.low as u64, size_unit), format_size(e.high as u64, size_unit)
+    );
+    logger.log("----------------------------------------------------------------", false, false, false).await;
+    logger.log(&format!("Estimated directories: {}", count(&result.dirs)), false, false, false).await;
+    logger.log(&format!("Estimated files and symlinks: {}", count(&result.files)), false, false, false).await;
+    logger.log(&format!("Estimated size: {}", size(&result.bytes)), false, false, false).await;
+    logger.log(&format!(
+        "Counted {} directories completely and sampled {} of {} subtrees below them",
+        result.exact_dirs, result.sampled, result.subtrees
+    ), false, false, false).await;
+    logger.log(&format!("Estimation time: {:?}", elapsed), false, false, false).await;
+}
+
+/// Runs an estimation and reports the result.
+///
+/// # Arguments
+///
+/// * `roots` - The target paths, with patterns already expanded.
+/// * `fraction` - The fraction of subtrees to sample, between 0 and 1.
+/// * `logger` - An instance of the Logger.
+/// * `size_unit` - The unit used to display sizes.
+/// * `state_dir` - The state directory holding the filesystem calibrations, if known.
+/// * `threads` - The number of workers a run would use.
+///
+/// # Returns
+///
+/// * `Result<(), tokio::task::JoinError>` - Ok if the estimation ran to completion.
+pub async fn run(roots: Vec<PathBuf>, fraction: f64, logger: &Arc<Logger>, size_unit: SizeUnit, state_dir: Option<PathBuf>, threads: usize) -> Result<(), tokio::task::JoinError> {
+    let start = Instant::now();
+    let sampled_roots = roots.clone();
+    let result = tokio::task::spawn_blocking(move || estimate(&sampled_roots, fraction)).await?;
+    print_estimate(&result, start.elapsed(), logger, size_unit).await;
+    let expected = Objects { files: result.files.total, dirs: result.dirs.total };
+    let worst = Objects { files: result.files.high, dirs: result.dirs.high };
+    forecast::print_forecast(&roots, expected, worst, state_dir.as_deref(), threads, logger).await;
+    logger.log("--------------- Estimate Complete -------------------------------", false, false, false).await;
+    Ok(())
+}
+
+/// A small xorshift generator for picking the sample. It doesn't need to be cryptographically
+/// strong, only different from run to run.
+struct Rng(u64);
+
+impl Rng {
+    /// Seeds the generator from the clock and the process ID.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - A new generator.
+    fn seeded() -> Self {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos() as u64);
+        Self((nanos ^ ((std::process::id() as u64) << 32)) | 1)
+    }
+
+    /// Returns a number in `0..bound`.
+    ///
+    /// # Arguments
+    ///
+    /// * `bound` - The exclusive upper bound. Must not be zero.
+    ///
+    /// # Returns
+    ///
+    /// * `usize` - The random number.
+    fn below(&mut self, bound: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % bound as u64) as usize
+    }
+}
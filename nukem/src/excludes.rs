@@ -0,0 +1,470 @@
+//! This module keeps excluded paths out of a run, and narrows a run down to included paths.
+//!
+//! '--exclude' takes glob patterns for paths that must survive the run. A pattern without a '/'
+//! is matched against the basename of each object (e.g. 'keep.txt'), and any other pattern
+//! against the whole path as the crawler sees it (e.g. '**/.git/**'). A pattern ending in '/**'
+//! also covers the directory itself.
+//!
+//! '--exclude-from' reads rules from a file with gitignore semantics: blank lines and lines
+//! starting with '#' are ignored, a trailing '/' only matches directories, a leading '!' brings
+//! back something an earlier rule excluded, and the last matching rule wins. A rule without a '/'
+//! (other than a trailing one) matches basenames anywhere, and any other rule is relative to the
+//! directory holding the file. Rules from files are applied first, so '--exclude' always wins.
+//!
+//! A '.nukemignore' file in a directory the crawler reads protects objects below it the same way,
+//! so a team can keep parts of a shared tree safe without anyone changing the command line. Its
+//! rules have the semantics of '--exclude-from' rules, but only apply within that directory: a
+//! rule without a '/' matches basenames anywhere below it, and any other rule is relative to it.
+//! Like '.gitignore' files, the files are layered as the crawler descends, so the rules of a
+//! deeper file come after those of the files above it and win over them. Command-line excludes
+//! still win over every file. The '.nukemignore' file itself is always kept, so the protection
+//! outlasts the run, and a directory whose file can't be read or parsed is kept as a whole.
+//!
+//! '--include' takes patterns written like '--exclude' patterns for the only paths to delete, e.g.
+//! '--include *.o --include *.a'. Includes work like a name filter: an included directory is deleted
+//! along with everything in it, other directories are kept and searched for included objects.
+//! Excludes always take precedence, so an excluded path is kept even if it is also included, and
+//! the other filters apply to included objects as usual.
+//!
+//! The crawler never descends into an excluded directory, keeps every directory that holds an
+//! excluded object, and deletes the rest of their contents one by one. As a last line of defense,
+//! the deleter refuses to remove a directory tree that contains anything excluded.
+
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use glob::{MatchOptions, Pattern};
+use crate::args::Args;
+use crate::utility::glob_options;
+
+/// The name of the files that protect the directory they are in.
+pub const IGNORE_FILE: &str = ".nukemignore";
+
+/// What part of a path a rule is matched against.
+#[derive(Debug, Clone)]
+enum Scope {
+    /// The basename of the object.
+    Basename,
+    /// The basename of an object below this directory. Objects outside of it never match.
+    Within(PathBuf),
+    /// The path as the crawler sees it.
+    Path,
+    /// The path relative to this directory. Objects outside of it never match.
+    Relative(PathBuf),
+}
+
+/// A single exclude rule.
+#[derive(Debug, Clone)]
+struct Rule {
+    pattern: Pattern,
+    // the pattern without its trailing '/**', which matches the directory itself
+    base: Option<Pattern>,
+    scope: Scope,
+    negated: bool,
+    dir_only: bool,
+}
+
+/// The paths excluded from the run.
+#[derive(Debug, Clone, Default)]
+pub struct Excludes {
+    rules: Vec<Rule>,
+    // the rules from files come first, the command-line patterns after them
+    file_rules: usize,
+    case_sensitive: bool,
+    // relative rules are matched against absolute paths, which are resolved from here
+    current_dir: PathBuf,
+}
+
+impl Excludes {
+    /// Builds the excludes from the command-line arguments, reading any rules files.
+    ///
+    /// # Arguments
+    ///
+    /// * `args` - Command-line arguments.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, String>` - Ok with the excludes, Err with a message if a pattern is invalid
+    ///   or a rules file can't be read.
+    pub fn from_args(args: &Args) -> Result<Self, String> {
+        let current_dir = std::env::current_dir().unwrap_or_default();
+        let mut rules = Vec::new();
+        for file in &args.exclude_from {
+            let text = fs::read_to_string(file).map_err(|e| format!("Unable to read --exclude-from file '{}': {}", file.display(), e))?;
+            let dir = absolute(&current_dir, file.parent().unwrap_or(Path::new("")));
+            for (number, line) in text.lines().enumerate() {
+                if let Some(rule) = Self::parse_rule(line, &dir).map_err(|e| format!("{}:{}: {}", file.display(), number + 1, e))? {
+                    rules.push(rule);
+                }
+            }
+        }
+        let file_rules = rules.len();
+        for text in &args.exclude {
+            rules.push(Self::parse_pattern(text, "--exclude")?);
+        }
+        Ok(Self { rules, file_rules, case_sensitive: !args.ignore_case, current_dir })
+    }
+
+    /// Layers the rules of a '.nukemignore' file on top of the excludes, for everything below the
+    /// directory holding it.
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - The directory holding the file, as the crawler sees it.
+    /// * `text` - The contents of the file.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, String>` - Ok with the layered excludes, Err with a message if a rule is invalid.
+    pub fn layer(&self, dir: &Path, text: &str) -> Result<Self, String> {
+        let dir = absolute(&self.current_dir, dir);
+        let mut layer = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            if let Some(mut rule) = Self::parse_rule(line, &dir).map_err(|e| format!("{}:{}: {}", dir.join(IGNORE_FILE).display(), number + 1, e))? {
+                if let Scope::Basename = rule.scope {
+                    rule.scope = Scope::Within(dir.clone());
+                }
+                layer.push(rule);
+            }
+        }
+        // the file itself is kept last, so no rule within it can give it up
+        let own = Pattern::new(&Pattern::escape(IGNORE_FILE)).map_err(|e| e.to_string())?;
+        layer.push(Rule { pattern: own, base: None, scope: Scope::Relative(dir), negated: false, dir_only: false });
+        let mut layered = self.clone();
+        let added = layer.len();
+        layered.rules.splice(self.file_rules..self.file_rules, layer);
+        layered.file_rules += added;
+        Ok(layered)
+    }
+
+    /// Parses a pattern given on the command line.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The pattern.
+    /// * `option` - The option it was given with, for the error message.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Rule, String>` - Ok with the rule, Err with a message if the pattern is invalid.
+    fn parse_pattern(text: &str, option: &str) -> Result<Rule, String> {
+        let invalid = |e: glob::PatternError| format!("Invalid {} pattern '{}': {}", option, text, e);
+        let base = match text.strip_suffix("/**") {
+            Some(base) if !base.is_empty() => Some(Pattern::new(base).map_err(invalid)?),
+            _ => None,
+        };
+        let scope = if text.contains('/') { Scope::Path } else { Scope::Basename };
+        Ok(Rule { pattern: Pattern::new(text).map_err(invalid)?, base, scope, negated: false, dir_only: false })
+    }
+
+    /// Parses one line of a rules file.
+    ///
+    /// # Arguments
+    ///
+    /// * `line` - The line.
+    /// * `dir` - The absolute path of the directory holding the rules file.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<Rule>, String>` - Ok with the rule, or None for blank lines and comments.
+    ///   Err with a message if the pattern is invalid.
+    fn parse_rule(line: &str, dir: &Path) -> Result<Option<Rule>, String> {
+        // trailing spaces are ignored unless they are escaped with a backslash
+        let mut line = line.trim_end_matches(['\r', '\n']).to_string();
+        while line.ends_with(' ') && !line.ends_with("\\ ") {
+            line.pop();
+        }
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(None);
+        }
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line.as_str()),
+        };
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(rest) if !rest.ends_with('\\') => (true, rest),
+            _ => (false, line),
+        };
+        let anchored = line.contains('/');
+        let line = line.strip_prefix('/').unwrap_or(line);
+        if line.is_empty() {
+            return Ok(None);
+        }
+
+        // gitignore escapes characters with a backslash, glob patterns with brackets
+        let mut glob = String::new();
+        let mut chars = line.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some(escaped) => glob.push_str(&Pattern::escape(&escaped.to_string())),
+                    None => return Err("trailing backslash".into()),
+                },
+                c => glob.push(c),
+            }
+        }
+        let pattern = Pattern::new(&glob).map_err(|e| format!("invalid pattern '{}': {}", line, e))?;
+        let scope = if anchored { Scope::Relative(dir.to_path_buf()) } else { Scope::Basename };
+        Ok(Some(Rule { pattern, base: None, scope, negated, dir_only }))
+    }
+
+    /// Checks whether any pattern was given.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if there are no excludes.
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Checks whether a path is excluded. The last rule that matches decides.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path to check.
+    /// * `is_dir` - Whether the path is a directory.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if the path is excluded.
+    pub fn is_excluded(&self, path: &Path, is_dir: bool) -> bool {
+        let options = match_options(self.case_sensitive);
+        let name = path.file_name().map(|name| name.to_string_lossy());
+        let mut absolute_path = None;
+        let mut excluded = false;
+        for rule in &self.rules {
+            if rule.dir_only && !is_dir {
+                continue;
+            }
+            let matched = match rule.scope {
+                Scope::Relative(ref dir) => {
+                    let absolute_path = absolute_path.get_or_insert_with(|| absolute(&self.current_dir, path));
+                    absolute_path.strip_prefix(dir).is_ok_and(|relative| {
+                        !relative.as_os_str().is_empty() && rule.pattern.matches_path_with(relative, options)
+                    })
+                }
+                Scope::Within(ref dir) => {
+                    let absolute_path = absolute_path.get_or_insert_with(|| absolute(&self.current_dir, path));
+                    absolute_path.starts_with(dir) && absolute_path != dir
+                        && name.as_deref().is_some_and(|name| rule.pattern.matches_with(name, options))
+                }
+                _ => rule.matches(path, name.as_deref(), options),
+            };
+            if matched {
+                excluded = !rule.negated;
+            }
+        }
+        excluded
+    }
+
+    /// Looks for an excluded object within a directory tree, without following symlinks.
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - The directory at the top of the tree.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<PathBuf>` - The first excluded object found, or None if there is none.
+    pub fn find_within(&self, dir: &Path) -> Option<PathBuf> {
+        let mut pending = vec![dir.to_path_buf()];
+        while let Some(dir) = pending.pop() {
+            let entries = match fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+            for entry in entries.filter_map(Result::ok) {
+                let path = entry.path();
+                let is_dir = entry.file_type().is_ok_and(|file_type| file_type.is_dir());
+                if self.is_excluded(&path, is_dir) {
+                    return Some(path);
+                }
+                if is_dir {
+                    pending.push(path);
+                }
+            }
+        }
+        None
+    }
+}
+
+/// The only paths to delete, given with '--include'.
+#[derive(Debug, Clone, Default)]
+pub struct Includes {
+    rules: Vec<Rule>,
+    case_sensitive: bool,
+}
+
+impl Includes {
+    /// Builds the includes from the command-line arguments.
+    ///
+    /// # Arguments
+    ///
+    /// * `args` - Command-line arguments.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, String>` - Ok with the includes, Err with a message if a pattern is invalid.
+    pub fn from_args(args: &Args) -> Result<Self, String> {
+        let rules = args.include.iter().map(|text| Excludes::parse_pattern(text, "--include")).collect::<Result<_, _>>()?;
+        Ok(Self { rules, case_sensitive: !args.ignore_case })
+    }
+
+    /// Checks whether any pattern was given.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if there are no includes.
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Checks whether a path matches any of the includes.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path to check.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if the path is included.
+    pub fn matches(&self, path: &Path) -> bool {
+        let options = match_options(self.case_sensitive);
+        let name = path.file_name().map(|name| name.to_string_lossy());
+        self.rules.iter().any(|rule| rule.matches(path, name.as_deref(), options))
+    }
+}
+
+impl Rule {
+    /// Matches a rule given on the command line against a path.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path as the crawler sees it.
+    /// * `name` - The basename of the path.
+    /// * `options` - The match options.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if the rule matches. Rules confined to a directory never do.
+    fn matches(&self, path: &Path, name: Option<&str>, options: MatchOptions) -> bool {
+        match self.scope {
+            Scope::Basename => name.is_some_and(|name| self.pattern.matches_with(name, options)),
+            Scope::Path => {
+                self.pattern.matches_path_with(path, options)
+                    || self.base.as_ref().is_some_and(|base| base.matches_path_with(path, options))
+            }
+            Scope::Relative(_) | Scope::Within(_) => false,
+        }
+    }
+}
+
+/// Builds the options rules are matched with.
+///
+/// # Arguments
+///
+/// * `case_sensitive` - Whether to match case-sensitively.
+///
+/// # Returns
+///
+/// * `MatchOptions` - The options. Unlike the target patterns, '*' never matches across a '/'.
+fn match_options(case_sensitive: bool) -> MatchOptions {
+    MatchOptions { require_literal_separator: true, require_literal_leading_dot: false, ..glob_options(!case_sensitive) }
+}
+
+/// Makes a path absolute and removes '.' and '..' components, without resolving symlinks.
+///
+/// # Arguments
+///
+/// * `current_dir` - The directory relative paths are resolved from.
+/// * `path` - The path.
+///
+/// # Returns
+///
+/// * `PathBuf` - The absolute path.
+fn absolute(current_dir: &Path, path: &Path) -> PathBuf {
+    let mut absolute = PathBuf::new();
+    for component in current_dir.join(path).components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                absolute.pop();
+            }
+            component => absolute.push(component),
+        }
+    }
+    absolute
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use super::{Excludes, IGNORE_FILE};
+
+    // excludes with the rules of a '--exclude-from' file in /srv, then the '--exclude' patterns
+    fn excludes(file: &str, patterns: &[&str]) -> Excludes {
+        let mut rules: Vec<_> = file.lines().filter_map(|line| Excludes::parse_rule(line, Path::new("/srv")).unwrap()).collect();
+        let file_rules = rules.len();
+        rules.extend(patterns.iter().map(|text| Excludes::parse_pattern(text, "--exclude").unwrap()));
+        Excludes { rules, file_rules, case_sensitive: true, current_dir: PathBuf::from("/") }
+    }
+
+    fn excluded(excludes: &Excludes, path: &str) -> bool {
+        excludes.is_excluded(Path::new(path), false)
+    }
+
+    #[test]
+    fn lets_the_last_matching_rule_decide() {
+        let keep_one = excludes("*.log\n!keep.log", &[]);
+        assert!(excluded(&keep_one, "/srv/app/a.log"));
+        assert!(!excluded(&keep_one, "/srv/app/keep.log"));
+        assert!(!excluded(&keep_one, "/srv/app/a.txt"));
+
+        // a negation before the rule it means to override is overridden itself
+        let too_early = excludes("!keep.log\n*.log", &[]);
+        assert!(excluded(&too_early, "/srv/app/keep.log"));
+
+        let again = excludes("*.log\n!keep.log\nkeep.*", &[]);
+        assert!(excluded(&again, "/srv/app/keep.log"));
+    }
+
+    #[test]
+    fn negates_only_what_the_rule_matches() {
+        let excludes = excludes("# comment\n\ncache/\n!/cache/\n/data/*.db\n!/data/keep.db", &[]);
+        assert!(excludes.is_excluded(Path::new("/srv/app/cache"), true));
+        assert!(!excludes.is_excluded(Path::new("/srv/cache"), true));
+        assert!(!excluded(&excludes, "/srv/app/cache"));
+        assert!(excluded(&excludes, "/srv/data/old.db"));
+        assert!(!excluded(&excludes, "/srv/data/keep.db"));
+        assert!(!excluded(&excludes, "/srv/app/data/old.db"));
+    }
+
+    #[test]
+    fn lets_command_line_excludes_win_over_negations() {
+        let excludes = excludes("!keep.log", &["*.log"]);
+        assert!(excluded(&excludes, "/srv/keep.log"));
+    }
+
+    #[test]
+    fn layers_deeper_ignore_files_after_the_ones_above() {
+        let top = excludes("", &[]).layer(Path::new("/srv"), "*.tmp").unwrap();
+        let deeper = top.layer(Path::new("/srv/app"), "!*.tmp").unwrap();
+        assert!(excluded(&deeper, "/srv/a.tmp"));
+        assert!(!excluded(&deeper, "/srv/app/a.tmp"));
+        assert!(!excluded(&deeper, "/srv/app/lib/a.tmp"));
+
+        let reversed = excludes("", &[]).layer(Path::new("/srv"), "!*.tmp").unwrap().layer(Path::new("/srv/app"), "*.tmp").unwrap();
+        assert!(!excluded(&reversed, "/srv/a.tmp"));
+        assert!(excluded(&reversed, "/srv/app/a.tmp"));
+
+        // a command-line exclude still wins over the negation in the deeper file
+        let command_line = excludes("", &["*.tmp"]).layer(Path::new("/srv/app"), "!*.tmp").unwrap();
+        assert!(excluded(&command_line, "/srv/app/a.tmp"));
+    }
+
+    #[test]
+    fn never_gives_up_the_ignore_file_itself() {
+        let excludes = excludes("", &[]).layer(Path::new("/srv"), &format!("!{}\n!*", IGNORE_FILE)).unwrap();
+        assert!(excluded(&excludes, &format!("/srv/{}", IGNORE_FILE)));
+        assert!(!excluded(&excludes, "/srv/other"));
+    }
+}
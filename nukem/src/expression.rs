@@ -0,0 +1,575 @@
+//! This module parses and evaluates '--where' expressions, a small find-style language for
+//! retention rules that the other filters can't express on their own, such as
+//! `size > 100M && mtime > 90d && name ~ "*.bak"`.
+//!
+//! An expression combines comparisons with `&&`, `||`, `!` and parentheses; `&&` binds tighter
+//! than `||`. Each comparison is a field, an operator and a value:
+//!
+//! * `size`, `nlink`, `uid` and `gid` compare numbers with `<`, `<=`, `>`, `>=`, `==` and `!=`.
+//!   Sizes take the units of '--min-size' (e.g. 100M).
+//! * `mtime`, `atime` and `ctime` compare the age of a file with a duration such as 90d or 12h,
+//!   so `mtime > 90d` means the file was last modified more than 90 days ago.
+//! * `user` and `group` compare the owner with `==` and `!=`, by name.
+//! * `name` is the basename and `path` the full path. Both compare as text with `==` and `!=`,
+//!   match a wildcard pattern with `~` and `!~` and a regular expression with `=~`.
+//! * `ext` compares the extension the way '--ext' does, with `==` and `!=`.
+//! * `type` is `f` (regular file) or `l` (symlink), with `==` and `!=`.
+//!
+//! Values with spaces or operator characters are quoted with single or double quotes.
+//! '--ignore-case' applies to the text comparisons. The expression is parsed once, and evaluated
+//! once for every file, symlink and directory the crawler finds, from the metadata it has already
+//! read, in dry runs and real runs alike. A directory is only deleted when everything within it
+//! matched and it matches itself, by its metadata from before it was crawled, so `mtime > 90d`
+//! keeps a directory that was modified recently and `size > 1M` keeps any ordinary directory.
+
+use std::fs::Metadata;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use glob::{MatchOptions, Pattern};
+use crate::args::{parse_duration, parse_size};
+use crate::filters::{lookup_group, lookup_user};
+use crate::regex::Regex;
+use crate::utility::{glob_options, has_extension, name_regex};
+
+/// A parsed '--where' expression.
+#[derive(Debug, Clone)]
+pub struct Expression {
+    root: Node,
+    // the time ages are measured from, fixed when the expression is parsed
+    now: i64,
+    options: MatchOptions,
+    ignore_case: bool,
+}
+
+/// A node of the expression tree.
+#[derive(Debug, Clone)]
+enum Node {
+    And(Box<Node>, Box<Node>),
+    Or(Box<Node>, Box<Node>),
+    Not(Box<Node>),
+    Compare(Comparison),
+}
+
+/// A single comparison.
+#[derive(Debug, Clone)]
+enum Comparison {
+    Number { field: NumberField, op: Op, value: u64 },
+    Age { field: TimeField, op: Op, seconds: i64 },
+    Text { field: TextField, equal: bool, value: String },
+    Glob { field: TextField, matching: bool, pattern: Pattern },
+    Regex { field: TextField, regex: Regex },
+    Ext { equal: bool, ext: String },
+    Type { equal: bool, symlink: bool },
+}
+
+#[derive(Debug, Clone, Copy)]
+enum NumberField {
+    Size,
+    Nlink,
+    Uid,
+    Gid,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum TimeField {
+    Mtime,
+    Atime,
+    Ctime,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum TextField {
+    Name,
+    Path,
+}
+
+/// A comparison operator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+    Glob,
+    NotGlob,
+    Regex,
+}
+
+impl Op {
+    /// Compares two numbers.
+    ///
+    /// # Arguments
+    ///
+    /// * `left` - The value of the field.
+    /// * `right` - The value it is compared with.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if the comparison holds.
+    fn compare<T: PartialOrd>(self, left: T, right: T) -> bool {
+        match self {
+            Op::Lt => left < right,
+            Op::Le => left <= right,
+            Op::Gt => left > right,
+            Op::Ge => left >= right,
+            Op::Eq => left == right,
+            Op::Ne => left != right,
+            Op::Glob | Op::NotGlob | Op::Regex => false,
+        }
+    }
+
+    /// Checks whether the operator orders numbers.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True for '<', '<=', '>', '>=', '==' and '!='.
+    fn is_numeric(self) -> bool {
+        !matches!(self, Op::Glob | Op::NotGlob | Op::Regex)
+    }
+}
+
+/// A token of the expression.
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Quoted(String),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+    Op(Op),
+}
+
+impl Expression {
+    /// Parses an expression.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The expression given to '--where'.
+    /// * `ignore_case` - Whether '--ignore-case' was specified.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, String>` - Ok with the expression, Err with a message if it is invalid.
+    pub fn parse(text: &str, ignore_case: bool) -> Result<Self, String> {
+        let tokens = tokenize(text)?;
+        let mut parser = Parser { tokens, pos: 0, ignore_case };
+        let root = parser.parse_or()?;
+        if let Some((token, at)) = parser.tokens.get(parser.pos) {
+            return Err(format!("unexpected {} at position {}", describe(token), at + 1));
+        }
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() as i64;
+        Ok(Self { root, now, options: glob_options(ignore_case), ignore_case })
+    }
+
+    /// Evaluates the expression for a file, symlink or directory.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the object.
+    /// * `metadata` - The metadata of the object, read without following symlinks.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if the object matches.
+    pub fn matches(&self, path: &Path, metadata: &Metadata) -> bool {
+        self.evaluate(&self.root, path, metadata)
+    }
+
+    /// Evaluates a node of the expression.
+    ///
+    /// # Arguments
+    ///
+    /// * `node` - The node.
+    /// * `path` - The path of the object.
+    /// * `metadata` - The metadata of the object.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if the node holds for the object.
+    fn evaluate(&self, node: &Node, path: &Path, metadata: &Metadata) -> bool {
+        match node {
+            Node::And(left, right) => self.evaluate(left, path, metadata) && self.evaluate(right, path, metadata),
+            Node::Or(left, right) => self.evaluate(left, path, metadata) || self.evaluate(right, path, metadata),
+            Node::Not(inner) => !self.evaluate(inner, path, metadata),
+            Node::Compare(comparison) => self.compare(comparison, path, metadata),
+        }
+    }
+
+    /// Evaluates a comparison.
+    ///
+    /// # Arguments
+    ///
+    /// * `comparison` - The comparison.
+    /// * `path` - The path of the object.
+    /// * `metadata` - The metadata of the object.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if the comparison holds for the object.
+    fn compare(&self, comparison: &Comparison, path: &Path, metadata: &Metadata) -> bool {
+        match comparison {
+            Comparison::Number { field, op, value } => {
+                let actual = match field {
+                    NumberField::Size => metadata.len(),
+                    NumberField::Nlink => metadata.nlink(),
+                    NumberField::Uid => metadata.uid() as u64,
+                    NumberField::Gid => metadata.gid() as u64,
+                };
+                op.compare(actual, *value)
+            }
+            Comparison::Age { field, op, seconds } => {
+                let time = match field {
+                    TimeField::Mtime => metadata.mtime(),
+                    TimeField::Atime => metadata.atime(),
+                    TimeField::Ctime => metadata.ctime(),
+                };
+                op.compare(self.now - time, *seconds)
+            }
+            Comparison::Text { field, equal, value } => {
+                let text = field_text(*field, path);
+                let same = if self.ignore_case { text.to_lowercase() == value.to_lowercase() } else { text == *value };
+                same == *equal
+            }
+            Comparison::Glob { field, matching, pattern } => pattern.matches_with(&field_text(*field, path), self.options) == *matching,
+            Comparison::Regex { field, regex } => regex.is_match(&field_text(*field, path)),
+            Comparison::Ext { equal, ext } => {
+                let name = field_text(TextField::Name, path);
+                has_extension(&name, ext, self.ignore_case) == *equal
+            }
+            Comparison::Type { equal, symlink } => {
+                let actual = if *symlink { metadata.file_type().is_symlink() } else { metadata.is_file() };
+                actual == *equal
+            }
+        }
+    }
+}
+
+/// Retrieves the text a comparison looks at.
+///
+/// # Arguments
+///
+/// * `field` - The field.
+/// * `path` - The path of the object.
+///
+/// # Returns
+///
+/// * `String` - The basename or the full path.
+fn field_text(field: TextField, path: &Path) -> String {
+    match field {
+        TextField::Name => path.file_name().map_or_else(|| path.to_string_lossy(), |name| name.to_string_lossy()).into_owned(),
+        TextField::Path => path.to_string_lossy().into_owned(),
+    }
+}
+
+/// Describes a token for an error message.
+///
+/// # Arguments
+///
+/// * `token` - The token.
+///
+/// # Returns
+///
+/// * `String` - What the token is.
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Word(word) => format!("'{}'", word),
+        Token::Quoted(text) => format!("\"{}\"", text),
+        Token::And => "'&&'".into(),
+        Token::Or => "'||'".into(),
+        Token::Not => "'!'".into(),
+        Token::Open => "'('".into(),
+        Token::Close => "')'".into(),
+        Token::Op(_) => "an operator".into(),
+    }
+}
+
+/// Splits an expression into tokens.
+///
+/// # Arguments
+///
+/// * `text` - The expression.
+///
+/// # Returns
+///
+/// * `Result<Vec<(Token, usize)>, String>` - Ok with the tokens and their positions, Err with a
+///   message if a quote isn't closed or a character can't start a token.
+fn tokenize(text: &str) -> Result<Vec<(Token, usize)>, String> {
+    const OPERATORS: [(&str, Option<Op>); 14] = [
+        ("&&", None), ("||", None), ("<=", Some(Op::Le)), (">=", Some(Op::Ge)), ("==", Some(Op::Eq)),
+        ("!=", Some(Op::Ne)), ("!~", Some(Op::NotGlob)), ("=~", Some(Op::Regex)), ("<", Some(Op::Lt)),
+        (">", Some(Op::Gt)), ("~", Some(Op::Glob)), ("=", Some(Op::Eq)), ("!", None), ("(", None),
+    ];
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    'next: while pos < chars.len() {
+        let c = chars[pos];
+        if c.is_whitespace() {
+            pos += 1;
+            continue;
+        }
+        if c == ')' {
+            tokens.push((Token::Close, pos));
+            pos += 1;
+            continue;
+        }
+        if c == '"' || c == '\'' {
+            let start = pos;
+            let mut value = String::new();
+            pos += 1;
+            while pos < chars.len() && chars[pos] != c {
+                // a backslash keeps the quote or backslash after it
+                if chars[pos] == '\\' && matches!(chars.get(pos + 1), Some(&next) if next == c || next == '\\') {
+                    pos += 1;
+                }
+                value.push(chars[pos]);
+                pos += 1;
+            }
+            if pos == chars.len() {
+                return Err(format!("unclosed quote at position {}", start + 1));
+            }
+            tokens.push((Token::Quoted(value), start));
+            pos += 1;
+            continue;
+        }
+        for (operator, op) in OPERATORS.iter() {
+            let operator: Vec<char> = operator.chars().collect();
+            if chars[pos..].starts_with(&operator) {
+                let token = match (operator.as_slice(), op) {
+                    (_, Some(op)) => Token::Op(*op),
+                    (['&', '&'], None) => Token::And,
+                    (['|', '|'], None) => Token::Or,
+                    (['!'], None) => Token::Not,
+                    _ => Token::Open,
+                };
+                tokens.push((token, pos));
+                pos += operator.len();
+                continue 'next;
+            }
+        }
+        let start = pos;
+        while pos < chars.len() && !chars[pos].is_whitespace() && !"&|!<>=~()\"'".contains(chars[pos]) {
+            pos += 1;
+        }
+        if pos == start {
+            return Err(format!("unexpected '{}' at position {}", c, pos + 1));
+        }
+        tokens.push((Token::Word(chars[start..pos].iter().collect()), start));
+    }
+    Ok(tokens)
+}
+
+/// A recursive descent parser over the tokens of an expression.
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+    ignore_case: bool,
+}
+
+impl Parser {
+    /// Parses alternatives separated by '||'.
+    fn parse_or(&mut self) -> Result<Node, String> {
+        let mut node = self.parse_and()?;
+        while self.eat(&Token::Or) {
+            node = Node::Or(Box::new(node), Box::new(self.parse_and()?));
+        }
+        Ok(node)
+    }
+
+    /// Parses conditions separated by '&&'.
+    fn parse_and(&mut self) -> Result<Node, String> {
+        let mut node = self.parse_unary()?;
+        while self.eat(&Token::And) {
+            node = Node::And(Box::new(node), Box::new(self.parse_unary()?));
+        }
+        Ok(node)
+    }
+
+    /// Parses a negation, a parenthesized expression or a comparison.
+    fn parse_unary(&mut self) -> Result<Node, String> {
+        if self.eat(&Token::Not) {
+            return Ok(Node::Not(Box::new(self.parse_unary()?)));
+        }
+        if self.eat(&Token::Open) {
+            let node = self.parse_or()?;
+            if !self.eat(&Token::Close) {
+                return Err(self.expected("')'"));
+            }
+            return Ok(node);
+        }
+        self.parse_comparison()
+    }
+
+    /// Parses a comparison of a field with a value.
+    fn parse_comparison(&mut self) -> Result<Node, String> {
+        let (field, at) = match self.tokens.get(self.pos) {
+            Some((Token::Word(field), at)) => (field.clone(), *at),
+            _ => return Err(self.expected("a field such as size, mtime or name")),
+        };
+        self.pos += 1;
+        let op = match self.tokens.get(self.pos) {
+            Some((Token::Op(op), _)) => *op,
+            _ => return Err(self.expected(&format!("an operator after '{}'", field))),
+        };
+        self.pos += 1;
+        let value = match self.tokens.get(self.pos) {
+            Some((Token::Word(value), _)) | Some((Token::Quoted(value), _)) => value.clone(),
+            _ => return Err(self.expected(&format!("a value to compare '{}' with", field))),
+        };
+        self.pos += 1;
+
+        let invalid_op = || format!("'{}' can't be compared with that operator (at position {})", field, at + 1);
+        let equality = |op: Op| match op {
+            Op::Eq => Ok(true),
+            Op::Ne => Ok(false),
+            _ => Err(invalid_op()),
+        };
+        let comparison = match field.as_str() {
+            "size" | "nlink" | "uid" | "gid" => {
+                if !op.is_numeric() {
+                    return Err(invalid_op());
+                }
+                let (field, value) = match field.as_str() {
+                    "size" => (NumberField::Size, parse_size(&value)?),
+                    "nlink" => (NumberField::Nlink, parse_number(&value)?),
+                    "uid" => (NumberField::Uid, parse_number(&value)?),
+                    _ => (NumberField::Gid, parse_number(&value)?),
+                };
+                Comparison::Number { field, op, value }
+            }
+            "mtime" | "atime" | "ctime" => {
+                if !op.is_numeric() {
+                    return Err(invalid_op());
+                }
+                let field = match field.as_str() {
+                    "mtime" => TimeField::Mtime,
+                    "atime" => TimeField::Atime,
+                    _ => TimeField::Ctime,
+                };
+                Comparison::Age { field, op, seconds: parse_duration(&value)?.as_secs() as i64 }
+            }
+            "user" => Comparison::Number { field: NumberField::Uid, op: if equality(op)? { Op::Eq } else { Op::Ne }, value: lookup_user(&value)? as u64 },
+            "group" => Comparison::Number { field: NumberField::Gid, op: if equality(op)? { Op::Eq } else { Op::Ne }, value: lookup_group(&value)? as u64 },
+            "name" | "path" => {
+                let field = if field == "name" { TextField::Name } else { TextField::Path };
+                match op {
+                    Op::Eq | Op::Ne => Comparison::Text { field, equal: op == Op::Eq, value },
+                    Op::Glob | Op::NotGlob => {
+                        let pattern = Pattern::new(&value).map_err(|e| format!("invalid pattern '{}': {}", value, e))?;
+                        Comparison::Glob { field, matching: op == Op::Glob, pattern }
+                    }
+                    Op::Regex => Comparison::Regex { field, regex: name_regex(&value, self.ignore_case).map_err(|e| format!("invalid regular expression '{}': {}", value, e))? },
+                    _ => return Err(invalid_op()),
+                }
+            }
+            "ext" => Comparison::Ext { equal: equality(op)?, ext: value.trim_start_matches('.').to_string() },
+            "type" => {
+                let symlink = match value.as_str() {
+                    "f" => false,
+                    "l" => true,
+                    _ => return Err(format!("invalid type '{}': expected f (file) or l (symlink)", value)),
+                };
+                Comparison::Type { equal: equality(op)?, symlink }
+            }
+            _ => return Err(format!("unknown field '{}' at position {}", field, at + 1)),
+        };
+        Ok(Node::Compare(comparison))
+    }
+
+    /// Moves past the next token if it is the one given.
+    fn eat(&mut self, token: &Token) -> bool {
+        if self.tokens.get(self.pos).is_some_and(|(next, _)| next == token) {
+            self.pos += 1;
+            return true;
+        }
+        false
+    }
+
+    /// Builds the message for a missing token.
+    fn expected(&self, what: &str) -> String {
+        match self.tokens.get(self.pos) {
+            Some((token, at)) => format!("expected {} at position {}, found {}", what, at + 1, describe(token)),
+            None => format!("expected {} at the end", what),
+        }
+    }
+}
+
+/// Parses a plain number.
+///
+/// # Arguments
+///
+/// * `value` - The number.
+///
+/// # Returns
+///
+/// * `Result<u64, String>` - Ok with the number, Err with a message if it isn't one.
+fn parse_number(value: &str) -> Result<u64, String> {
+    value.parse().map_err(|_| format!("invalid number '{}'", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+    use super::Expression;
+
+    fn error(text: &str) -> String {
+        Expression::parse(text, false).unwrap_err()
+    }
+
+    // a fresh 2048 byte file to evaluate expressions against
+    fn sample(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("nukem-expression-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        fs::write(&path, [0u8; 2048]).unwrap();
+        path
+    }
+
+    fn matches(text: &str, path: &PathBuf) -> bool {
+        Expression::parse(text, false).unwrap().matches(path, &fs::symlink_metadata(path).unwrap())
+    }
+
+    #[test]
+    fn reports_where_the_expression_is_invalid() {
+        assert_eq!(error("colour == red"), "unknown field 'colour' at position 1");
+        assert_eq!(error("size > 1K && name ~ *.log size"), "unexpected 'size' at position 27");
+        assert_eq!(error("size"), "expected an operator after 'size' at the end");
+        assert_eq!(error("size >"), "expected a value to compare 'size' with at the end");
+        assert_eq!(error("(size > 1K"), "expected ')' at the end");
+        assert_eq!(error("size > 1K)"), "unexpected ')' at position 10");
+        assert_eq!(error("&& size > 1K"), "expected a field such as size, mtime or name at position 1, found '&&'");
+        assert_eq!(error("name == \"a.log"), "unclosed quote at position 9");
+    }
+
+    #[test]
+    fn rejects_operators_and_values_a_field_cant_take() {
+        assert_eq!(error("name > a"), "'name' can't be compared with that operator (at position 1)");
+        assert_eq!(error("size ~ 1K"), "'size' can't be compared with that operator (at position 1)");
+        assert_eq!(error("ext < log"), "'ext' can't be compared with that operator (at position 1)");
+        assert_eq!(error("type == d"), "invalid type 'd': expected f (file) or l (symlink)");
+        assert_eq!(error("nlink > many"), "invalid number 'many'");
+        assert!(error("name =~ \"a**\"").starts_with("invalid regular expression 'a**'"));
+        assert!(Expression::parse("mtime > soon", false).is_err());
+        assert!(Expression::parse("size > 10Q", false).is_err());
+    }
+
+    #[test]
+    fn evaluates_comparisons_and_precedence() {
+        let path = sample("report.log");
+        assert!(matches("size == 2K && type == f", &path));
+        assert!(matches("size > 1K && name ~ '*.log'", &path));
+        assert!(!matches("size > 1K && name !~ '*.log'", &path));
+        assert!(matches("name == tmp || size > 1K && ext == .log", &path));
+        assert!(!matches("(name == tmp || size > 1K) && ext == bak", &path));
+        assert!(matches("!(mtime > 1d) && name =~ '^report\\.'", &path));
+        assert!(!matches("!mtime < 1d", &path));
+        fs::remove_file(&path).unwrap();
+        let _ = fs::remove_dir(path.parent().unwrap());
+    }
+}
@@ -0,0 +1,302 @@
+//! This module keeps the failures of a run in a form that can be consumed by other tools.
+//!
+//! Every failure is numbered in the order it happened and recorded with the operation that
+//! failed, the OS error code and its symbolic name (such as 'EACCES'), and the message. The log
+//! shows paths longer than a screen line shortened to their start and end, followed by the number
+//! of the failure, and the full paths are written to `<state dir>/runs/<run id>.failures`, one
+//! tab-separated line per failure, so the shortened form can be looked up by its number:
+//!
+//! ```text
+//! #index  operation  errno  name  message  path
+//! ```
+//!
+//! Tabs, newlines and backslashes in the message and the path are escaped with a backslash. The
+//! list of paths that '--retry-failed' reads is kept as before, in `<run id>.failed`.
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use crate::slowops::OpKind;
+
+// Paths longer than this many characters are shortened in the log.
+const MAX_SHOWN: usize = 160;
+// How many characters of the start and of the end of a shortened path are shown.
+const SHOWN_HEAD: usize = 60;
+const SHOWN_TAIL: usize = 80;
+
+/// An I/O error together with the operation that ran into it.
+#[derive(Debug)]
+pub struct OpError {
+    pub kind: OpKind,
+    pub source: io::Error,
+}
+
+impl OpError {
+    /// Wraps an I/O error for a failed operation.
+    ///
+    /// # Arguments
+    ///
+    /// * `kind` - The operation that failed.
+    /// * `source` - The error it returned.
+    ///
+    /// # Returns
+    ///
+    /// * `Box<Self>` - The error, boxed the way the deleter returns its errors.
+    pub fn boxed(kind: OpKind, source: io::Error) -> Box<Self> {
+        Box::new(Self { kind, source })
+    }
+}
+
+impl fmt::Display for OpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} failed: {}", self.kind.label(), self.source)
+    }
+}
+
+impl Error for OpError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+/// Finds the I/O error behind an error returned by the deleter.
+///
+/// # Arguments
+///
+/// * `e` - The error.
+///
+/// # Returns
+///
+/// * `Option<&io::Error>` - The I/O error, or None if it wasn't one.
+pub fn io_error<'a>(e: &'a (dyn Error + Send + Sync + 'static)) -> Option<&'a io::Error> {
+    match e.downcast_ref::<OpError>() {
+        Some(op_error) => Some(&op_error.source),
+        None => e.downcast_ref::<io::Error>(),
+    }
+}
+
+/// A path that could not be processed.
+#[derive(Debug, Clone)]
+pub struct Failure {
+    pub index: u64,
+    pub path: PathBuf,
+    pub operation: Option<OpKind>,
+    pub errno: Option<i32>,
+    pub message: String,
+}
+
+impl Failure {
+    /// Describes the failure for the log, with a long path shortened.
+    ///
+    /// # Returns
+    ///
+    /// * `String` - E.g. 'unlink /data/x: Permission denied (EACCES, errno 13) [failure #3]'.
+    pub fn describe(&self) -> String {
+        let code = match self.errno {
+            Some(errno) => format!(" ({}, errno {})", errno_name(errno).unwrap_or("unknown"), errno),
+            None => String::new(),
+        };
+        format!(
+            "{} {}: {}{} [failure #{}]",
+            self.operation.map_or("process", |kind| kind.label()), short_path(&self.path), self.message, code, self.index
+        )
+    }
+
+    /// Formats the failure as a line of the failures file, without the trailing newline.
+    ///
+    /// # Returns
+    ///
+    /// * `String` - The tab-separated fields of the failure.
+    pub fn to_line(&self) -> String {
+        [
+            self.index.to_string(),
+            self.operation.map_or("process", |kind| kind.label()).to_string(),
+            self.errno.map_or_else(|| "-".to_string(), |errno| errno.to_string()),
+            self.errno.and_then(errno_name).unwrap_or("-").to_string(),
+            escape(&self.message),
+            escape(&self.path.to_string_lossy()),
+        ].join("\t")
+    }
+}
+
+/// Shared list of the failures of a run. Cloning it shares the underlying list, so every worker
+/// records into the same one.
+#[derive(Clone, Default)]
+pub struct Failures {
+    failures: Arc<Mutex<Vec<Failure>>>,
+}
+
+impl Failures {
+    /// Creates an empty list.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - A new instance of Failures.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a path that could not be processed.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path.
+    /// * `e` - The error processing it returned.
+    ///
+    /// # Returns
+    ///
+    /// * `Failure` - The recorded failure, with its number.
+    pub async fn record(&self, path: &Path, e: &(dyn Error + Send + Sync + 'static)) -> Failure {
+        let (operation, message) = match e.downcast_ref::<OpError>() {
+            Some(op_error) => (Some(op_error.kind), message(&op_error.source)),
+            None => (None, io_error(e).map_or_else(|| e.to_string(), message)),
+        };
+        self.push(path, operation, io_error(e).and_then(io::Error::raw_os_error), message).await
+    }
+
+    /// Records a directory that could not be removed by '--prune-empty-dirs'.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The directory.
+    /// * `kind` - The operation that failed.
+    /// * `e` - The error it returned.
+    ///
+    /// # Returns
+    ///
+    /// * `Failure` - The recorded failure, with its number.
+    pub async fn record_io(&self, path: &Path, kind: OpKind, e: &io::Error) -> Failure {
+        self.push(path, Some(kind), e.raw_os_error(), message(e)).await
+    }
+
+    /// Retrieves the failures.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<Failure>` - The failures in the order they happened.
+    pub async fn all(&self) -> Vec<Failure> {
+        self.failures.lock().await.clone()
+    }
+
+    /// Retrieves the paths that failed.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<PathBuf>` - The failed paths in path order, without duplicates.
+    pub async fn paths(&self) -> Vec<PathBuf> {
+        let mut paths: Vec<PathBuf> = self.failures.lock().await.iter().map(|failure| failure.path.clone()).collect();
+        paths.sort();
+        paths.dedup();
+        paths
+    }
+
+    /// Numbers a failure and adds it to the list.
+    async fn push(&self, path: &Path, operation: Option<OpKind>, errno: Option<i32>, message: String) -> Failure {
+        let mut failures = self.failures.lock().await;
+        let failure = Failure { index: failures.len() as u64 + 1, path: path.to_path_buf(), operation, errno, message };
+        failures.push(failure.clone());
+        failure
+    }
+}
+
+/// Shortens a path for the log if it is too long to read, keeping its start and its end.
+///
+/// # Arguments
+///
+/// * `path` - The path.
+///
+/// # Returns
+///
+/// * `String` - The path, or e.g. '/data/a/b…(312 more)…/y/z.log' if it was shortened.
+pub fn short_path(path: &Path) -> String {
+    let text = path.to_string_lossy();
+    let chars: Vec<char> = text.chars().collect();
+    if chars.len() <= MAX_SHOWN {
+        return text.into_owned();
+    }
+    let head: String = chars[..SHOWN_HEAD].iter().collect();
+    let tail: String = chars[chars.len() - SHOWN_TAIL..].iter().collect();
+    format!("{}…({} more)…{}", head, chars.len() - SHOWN_HEAD - SHOWN_TAIL, tail)
+}
+
+/// Looks up the symbolic name of an OS error code.
+///
+/// # Arguments
+///
+/// * `errno` - The error code.
+///
+/// # Returns
+///
+/// * `Option<&'static str>` - The name, such as 'EACCES', or None for a code not listed here.
+pub fn errno_name(errno: i32) -> Option<&'static str> {
+    let name = match errno {
+        libc::EPERM => "EPERM",
+        libc::ENOENT => "ENOENT",
+        libc::EINTR => "EINTR",
+        libc::EIO => "EIO",
+        libc::ENXIO => "ENXIO",
+        libc::EBADF => "EBADF",
+        libc::EAGAIN => "EAGAIN",
+        libc::ENOMEM => "ENOMEM",
+        libc::EACCES => "EACCES",
+        libc::EFAULT => "EFAULT",
+        libc::EBUSY => "EBUSY",
+        libc::EEXIST => "EEXIST",
+        libc::EXDEV => "EXDEV",
+        libc::ENODEV => "ENODEV",
+        libc::ENOTDIR => "ENOTDIR",
+        libc::EISDIR => "EISDIR",
+        libc::EINVAL => "EINVAL",
+        libc::ENFILE => "ENFILE",
+        libc::EMFILE => "EMFILE",
+        libc::ETXTBSY => "ETXTBSY",
+        libc::EFBIG => "EFBIG",
+        libc::ENOSPC => "ENOSPC",
+        libc::EROFS => "EROFS",
+        libc::EMLINK => "EMLINK",
+        libc::ENAMETOOLONG => "ENAMETOOLONG",
+        libc::ENOTEMPTY => "ENOTEMPTY",
+        libc::ELOOP => "ELOOP",
+        libc::EOVERFLOW => "EOVERFLOW",
+        libc::EOPNOTSUPP => "EOPNOTSUPP",
+        libc::ETIMEDOUT => "ETIMEDOUT",
+        libc::ESTALE => "ESTALE",
+        libc::EDQUOT => "EDQUOT",
+        libc::ECANCELED => "ECANCELED",
+        _ => return None,
+    };
+    Some(name)
+}
+
+/// Describes an I/O error without the 'os error' code, which is given separately.
+///
+/// # Arguments
+///
+/// * `e` - The error.
+///
+/// # Returns
+///
+/// * `String` - The message, such as 'Permission denied'.
+fn message(e: &io::Error) -> String {
+    let text = e.to_string();
+    match e.raw_os_error() {
+        Some(errno) => text.trim_end_matches(&format!(" (os error {})", errno)).to_string(),
+        None => text,
+    }
+}
+
+/// Escapes the characters that separate fields and lines.
+///
+/// # Arguments
+///
+/// * `field` - The field.
+///
+/// # Returns
+///
+/// * `String` - The field with backslashes, tabs and newlines escaped.
+fn escape(field: &str) -> String {
+    field.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n")
+}
@@ -0,0 +1,1028 @@
+//! This module decides which files and symlinks are deleted when the user narrows a run down
+//! with filters.
+//!
+//! Without filters everything under the targets is deleted. With filters, the crawler checks the
+//! metadata of every file and symlink it finds, sends the ones that match to the deleters and
+//! leaves the rest in place. A directory is only deleted when everything within it matched and
+//! it meets the filters itself; otherwise it is kept along with the files that didn't match.
+//! Only the name filters are passed down: everything else is checked against the metadata of
+//! the directory, read before it was crawled, so an empty directory never matches a filter just
+//! because there is nothing in it to fail it.
+//!
+//! Name filters ('--name-regex') are checked against the basename of each file and directory. A
+//! directory whose name matches counts as matching everything within it, so its contents only
+//! have to meet the other filters. A directory whose name doesn't match is kept, and only the
+//! matching objects within it are deleted.
+//!
+//! '--include' patterns (see the excludes module) and '--hidden-only', which matches names starting
+//! with a dot, work like name filters too, so an included or hidden directory is deleted with
+//! everything in it. '--skip-hidden' keeps hidden files and directories
+//! instead; the crawler leaves hidden directories unread, and counts what it kept separately.
+//! When a target is on a FAT, NTFS or SMB filesystem, an object with the hidden attribute of
+//! Windows counts as hidden too, whatever its name.
+//!
+//! Owner filters ('--uid', '--gid', '--user' and '--group') are checked against the owner of each
+//! file, symlink and directory. Objects owned by anyone else are counted separately in the final report.
+//! '--only-mine' keeps everything that isn't owned by the user who ran nukem, directories
+//! included, even a run as root; with sudo, that's the user who ran sudo.
+//! The permission filter ('--perm') works like find's '-perm' and is counted separately too. It
+//! matches regular files and directories by their own mode, and never symlinks, since the mode of
+//! a symlink is meaningless. The access time
+//! filter ('--not-accessed-in') keeps files and directories that were read recently, which is
+//! checked again right before each file is deleted, and is counted separately as well. The
+//! creation time filter ('--created-before') goes by the birth time of each object, which stays
+//! put however often it is modified later. Filesystems that don't record it are refused up front,
+//! and objects on one below a target are kept. '--min-age' keeps directories that changed
+//! recently as well as files, by their times from before the crawl.
+//!
+//! The name filters, the size range ('--min-size' and '--max-size'), '--ext', '--where' and the
+//! content filters are the criteria an object is selected by, and '--match' decides whether it has
+//! to meet all of them (the default) or any. '--not' selects what they don't, e.g. everything but
+//! the files that are both small and recent. The owner, permission, access and creation time,
+//! hard link, type and empty file filters always narrow the selection down on top of that, and
+//! are never negated. A directory is selected by the same criteria, with its own size for the
+//! size range and its own metadata for '--where'; it has no extension and no contents, so it
+//! never meets '--ext', '--mime' or '--magic'.
+//!
+//! With '--tier' (see the tiers module), a file younger than every tier is kept and counted
+//! separately, and every directory is kept, since it could hold files of several tiers.
+//!
+//! '--skip-hardlinked' keeps files that have other hard links, whose space wouldn't be freed by
+//! deleting just this name. It is checked again right before each file is deleted, since a link
+//! may have been added after the crawl.
+//!
+//! The type filter ('--type') selects regular files, directories and symlinks like find's '-type'.
+//! A directory is still only deleted when everything within it was, so '--type d' on its own only
+//! removes directories that hold nothing but other directories.
+//!
+//! A '--where' expression (see the expression module) is evaluated for every file, symlink and
+//! directory like the metadata filters, and what it doesn't match is counted as filtered out.
+//!
+//! Content filters ('--mime' and '--magic', see the content module) read the first bytes of every
+//! regular file that passed all other filters, and keep the files whose contents are of another
+//! kind. They are counted as filtered out too.
+//!
+//! Extension filters ('--ext'), '--empty-files-only' and a '--type' without 'd' only ever delete
+//! files (or symlinks), so every directory is kept and the directory skeleton survives the run.
+//! The deleters count the deleted files per extension. The same goes for '--action compress' and
+//! '--action move', which hand every file to the deleters on its own.
+
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::fs::Metadata;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex;
+use crate::args::{Action, Args, MatchMode};
+use crate::regex::Regex;
+use crate::excludes::Includes;
+use crate::skipped::SkipReason;
+use crate::utility::{has_extension, name_regex};
+use crate::expression::Expression;
+use crate::content::ContentFilter;
+use crate::environment;
+use crate::tiers::Tiers;
+
+// Filesystem types that keep the attributes of Windows.
+const DOS_FILESYSTEMS: [&str; 7] = ["vfat", "msdos", "ntfs", "ntfs3", "fuseblk", "cifs", "smb3"];
+// The hidden attribute, the same bit on FAT, NTFS and SMB.
+const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+// _IOR('r', 0x10, __u32) from linux/msdos_fs.h.
+const FAT_IOCTL_GET_ATTRIBUTES: libc::c_ulong = 0x8004_7210;
+
+/// The criteria a file or symlink has to meet to be deleted.
+#[derive(Debug, Clone, Default)]
+pub struct Filters {
+    min_size: Option<u64>,
+    max_size: Option<u64>,
+    names: Vec<Regex>,
+    includes: Includes,
+    extensions: Vec<String>,
+    uids: Vec<u32>,
+    mine: Option<u32>,
+    gids: Vec<u32>,
+    perm: Option<PermFilter>,
+    types: Option<TypeSet>,
+    accessed_before: Option<i64>,
+    created_before: Option<i64>,
+    changed_before: Option<i64>,
+    tiers: Tiers,
+    skip_hardlinked: bool,
+    empty_only: bool,
+    prune_empty: bool,
+    whole_trees: bool,
+    per_file: bool,
+    ignore_case: bool,
+    files_only: bool,
+    skip_hidden: bool,
+    hidden_only: bool,
+    dos_attributes: bool,
+    expression: Option<Expression>,
+    content: Option<ContentFilter>,
+    match_mode: MatchMode,
+    negate: bool,
+}
+
+impl Filters {
+    /// Builds the filters from the command-line arguments. The expressions and the user and group
+    /// names have already been checked by the Validator.
+    ///
+    /// # Arguments
+    ///
+    /// * `args` - Command-line arguments.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The filters requested by the user.
+    pub fn from_args(args: &Args) -> Self {
+        Self {
+            min_size: args.min_size,
+            max_size: args.max_size,
+            names: args.name_regex.iter().filter_map(|pattern| name_regex(pattern, args.ignore_case).ok()).collect(),
+            includes: Includes::from_args(args).unwrap_or_default(),
+            extensions: args.ext.iter().map(|ext| ext.trim_start_matches('.').to_string()).collect(),
+            uids: args.uid.iter().copied().chain(args.user.iter().filter_map(|name| lookup_user(name).ok())).collect(),
+            mine: args.only_mine.then(invoking_uid),
+            gids: args.gid.iter().copied().chain(args.group.iter().filter_map(|name| lookup_group(name).ok())).collect(),
+            perm: args.perm,
+            types: args.file_type,
+            accessed_before: args.not_accessed_in.map(access_cutoff),
+            created_before: args.created_before,
+            changed_before: args.min_age.map(access_cutoff),
+            tiers: Tiers::new(&args.tier),
+            skip_hardlinked: args.skip_hardlinked,
+            empty_only: args.empty_files_only,
+            prune_empty: args.prune_empty_dirs,
+            whole_trees: args.trash || args.undoable || args.archive_to.is_some(),
+            // owner reports, plans and --max-delete go through every file, but a tree that goes to
+            // the trash, is staged or is archived is handled as a whole, so its files can't be
+            // counted one by one
+            per_file: (args.owner_report || args.plan.is_some() || !args.max_delete.is_empty()) && !args.trash && !args.undoable && args.archive_to.is_none(),
+            ignore_case: args.ignore_case,
+            // a directory may hold files of several tiers
+            files_only: args.action != Action::Delete || !args.tier.is_empty(),
+            skip_hidden: args.skip_hidden,
+            hidden_only: args.hidden_only,
+            // the hidden attribute is only looked up where a target's filesystem can carry it
+            dos_attributes: (args.skip_hidden || args.hidden_only) && environment::target_filesystems(&args.paths).iter()
+                .any(|(_, filesystem)| DOS_FILESYSTEMS.contains(&filesystem.as_str())),
+            expression: args.where_expr.as_deref().and_then(|text| Expression::parse(text, args.ignore_case).ok()),
+            content: ContentFilter::new(&args.mime, &args.magic, args.sniff_readers),
+            match_mode: args.match_mode,
+            negate: args.negate,
+        }
+    }
+
+    /// Checks whether any filter was requested.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if at least one filter is set.
+    pub fn is_active(&self) -> bool {
+        self.checks_contents() || self.has_name_filters() || self.skip_hidden
+    }
+
+    /// Checks whether any filter looks at the metadata of each file and symlink.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if at least one metadata filter is set.
+    pub fn checks_contents(&self) -> bool {
+        self.min_size.is_some() || self.max_size.is_some() || !self.extensions.is_empty() || self.empty_only || self.prune_empty || self.types.is_some()
+            || !self.uids.is_empty() || !self.gids.is_empty() || self.mine.is_some() || self.perm.is_some() || self.accessed_before.is_some()
+            || self.created_before.is_some() || self.changed_before.is_some() || self.skip_hardlinked || self.expression.is_some() || self.content.is_some()
+    }
+
+    /// Checks a file or symlink against the owner, permission, access time, creation time, minimum
+    /// age, tier and hard link filters, which are counted separately from the other filters.
+    ///
+    /// # Arguments
+    ///
+    /// * `metadata` - The metadata of the object, read without following symlinks.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<SkipReason>` - The reason to keep the object, or None if it passes these filters.
+    pub fn rejects(&self, metadata: &Metadata) -> Option<SkipReason> {
+        if !self.owned(metadata) {
+            return Some(SkipReason::Owner);
+        }
+        if self.skip_hardlinked && metadata.nlink() > 1 {
+            return Some(SkipReason::Hardlinked);
+        }
+        if let Some(perm) = self.perm {
+            if !(metadata.is_file() && perm.matches(metadata.mode())) {
+                return Some(SkipReason::Permissions);
+            }
+        }
+        if !self.tiers.is_empty() && self.tiers.pick(metadata).is_none() {
+            return Some(SkipReason::BelowTiers);
+        }
+        self.rejects_times(metadata)
+    }
+
+    /// Checks a directory against the owner, permission, access time, creation time and minimum
+    /// age filters, read before it was crawled.
+    ///
+    /// # Arguments
+    ///
+    /// * `metadata` - The metadata of the directory.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<SkipReason>` - The reason to keep the directory, or None if it may be deleted.
+    pub fn rejects_dir(&self, metadata: &Metadata) -> Option<SkipReason> {
+        if !self.owned(metadata) {
+            return Some(SkipReason::Owner);
+        }
+        if self.perm.is_some_and(|perm| !perm.matches(metadata.mode())) {
+            return Some(SkipReason::Permissions);
+        }
+        // crawling the directory reads it and deleting within it changes it, so only the times
+        // from before count
+        self.rejects_times(metadata)
+    }
+
+    /// Checks an object against the creation time, minimum age and access time filters.
+    ///
+    /// # Arguments
+    ///
+    /// * `metadata` - The metadata of the object.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<SkipReason>` - The reason to keep the object, or None if it passes these filters.
+    fn rejects_times(&self, metadata: &Metadata) -> Option<SkipReason> {
+        if let Some(cutoff) = self.created_before {
+            match birth_time(metadata) {
+                Some(created) if created < cutoff => {}
+                Some(_) => return Some(SkipReason::RecentlyCreated),
+                None => return Some(SkipReason::UnknownBirthTime),
+            }
+        }
+        if self.changed_before.is_some_and(|cutoff| last_change(metadata) >= cutoff) {
+            return Some(SkipReason::MinAge);
+        }
+        match self.accessed_before {
+            Some(cutoff) if metadata.atime() >= cutoff => Some(SkipReason::RecentlyAccessed),
+            _ => None,
+        }
+    }
+
+    /// Checks an object against '--uid', '--gid', '--user', '--group' and '--only-mine'.
+    ///
+    /// # Arguments
+    ///
+    /// * `metadata` - The metadata of the object.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if the object is owned by one of the selected users and groups.
+    fn owned(&self, metadata: &Metadata) -> bool {
+        (self.uids.is_empty() || self.uids.contains(&metadata.uid()))
+            && (self.gids.is_empty() || self.gids.contains(&metadata.gid()))
+            && self.mine.is_none_or(|uid| metadata.uid() == uid)
+    }
+
+    /// Returns the tiers of '--tier', which pick the action for each file.
+    ///
+    /// # Returns
+    ///
+    /// * `&Tiers` - The tiers, empty if '--tier' wasn't specified.
+    pub fn tiers(&self) -> &Tiers {
+        &self.tiers
+    }
+
+    /// Checks whether directories have to be kept even if everything within them matched.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if only files may be deleted.
+    pub fn keeps_directories(&self) -> bool {
+        !self.extensions.is_empty() || self.empty_only || self.files_only || self.types.is_some_and(|types| !types.dirs)
+    }
+
+    /// Checks whether only empty directories are removed, so nothing that holds a file is touched.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if '--prune-empty-dirs' was specified.
+    pub fn prunes_empty(&self) -> bool {
+        self.prune_empty
+    }
+
+    /// Checks whether a directory is moved with everything within it, so nothing within it may be
+    /// acted on separately.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if '--trash', '--undoable' or '--archive-to' was specified.
+    pub fn moves_whole_trees(&self) -> bool {
+        self.whole_trees
+    }
+
+    /// Finds which of the '--ext' extensions a file name ends with. Extensions may have more than
+    /// one part (e.g. 'tar.gz'), and a name that consists of only the extension (e.g. '.log')
+    /// doesn't have one.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the file.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<&str>` - The extension as it was listed, or None if the name has none of them.
+    pub fn extension_of(&self, path: &Path) -> Option<&str> {
+        let name = path.file_name()?.to_string_lossy();
+        self.extensions.iter().find(|ext| has_extension(&name, ext, self.ignore_case)).map(String::as_str)
+    }
+
+    /// Checks whether the entries of a directory have to be checked one by one. Within a
+    /// directory whose name matched, only the metadata filters are left. When only files are
+    /// acted on, or deletions are counted per owner, every entry has to be sent on its own.
+    ///
+    /// # Arguments
+    ///
+    /// * `name_matched` - True if the directory, or one of its parents, matched the name filters.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if each entry has to be checked.
+    pub fn applies_within(&self, name_matched: bool) -> bool {
+        self.checks_contents() || self.files_only || self.per_file || self.skip_hidden || self.negate || (!name_matched && self.has_name_filters())
+    }
+
+    /// Checks whether a directory that held nothing but selected objects may be deleted itself.
+    /// It is judged by the criteria like a file, from its own metadata read before it was
+    /// crawled, so an empty directory doesn't match just because there is nothing in it.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the directory.
+    /// * `metadata` - The metadata of the directory, read before it was crawled.
+    /// * `name_matched` - True if the directory, or one of its parents, matched the name filters.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if the directory may be deleted.
+    pub fn selects_dir(&self, path: &Path, metadata: &Metadata, name_matched: bool) -> bool {
+        let mut results = Vec::new();
+        if self.has_name_filters() {
+            results.push(name_matched);
+        }
+        if self.min_size.is_some() || self.max_size.is_some() {
+            results.push(self.in_size_range(metadata.len()));
+        }
+        if let Some(ref expression) = self.expression {
+            results.push(expression.matches(path, metadata));
+        }
+        // a directory has no extension and no contents to be recognized by
+        if !self.extensions.is_empty() || self.content.is_some() {
+            results.push(false);
+        }
+        self.combine(&results)
+    }
+
+    /// Checks whether any of the criteria that '--match' combines was given.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if there is something to combine or negate.
+    pub fn has_criteria(&self) -> bool {
+        self.has_name_filters() || self.min_size.is_some() || self.max_size.is_some() || !self.extensions.is_empty()
+            || self.expression.is_some() || self.content.is_some()
+    }
+
+    /// Checks whether objects are judged by their names.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if '--name-regex', '--include' or '--hidden-only' was specified.
+    fn has_name_filters(&self) -> bool {
+        !self.names.is_empty() || !self.includes.is_empty() || self.hidden_only
+    }
+
+    /// Checks whether hidden objects found within the targets are kept.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if '--skip-hidden' was specified.
+    pub fn skips_hidden(&self) -> bool {
+        self.skip_hidden
+    }
+
+    /// Checks whether an object is hidden: its name starts with a dot, or, on a filesystem from
+    /// Windows, it has the hidden attribute.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the object.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if the object is hidden.
+    pub fn is_hidden(&self, path: &Path) -> bool {
+        has_hidden_name(path) || (self.dos_attributes && has_hidden_attribute(path))
+    }
+
+    /// Checks whether the basename of a path matches any of the name filters, and the path any of
+    /// the includes.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the object.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if the name matches, or if there are no name filters.
+    pub fn matches_name(&self, path: &Path) -> bool {
+        if (self.hidden_only && !self.is_hidden(path)) || !(self.includes.is_empty() || self.includes.matches(path)) {
+            return false;
+        }
+        if self.names.is_empty() {
+            return true;
+        }
+        let name = path.file_name().map_or_else(|| path.to_string_lossy(), |name| name.to_string_lossy());
+        self.names.iter().any(|regex| regex.is_match(&name))
+    }
+
+    /// Checks whether a file or symlink is selected by the name, size, extension and '--where'
+    /// criteria, without the content filters, which can't be used with '--refine'.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the object.
+    /// * `metadata` - The metadata of the object, read without following symlinks.
+    /// * `name_matched` - True if the object, or one of its parents, matched the name filters.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if the object should be deleted.
+    pub fn matches(&self, path: &Path, metadata: &Metadata, name_matched: bool) -> bool {
+        self.meets_constraints(metadata) && self.combine(&self.criteria(path, metadata, name_matched))
+    }
+
+    /// Checks whether a file or symlink is selected by all of the criteria, reading its contents
+    /// for '--mime' and '--magic' only if the other criteria leave the outcome open. The metadata
+    /// is that of the object itself, so a symlink is judged by the link and not by its target.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the object.
+    /// * `metadata` - The metadata of the object, read without following symlinks.
+    /// * `name_matched` - True if the object, or one of its parents, matched the name filters.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if the object should be deleted.
+    pub async fn selects(&self, path: &Path, metadata: &Metadata, name_matched: bool) -> bool {
+        if !self.meets_constraints(metadata) {
+            return false;
+        }
+        let mut results = self.criteria(path, metadata, name_matched);
+        if let Some(ref content) = self.content {
+            let decided = match self.match_mode {
+                MatchMode::All => results.contains(&false),
+                MatchMode::Any => results.contains(&true),
+            };
+            if !decided {
+                results.push(content.matches(path, metadata).await);
+            }
+        }
+        self.combine(&results)
+    }
+
+    /// Checks the filters that narrow down what is deleted whatever '--match' and '--not' say.
+    ///
+    /// # Arguments
+    ///
+    /// * `metadata` - The metadata of the object, read without following symlinks.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if the object may be deleted.
+    fn meets_constraints(&self, metadata: &Metadata) -> bool {
+        // pruning only removes directories, so every file is kept
+        if self.prune_empty || self.types.is_some_and(|types| !types.allows(metadata)) {
+            return false;
+        }
+        !self.empty_only || (metadata.is_file() && metadata.len() == 0)
+    }
+
+    /// Evaluates each of the criteria that were given, except the content filters.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the object.
+    /// * `metadata` - The metadata of the object, read without following symlinks.
+    /// * `name_matched` - True if the object, or one of its parents, matched the name filters.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<bool>` - Whether the object meets each criterion.
+    fn criteria(&self, path: &Path, metadata: &Metadata, name_matched: bool) -> Vec<bool> {
+        let size = metadata.len();
+        let mut results = Vec::new();
+        if self.has_name_filters() {
+            results.push(name_matched);
+        }
+        if self.min_size.is_some() || self.max_size.is_some() {
+            results.push(self.in_size_range(size));
+        }
+        if !self.extensions.is_empty() {
+            results.push(self.extension_of(path).is_some());
+        }
+        if let Some(ref expression) = self.expression {
+            results.push(expression.matches(path, metadata));
+        }
+        results
+    }
+
+    /// Checks a size against '--min-size' and '--max-size'.
+    ///
+    /// # Arguments
+    ///
+    /// * `size` - The size of the object.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if the size is within the range.
+    fn in_size_range(&self, size: u64) -> bool {
+        self.min_size.is_none_or(|min| size >= min) && self.max_size.is_none_or(|max| size <= max)
+    }
+
+    /// Combines the outcome of the criteria as '--match' and '--not' ask for.
+    ///
+    /// # Arguments
+    ///
+    /// * `results` - Whether the object meets each criterion.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if the object is selected. Without any criteria everything is.
+    fn combine(&self, results: &[bool]) -> bool {
+        if results.is_empty() {
+            return true;
+        }
+        let matched = match self.match_mode {
+            MatchMode::All => results.iter().all(|&result| result),
+            MatchMode::Any => results.iter().any(|&result| result),
+        };
+        matched != self.negate
+    }
+}
+
+/// How the mode of a file is compared with the '--perm' bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PermMatch {
+    /// The permission bits are exactly these.
+    Exact,
+    /// All of these bits are set ('-MODE').
+    All,
+    /// Any of these bits is set ('/MODE').
+    Any,
+}
+
+/// A '--perm' filter, written like the mode given to find's '-perm': '0777' for an exact mode,
+/// '-0002' for all of the bits and '/o+w' for any of them. Modes are octal or symbolic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PermFilter {
+    mode: u32,
+    how: PermMatch,
+}
+
+impl PermFilter {
+    /// Parses a '--perm' value.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The value, e.g. '0777', '-g+w' or '/o+w,g+w'.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, String>` - Ok with the filter, Err with a message if the mode is invalid.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let (how, mode) = match text.chars().next() {
+            Some('-') => (PermMatch::All, &text[1..]),
+            Some('/') => (PermMatch::Any, &text[1..]),
+            _ => (PermMatch::Exact, text),
+        };
+        let invalid = || format!("Invalid permission mode '{}': expected an octal mode such as 0644 or a symbolic one such as o+w", text);
+        let mode = if !mode.is_empty() && mode.chars().all(|c| c.is_digit(8)) {
+            u32::from_str_radix(mode, 8).ok().filter(|mode| *mode <= 0o7777).ok_or_else(invalid)?
+        } else {
+            Self::parse_symbolic(mode).ok_or_else(invalid)?
+        };
+        Ok(Self { mode, how })
+    }
+
+    /// Parses a symbolic mode such as 'u+rw,g+w' or 'a=r', starting from no bits set.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The symbolic mode.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<u32>` - The mode bits, or None if the mode is invalid.
+    fn parse_symbolic(text: &str) -> Option<u32> {
+        let mut mode = 0;
+        for clause in text.split(',') {
+            let op = clause.find(['+', '='])?;
+            let (who, perms) = (&clause[..op], &clause[op + 1..]);
+            let mut who_mask = 0;
+            for c in who.chars() {
+                who_mask |= match c {
+                    'u' => 0o4700,
+                    'g' => 0o2070,
+                    'o' => 0o1007,
+                    'a' => 0o7777,
+                    _ => return None,
+                };
+            }
+            if who_mask == 0 {
+                who_mask = 0o7777;
+            }
+            let mut bits = 0;
+            for c in perms.chars() {
+                bits |= match c {
+                    'r' => 0o444,
+                    'w' => 0o222,
+                    'x' => 0o111,
+                    's' => 0o6000,
+                    't' => 0o1000,
+                    _ => return None,
+                };
+            }
+            mode |= who_mask & bits;
+        }
+        Some(mode)
+    }
+
+    /// Checks whether a mode matches the filter.
+    ///
+    /// # Arguments
+    ///
+    /// * `mode` - The mode of the file.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if the mode matches.
+    pub fn matches(&self, mode: u32) -> bool {
+        let mode = mode & 0o7777;
+        match self.how {
+            PermMatch::Exact => mode == self.mode,
+            PermMatch::All => mode & self.mode == self.mode,
+            // as with find, '/000' matches every file
+            PermMatch::Any => self.mode == 0 || mode & self.mode != 0,
+        }
+    }
+}
+
+impl std::fmt::Display for PermFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let prefix = match self.how {
+            PermMatch::Exact => "",
+            PermMatch::All => "-",
+            PermMatch::Any => "/",
+        };
+        write!(f, "{}{:04o}", prefix, self.mode)
+    }
+}
+
+/// The object types selected with '--type', written like find's '-type': 'f' for regular files,
+/// 'd' for directories and 'l' for symlinks, combined as 'f,l' or 'fl'.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TypeSet {
+    files: bool,
+    dirs: bool,
+    symlinks: bool,
+}
+
+impl TypeSet {
+    /// Parses a '--type' value.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The value, e.g. 'f', 'l' or 'f,d'.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, String>` - Ok with the selected types, Err with a message if a type is unknown.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut types = Self { files: false, dirs: false, symlinks: false };
+        for c in text.chars().filter(|&c| c != ',') {
+            match c {
+                'f' => types.files = true,
+                'd' => types.dirs = true,
+                'l' => types.symlinks = true,
+                _ => return Err(format!("Invalid type '{}' in '{}': expected f (file), d (directory) or l (symlink)", c, text)),
+            }
+        }
+        if types == (Self { files: false, dirs: false, symlinks: false }) {
+            return Err("--type needs at least one of f, d or l".into());
+        }
+        Ok(types)
+    }
+
+    /// Checks whether an object is of one of the selected types. Special files never are.
+    ///
+    /// # Arguments
+    ///
+    /// * `metadata` - The metadata of the object, read without following symlinks.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if the object may be deleted.
+    pub fn allows(&self, metadata: &Metadata) -> bool {
+        let file_type = metadata.file_type();
+        (file_type.is_file() && self.files) || (file_type.is_dir() && self.dirs) || (file_type.is_symlink() && self.symlinks)
+    }
+}
+
+impl std::fmt::Display for TypeSet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let letters: Vec<&str> = [(self.files, "f"), (self.dirs, "d"), (self.symlinks, "l")]
+            .iter().filter(|(selected, _)| *selected).map(|(_, letter)| *letter).collect();
+        write!(f, "{}", letters.join(","))
+    }
+}
+
+/// Checks whether the name of an object starts with a dot.
+///
+/// # Arguments
+///
+/// * `path` - The path of the object.
+///
+/// # Returns
+///
+/// * `bool` - True if the basename starts with '.'.
+fn has_hidden_name(path: &Path) -> bool {
+    path.file_name().is_some_and(|name| name.as_bytes().first() == Some(&b'.'))
+}
+
+/// Checks whether an object has the hidden attribute of Windows. NTFS (ntfs3 and ntfs-3g) and
+/// SMB mounts give the attributes as an extended attribute, and FAT through an ioctl on the file.
+///
+/// # Arguments
+///
+/// * `path` - The path of the object.
+///
+/// # Returns
+///
+/// * `bool` - True if the attribute is set, false if it isn't or can't be read.
+fn has_hidden_attribute(path: &Path) -> bool {
+    let c_path = match CString::new(path.as_os_str().as_bytes()) {
+        Ok(c_path) => c_path,
+        Err(_) => return false,
+    };
+    let mut value = [0u8; 4];
+    for (name, big_endian) in [(&b"system.ntfs_attrib_be\0"[..], true), (&b"user.cifs.dosattrib\0"[..], false)] {
+        let size = unsafe { libc::lgetxattr(c_path.as_ptr(), name.as_ptr().cast(), value.as_mut_ptr().cast(), value.len()) };
+        if size == 4 {
+            let attributes = if big_endian { u32::from_be_bytes(value) } else { u32::from_ne_bytes(value) };
+            return attributes & FILE_ATTRIBUTE_HIDDEN != 0;
+        }
+    }
+    let fd = unsafe { libc::open(c_path.as_ptr(), libc::O_RDONLY | libc::O_NOFOLLOW | libc::O_NONBLOCK | libc::O_CLOEXEC) };
+    if fd < 0 {
+        return false;
+    }
+    let mut attributes: u32 = 0;
+    let status = unsafe { libc::ioctl(fd, FAT_IOCTL_GET_ATTRIBUTES, &mut attributes) };
+    unsafe { libc::close(fd) };
+    status == 0 && attributes & FILE_ATTRIBUTE_HIDDEN != 0
+}
+
+/// Works out the access time cutoff of '--not-accessed-in'.
+///
+/// # Arguments
+///
+/// * `age` - How long files must not have been read.
+///
+/// # Returns
+///
+/// * `i64` - The time, in seconds since the epoch, files must not have been read after.
+pub fn access_cutoff(age: Duration) -> i64 {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    now.saturating_sub(age).as_secs() as i64
+}
+
+/// Reads when a file was last modified or changed, for '--min-age'. The change time can't be
+/// set back like the modification time can, and a time in the future, from a server whose clock
+/// is ahead, counts as recent.
+///
+/// # Arguments
+///
+/// * `metadata` - The metadata of the file.
+///
+/// # Returns
+///
+/// * `i64` - The newer of the two times, in seconds since the epoch.
+pub fn last_change(metadata: &Metadata) -> i64 {
+    metadata.mtime().max(metadata.ctime())
+}
+
+/// Reads when a file was created. On Linux the birth time comes from statx, which only some
+/// filesystems fill in, such as ext4, XFS and Btrfs.
+///
+/// # Arguments
+///
+/// * `metadata` - The metadata of the file.
+///
+/// # Returns
+///
+/// * `Option<i64>` - The time in seconds since the epoch, or None if the filesystem doesn't record it.
+pub fn birth_time(metadata: &Metadata) -> Option<i64> {
+    let created = metadata.created().ok()?;
+    Some(created.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs() as i64))
+}
+
+/// Checks whether a path is on a filesystem mounted with 'noatime', where reading a file doesn't
+/// update its access time.
+///
+/// # Arguments
+///
+/// * `path` - Any path on the filesystem.
+///
+/// # Returns
+///
+/// * `bool` - True if access times aren't kept up to date.
+pub fn is_noatime(path: &Path) -> bool {
+    let c_path = match CString::new(path.as_os_str().as_bytes()) {
+        Ok(c_path) => c_path,
+        Err(_) => return false,
+    };
+    let mut stats: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stats) } != 0 {
+        return false;
+    }
+    stats.f_flag & libc::ST_NOATIME != 0
+}
+
+/// Finds the user '--only-mine' keeps the objects of. A run through sudo belongs to the user who
+/// ran sudo rather than to root.
+///
+/// # Returns
+///
+/// * `u32` - The user ID.
+pub fn invoking_uid() -> u32 {
+    let uid = unsafe { libc::getuid() };
+    if uid != 0 {
+        return uid;
+    }
+    std::env::var("SUDO_UID").ok().and_then(|uid| uid.parse().ok()).unwrap_or(uid)
+}
+
+/// Looks up the user ID of a user name.
+///
+/// # Arguments
+///
+/// * `name` - The user name.
+///
+/// # Returns
+///
+/// * `Result<u32, String>` - Ok with the user ID, Err with a message if there is no such user.
+pub fn lookup_user(name: &str) -> Result<u32, String> {
+    let unknown = || format!("Unknown user '{}'", name);
+    let c_name = CString::new(name).map_err(|_| unknown())?;
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buffer = vec![0 as libc::c_char; 16384];
+    let mut result = std::ptr::null_mut();
+    let status = unsafe { libc::getpwnam_r(c_name.as_ptr(), &mut passwd, buffer.as_mut_ptr(), buffer.len(), &mut result) };
+    if status != 0 || result.is_null() {
+        return Err(unknown());
+    }
+    Ok(passwd.pw_uid)
+}
+
+/// Looks up the group ID of a group name.
+///
+/// # Arguments
+///
+/// * `name` - The group name.
+///
+/// # Returns
+///
+/// * `Result<u32, String>` - Ok with the group ID, Err with a message if there is no such group.
+pub fn lookup_group(name: &str) -> Result<u32, String> {
+    let unknown = || format!("Unknown group '{}'", name);
+    let c_name = CString::new(name).map_err(|_| unknown())?;
+    let mut group: libc::group = unsafe { std::mem::zeroed() };
+    let mut buffer = vec![0 as libc::c_char; 16384];
+    let mut result = std::ptr::null_mut();
+    let status = unsafe { libc::getgrnam_r(c_name.as_ptr(), &mut group, buffer.as_mut_ptr(), buffer.len(), &mut result) };
+    if status != 0 || result.is_null() {
+        return Err(unknown());
+    }
+    Ok(group.gr_gid)
+}
+
+/// Shared count of deleted files per '--ext' extension. Cloning it shares the underlying counts.
+#[derive(Clone)]
+pub struct ExtensionCounts {
+    filters: Arc<Filters>,
+    counts: Arc<Mutex<HashMap<String, u64>>>,
+}
+
+impl ExtensionCounts {
+    /// Creates an empty count.
+    ///
+    /// # Arguments
+    ///
+    /// * `filters` - The filters holding the extensions to count.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - A new instance of ExtensionCounts.
+    pub fn new(filters: Arc<Filters>) -> Self {
+        Self { filters, counts: Arc::new(Mutex::new(HashMap::new())) }
+    }
+
+    /// Records a deleted file under its extension.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the deleted file.
+    pub async fn record(&self, path: &Path) {
+        if let Some(ext) = self.filters.extension_of(path) {
+            *self.counts.lock().await.entry(ext.to_string()).or_insert(0) += 1;
+        }
+    }
+
+    /// Retrieves the number of deleted files per extension, in the order they were listed.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<(String, u64)>` - Each extension with its count.
+    pub async fn counts(&self) -> Vec<(String, u64)> {
+        let counts = self.counts.lock().await;
+        self.filters.extensions.iter().map(|ext| (ext.clone(), counts.get(ext).copied().unwrap_or(0))).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PermFilter;
+
+    fn mode(text: &str) -> u32 {
+        PermFilter::parse(text).unwrap().mode
+    }
+
+    fn matches(text: &str, mode: u32) -> bool {
+        PermFilter::parse(text).unwrap().matches(mode)
+    }
+
+    #[test]
+    fn matches_a_bare_octal_mode_exactly() {
+        assert_eq!(mode("0644"), 0o644);
+        assert_eq!(mode("4755"), 0o4755);
+        assert!(matches("644", 0o100644));
+        assert!(!matches("644", 0o100664));
+        assert!(!matches("644", 0o100600));
+        assert!(matches("0", 0o100000));
+    }
+
+    #[test]
+    fn matches_all_bits_with_a_dash_and_any_bit_with_a_slash() {
+        assert!(matches("-022", 0o100622));
+        assert!(matches("-022", 0o100777));
+        assert!(!matches("-022", 0o100620));
+        assert!(matches("/022", 0o100620));
+        assert!(matches("/022", 0o100602));
+        assert!(!matches("/022", 0o100600));
+        // as with find, no bits at all match every file
+        assert!(matches("/000", 0o100000));
+        assert!(matches("-000", 0o100000));
+        assert!(matches("-g+w", 0o100664));
+        assert!(!matches("-g+w", 0o100644));
+    }
+
+    #[test]
+    fn parses_symbolic_modes() {
+        assert_eq!(mode("u+s"), 0o4000);
+        assert_eq!(mode("g+s"), 0o2000);
+        assert_eq!(mode("o+t"), 0o1000);
+        assert_eq!(mode("a=r"), 0o444);
+        assert_eq!(mode("+x"), 0o111);
+        assert_eq!(mode("u+rw,g+w"), 0o620);
+        assert_eq!(mode("/o+w,g+w"), 0o022);
+        assert_eq!(mode("ug=rwx,o=rx"), 0o775);
+        assert!(matches("/u+s,g+s", 0o102755));
+        assert!(!matches("/u+s,g+s", 0o100755));
+    }
+
+    #[test]
+    fn rejects_invalid_modes() {
+        for text in ["9", "u+z", "", "-", "/", "10000", "x+r", "u", "u+r,", "0x644"] {
+            assert!(PermFilter::parse(text).is_err(), "'{}' should be rejected", text);
+        }
+        assert_eq!(
+            PermFilter::parse("u+z").unwrap_err(),
+            "Invalid permission mode 'u+z': expected an octal mode such as 0644 or a symbolic one such as o+w"
+        );
+    }
+}
@@ -0,0 +1,420 @@
+//! This module forecasts how long a purge takes and how much metadata load it puts on the
+//! filesystem, so operators can pick a maintenance window.
+//!
+//! The forecast is based on calibration data gathered with the `bench` subcommand, which builds a
+//! scratch tree of empty files on a filesystem, crawls it and removes it again with the run's
+//! number of workers, and stores the measured stat, unlink and rmdir rates under
+//! `<state dir>/calibration`. With '--estimate', the estimated number of objects is combined with
+//! the calibration of each target's filesystem. The crawlers and deleters run at the same time,
+//! so a run takes about as long as the slower of the two, and at most as long as both together.
+//! The scratch tree is read with warm caches, so a crawl of cold data can be slower than forecast.
+
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+use chrono::Local;
+use crate::logger::Logger;
+
+/// Measured metadata rates of one filesystem.
+#[derive(Debug, Clone, Default)]
+pub struct Calibration {
+    pub filesystem: PathBuf,
+    pub fs_type: String,
+    pub measured: String,
+    pub threads: usize,
+    pub files: usize,
+    pub stat_rate: f64,
+    pub unlink_rate: f64,
+    pub rmdir_rate: f64,
+}
+
+/// Numbers of objects to forecast for.
+#[derive(Debug, Clone, Copy)]
+pub struct Objects {
+    pub files: f64,
+    pub dirs: f64,
+}
+
+// Number of files placed in each directory of the scratch tree.
+const FILES_PER_DIR: usize = 100;
+
+impl Calibration {
+    /// Serializes the calibration as `key: value` lines.
+    ///
+    /// # Returns
+    ///
+    /// * `String` - The serialized calibration.
+    fn to_text(&self) -> String {
+        format!(
+            "filesystem: {}\nfs_type: {}\nmeasured: {}\nthreads: {}\nfiles: {}\nstat_rate: {:.1}\nunlink_rate: {:.1}\nrmdir_rate: {:.1}\n",
+            self.filesystem.display(), self.fs_type, self.measured, self.threads, self.files,
+            self.stat_rate, self.unlink_rate, self.rmdir_rate
+        )
+    }
+
+    /// Parses a calibration written by `to_text`. Unknown keys are ignored.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The serialized calibration.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The parsed calibration.
+    fn from_text(text: &str) -> Self {
+        let mut calibration = Self::default();
+        for line in text.lines() {
+            let (key, value) = match line.split_once(": ") {
+                Some(pair) => pair,
+                None => continue,
+            };
+            match key {
+                "filesystem" => calibration.filesystem = PathBuf::from(value),
+                "fs_type" => calibration.fs_type = value.to_string(),
+                "measured" => calibration.measured = value.to_string(),
+                "threads" => calibration.threads = value.parse().unwrap_or(0),
+                "files" => calibration.files = value.parse().unwrap_or(0),
+                "stat_rate" => calibration.stat_rate = value.parse().unwrap_or(0.0),
+                "unlink_rate" => calibration.unlink_rate = value.parse().unwrap_or(0.0),
+                "rmdir_rate" => calibration.rmdir_rate = value.parse().unwrap_or(0.0),
+                _ => {}
+            }
+        }
+        calibration
+    }
+
+    /// Checks that every rate was measured, so a damaged file isn't used for a forecast.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if the rates can be used.
+    fn is_usable(&self) -> bool {
+        self.stat_rate > 0.0 && self.unlink_rate > 0.0 && self.rmdir_rate > 0.0
+    }
+
+    /// Works out how long removing a number of objects takes on this filesystem.
+    ///
+    /// # Arguments
+    ///
+    /// * `objects` - The objects to remove.
+    ///
+    /// # Returns
+    ///
+    /// * `(f64, f64)` - The seconds spent crawling and the seconds spent deleting.
+    fn durations(&self, objects: &Objects) -> (f64, f64) {
+        let crawl = (objects.files + objects.dirs) / self.stat_rate;
+        let delete = objects.files / self.unlink_rate + objects.dirs / self.rmdir_rate;
+        (crawl, delete)
+    }
+}
+
+/// Finds the filesystem a path is on, from the mount table.
+///
+/// # Arguments
+///
+/// * `path` - The path.
+///
+/// # Returns
+///
+/// * `Option<(PathBuf, String)>` - The mount point and filesystem type, or None if the path
+///   can't be resolved.
+pub fn filesystem_of(path: &Path) -> Option<(PathBuf, String)> {
+    let path = fs::canonicalize(path).ok()?;
+    let mounts = fs::read_to_string("/proc/self/mountinfo").unwrap_or_default();
+    let mut best: Option<(PathBuf, String)> = None;
+    for line in mounts.lines() {
+        // the mount point is the fifth field, and the type follows the ' - ' separator
+        let (fields, rest) = match line.split_once(" - ") {
+            Some(pair) => pair,
+            None => continue,
+        };
+        let mount = match fields.split(' ').nth(4) {
+            Some(mount) => PathBuf::from(unescape(mount)),
+            None => continue,
+        };
+        let fs_type = rest.split(' ').next().unwrap_or("").to_string();
+        // later mounts hide earlier ones at the same place
+        if path.starts_with(&mount) && best.as_ref().is_none_or(|(found, _)| mount.as_os_str().len() >= found.as_os_str().len()) {
+            best = Some((mount, fs_type));
+        }
+    }
+    best.or_else(|| Some((PathBuf::from("/"), String::new())))
+}
+
+/// Decodes the octal escapes the mount table uses for spaces and other special characters.
+///
+/// # Arguments
+///
+/// * `field` - A field of the mount table.
+///
+/// # Returns
+///
+/// * `String` - The decoded field.
+fn unescape(field: &str) -> String {
+    let bytes = field.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let code = field.get(i + 1..i + 4).and_then(|digits| u8::from_str_radix(digits, 8).ok());
+        match code {
+            Some(code) if bytes[i] == b'\\' => {
+                decoded.push(code);
+                i += 4;
+            }
+            _ => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Measures the metadata rates of the filesystem a directory is on. A scratch tree of empty files
+/// is created within the directory and removed again.
+///
+/// # Arguments
+///
+/// * `dir` - The directory to measure in.
+/// * `threads` - The number of workers.
+/// * `files` - The number of files in the scratch tree.
+///
+/// # Returns
+///
+/// * `Result<Calibration, String>` - Ok with the measured rates, Err with a message if the
+///   scratch tree could not be created or removed.
+pub fn bench(dir: &Path, threads: usize, files: usize) -> Result<Calibration, String> {
+    if !dir.is_dir() {
+        return Err(format!("'{}' is not a directory", dir.display()));
+    }
+    let (filesystem, fs_type) = filesystem_of(dir).ok_or_else(|| format!("Unable to resolve '{}'", dir.display()))?;
+    let root = dir.join(format!(".nukem-bench-{}", std::process::id()));
+    let dirs: Vec<PathBuf> = (0..files.max(1).div_ceil(FILES_PER_DIR)).map(|i| root.join(format!("d{:05}", i))).collect();
+    let files = dirs.len() * FILES_PER_DIR;
+    let threads = threads.clamp(1, dirs.len());
+
+    fs::create_dir(&root).map_err(|e| format!("Unable to create '{}': {}", root.display(), e))?;
+    let measured = measure(&dirs, threads);
+    let cleanup = fs::remove_dir_all(&root);
+    let (stat_secs, unlink_secs, rmdir_secs) = measured.map_err(|e| format!("Benchmark in '{}' failed: {}", root.display(), e))?;
+    cleanup.map_err(|e| format!("Unable to remove '{}': {}", root.display(), e))?;
+
+    Ok(Calibration {
+        filesystem,
+        fs_type,
+        measured: Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+        threads,
+        files,
+        stat_rate: (files + dirs.len()) as f64 / stat_secs,
+        unlink_rate: files as f64 / unlink_secs,
+        rmdir_rate: dirs.len() as f64 / rmdir_secs,
+    })
+}
+
+/// Builds the scratch tree and times crawling it, unlinking its files and removing its
+/// directories, each spread over the workers.
+///
+/// # Arguments
+///
+/// * `dirs` - The directories of the scratch tree.
+/// * `threads` - The number of workers.
+///
+/// # Returns
+///
+/// * `io::Result<(f64, f64, f64)>` - Ok with the seconds taken by the stat, unlink and rmdir
+///   phases.
+fn measure(dirs: &[PathBuf], threads: usize) -> io::Result<(f64, f64, f64)> {
+    let names: Vec<String> = (0..FILES_PER_DIR).map(|i| format!("f{:04}", i)).collect();
+    parallel(dirs, threads, |dir| {
+        fs::create_dir(dir)?;
+        names.iter().try_for_each(|name| File::create_new(dir.join(name)).map(drop))
+    })?;
+    let stat = parallel(dirs, threads, |dir| {
+        fs::symlink_metadata(dir)?;
+        for entry in fs::read_dir(dir)? {
+            entry?.metadata()?;
+        }
+        Ok(())
+    })?;
+    let unlink = parallel(dirs, threads, |dir| names.iter().try_for_each(|name| fs::remove_file(dir.join(name))))?;
+    let rmdir = parallel(dirs, threads, |dir| fs::remove_dir(dir))?;
+    Ok((stat, unlink, rmdir))
+}
+
+/// Runs an operation on every directory, spread over a number of workers.
+///
+/// # Arguments
+///
+/// * `dirs` - The directories.
+/// * `threads` - The number of workers.
+/// * `operation` - The operation to run on each directory.
+///
+/// # Returns
+///
+/// * `io::Result<f64>` - Ok with the seconds it took, Err with the first error.
+fn parallel<F>(dirs: &[PathBuf], threads: usize, operation: F) -> io::Result<f64>
+where
+    F: Fn(&Path) -> io::Result<()> + Sync,
+{
+    let start = Instant::now();
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|worker| {
+                let operation = &operation;
+                scope.spawn(move || dirs.iter().skip(worker).step_by(threads).try_for_each(|dir| operation(dir)))
+            })
+            .collect();
+        workers.into_iter().try_for_each(|worker| worker.join().unwrap_or_else(|_| Err(io::Error::other("worker panicked"))))
+    })?;
+    // a timer that didn't advance would make the rate infinite
+    Ok(start.elapsed().as_secs_f64().max(1e-6))
+}
+
+/// Finds the file a filesystem's calibration is stored in.
+///
+/// # Arguments
+///
+/// * `state_dir` - The state directory.
+/// * `filesystem` - The mount point of the filesystem.
+///
+/// # Returns
+///
+/// * `PathBuf` - The path of the calibration file.
+fn calibration_path(state_dir: &Path, filesystem: &Path) -> PathBuf {
+    let name = filesystem.to_string_lossy().trim_matches('/').replace('/', "_");
+    let name = if name.is_empty() { "root".to_string() } else { name };
+    state_dir.join("calibration").join(format!("{}.txt", name))
+}
+
+/// Saves a calibration to the state directory, replacing an older one for the same filesystem.
+///
+/// # Arguments
+///
+/// * `state_dir` - The state directory.
+/// * `calibration` - The calibration to save.
+///
+/// # Returns
+///
+/// * `io::Result<PathBuf>` - Ok with the path of the stored calibration if successful.
+pub fn save(state_dir: &Path, calibration: &Calibration) -> io::Result<PathBuf> {
+    let path = calibration_path(state_dir, &calibration.filesystem);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&path, calibration.to_text())?;
+    Ok(path)
+}
+
+/// Loads the calibration of a filesystem.
+///
+/// # Arguments
+///
+/// * `state_dir` - The state directory.
+/// * `filesystem` - The mount point of the filesystem.
+///
+/// # Returns
+///
+/// * `Option<Calibration>` - The calibration, or None if the filesystem hasn't been measured.
+fn load(state_dir: &Path, filesystem: &Path) -> Option<Calibration> {
+    let text = fs::read_to_string(calibration_path(state_dir, filesystem)).ok()?;
+    let calibration = Calibration::from_text(&text);
+    (calibration.filesystem == filesystem && calibration.is_usable()).then_some(calibration)
+}
+
+/// Prints a calibration for the `bench` subcommand.
+///
+/// # Arguments
+///
+/// * `calibration` - The calibration to print.
+pub fn print_calibration(calibration: &Calibration) {
+    println!("Filesystem: {} ({})", calibration.filesystem.display(), calibration.fs_type);
+    println!("Measured: {}", calibration.measured);
+    println!("Worker threads: {}", calibration.threads);
+    println!("Scratch files: {}", calibration.files);
+    println!("Stat rate: {:.0} per second", calibration.stat_rate);
+    println!("Unlink rate: {:.0} per second", calibration.unlink_rate);
+    println!("Rmdir rate: {:.0} per second", calibration.rmdir_rate);
+}
+
+/// Logs the forecast duration and IOPS load of removing the estimated objects. Without a
+/// calibration for every target filesystem, a hint on how to get one is logged instead.
+///
+/// # Arguments
+///
+/// * `roots` - The target paths, with patterns already expanded.
+/// * `expected` - The estimated number of objects.
+/// * `worst` - The upper end of the estimate.
+/// * `state_dir` - The state directory, if known.
+/// * `threads` - The number of workers the run would use.
+/// * `logger` - An instance of the Logger.
+pub async fn print_forecast(roots: &[PathBuf], expected: Objects, worst: Objects, state_dir: Option<&Path>, threads: usize, logger: &Arc<Logger>) {
+    let mut filesystems: Vec<PathBuf> = roots.iter().filter_map(|root| filesystem_of(root)).map(|(mount, _)| mount).collect();
+    filesystems.sort();
+    filesystems.dedup();
+    let mut calibrations = Vec::new();
+    for filesystem in &filesystems {
+        match state_dir.and_then(|dir| load(dir, filesystem)) {
+            Some(calibration) => calibrations.push(calibration),
+            None => {
+                logger.log(&format!(
+                    "No calibration for the filesystem at {}. Run 'nukem bench <DIR>' there to get a duration forecast",
+                    filesystem.display()
+                ), false, false, false).await;
+            }
+        }
+    }
+    if calibrations.is_empty() || calibrations.len() < filesystems.len() {
+        return;
+    }
+
+    // across several filesystems, the slowest one sets the pace
+    let slowest = calibrations.iter().max_by(|a, b| {
+        let (a_crawl, a_delete) = a.durations(&expected);
+        let (b_crawl, b_delete) = b.durations(&expected);
+        a_crawl.max(a_delete).total_cmp(&b_crawl.max(b_delete))
+    });
+    let calibration = match slowest {
+        Some(calibration) => calibration,
+        None => return,
+    };
+    let (crawl, delete) = calibration.durations(&expected);
+    let duration = crawl.max(delete);
+    let (worst_crawl, worst_delete) = calibration.durations(&worst);
+    // every object is stat'ed once and removed once
+    let operations = 2.0 * (expected.files + expected.dirs);
+
+    logger.log(&format!(
+        "Forecast based on {} ({}), calibrated {} with {} worker threads",
+        calibration.filesystem.display(), calibration.fs_type, calibration.measured, calibration.threads
+    ), false, false, false).await;
+    if calibration.threads != threads {
+        logger.log(&format!("This run would use {} worker threads, so the forecast is less reliable", threads), false, false, false).await;
+    }
+    logger.log(&format!(
+        "Forecast duration: ~{} (up to {})", format_duration(duration), format_duration(worst_crawl + worst_delete)
+    ), false, false, false).await;
+    logger.log(&format!(
+        "Forecast load: ~{:.0} metadata operations (~{:.0} IOPS)", operations, operations / duration.max(1.0)
+    ), false, false, false).await;
+}
+
+/// Formats a number of seconds for a forecast.
+///
+/// # Arguments
+///
+/// * `secs` - The number of seconds.
+///
+/// # Returns
+///
+/// * `String` - The duration, such as '1h 05m' or '42s'.
+fn format_duration(secs: f64) -> String {
+    let secs = secs.round() as u64;
+    match secs {
+        0 => "under a second".to_string(),
+        1..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m {:02}s", secs / 60, secs % 60),
+        _ => format!("{}h {:02}m", secs / 3600, secs % 3600 / 60),
+    }
+}
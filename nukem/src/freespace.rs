@@ -0,0 +1,96 @@
+//! This module measures how much space a run actually freed.
+//!
+//! The total size in the final report is computed from the sizes of the deleted files, which can
+//! overstate what the filesystem gets back: hardlinked files keep their data, files held open by
+//! other processes are not released until they are closed, and snapshots keep old blocks alive.
+//! The free space of every filesystem holding a target is sampled with statvfs before and after
+//! the run so the actual difference can be reported next to the computed one.
+
+use std::collections::HashSet;
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+/// The free space of the target filesystems, captured before the run.
+pub struct SpaceProbe {
+    probe_paths: Vec<PathBuf>,
+    free_before: u64,
+}
+
+impl SpaceProbe {
+    /// Captures the free space of every filesystem holding one of the targets. The targets
+    /// themselves are about to be deleted, so their parent directories are sampled instead, and
+    /// each filesystem is only counted once.
+    ///
+    /// # Arguments
+    ///
+    /// * `targets` - The expanded deletion targets.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<Self>` - The probe, or None if no filesystem could be sampled.
+    pub fn capture(targets: &[PathBuf]) -> Option<Self> {
+        let mut devices = HashSet::new();
+        let mut probe_paths = Vec::new();
+        for target in targets {
+            let parent = match target.canonicalize().ok().and_then(|path| path.parent().map(Path::to_path_buf)) {
+                Some(parent) => parent,
+                None => continue,
+            };
+            if let Ok(metadata) = std::fs::metadata(&parent) {
+                if devices.insert(metadata.dev()) {
+                    probe_paths.push(parent);
+                }
+            }
+        }
+        let free_before = Self::total_free(&probe_paths)?;
+        Some(Self { probe_paths, free_before })
+    }
+
+    /// Samples the free space again and computes the difference with the first sample.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<i64>` - The number of bytes freed. This can be negative when other processes
+    ///   wrote to the same filesystems during the run.
+    pub fn freed(&self) -> Option<i64> {
+        let free_after = Self::total_free(&self.probe_paths)?;
+        Some(free_after as i64 - self.free_before as i64)
+    }
+
+    /// Adds up the space available to unprivileged users on each probed filesystem.
+    ///
+    /// # Arguments
+    ///
+    /// * `paths` - One path per filesystem.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<u64>` - The total free space in bytes, or None if any filesystem can't be sampled.
+    fn total_free(paths: &[PathBuf]) -> Option<u64> {
+        if paths.is_empty() {
+            return None;
+        }
+        paths.iter().map(|path| free_space(path)).sum()
+    }
+}
+
+/// Reads the space available to unprivileged users on the filesystem holding a path.
+///
+/// # Arguments
+///
+/// * `path` - Any path on the filesystem.
+///
+/// # Returns
+///
+/// * `Option<u64>` - The free space in bytes, or None if statvfs failed.
+pub fn free_space(path: &Path) -> Option<u64> {
+    let c_path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stats: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: c_path is a valid NUL-terminated string and stats is a properly sized out-parameter
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stats) } != 0 {
+        return None;
+    }
+    Some(stats.f_bavail as u64 * stats.f_frsize as u64)
+}
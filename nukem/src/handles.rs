@@ -0,0 +1,100 @@
+//! This module finds processes that hold files open within the deletion targets.
+//!
+//! Deleting a file that another process still has open only removes its name; the space is not
+//! freed until the last handle is closed. On Linux, the symlinks in /proc/<pid>/fd are scanned to
+//! report which processes are responsible. On other platforms no handles are reported.
+
+use std::path::{Path, PathBuf};
+
+/// A file handle held open by a running process.
+#[derive(Debug, Clone)]
+pub struct OpenHandle {
+    pub pid: u32,
+    pub command: String,
+    pub path: PathBuf,
+}
+
+/// Finds every open file handle that points at or below one of the given roots.
+///
+/// # Arguments
+///
+/// * `roots` - The paths to look for. They should be canonicalized, since /proc reports
+///   resolved paths.
+///
+/// # Returns
+///
+/// * `Vec<OpenHandle>` - The open handles found, ordered by process ID.
+#[cfg(target_os = "linux")]
+pub fn find_open_handles(roots: &[PathBuf]) -> Vec<OpenHandle> {
+    let mut handles = Vec::new();
+    let processes = match std::fs::read_dir("/proc") {
+        Ok(processes) => processes,
+        Err(_) => return handles,
+    };
+
+    for process in processes.filter_map(Result::ok) {
+        // only the numeric entries of /proc are processes
+        let pid: u32 = match process.file_name().to_string_lossy().parse() {
+            Ok(pid) => pid,
+            Err(_) => continue,
+        };
+        // processes owned by other users can't be inspected without privilege, so skip them
+        let fds = match std::fs::read_dir(process.path().join("fd")) {
+            Ok(fds) => fds,
+            Err(_) => continue,
+        };
+        for fd in fds.filter_map(Result::ok) {
+            if let Ok(target) = std::fs::read_link(fd.path()) {
+                if roots.iter().any(|root| target.starts_with(root)) {
+                    handles.push(OpenHandle { pid, command: process_command(pid), path: target });
+                }
+            }
+        }
+    }
+    handles.sort_by_key(|handle| handle.pid);
+    handles
+}
+
+/// Finds every open file handle that points at or below one of the given roots.
+///
+/// # Arguments
+///
+/// * `roots` - The paths to look for.
+///
+/// # Returns
+///
+/// * `Vec<OpenHandle>` - Always empty, open handles can only be found on Linux.
+#[cfg(not(target_os = "linux"))]
+pub fn find_open_handles(_roots: &[PathBuf]) -> Vec<OpenHandle> {
+    Vec::new()
+}
+
+/// Canonicalizes a list of paths so they can be compared against /proc symlink targets. Paths
+/// that can't be resolved are left out.
+///
+/// # Arguments
+///
+/// * `paths` - The paths to canonicalize.
+///
+/// # Returns
+///
+/// * `Vec<PathBuf>` - The canonicalized paths.
+pub fn canonical_roots(paths: &[PathBuf]) -> Vec<PathBuf> {
+    paths.iter().filter_map(|path| Path::new(path).canonicalize().ok()).collect()
+}
+
+/// Reads the command name of a process.
+///
+/// # Arguments
+///
+/// * `pid` - The process ID.
+///
+/// # Returns
+///
+/// * `String` - The command name, or "?" if it can't be read.
+#[cfg(target_os = "linux")]
+fn process_command(pid: u32) -> String {
+    std::fs::read_to_string(format!("/proc/{}/comm", pid))
+        .map(|comm| comm.trim().to_string())
+        .unwrap_or_else(|_| "?".into())
+}
@@ -0,0 +1,305 @@
+//! This module keeps a history of application runs in a local state directory.
+//!
+//! At the end of every run a short summary (arguments, totals, duration and outcome) is written to
+//! `<state dir>/runs/<run id>.txt`, and the paths that could not be deleted, if any, to
+//! `<state dir>/runs/<run id>.failed` so they can be retried with '--retry-failed', with the details of
+//! each failure in `<state dir>/runs/<run id>.failures`. The `history` subcommand lists past runs and the `show`
+//! subcommand prints a stored summary. The state directory defaults to `$XDG_STATE_HOME/nukem`,
+//! falling back to `~/.local/state/nukem`, and can be changed with '--state-dir'.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use chrono::Local;
+use crate::failures::Failure;
+use crate::args::{Args, SizeUnit};
+use crate::utility::{format_size, RunSummary};
+
+/// A stored summary of one application run.
+#[derive(Debug, Clone, Default)]
+pub struct RunRecord {
+    pub run_id: String,
+    pub started: String,
+    pub arguments: String,
+    pub dry_run: bool,
+    pub found: u64,
+    pub deleted: u64,
+    pub failed: u64,
+    pub skipped: u64,
+    pub total_size: u64,
+    pub duration_secs: f64,
+    pub outcome: String,
+}
+
+impl RunRecord {
+    /// Creates a record for the current run.
+    ///
+    /// # Arguments
+    ///
+    /// * `args` - Command-line arguments.
+    /// * `summary` - The totals of the run.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - A new instance of the RunRecord.
+    pub fn new(args: &Args, summary: &RunSummary) -> Self {
+        let now = Local::now();
+        let outcome = if summary.interrupted {
+            "interrupted"
+        } else if !summary.accounting_ok {
+            "accounting error"
+        } else if summary.failed > 0 {
+            "completed with failures"
+        } else {
+            "completed"
+        };
+        Self {
+            run_id: format!("{}-{}", now.format("%Y%m%d-%H%M%S"), std::process::id()),
+            started: (now - chrono::Duration::from_std(summary.elapsed).unwrap_or_default()).format("%Y-%m-%d %H:%M:%S").to_string(),
+            arguments: std::env::args().skip(1).collect::<Vec<_>>().join(" "),
+            dry_run: args.dry_run,
+            found: summary.found,
+            deleted: summary.deleted,
+            failed: summary.failed,
+            skipped: summary.skipped,
+            total_size: summary.total_size,
+            duration_secs: summary.elapsed.as_secs_f64(),
+            outcome: outcome.to_string(),
+        }
+    }
+
+    /// Serializes the record as `key: value` lines.
+    ///
+    /// # Returns
+    ///
+    /// * `String` - The serialized record.
+    fn to_text(&self) -> String {
+        format!(
+            "run_id: {}\nstarted: {}\narguments: {}\ndry_run: {}\nfound: {}\ndeleted: {}\nfailed: {}\nskipped: {}\ntotal_size: {}\nduration_secs: {:.3}\noutcome: {}\n",
+            self.run_id, self.started, self.arguments, self.dry_run, self.found, self.deleted,
+            self.failed, self.skipped, self.total_size, self.duration_secs, self.outcome
+        )
+    }
+
+    /// Parses a record written by `to_text`. Unknown keys are ignored so older and newer
+    /// versions can read each other's history.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The serialized record.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The parsed record.
+    fn from_text(text: &str) -> Self {
+        let mut record = Self::default();
+        for line in text.lines() {
+            let (key, value) = match line.split_once(": ") {
+                Some(pair) => pair,
+                None => continue,
+            };
+            match key {
+                "run_id" => record.run_id = value.to_string(),
+                "started" => record.started = value.to_string(),
+                "arguments" => record.arguments = value.to_string(),
+                "dry_run" => record.dry_run = value == "true",
+                "found" => record.found = value.parse().unwrap_or(0),
+                "deleted" => record.deleted = value.parse().unwrap_or(0),
+                "failed" => record.failed = value.parse().unwrap_or(0),
+                "skipped" => record.skipped = value.parse().unwrap_or(0),
+                "total_size" => record.total_size = value.parse().unwrap_or(0),
+                "duration_secs" => record.duration_secs = value.parse().unwrap_or(0.0),
+                "outcome" => record.outcome = value.to_string(),
+                _ => {}
+            }
+        }
+        record
+    }
+}
+
+/// Resolves the state directory.
+///
+/// # Arguments
+///
+/// * `state_dir` - The directory given with '--state-dir', if any.
+///
+/// # Returns
+///
+/// * `Option<PathBuf>` - The state directory, or None if no home directory is known.
+pub fn state_dir(state_dir: Option<&PathBuf>) -> Option<PathBuf> {
+    if let Some(dir) = state_dir {
+        return Some(dir.clone());
+    }
+    if let Some(dir) = std::env::var_os("XDG_STATE_HOME").filter(|dir| !dir.is_empty()) {
+        return Some(PathBuf::from(dir).join("nukem"));
+    }
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/state/nukem"))
+}
+
+/// Saves a run record to the state directory.
+///
+/// # Arguments
+///
+/// * `state_dir` - The state directory.
+/// * `record` - The record to save.
+///
+/// # Returns
+///
+/// * `io::Result<PathBuf>` - Ok with the path of the stored record if successful.
+pub fn save(state_dir: &Path, record: &RunRecord) -> io::Result<PathBuf> {
+    let runs_dir = state_dir.join("runs");
+    fs::create_dir_all(&runs_dir)?;
+    let path = runs_dir.join(format!("{}.txt", record.run_id));
+    fs::write(&path, record.to_text())?;
+    Ok(path)
+}
+
+/// Saves the paths that could not be deleted in a run, one per line.
+///
+/// # Arguments
+///
+/// * `state_dir` - The state directory.
+/// * `run_id` - The ID of the run.
+/// * `paths` - The failed paths.
+///
+/// # Returns
+///
+/// * `io::Result<PathBuf>` - Ok with the path of the stored list if successful.
+pub fn save_failures(state_dir: &Path, run_id: &str, paths: &[PathBuf]) -> io::Result<PathBuf> {
+    let runs_dir = state_dir.join("runs");
+    fs::create_dir_all(&runs_dir)?;
+    let path = runs_dir.join(format!("{}.failed", run_id));
+    let mut text = String::new();
+    for failed in paths {
+        text.push_str(&failed.to_string_lossy());
+        text.push('\n');
+    }
+    fs::write(&path, text)?;
+    Ok(path)
+}
+
+/// Saves the details of the failures of a run, one numbered failure per line, so the failures
+/// in the log can be looked up by their number.
+///
+/// # Arguments
+///
+/// * `state_dir` - The state directory.
+/// * `run_id` - The ID of the run.
+/// * `failures` - The failures in the order they happened.
+///
+/// # Returns
+///
+/// * `io::Result<PathBuf>` - Ok with the path of the stored details if successful.
+pub fn save_failure_details(state_dir: &Path, run_id: &str, failures: &[Failure]) -> io::Result<PathBuf> {
+    let runs_dir = state_dir.join("runs");
+    fs::create_dir_all(&runs_dir)?;
+    let path = runs_dir.join(format!("{}.failures", run_id));
+    let mut text = String::from("#index\toperation\terrno\tname\tmessage\tpath\n");
+    for failure in failures {
+        text.push_str(&failure.to_line());
+        text.push('\n');
+    }
+    fs::write(&path, text)?;
+    Ok(path)
+}
+
+/// Loads a list of failed paths, either from a file or from the stored failures of a past run.
+///
+/// # Arguments
+///
+/// * `state_dir` - The state directory, if known.
+/// * `source` - A path to a file with one path per line, or the ID of a run.
+///
+/// # Returns
+///
+/// * `Result<Vec<PathBuf>, String>` - Ok with the paths, Err with a message if there is no such list.
+pub fn load_failures(state_dir: Option<&Path>, source: &str) -> Result<Vec<PathBuf>, String> {
+    let path = match state_dir.map(|dir| dir.join("runs").join(format!("{}.failed", source))) {
+        Some(stored) if !Path::new(source).is_file() => stored,
+        _ => PathBuf::from(source),
+    };
+    let text = fs::read_to_string(&path).map_err(|e| format!("Unable to read the failures of '{}' from {}: {}", source, path.display(), e))?;
+    Ok(text.lines().filter(|line| !line.is_empty()).map(PathBuf::from).collect())
+}
+
+/// Loads every stored run record, oldest first.
+///
+/// # Arguments
+///
+/// * `state_dir` - The state directory.
+///
+/// # Returns
+///
+/// * `Vec<RunRecord>` - The stored records.
+pub fn list(state_dir: &Path) -> Vec<RunRecord> {
+    let mut records: Vec<RunRecord> = fs::read_dir(state_dir.join("runs"))
+        .map(|entries| {
+            entries
+                .filter_map(Result::ok)
+                .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "txt"))
+                .filter_map(|entry| fs::read_to_string(entry.path()).ok())
+                .map(|text| RunRecord::from_text(&text))
+                .collect()
+        })
+        .unwrap_or_default();
+    // run IDs start with a sortable timestamp
+    records.sort_by(|a, b| a.run_id.cmp(&b.run_id));
+    records
+}
+
+/// Loads a single stored run record.
+///
+/// # Arguments
+///
+/// * `state_dir` - The state directory.
+/// * `run_id` - The ID of the run.
+///
+/// # Returns
+///
+/// * `Result<RunRecord, String>` - Ok with the record, Err with a message if it doesn't exist.
+pub fn load(state_dir: &Path, run_id: &str) -> Result<RunRecord, String> {
+    let path = state_dir.join("runs").join(format!("{}.txt", run_id));
+    fs::read_to_string(&path)
+        .map(|text| RunRecord::from_text(&text))
+        .map_err(|_| format!("No stored run with ID '{}' in {}", run_id, state_dir.display()))
+}
+
+/// Prints one line per stored run for the `history` subcommand.
+///
+/// # Arguments
+///
+/// * `records` - The stored records.
+/// * `size_unit` - The unit used to display sizes.
+pub fn print_history(records: &[RunRecord], size_unit: SizeUnit) {
+    if records.is_empty() {
+        println!("No runs recorded yet");
+        return;
+    }
+    println!("RUN ID                   STARTED                  FOUND    DELETED   FAILED           SIZE  OUTCOME");
+    for record in records {
+        let outcome = if record.dry_run { format!("{} (dry run)", record.outcome) } else { record.outcome.clone() };
+        println!("{:<24} {:<19} {:>10} {:>10} {:>8} {:>14}  {}",
+            record.run_id, record.started, record.found, record.deleted, record.failed,
+            format_size(record.total_size, size_unit), outcome);
+    }
+}
+
+/// Prints a stored run record for the `show` subcommand.
+///
+/// # Arguments
+///
+/// * `record` - The record to print.
+/// * `size_unit` - The unit used to display sizes.
+pub fn print_record(record: &RunRecord, size_unit: SizeUnit) {
+    println!("Run ID: {}", record.run_id);
+    println!("Started: {}", record.started);
+    println!("Arguments: {}", record.arguments);
+    println!("Dry run: {}", record.dry_run);
+    println!("Objects found: {}", record.found);
+    println!("Objects deleted: {}", record.deleted);
+    println!("Failed deletions: {}", record.failed);
+    println!("Skipped: {}", record.skipped);
+    println!("Total size: {}", format_size(record.total_size, size_unit));
+    println!("Execution time: {:.3}s", record.duration_secs);
+    println!("Outcome: {}", record.outcome);
+}
@@ -0,0 +1,90 @@
+//! This module lets the deleters tell whether an object is still the one the crawler found.
+//!
+//! The crawler decides what to delete from the metadata it read, and the deleters act on it
+//! later. In between, whoever can write to a directory within the targets can rename the object
+//! away and put something else in its place, such as a symlink to '/etc' or a file that must not
+//! be deleted. Every object the crawler sends carries the device and inode it had when it was
+//! crawled. The deleter reads the metadata again before acting and refuses an object whose device
+//! or inode changed: it is logged as an error and counted as "replaced" in the final report, and a
+//! later run can look at it again.
+//!
+//! With '--tier', an object also carries the tier the crawler picked for it, which tells the
+//! deleter what to do with it.
+
+use std::fmt;
+use std::fs::Metadata;
+use std::os::unix::fs::MetadataExt;
+use std::path::PathBuf;
+
+/// The device and inode of an object.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Identity {
+    pub device: u64,
+    pub inode: u64,
+}
+
+impl Identity {
+    /// Takes the identity of an object from its metadata.
+    ///
+    /// # Arguments
+    ///
+    /// * `metadata` - The metadata, read without following symlinks.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The identity.
+    pub fn of(metadata: &Metadata) -> Self {
+        Self {
+            device: metadata.dev(),
+            inode: metadata.ino(),
+        }
+    }
+}
+
+impl fmt::Display for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "inode {} on device {}", self.inode, self.device)
+    }
+}
+
+/// An object the crawler sends to the deleters.
+#[derive(Debug, Clone)]
+pub struct Found {
+    pub path: PathBuf,
+    pub identity: Identity,
+    /// The index of the tier the object goes to (see tiers::Tiers::pick), or None without '--tier'.
+    pub tier: Option<usize>,
+}
+
+impl Found {
+    /// Pairs a path with the metadata it was crawled with.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the object.
+    /// * `metadata` - Its metadata, read without following symlinks.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The object to send.
+    pub fn new(path: PathBuf, metadata: &Metadata) -> Self {
+        Self {
+            path,
+            identity: Identity::of(metadata),
+            tier: None,
+        }
+    }
+
+    /// Puts the object in the tier the crawler picked for it.
+    ///
+    /// # Arguments
+    ///
+    /// * `tier` - The index of the tier, or None without '--tier'.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The object to send.
+    pub fn in_tier(self, tier: Option<usize>) -> Self {
+        Self { tier, ..self }
+    }
+}
@@ -0,0 +1,308 @@
+//! This module deals with objects protected by the immutable and append-only attributes
+//! ('chattr +i' and '+a').
+//!
+//! Not even root can unlink an immutable file, or remove anything from a directory that is
+//! immutable or append-only, so deleting such objects fails with 'Operation not permitted' like a
+//! plain permission problem would. When a removal fails that way, the attributes of its directory
+//! and of the object are read, and an object that turns out to be protected is kept and reported
+//! separately instead of as a failure. For a directory tree, the tree is searched for the object
+//! that blocked it.
+//!
+//! With '--clear-immutable' the attributes are taken off the objects that are being deleted, and
+//! the removal is tried again. That needs the CAP_LINUX_IMMUTABLE capability, which root has.
+//! Directories that are kept are never changed, so a file in a protected directory is kept even
+//! then. Attributes are only available on Linux; elsewhere nothing is found protected.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+// The attribute bits of FS_IOC_GETFLAGS, from linux/fs.h.
+#[cfg(target_os = "linux")]
+const IMMUTABLE: libc::c_int = 0x10;
+#[cfg(target_os = "linux")]
+const APPEND_ONLY: libc::c_int = 0x20;
+
+/// Why a protected object can't be removed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Protection {
+    /// The object itself, or an object within the directory tree, is immutable or append-only.
+    Object(PathBuf, &'static str),
+    /// The directory holding the object is immutable or append-only.
+    Parent(PathBuf, &'static str),
+}
+
+impl Protection {
+    /// Describes the protection for the report.
+    ///
+    /// # Returns
+    ///
+    /// * `String` - E.g. '/data/x is immutable'.
+    pub fn describe(&self) -> String {
+        match self {
+            Protection::Object(path, attribute) => format!("{} is {}", path.display(), attribute),
+            Protection::Parent(path, attribute) => format!("its directory {} is {}", path.display(), attribute),
+        }
+    }
+}
+
+/// What happened to a protected object.
+pub enum Outcome {
+    /// The object isn't protected, so the removal failed for another reason.
+    Unprotected,
+    /// The object is protected and was kept.
+    Kept,
+    /// The attributes were cleared, or nothing protects the object anymore, so the removal can be
+    /// tried again.
+    Cleared,
+}
+
+/// The protected objects that were kept, and whether their attributes are cleared.
+#[derive(Clone, Default)]
+pub struct Immutables {
+    clear: bool,
+    kept: Arc<Mutex<Vec<(PathBuf, Protection)>>>,
+    cleared: Arc<Mutex<u64>>,
+}
+
+impl Immutables {
+    /// Creates an empty list.
+    ///
+    /// # Arguments
+    ///
+    /// * `clear` - A boolean indicating whether '--clear-immutable' was specified.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - A new instance of Immutables.
+    pub fn new(clear: bool) -> Self {
+        Self { clear, ..Self::default() }
+    }
+
+    /// Looks into an object that couldn't be removed because the operation wasn't permitted.
+    /// A protected object is either recorded as kept or, with '--clear-immutable', has its
+    /// attributes cleared.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The object.
+    /// * `is_dir` - Whether the object is a directory tree.
+    ///
+    /// # Returns
+    ///
+    /// * `(Outcome, Option<String>)` - What happened, and a message if clearing the attributes failed.
+    pub async fn handle(&self, path: &Path, is_dir: bool) -> (Outcome, Option<String>) {
+        let object = path.to_path_buf();
+        let protection = match tokio::task::spawn_blocking(move || find(&object, is_dir)).await.ok().flatten() {
+            Some(protection) => protection,
+            // another worker may have cleared the attributes since, while removing a tree around it
+            None if self.clear => return (Outcome::Cleared, None),
+            None => return (Outcome::Unprotected, None),
+        };
+        let mut error = None;
+        if self.clear {
+            if let Protection::Object(..) = protection {
+                let object = path.to_path_buf();
+                match tokio::task::spawn_blocking(move || clear_tree(&object)).await.unwrap_or_else(|e| Err(io::Error::other(e))) {
+                    Ok(count) => {
+                        *self.cleared.lock().await += count;
+                        return (Outcome::Cleared, None);
+                    }
+                    Err(e) => error = Some(format!("Unable to clear the attributes of {:?}: {}", path, e)),
+                }
+            }
+        }
+        self.kept.lock().await.push((path.to_path_buf(), protection));
+        (Outcome::Kept, error)
+    }
+
+    /// Retrieves the protected objects that were kept.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<(PathBuf, Protection)>` - The kept objects in path order, with what protected each.
+    pub async fn kept(&self) -> Vec<(PathBuf, Protection)> {
+        let mut kept = self.kept.lock().await.clone();
+        kept.sort_by(|a, b| a.0.cmp(&b.0));
+        kept
+    }
+
+    /// Retrieves the number of objects whose attributes were cleared.
+    ///
+    /// # Returns
+    ///
+    /// * `u64` - The number of objects.
+    pub async fn cleared(&self) -> u64 {
+        *self.cleared.lock().await
+    }
+}
+
+/// Finds what protects an object. Its directory is checked first, since clearing the attributes
+/// of the object doesn't help while the directory is protected.
+///
+/// # Arguments
+///
+/// * `path` - The object.
+/// * `is_dir` - Whether the whole directory tree below the object has to be searched.
+///
+/// # Returns
+///
+/// * `Option<Protection>` - The protection, or None if nothing is immutable or append-only.
+fn find(path: &Path, is_dir: bool) -> Option<Protection> {
+    let parent = path.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    if let Some(attribute) = attribute(parent) {
+        return Some(Protection::Parent(parent.to_path_buf(), attribute));
+    }
+    if is_dir {
+        find_within(path)
+    } else {
+        attribute(path).map(|attribute| Protection::Object(path.to_path_buf(), attribute))
+    }
+}
+
+/// Searches a directory tree for an immutable or append-only object, without following symlinks.
+///
+/// # Arguments
+///
+/// * `dir` - The directory at the top of the tree.
+///
+/// # Returns
+///
+/// * `Option<Protection>` - The first protected object found, or None if there is none.
+fn find_within(dir: &Path) -> Option<Protection> {
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        if let Some(attribute) = attribute(&dir) {
+            return Some(Protection::Object(dir, attribute));
+        }
+        for entry in std::fs::read_dir(&dir).into_iter().flatten().filter_map(Result::ok) {
+            match entry.file_type() {
+                Ok(file_type) if file_type.is_dir() => pending.push(entry.path()),
+                Ok(file_type) if file_type.is_file() => {
+                    if let Some(attribute) = attribute(&entry.path()) {
+                        return Some(Protection::Object(entry.path(), attribute));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    None
+}
+
+/// Clears the immutable and append-only attributes of an object and of everything within it.
+///
+/// # Arguments
+///
+/// * `path` - The object at the top of the tree.
+///
+/// # Returns
+///
+/// * `io::Result<u64>` - Ok with the number of objects that had an attribute cleared, Err if one
+///   couldn't be cleared.
+fn clear_tree(path: &Path) -> io::Result<u64> {
+    let mut cleared = 0;
+    let mut pending = vec![path.to_path_buf()];
+    while let Some(path) = pending.pop() {
+        let metadata = std::fs::symlink_metadata(&path)?;
+        if metadata.is_dir() {
+            pending.extend(std::fs::read_dir(&path)?.filter_map(Result::ok).map(|entry| entry.path()));
+        }
+        if (metadata.is_dir() || metadata.is_file()) && clear(&path)? {
+            cleared += 1;
+        }
+    }
+    Ok(cleared)
+}
+
+/// Reads whether a file or directory is immutable or append-only.
+///
+/// # Arguments
+///
+/// * `path` - The file or directory. Symlinks can't carry the attributes.
+///
+/// # Returns
+///
+/// * `Option<&'static str>` - 'immutable' or 'append-only', or None if it is neither.
+#[cfg(target_os = "linux")]
+fn attribute(path: &Path) -> Option<&'static str> {
+    let flags = flags(path).ok()?.1;
+    if flags & IMMUTABLE != 0 {
+        Some("immutable")
+    } else if flags & APPEND_ONLY != 0 {
+        Some("append-only")
+    } else {
+        None
+    }
+}
+
+/// Reads whether a file or directory is immutable or append-only.
+///
+/// # Returns
+///
+/// * `Option<&'static str>` - Always None, the attributes only exist on Linux.
+#[cfg(not(target_os = "linux"))]
+fn attribute(_path: &Path) -> Option<&'static str> {
+    None
+}
+
+/// Opens a file or directory and reads its attributes.
+///
+/// # Arguments
+///
+/// * `path` - The file or directory.
+///
+/// # Returns
+///
+/// * `io::Result<(std::fs::File, libc::c_int)>` - The open object and its attribute flags.
+#[cfg(target_os = "linux")]
+fn flags(path: &Path) -> io::Result<(std::fs::File, libc::c_int)> {
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::os::unix::io::AsRawFd;
+    // opening for reading is allowed on protected objects, and never blocks on FIFOs
+    let file = std::fs::OpenOptions::new().read(true).custom_flags(libc::O_NOFOLLOW | libc::O_NONBLOCK).open(path)?;
+    let mut flags: libc::c_int = 0;
+    if unsafe { libc::ioctl(file.as_raw_fd(), libc::FS_IOC_GETFLAGS, &mut flags) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok((file, flags))
+}
+
+/// Clears the immutable and append-only attributes of a file or directory.
+///
+/// # Arguments
+///
+/// * `path` - The file or directory.
+///
+/// # Returns
+///
+/// * `io::Result<bool>` - Ok with true if an attribute was cleared, false if there was none.
+#[cfg(target_os = "linux")]
+fn clear(path: &Path) -> io::Result<bool> {
+    use std::os::unix::io::AsRawFd;
+    let (file, flags) = match flags(path) {
+        Ok(found) => found,
+        // filesystems without attributes have nothing to clear
+        Err(e) if e.raw_os_error() == Some(libc::ENOTTY) || e.raw_os_error() == Some(libc::EOPNOTSUPP) => return Ok(false),
+        Err(e) => return Err(e),
+    };
+    if flags & (IMMUTABLE | APPEND_ONLY) == 0 {
+        return Ok(false);
+    }
+    let cleared = flags & !(IMMUTABLE | APPEND_ONLY);
+    if unsafe { libc::ioctl(file.as_raw_fd(), libc::FS_IOC_SETFLAGS, &cleared) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(true)
+}
+
+/// Clears the immutable and append-only attributes of a file or directory.
+///
+/// # Returns
+///
+/// * `io::Result<bool>` - Always Ok with false, the attributes only exist on Linux.
+#[cfg(not(target_os = "linux"))]
+fn clear(_path: &Path) -> io::Result<bool> {
+    Ok(false)
+}
@@ -0,0 +1,170 @@
+//! This module keeps files that running processes have open for '--skip-in-use'.
+//!
+//! Deleting a log file that a service still writes to only removes its name: the service goes on
+//! writing into a file nobody can find, and the space isn't freed until it is restarted. On Linux,
+//! every file descriptor in /proc/<pid>/fd and every file mapped in /proc/<pid>/maps is looked up,
+//! and a file that one of them refers to is kept, as is a directory tree holding such a file. The
+//! list is read when the run starts and read again whenever it is older than a couple of seconds,
+//! so a file that was opened just before it is deleted can still slip through. Processes of other
+//! users can only be inspected with privilege. On other platforms no files are found in use.
+
+use std::collections::HashSet;
+use std::fs::Metadata;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use tokio::task;
+
+// How long a list of open files is used before it is read again.
+const REFRESH: Duration = Duration::from_secs(2);
+
+/// The files held open by running processes at some point in time.
+#[derive(Debug)]
+struct Snapshot {
+    taken: Instant,
+    // device and inode of every open file, which also covers files reached through another name
+    inodes: HashSet<(u64, u64)>,
+    paths: Vec<PathBuf>,
+}
+
+/// The files in use by other processes, and the ones that were kept because of that.
+#[derive(Debug)]
+pub struct InUse {
+    snapshot: Mutex<Snapshot>,
+    kept: Mutex<Vec<PathBuf>>,
+}
+
+impl InUse {
+    /// Reads the files that running processes have open.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - A new instance of InUse.
+    pub fn load() -> Self {
+        Self { snapshot: Mutex::new(scan()), kept: Mutex::new(Vec::new()) }
+    }
+
+    /// Checks whether a file or symlink is open in another process.
+    ///
+    /// # Arguments
+    ///
+    /// * `metadata` - The metadata of the file, read without following symlinks.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if it is in use.
+    pub async fn is_open(&self, metadata: &Metadata) -> bool {
+        self.current().await.inodes.contains(&(metadata.dev(), metadata.ino()))
+    }
+
+    /// Finds a file within a directory that another process has open.
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - The canonicalized directory.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<PathBuf>` - One of the open files, or None if nothing within it is in use.
+    pub async fn open_within(&self, dir: &Path) -> Option<PathBuf> {
+        self.current().await.paths.iter().find(|path| path.starts_with(dir)).cloned()
+    }
+
+    /// Records a path that was kept because it was in use.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The file or directory.
+    pub async fn record(&self, path: &Path) {
+        self.kept.lock().await.push(path.to_path_buf());
+    }
+
+    /// Retrieves the paths that were kept because they were in use.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<PathBuf>` - The kept paths in path order.
+    pub async fn kept(&self) -> Vec<PathBuf> {
+        let mut kept = self.kept.lock().await.clone();
+        kept.sort();
+        kept
+    }
+
+    /// Retrieves the list of open files, reading it again if it is out of date.
+    ///
+    /// # Returns
+    ///
+    /// * `tokio::sync::MutexGuard<'_, Snapshot>` - The current list.
+    async fn current(&self) -> tokio::sync::MutexGuard<'_, Snapshot> {
+        let mut snapshot = self.snapshot.lock().await;
+        if snapshot.taken.elapsed() >= REFRESH {
+            if let Ok(fresh) = task::spawn_blocking(scan).await {
+                *snapshot = fresh;
+            }
+        }
+        snapshot
+    }
+}
+
+/// Reads the files that running processes other than this one have open or mapped.
+///
+/// # Returns
+///
+/// * `Snapshot` - The open files.
+#[cfg(target_os = "linux")]
+fn scan() -> Snapshot {
+    let mut snapshot = Snapshot { taken: Instant::now(), inodes: HashSet::new(), paths: Vec::new() };
+    let processes = match std::fs::read_dir("/proc") {
+        Ok(processes) => processes,
+        Err(_) => return snapshot,
+    };
+    // nukem's own handles are the directories it is crawling and its log file
+    let own_pid = std::process::id();
+    for process in processes.filter_map(Result::ok) {
+        match process.file_name().to_string_lossy().parse::<u32>() {
+            Ok(pid) if pid != own_pid => {}
+            _ => continue,
+        }
+        if let Ok(fds) = std::fs::read_dir(process.path().join("fd")) {
+            for fd in fds.filter_map(Result::ok) {
+                // pipes, sockets and the like don't resolve to a path
+                let (metadata, target) = match (std::fs::metadata(fd.path()), std::fs::read_link(fd.path())) {
+                    (Ok(metadata), Ok(target)) if target.is_absolute() => (metadata, target),
+                    _ => continue,
+                };
+                snapshot.inodes.insert((metadata.dev(), metadata.ino()));
+                snapshot.paths.push(target);
+            }
+        }
+        let maps = std::fs::read_to_string(process.path().join("maps")).unwrap_or_default();
+        // columns: address perms offset dev inode path
+        for line in maps.lines() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let (dev, inode, path) = match (fields.get(3), fields.get(4).and_then(|inode| inode.parse::<u64>().ok()), fields.get(5)) {
+                (Some(dev), Some(inode), Some(path)) if inode != 0 && path.starts_with('/') => (dev, inode, path),
+                _ => continue,
+            };
+            let dev = match dev.split_once(':').map(|(major, minor)| (u32::from_str_radix(major, 16), u32::from_str_radix(minor, 16))) {
+                Some((Ok(major), Ok(minor))) => libc::makedev(major, minor),
+                _ => continue,
+            };
+            if snapshot.inodes.insert((dev, inode)) {
+                snapshot.paths.push(PathBuf::from(path));
+            }
+        }
+    }
+    snapshot.paths.sort();
+    snapshot.paths.dedup();
+    snapshot
+}
+
+/// Reads the files that running processes other than this one have open or mapped.
+///
+/// # Returns
+///
+/// * `Snapshot` - Always empty, open files can only be found on Linux.
+#[cfg(not(target_os = "linux"))]
+fn scan() -> Snapshot {
+    Snapshot { taken: Instant::now(), inodes: HashSet::new(), paths: Vec::new() }
+}
@@ -0,0 +1,179 @@
+//! This module writes the event journal of '--event-journal', a compact binary record of what a
+//! run removed from the targets.
+//!
+//! Indexers, search engines and backup catalogs otherwise have to scan a whole tree again after a
+//! purge to find out which of their records are gone. The journal lists exactly those objects, in
+//! the order they disappeared, so they can be invalidated directly. A file that was compressed or
+//! moved away counts as removed from where it was, and a directory tree removed as a whole is a
+//! single event, covering everything below its path.
+//!
+//! Numbers are unsigned LEB128 varints. The journal starts with the 8 bytes 'NUKEMJNL', a format
+//! version byte (1) and the start of the run in seconds since the epoch, followed by one event per
+//! removed object:
+//!
+//! ```text
+//! kind  1 byte: 1 file, 2 symlink, 3 directory tree
+//! time  milliseconds since the start of the run
+//! dev   device number
+//! ino   inode number
+//! size  size in bytes (0 for directories)
+//! path  length, followed by the raw bytes of the path
+//! ```
+//!
+//! Nothing is written in a dry run, since nothing is removed. A journal cut short by a crash ends
+//! in a partial event, which readers should ignore.
+
+use std::fs::{File, Metadata};
+use std::io::{self, BufWriter, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex;
+
+// Identifies an event journal, followed by the version of its format.
+const MAGIC: &[u8; 8] = b"NUKEMJNL";
+const VERSION: u8 = 1;
+
+/// What kind of object an event removed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EventKind {
+    /// A regular file.
+    File = 1,
+    /// A symlink.
+    Symlink = 2,
+    /// A directory, along with everything within it.
+    Directory = 3,
+}
+
+impl EventKind {
+    /// Finds the kind of event for an object.
+    ///
+    /// # Arguments
+    ///
+    /// * `metadata` - The metadata of the object, read without following symlinks.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The kind of event.
+    pub fn of(metadata: &Metadata) -> Self {
+        if metadata.is_dir() {
+            EventKind::Directory
+        } else if metadata.file_type().is_symlink() {
+            EventKind::Symlink
+        } else {
+            EventKind::File
+        }
+    }
+}
+
+/// The open journal and what has been written to it.
+struct State {
+    writer: BufWriter<File>,
+    events: u64,
+    // the first write that failed; later events are dropped
+    error: Option<io::Error>,
+}
+
+/// Shared writer of the event journal. Cloning it shares the underlying file, so every worker
+/// writes into the same journal.
+#[derive(Clone)]
+pub struct Journal {
+    path: PathBuf,
+    started: Instant,
+    state: Arc<Mutex<State>>,
+}
+
+impl Journal {
+    /// Creates the journal and writes its header, replacing any earlier journal at the path.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The file to write.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, String>` - Ok with the journal, Err with a message if it can't be written.
+    pub fn create(path: &Path) -> Result<Self, String> {
+        let fail = |e: io::Error| format!("Unable to write the event journal {}: {}", path.display(), e);
+        let mut writer = BufWriter::new(File::create(path).map_err(fail)?);
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let mut header = MAGIC.to_vec();
+        header.push(VERSION);
+        push_varint(&mut header, now);
+        writer.write_all(&header).map_err(fail)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            started: Instant::now(),
+            state: Arc::new(Mutex::new(State { writer, events: 0, error: None })),
+        })
+    }
+
+    /// Records an object that was removed.
+    ///
+    /// # Arguments
+    ///
+    /// * `kind` - What kind of object it was.
+    /// * `path` - The path of the object.
+    /// * `metadata` - The metadata of the object, read before it was removed.
+    pub async fn record(&self, kind: EventKind, path: &Path, metadata: &Metadata) {
+        let bytes = path.as_os_str().as_bytes();
+        let mut event = Vec::with_capacity(bytes.len() + 32);
+        event.push(kind as u8);
+        push_varint(&mut event, self.started.elapsed().as_millis() as u64);
+        push_varint(&mut event, metadata.dev());
+        push_varint(&mut event, metadata.ino());
+        push_varint(&mut event, if kind == EventKind::Directory { 0 } else { metadata.len() });
+        push_varint(&mut event, bytes.len() as u64);
+        event.extend_from_slice(bytes);
+        let mut state = self.state.lock().await;
+        if state.error.is_some() {
+            return;
+        }
+        match state.writer.write_all(&event) {
+            Ok(()) => state.events += 1,
+            Err(e) => state.error = Some(e),
+        }
+    }
+
+    /// Writes out what is still buffered and makes sure it reached the disk.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<u64, String>` - Ok with the number of events written, Err with a message if the
+    ///   journal is incomplete.
+    pub async fn finish(&self) -> Result<u64, String> {
+        let mut state = self.state.lock().await;
+        let result = match state.error.take() {
+            Some(e) => Err(e),
+            None => state.writer.flush().and_then(|()| state.writer.get_ref().sync_data()),
+        };
+        result.map(|()| state.events).map_err(|e| format!(
+            "The event journal {} is incomplete after {} events: {}", self.path.display(), state.events, e
+        ))
+    }
+
+    /// Retrieves the path of the journal.
+    ///
+    /// # Returns
+    ///
+    /// * `&Path` - The file the journal is written to.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// Appends a number as an unsigned LEB128 varint.
+///
+/// # Arguments
+///
+/// * `out` - The bytes to append to.
+/// * `value` - The number.
+fn push_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
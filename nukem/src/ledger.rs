@@ -0,0 +1,242 @@
+//! This module keeps the ledger of '--cooldown', which stops overlapping runs from deleting a
+//! path again right after another run deleted it.
+//!
+//! Cleanup policies scheduled on the same trees often overlap, and an application that keeps
+//! re-creating a file or directory would see it deleted every time one of them runs. With
+//! '--cooldown', every object a run deletes is added to a ledger in the state directory, with its
+//! inode, its modification time and when it was deleted. A later run with '--cooldown' keeps any
+//! object whose path, or a directory above it, was deleted within the cooldown: it was re-created
+//! since, and is counted as "in cooldown". Dry runs read the ledger but don't add to it.
+//!
+//! The ledger is a text file with a header line followed by one tab-separated line per path,
+//! with the time of the deletion in seconds since the epoch, the inode, the modification time and
+//! the path, escaped like the undo manifest. It is rewritten at the end of every run that isn't a
+//! dry run, under a lock so concurrent runs don't lose each other's entries, and entries older
+//! than the cooldown of that run are dropped.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File, Metadata, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex;
+use crate::undo::{escape, unescape};
+
+const HEADER: &str = "# nukem ledger 1";
+
+/// A deletion recorded in the ledger.
+#[derive(Debug, Clone, Copy)]
+pub struct Entry {
+    /// When the object was deleted, in seconds since the epoch.
+    pub deleted: i64,
+    /// The inode of the object.
+    pub inode: u64,
+    /// The modification time of the object when it was deleted.
+    pub mtime: i64,
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let time = |seconds: i64| chrono::DateTime::from_timestamp(seconds, 0).map(|time| time.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M:%S").to_string()).unwrap_or_default();
+        write!(f, "inode {}, modified {}, deleted {}", self.inode, time(self.mtime), time(self.deleted))
+    }
+}
+
+/// The ledger of recent deletions. Cloning it shares the deletions of the run, so every worker
+/// records into the same ledger.
+#[derive(Clone)]
+pub struct Ledger {
+    path: PathBuf,
+    cooldown: Duration,
+    // the deletions of earlier runs within the cooldown, by path
+    recent: Arc<HashMap<PathBuf, Entry>>,
+    // the deletions of this run
+    deleted: Arc<Mutex<Vec<(PathBuf, Entry)>>>,
+}
+
+impl Ledger {
+    /// Reads the deletions within the cooldown from the ledger in the state directory.
+    ///
+    /// # Arguments
+    ///
+    /// * `state_dir` - The state directory.
+    /// * `cooldown` - How long a deleted path is left alone.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, String>` - Ok with the ledger, which is empty if there is none yet, Err
+    ///   with a message if it can't be read.
+    pub fn load(state_dir: &Path, cooldown: Duration) -> Result<Self, String> {
+        let path = state_dir.join("ledger");
+        let recent = read(&path, cutoff(cooldown)).map_err(|e| format!("Unable to read the ledger {}: {}", path.display(), e))?;
+        Ok(Self {
+            path,
+            cooldown,
+            recent: Arc::new(recent),
+            deleted: Arc::new(Mutex::new(Vec::new())),
+        })
+    }
+
+    /// Retrieves the file of the ledger.
+    ///
+    /// # Returns
+    ///
+    /// * `&Path` - The ledger.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Finds whether an object is within the cooldown of an earlier deletion.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The object.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<(PathBuf, Entry)>` - The path that was deleted within the cooldown, the object's
+    ///   own or a directory above it, and its deletion, or None if the object may be deleted.
+    pub fn deleted_within(&self, path: &Path) -> Option<(PathBuf, Entry)> {
+        if self.recent.is_empty() {
+            return None;
+        }
+        let path = std::path::absolute(path).ok()?;
+        path.ancestors().find_map(|ancestor| self.recent.get(ancestor).map(|entry| (ancestor.to_path_buf(), *entry)))
+    }
+
+    /// Records an object the run deleted.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The object.
+    /// * `metadata` - The metadata of the object, read before it was deleted.
+    pub async fn record(&self, path: &Path, metadata: &Metadata) {
+        let Ok(path) = std::path::absolute(path) else {
+            return;
+        };
+        let entry = Entry { deleted: now(), inode: metadata.ino(), mtime: metadata.mtime() };
+        self.deleted.lock().await.push((path, entry));
+    }
+
+    /// Adds the deletions of the run to the ledger and drops the entries older than the cooldown.
+    /// The ledger is read again under a lock first, so the entries of runs that ended in the
+    /// meantime are kept.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(usize, usize), String>` - Ok with the number of deletions added and of entries
+    ///   in the ledger, Err with a message if it can't be written.
+    pub async fn save(&self) -> Result<(usize, usize), String> {
+        let deleted = self.deleted.lock().await.clone();
+        let (added, path, cooldown) = (deleted.len(), self.path.clone(), self.cooldown);
+        let fail = |e: io::Error| format!("Unable to write the ledger {}: {}", self.path.display(), e);
+        let total = tokio::task::spawn_blocking(move || write(&path, cutoff(cooldown), &deleted)).await.map_err(|e| e.to_string())?.map_err(fail)?;
+        Ok((added, total))
+    }
+}
+
+/// Reads the entries of a ledger.
+///
+/// # Arguments
+///
+/// * `path` - The ledger.
+/// * `cutoff` - The time entries have to be deleted after to be kept.
+///
+/// # Returns
+///
+/// * `io::Result<HashMap<PathBuf, Entry>>` - Ok with the entries by path, which is empty if the
+///   ledger doesn't exist, Err if it can't be read or isn't a ledger.
+fn read(path: &Path, cutoff: i64) -> io::Result<HashMap<PathBuf, Entry>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(e) => return Err(e),
+    };
+    let mut lines = BufReader::new(file).lines();
+    if lines.next().transpose()?.is_some_and(|header| header != HEADER) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "it is not a ledger"));
+    }
+    let mut entries = HashMap::new();
+    for line in lines {
+        let line = line?;
+        let fields: Vec<&str> = line.split('\t').collect();
+        // a line cut short by a crash is dropped along with anything else it can't make sense of
+        let entry = match fields[..] {
+            [deleted, inode, mtime, path] => deleted.parse().ok()
+                .zip(inode.parse().ok())
+                .zip(mtime.parse().ok())
+                .map(|((deleted, inode), mtime)| (unescape(path), Entry { deleted, inode, mtime })),
+            _ => None,
+        };
+        if let Some((path, entry)) = entry.filter(|(_, entry)| entry.deleted >= cutoff) {
+            entries.insert(path, entry);
+        }
+    }
+    Ok(entries)
+}
+
+/// Merges deletions into a ledger and replaces it with the result, under a lock on
+/// 'ledger.lock' next to it.
+///
+/// # Arguments
+///
+/// * `path` - The ledger.
+/// * `cutoff` - The time entries have to be deleted after to be kept.
+/// * `deleted` - The deletions to add.
+///
+/// # Returns
+///
+/// * `io::Result<usize>` - Ok with the number of entries in the ledger, Err if it can't be written.
+fn write(path: &Path, cutoff: i64, deleted: &[(PathBuf, Entry)]) -> io::Result<usize> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let lock = OpenOptions::new().write(true).create(true).truncate(false).open(path.with_extension("lock"))?;
+    // the lock is released when the file is closed
+    if unsafe { libc::flock(lock.as_raw_fd(), libc::LOCK_EX) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut entries = read(path, cutoff)?;
+    for (object, entry) in deleted {
+        entries.insert(object.clone(), *entry);
+    }
+    let mut sorted: Vec<(&PathBuf, &Entry)> = entries.iter().collect();
+    sorted.sort_by_key(|(_, entry)| entry.deleted);
+    let temporary = path.with_extension(format!("tmp.{}", std::process::id()));
+    let mut writer = BufWriter::new(File::create(&temporary)?);
+    writeln!(writer, "{}", HEADER)?;
+    for (object, entry) in &sorted {
+        writeln!(writer, "{}\t{}\t{}\t{}", entry.deleted, entry.inode, entry.mtime, escape(object))?;
+    }
+    let written = writer.flush().and_then(|()| writer.get_ref().sync_all()).and_then(|()| fs::rename(&temporary, path));
+    if written.is_err() {
+        let _ = fs::remove_file(&temporary);
+    }
+    written.map(|()| sorted.len())
+}
+
+/// Finds the time entries have to be deleted after to be within a cooldown.
+///
+/// # Arguments
+///
+/// * `cooldown` - The cooldown.
+///
+/// # Returns
+///
+/// * `i64` - The time, in seconds since the epoch.
+fn cutoff(cooldown: Duration) -> i64 {
+    now() - cooldown.as_secs() as i64
+}
+
+/// Retrieves the current time.
+///
+/// # Returns
+///
+/// * `i64` - The time, in seconds since the epoch.
+fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() as i64
+}
@@ -0,0 +1,106 @@
+//! This module provides a cooperative lock for runs that share a filesystem.
+//!
+//! On clustered filesystems (NFS, Lustre, GPFS) several nodes may be scheduled to run the same
+//! cleanup. When '--lock-file' is given, the run creates the lockfile exclusively before doing any
+//! work and removes it when done. Another node that finds the lockfile waits for it (up to
+//! '--lock-timeout') or gives up and reports who holds it. A lockfile left behind by a process
+//! that no longer exists on the same host is considered stale and taken over.
+
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use chrono::Local;
+
+/// A held run lock. The lockfile is removed when the lock is dropped.
+pub struct RunLock {
+    path: PathBuf,
+    owner: String,
+}
+
+impl RunLock {
+    // How often a held lock is retried while waiting.
+    const RETRY_INTERVAL: Duration = Duration::from_millis(500);
+
+    /// Acquires the lock, waiting for another holder to release it if a timeout is given.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the lockfile on the shared filesystem.
+    /// * `timeout` - How long to wait for the lock. Zero fails immediately if it is held.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<RunLock, String>` - Ok with the held lock, Err with a message naming the holder.
+    pub fn acquire(path: &Path, timeout: Duration) -> Result<RunLock, String> {
+        let owner = format!("{} {}", hostname(), std::process::id());
+        // a timeout too long to add to the clock waits for as long as it takes
+        let deadline = Instant::now().checked_add(timeout);
+        loop {
+            // O_EXCL creation is atomic, including on NFSv3 and later
+            match OpenOptions::new().write(true).create_new(true).open(path) {
+                Ok(mut file) => {
+                    writeln!(file, "{} {}", owner, Local::now().format("%Y-%m-%d %H:%M:%S"))
+                        .map_err(|e| format!("Failed to write lockfile '{}': {}", path.display(), e))?;
+                    return Ok(RunLock { path: path.to_path_buf(), owner });
+                }
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                    let holder = fs::read_to_string(path).unwrap_or_default();
+                    if Self::is_stale(&holder) {
+                        // remove the stale lockfile and race for it again
+                        let _ = fs::remove_file(path);
+                        continue;
+                    }
+                    if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                        return Err(format!("Lockfile '{}' is held by {}", path.display(), holder.trim()));
+                    }
+                    std::thread::sleep(Self::RETRY_INTERVAL);
+                }
+                Err(e) => return Err(format!("Failed to create lockfile '{}': {}", path.display(), e)),
+            }
+        }
+    }
+
+    /// Checks whether a lockfile was left behind by a process that no longer exists. This can
+    /// only be decided for processes on the current host.
+    ///
+    /// # Arguments
+    ///
+    /// * `holder` - The contents of the lockfile.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if the holder is known to be gone.
+    fn is_stale(holder: &str) -> bool {
+        let mut fields = holder.split_whitespace();
+        match (fields.next(), fields.next().and_then(|pid| pid.parse::<u32>().ok())) {
+            (Some(host), Some(pid)) => host == hostname() && !Path::new(&format!("/proc/{}", pid)).exists(),
+            _ => false,
+        }
+    }
+}
+
+impl Drop for RunLock {
+    fn drop(&mut self) {
+        // only remove the lockfile if it is still ours
+        let holder = fs::read_to_string(&self.path).unwrap_or_default();
+        if holder.starts_with(&self.owner) {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Reads the name of the current host.
+///
+/// # Returns
+///
+/// * `String` - The hostname, or "unknown" if it can't be read.
+pub fn hostname() -> String {
+    let mut buffer = [0u8; 256];
+    // SAFETY: the buffer is valid for its full length and gethostname NUL-terminates on success
+    if unsafe { libc::gethostname(buffer.as_mut_ptr() as *mut libc::c_char, buffer.len()) } != 0 {
+        return "unknown".into();
+    }
+    let end = buffer.iter().position(|&b| b == 0).unwrap_or(buffer.len());
+    String::from_utf8_lossy(&buffer[..end]).into_owned()
+}
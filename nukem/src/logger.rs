@@ -6,27 +6,55 @@
 //!        specified, then only the reports are printed
 //! '-l' : If this was specified, a logfile is created and all events will be written to it, in
 //!        addition to the console.
+//!
+//! With '--syslog', every message is also sent to a remote syslog server (see syslog.rs).
+//!
+//! Everything is printed through the console coordinator (see console.rs), in the order it was
+//! logged. While a view such as the live dashboard ('--dashboard') has taken the console over,
+//! nothing is printed and only the logfile is written. The most recent errors are kept so the
+//! dashboard can display them.
 
+use std::collections::VecDeque;
 use std::fs::OpenOptions;
 use std::io::Write;
 use std::sync::{Arc, Mutex};
 use tokio::sync::mpsc::{self, Sender};
+use tokio::sync::oneshot;
 use chrono::Local;
+use crate::console::{Console, Stream};
+use crate::syslog::{Severity, Syslog};
+
+/// Messages handled by the logging task.
+enum LogMessage {
+    /// A formatted line to print and write to the logfile, with the severity and the plain
+    /// message sent to the syslog server.
+    Line(String, Severity, String),
+    /// A request to acknowledge once every line queued before it has been written.
+    Flush(oneshot::Sender<()>),
+    /// A question to print before waiting for the answer on the console.
+    Prompt(String, oneshot::Sender<String>),
+}
 
 /// The `Logger` structure is responsible for logging messages.
 pub struct Logger {
-    sender: Sender<String>,
+    sender: Sender<LogMessage>,
     logfile: Option<Arc<Mutex<std::fs::File>>>,
     verbose: bool,
+    console: Arc<Console>,
+    recent_errors: Mutex<VecDeque<String>>,
 }
 
 impl Logger {
+    // Number of recent errors kept for the dashboard.
+    const RECENT_ERRORS: usize = 5;
+
     /// Creates a new `Logger` instance.
     ///
     /// # Arguments
     ///
     /// * `logfile_path` - An optional path to the logfile.
     /// * `verbose` - A boolean indicating whether verbosity is enabled.
+    /// * `syslog` - The syslog server messages are sent to, if any.
     ///
     /// # Returns
     ///
@@ -35,7 +63,7 @@ impl Logger {
     /// # Panics
     ///
     /// This function will panic if it fails to open the log file.
-    pub fn new(logfile_path: Option<String>, verbose: bool, buffer_size: usize) -> Arc<Self> {
+    pub fn new(logfile_path: Option<String>, verbose: bool, buffer_size: usize, mut syslog: Option<Syslog>) -> Arc<Self> {
         // set send and receive mpsc channel buffer size
         let (tx, mut rx) = mpsc::channel(buffer_size);
         let logfile = logfile_path.map(|path| {
@@ -49,21 +77,57 @@ impl Logger {
         });
 
         // create logger instance
-        let logger = Arc::new(Logger { sender: tx, logfile, verbose });
+        let logger = Arc::new(Logger {
+            sender: tx,
+            logfile,
+            verbose,
+            console: Console::new(),
+            recent_errors: Mutex::new(VecDeque::with_capacity(Self::RECENT_ERRORS)),
+        });
 
         let logger_clone = Arc::clone(&logger);
         // spawn a task that listens for messages on the receiving end ('rx')
         tokio::spawn(async move {
             while let Some(msg) = rx.recv().await {
-                // print the message to the console
-                println!("{}", msg);
+                let (msg, severity, plain) = match msg {
+                    LogMessage::Line(msg, severity, plain) => {
+                        // print the message, unless a view has taken the console over; errors
+                        // go to stderr as well
+                        if logger_clone.console.line(Stream::Stdout, &msg) && severity == Severity::Error {
+                            logger_clone.console.line(Stream::Stderr, &msg);
+                        }
+                        (msg, severity, plain)
+                    }
+                    LogMessage::Flush(ack) => {
+                        let _ = ack.send(());
+                        continue;
+                    }
+                    LogMessage::Prompt(question, answer) => {
+                        // nothing else is printed until the question is answered
+                        logger_clone.console.text(&format!("{} ", question));
+                        let reply = tokio::task::spawn_blocking(|| {
+                            let mut line = String::new();
+                            std::io::stdin().read_line(&mut line).map(|_| line).unwrap_or_default()
+                        }).await.unwrap_or_default();
+                        let reply = reply.trim().to_string();
+                        let msg = format!("{} {}", question, reply);
+                        let _ = answer.send(reply);
+                        (msg.clone(), Severity::Notice, msg)
+                    }
+                };
                 // if there is a logfile...
                 if let Some(ref file) = logger_clone.logfile {
                     // lock the logfile for safe, exclusive access
                     let mut file = file.lock().unwrap();
                     // write message to logfile
                     if let Err(e) = writeln!(file, "{}", msg) {
-                        eprintln!("Failed to write to log file: {:?}", e);
+                        logger_clone.console.line(Stream::Stderr, &format!("Failed to write to log file: {:?}", e));
+                    }
+                }
+                // sending may wait on the network, which mustn't hold up the other tasks
+                if let Some(ref mut syslog) = syslog {
+                    if let Some(error) = tokio::task::block_in_place(|| syslog.send(severity, &plain)) {
+                        logger_clone.console.line(Stream::Stderr, &error);
                     }
                 }
             }
@@ -104,10 +168,70 @@ impl Logger {
         };
 
         if is_error {
-            eprintln!("{}", formatted_message);
+            let mut recent_errors = self.recent_errors.lock().unwrap();
+            if recent_errors.len() == Self::RECENT_ERRORS {
+                recent_errors.pop_front();
+            }
+            recent_errors.push_back(formatted_message.clone());
         }
 
-        self.send_message(formatted_message).await;
+        let severity = if is_error {
+            Severity::Error
+        } else if verbose {
+            Severity::Info
+        } else {
+            Severity::Notice
+        };
+        self.send_message(formatted_message, severity, message).await;
+    }
+
+    /// Asks a question on the console and waits for the answer. The question is queued like
+    /// any message, so it comes after everything logged before it, and nothing else is printed
+    /// until it is answered. The question and the answer are written to the logfile.
+    ///
+    /// # Arguments
+    ///
+    /// * `question` - The question, printed without a newline.
+    ///
+    /// # Returns
+    ///
+    /// * `String` - The trimmed answer, empty if the console was closed.
+    pub async fn prompt(&self, question: &str) -> String {
+        let (answer, reply) = oneshot::channel();
+        if self.sender.send(LogMessage::Prompt(question.to_string(), answer)).await.is_err() {
+            return String::new();
+        }
+        reply.await.unwrap_or_default()
+    }
+
+    /// Waits until every message logged so far has been printed and written to the logfile.
+    /// This must be called before the application exits, otherwise queued messages are lost
+    /// when the runtime shuts down.
+    pub async fn flush(&self) {
+        let (ack, done) = oneshot::channel();
+        if self.sender.send(LogMessage::Flush(ack)).await.is_ok() {
+            let _ = done.await;
+        }
+    }
+
+    /// Retrieves the console the messages are printed to. A view that draws on the terminal
+    /// takes it over, which keeps the messages off the terminal until the view gives it back;
+    /// they are still written to the logfile, but not printed later.
+    ///
+    /// # Returns
+    ///
+    /// * `&Arc<Console>` - The console.
+    pub fn console(&self) -> &Arc<Console> {
+        &self.console
+    }
+
+    /// Retrieves the most recent error messages, oldest first.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<String>` - The recent error messages.
+    pub fn recent_errors(&self) -> Vec<String> {
+        self.recent_errors.lock().unwrap().iter().cloned().collect()
     }
 
     /// Sends a log message through the mpsc channel.
@@ -115,9 +239,11 @@ impl Logger {
     /// # Arguments
     ///
     /// * `message` - The message to send.
-    async fn send_message(&self, message: String) {
-        if self.sender.send(message).await.is_err() {
-            eprintln!("Failed to send message to logger");
+    /// * `severity` - The severity of the message on the syslog server.
+    /// * `plain` - The message without its prefixes, as sent to the syslog server.
+    async fn send_message(&self, message: String, severity: Severity, plain: &str) {
+        if self.sender.send(LogMessage::Line(message, severity, plain.to_string())).await.is_err() {
+            self.console.line(Stream::Stderr, "Failed to send message to logger");
         }
     }
 }
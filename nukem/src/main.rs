@@ -9,35 +9,156 @@ mod utility;
 mod crawler;
 mod deleter;
 mod config;
+mod watchdog;
+mod skipped;
+mod handles;
+mod freespace;
+mod history;
+mod alerts;
+mod lock;
+mod sockets;
+mod report;
+mod slowops;
+mod estimate;
+mod dashboard;
+mod retry;
+mod filters;
+mod regex;
+mod phases;
+mod excludes;
+mod journal;
+mod immutable;
+mod compress;
+mod sha256;
+mod relocate;
+mod refine;
+mod forecast;
+mod owners;
+mod purge;
+mod audit;
+mod userns;
+mod symlinks;
+mod patterns;
+mod cgroup;
+mod expression;
+mod failures;
+mod inuse;
+mod environment;
+mod content;
+mod prompt;
+mod completion;
+mod trash;
+mod undo;
+mod archive;
+mod termination;
+mod syslog;
+mod console;
+mod safefs;
+mod identity;
+mod plan;
+mod ledger;
+mod confirm;
+mod budget;
+mod sandbox;
+mod tiers;
 
 use std::sync::Arc;
 use std::time::Instant;
-use std::path::PathBuf;
-use tokio::sync::{mpsc, Mutex};
-use crate::crawler::Crawler;
-use crate::deleter::Deleter;
+use tokio::sync::Mutex;
+use crate::crawler::{Crawler, CrawlerOptions};
+use crate::prompt::Prompter;
+use crate::completion::Completion;
+use crate::deleter::{Deleter, DeleterOptions};
 use crate::logger::Logger;
-use crate::utility::{setup_channels, print_info, finalize};
+use crate::syslog::Syslog;
+use crate::utility::{setup_channels, print_info, finalize, expand_paths, print_open_handles, Counters, ReportSources, SharedReceiver};
 use crate::config::{define_threads, initialize_arguments};
 use crate::threads::ThreadInfo;
-use crate::args::Args;
+use crate::args::{Args, AuditCommand, Command};
+use crate::watchdog::Watchdog;
+use crate::freespace::SpaceProbe;
+use crate::alerts::Alerts;
+use crate::lock::RunLock;
+use crate::sockets::LiveSockets;
+use crate::inuse::InUse;
+use crate::report::DirReport;
+use crate::dashboard::{Dashboard, DashboardSources};
+use crate::filters::{ExtensionCounts, Filters};
+use crate::phases::{Phase, PhaseTimes};
+use crate::excludes::Excludes;
+use crate::safefs::Anchors;
+use crate::journal::Journal;
+use crate::trash::Trash;
+use crate::undo::Undo;
+use crate::plan::Plan;
+use crate::ledger::Ledger;
+use crate::budget::Budget;
+use crate::tiers::TierCounts;
+use crate::sandbox::Sandbox;
+use crate::archive::Archive;
+use crate::termination::Termination;
+use crate::symlinks::SymlinkCounts;
+use crate::slowops::DEFAULT_SLOWEST;
+use crate::cgroup::{Cgroup, Limits};
+use crate::owners::OwnerCounts;
 
 // this is an alias to improve readability and understandability
 type BoxedError = Box<dyn std::error::Error + Send + Sync>;
 
-/// Main function is asynchronous and makes heavy use of tokio concurrent tasks.
+/// Main function parses and validates the command-line arguments and, with '--sandbox',
+/// confines the process before the runtime starts its threads, which would stay unconfined
+/// otherwise. It then runs the application on the runtime.
+fn main() -> Result<(), BoxedError> {
+    let args = initialize_arguments()?;
+    // a run within a user namespace is confined by the copy of nukem started there, and the
+    // subcommands aren't confined
+    let sandbox = if args.sandbox && args.command.is_none() && (!args.userns || userns::is_inside()) {
+        Some(sandbox::restrict(&args)?)
+    } else {
+        None
+    };
+    tokio::runtime::Builder::new_multi_thread().enable_all().build()?.block_on(run(args, sandbox))
+}
+
+/// Runs the application. It is asynchronous and makes heavy use of tokio concurrent tasks.
 ///
-/// This function initializes the application by performing the following steps:
-/// 1. Parse command-line arguments and validate them.
+/// This function performs the following steps:
+/// 1. Take the parsed command-line arguments.
 /// 2. Set up the logger.
 /// 3. Define the number of threads to use based on the arguments.
 /// 4. Set up channels for inter-task communication.
 /// 5. Spawn tasks for file and directory crawling and deletion.
 /// 6. Await the completion of all tasks and print a summary report.
-#[tokio::main]
-async fn main() -> Result<(), BoxedError> {
-    // Parse command-line arguments and validate them.
-    let args = initialize_arguments()?;
+///
+/// # Arguments
+///
+/// * `args` - The parsed command-line arguments.
+/// * `sandbox` - What the run may still change, if it was confined with '--sandbox'.
+///
+/// # Returns
+///
+/// * `Result<(), BoxedError>` - Ok if the run completed, Err otherwise.
+async fn run(mut args: Args, sandbox: Option<Sandbox>) -> Result<(), BoxedError> {
+    // With '--userns', the run is done by a copy of nukem started within a user namespace.
+    if args.userns && !userns::is_inside() {
+        std::process::exit(userns::run_inside()?);
+    }
+    // Subcommands inspect the run history, calibrate a filesystem or carry out a manifest purge, and exit.
+    if let Some(ref command) = args.command {
+        return run_command(command, &args);
+    }
+    // '--explain-threads' shows how the thread count comes about, and exits.
+    if args.explain_threads {
+        for line in define_threads(&args)?.explain() {
+            println!("{}", line);
+        }
+        return Ok(());
+    }
+    // Take the coordination lock before doing any work. It is released when the run ends.
+    let _lock = match args.lock_file {
+        Some(ref path) => Some(RunLock::acquire(path, args.lock_timeout)?),
+        None => None,
+    };
     // Initialize the logger.
     let logger = initialize_logger(&args, args.buffer_size).await?;
 
@@ -49,68 +170,437 @@ async fn main() -> Result<(), BoxedError> {
 
     // Get the start time for calculating application runtime.
     let start = Instant::now();
+    let phases = PhaseTimes::new(start);
 
     // Print initial information about the run.
     print_info(&args, &logger, &thread_info, thread_info.total_thread_count).await?;
+    if let Some(ref sandbox) = sandbox {
+        let list = |dirs: &[std::path::PathBuf]| if dirs.is_empty() { "nothing".to_string() } else { dirs.iter().map(|dir| dir.display().to_string()).collect::<Vec<_>>().join(", ") };
+        logger.log(&format!("Sandbox: Landlock {}, removing within {}, writing to {}", sandbox.abi, list(&sandbox.removable), list(&sandbox.writable)), false, false, false).await;
+    }
+
+    // Contain the run in a cgroup with the requested limits. It is left when the run ends.
+    let _cgroup = if args.cgroup {
+        let cgroup = Cgroup::enter(&Limits::from_args(&args), &expand_paths(&args.paths, args.ignore_case))?;
+        logger.log(&format!("Running in cgroup {}", cgroup.path.display()), false, false, false).await;
+        if !cgroup.io_devices.is_empty() {
+            logger.log(&format!("I/O limits apply to devices {}", cgroup.io_devices.join(", ")), false, false, false).await;
+        }
+        Some(cgroup)
+    } else {
+        None
+    };
+
+    // When retrying a previous run, there may be nothing left to do.
+    if let Some(ref retry) = args.retry {
+        if retry.targets.is_empty() {
+            logger.log(&format!("Nothing to retry from '{}' ({} failed paths are already gone)", retry.source, retry.already_gone), false, false, false).await;
+            logger.flush().await;
+            return Ok(());
+        }
+    }
+
+    // Estimate the size of the purge from a sample and exit without deleting anything.
+    if args.estimate {
+        estimate::run(
+            expand_paths(&args.paths, args.ignore_case), args.sample, &logger, args.size_unit,
+            history::state_dir(args.state_dir.as_ref()), ThreadInfo::compute_thread_count(&args)?.total_thread_count
+        ).await?;
+        logger.flush().await;
+        return Ok(());
+    }
+
+    // Let the user refine the plan against a cached scan. An approved plan becomes a real run.
+    if args.refine {
+        match refine::run(&mut args, &logger).await? {
+            refine::Decision::Apply => args.dry_run = false,
+            refine::Decision::Quit => {
+                logger.log("Refinement ended without deleting anything", false, false, false).await;
+                logger.flush().await;
+                return Ok(());
+            }
+        }
+    }
+
+    // A run over '--confirm-over' only goes on once the operator typed the targets back.
+    if !args.dry_run && !args.confirm_over.is_empty() {
+        let confirmed = confirm::check(&args, &logger).await;
+        if confirmed.is_err() {
+            logger.flush().await;
+        }
+        confirmed?;
+    }
+
+    // Report processes that hold files open within the targets, if requested.
+    if args.check_open_handles {
+        let roots = handles::canonical_roots(&expand_paths(&args.paths, args.ignore_case));
+        let open_handles = tokio::task::spawn_blocking(move || handles::find_open_handles(&roots)).await?;
+        print_open_handles(&open_handles, &logger).await;
+    }
+
+    // Sample the free space of the target filesystems so the space actually freed can be
+    // reported. A dry run frees nothing, so there is nothing to compare.
+    let space_probe = if args.dry_run { None } else { SpaceProbe::capture(&expand_paths(&args.paths, args.ignore_case)) };
+
+    // Stop cleanly on SIGTERM, so a run killed at the end of a batch job still leaves its reports.
+    let termination = if !args.term_grace.is_zero() {
+        Some(Termination::listen(args.term_grace, Arc::clone(&logger))?)
+    } else if args.max_errors.is_some() {
+        Some(Termination::new())
+    } else {
+        None
+    };
 
     // Set up channels for inter-task communication.
     let (file_sender, dir_sender, file_receiver, dir_receiver) = setup_channels(&args);
+
+    // Start the memory watchdog if the user specified a memory limit. It watches the queues, so
+    // it can tell when throttling no longer frees anything.
+    let watchdog = args.memory_limit.map(Watchdog::new);
+    if let Some(ref watchdog) = watchdog {
+        watchdog.spawn(vec![file_sender.downgrade(), dir_sender.downgrade()], Arc::clone(&logger), args.size_unit);
+    }
     // Set up the deleter and shared state.
-    let (deleter, total_directories, total_files_symlinks, total_crawling_ops, total_stat_ops, total_deletion_ops) = setup_deleter(&args);
+    let filters = Arc::new(Filters::from_args(&args));
+    let excludes = Arc::new(Excludes::from_args(&args)?);
+    // nothing below the anchors of the targets is reached through a symlink
+    let anchors = Arc::new(Anchors::new(&args.paths));
+    // a dry run removes nothing, so there is nothing to journal
+    let journal = match args.event_journal {
+        Some(ref path) if !args.dry_run => Some(Journal::create(path)?),
+        Some(_) => {
+            logger.log("Not writing the event journal in a dry run", false, false, false).await;
+            None
+        }
+        None => None,
+    };
+    // a dry run only needs to know where the trash is
+    let trash = if args.trashes() { Some(Trash::open(!args.dry_run)?) } else { None };
+    let undo = if args.undoable {
+        let base = undo::base_dir(&args).ok_or("Unable to determine the undo directory. Use --undo-dir")?;
+        if args.dry_run {
+            logger.log("Not staging anything in a dry run", false, false, false).await;
+        }
+        Some(Undo::create(&base, !args.dry_run)?)
+    } else {
+        None
+    };
+    let archive = match args.archive_to {
+        Some(ref path) => {
+            if args.dry_run {
+                logger.log("Not writing the archive in a dry run", false, false, false).await;
+            }
+            Some(Archive::create(path, !args.dry_run)?)
+        }
+        None => None,
+    };
+    // 'nukem plan' records everything the dry run would delete
+    let plan = match args.plan {
+        Some(ref path) => Some(Plan::create(path, &args.paths, filters.is_active(), args.ignore_case)?),
+        None => None,
+    };
+    // what overlapping runs deleted within the cooldown is left alone
+    let ledger = match args.cooldown {
+        Some(cooldown) => {
+            let state_dir = history::state_dir(args.state_dir.as_ref()).ok_or("Unable to determine the state directory for --cooldown. Use --state-dir")?;
+            Some(Ledger::load(&state_dir, cooldown)?)
+        }
+        None => None,
+    };
+    let budget = Budget::from_args(&args, &logger);
+    let outputs = Outputs { journal, trash, undo, archive, plan, ledger };
+    let (deleter, counters) = setup_deleter(&args, &filters, &excludes, &anchors, outputs, termination.clone(), budget.clone());
 
-    // Spawn deleter tasks for files and directories.
+    let (skipped, dir_report, slow_ops, symlink_counts) = {
+        let deleter = deleter.lock().await;
+        (deleter.skipped.clone(), deleter.dir_report.clone(), deleter.slow_ops.clone(), deleter.symlink_counts.clone())
+    };
+
+    // Watch the alert thresholds while the run is in progress.
+    if let Some(alerts) = Alerts::from_args(&args) {
+        let failed_deletions = deleter.lock().await.failed_deletions.clone();
+        alerts.spawn(failed_deletions, start, Arc::clone(&logger));
+    }
+
+    // Everything up to here was preparation. The deleters start first and wait for the crawlers.
+    phases.record_until_now(Phase::Setup, start).await;
+    let delete_start = Instant::now();
+
+    // Spawn deleter tasks for files and directories. The directory pass sends the files it finds
+    // within directories to the file deleters as well, so they wait for both passes.
+    let file_pass = Completion::new();
+    let dir_pass = Completion::new();
     let deleter_handle_files = spawn_deleter_task(
-        &deleter, Arc::clone(&file_receiver), Arc::clone(&logger), args.verbose.clone(),
-        Arc::clone(&total_deletion_ops), Arc::clone(&total_directories), thread_info.clone(), true
+        &deleter, Arc::clone(&file_receiver), Arc::clone(&logger), args.verbose,
+        Arc::clone(&counters.deletion_ops), thread_info.clone(), vec![file_pass.clone(), dir_pass.clone()]
     );
 
     let deleter_handle_dirs = spawn_deleter_task(
-        &deleter, Arc::clone(&dir_receiver), Arc::clone(&logger), args.verbose.clone(),
-        Arc::clone(&total_deletion_ops), Arc::clone(&total_directories), thread_info.clone(), false
+        &deleter, Arc::clone(&dir_receiver), Arc::clone(&logger), args.verbose,
+        Arc::clone(&counters.deletion_ops), thread_info.clone(), vec![dir_pass.clone()]
     );
 
-    // Initialize the crawler.
-    let crawler = Crawler::new(
-        Arc::clone(&logger), file_sender.clone(), dir_sender.clone(), Arc::clone(&total_files_symlinks),
-        Arc::clone(&total_directories), Arc::clone(&total_crawling_ops), Arc::clone(&total_stat_ops), args.verbose
-    );
+    // Initialize the crawler. It shares the skip counter with the deleter.
+    let crawler = Crawler::new(Arc::clone(&logger), file_sender.clone(), dir_sender.clone(), &counters, CrawlerOptions {
+        verbose: args.verbose,
+        memory_pressure: watchdog.as_ref().map(|w| Arc::clone(&w.pressure)),
+        skipped: skipped.clone(),
+        ignore_case: args.ignore_case,
+        dir_report: dir_report.clone(),
+        slow_ops: slow_ops.clone(),
+        filters,
+        excludes,
+        phases: phases.clone(),
+        symlink_counts: symlink_counts.clone(),
+        prompter: Prompter::from_args(&args, &logger),
+        termination: termination.clone(),
+        one_file_system: args.one_file_system,
+        anchors,
+        kept_roots: Arc::new(if sandbox.is_some() { sandbox::target_roots(&args) } else { Vec::new() }),
+    });
+
+    let widest_dirs = crawler.widest_dirs();
+
+    // Show the live view while the run executes, if requested.
+    let dashboard = if args.dashboard {
+        let sources = DashboardSources {
+            total_files_symlinks: Arc::clone(&counters.files_symlinks),
+            total_directories: Arc::clone(&counters.directories),
+            deleter: deleter.lock().await.clone(),
+            file_queue: file_sender.downgrade(),
+            dir_queue: dir_sender.downgrade(),
+            queue_capacity: args.buffer_size,
+            workers: 2 * thread_info.total_thread_count,
+        };
+        Some(Dashboard::spawn(sources, Arc::clone(&logger), start).await)
+    } else {
+        None
+    };
 
     // Run crawler tasks for files and directories.
-    let crawler_handle_files = tokio::spawn(crawler.clone().run_crawlers_files(args.paths.clone()));
-    let crawler_handle_dirs = tokio::spawn(crawler.run_crawlers_dirs(args.paths.clone()));
+    let crawl_start = Instant::now();
+    let crawler_handle_files = tokio::spawn(crawler.clone().run_crawlers_files(args.paths.clone(), file_pass));
+    let crawler_handle_dirs = tokio::spawn(crawler.run_crawlers_dirs(args.paths.clone(), dir_pass));
 
     // Use the join! macro to run crawler and deleter tasks concurrently, then wait for all
     // of them to complete. If any errors happen, log them and continue working.
     tokio::join!(
+    // a terminated run doesn't wait for the crawlers, which may be stuck sending to the
+    // deleters that stopped
     async {
-        if let Err(e) = crawler_handle_files.await {
-            logger.log(&format!("Crawler error: {:?}", e), true, false, false).await;
+        tokio::select! {
+            crawled = crawler_handle_files => if let Err(e) = crawled {
+                logger.log(&format!("Crawler error: {:?}", e), true, false, false).await;
+            },
+            _ = termination::wait(termination.as_ref()) => {}
         }
-        drop(file_sender);
+        phases.record_until_now(Phase::Crawl, crawl_start).await;
     },
     async {
-        if let Err(e) = crawler_handle_dirs.await {
-            logger.log(&format!("Crawler error: {:?}", e), true, false, false).await;
+        tokio::select! {
+            crawled = crawler_handle_dirs => if let Err(e) = crawled {
+                logger.log(&format!("Crawler error: {:?}", e), true, false, false).await;
+            },
+            _ = termination::wait(termination.as_ref()) => {}
         }
-        drop(dir_sender);
+        phases.record_until_now(Phase::Crawl, crawl_start).await;
     },
     async {
         if let Err(e) = deleter_handle_files.await {
             logger.log(&format!("Deletion error: {:?}", e), true, false, false).await;
         }
+        phases.record_until_now(Phase::Delete, delete_start).await;
     },
     async {
         if let Err(e) = deleter_handle_dirs.await {
             logger.log(&format!("Deletion error: {:?}", e), true, false, false).await;
         }
+        phases.record_until_now(Phase::Delete, delete_start).await;
     }
 );
+    if let Some(dashboard) = dashboard {
+        dashboard.stop().await;
+    }
     // Print the final summary and report.
-    finalize(&deleter, &logger, start, total_directories, total_files_symlinks, total_crawling_ops, total_stat_ops, total_deletion_ops).await;
+    let interrupted = termination.as_ref().is_some_and(Termination::requested);
+    let sources = ReportSources {
+        start, counters, widest_dirs, space_probe, watchdog, phases: phases.clone(),
+        verbose: args.verbose,
+        list_slowest: args.verbose || args.slowest.is_some(),
+    };
+    let summary = finalize(&deleter, &logger, sources, args.size_unit, interrupted).await;
+    // Make sure the event journal is complete before anyone reads it.
+    let journal = deleter.lock().await.journal.clone();
+    if let Some(journal) = journal {
+        match journal.finish().await {
+            Ok(events) => logger.log(&format!("Event journal: {} removals written to {}", events, journal.path().display()), false, false, false).await,
+            Err(e) => logger.log(&e, true, false, false).await,
+        }
+    }
+    // End the archive, so it is complete on disk.
+    let archive = deleter.lock().await.archive.clone();
+    if let Some(archive) = archive.filter(|_| !args.dry_run) {
+        match archive.finish().await {
+            Ok(totals) => logger.log(&format!(
+                "Archive: {} objects in {} ({} compressed with {})",
+                totals.objects, archive.path().display(), utility::format_size(totals.bytes, args.size_unit), archive.compressor().program()
+            ), false, false, false).await,
+            Err(e) => logger.log(&e, true, false, false).await,
+        }
+    }
+    // Make sure the undo manifest is complete, and tell where it is.
+    let undo = deleter.lock().await.undo.clone();
+    if let Some(undo) = undo.filter(|_| !args.dry_run) {
+        match undo.finish().await {
+            Ok(objects) => {
+                logger.log(&format!("Undo manifest: {} objects staged in {} recorded in {}", objects, undo.dir().display(), undo.manifest().display()), false, false, false).await;
+                logger.log(&format!("Restore with: nukem restore {}", undo.manifest().display()), false, false, false).await;
+            }
+            Err(e) => logger.log(&e, true, false, false).await,
+        }
+    }
+    // End the plan, and tell how to apply it. A run that was stopped leaves an incomplete plan.
+    let plan = deleter.lock().await.plan.clone();
+    if let Some(plan) = plan {
+        match plan.finish(!interrupted).await {
+            Ok((objects, bytes)) => {
+                logger.log(&format!("Plan: {} objects ({}) written to {}", objects, utility::format_size(bytes, args.size_unit), plan.path().display()), false, false, false).await;
+                logger.log(&format!("Review it, then delete exactly these objects with: nukem apply {}", plan.path().display()), false, false, false).await;
+            }
+            Err(e) => logger.log(&e, true, false, false).await,
+        }
+    }
+    // Add what the run deleted to the ledger, for the cooldown of later runs.
+    let ledger = deleter.lock().await.ledger.clone();
+    if let Some(ledger) = ledger.filter(|_| !args.dry_run) {
+        match ledger.save().await {
+            Ok((added, total)) => logger.log(&format!("Ledger: {} deletions added, {} within the cooldown in {}", added, total, ledger.path().display()), false, false, false).await,
+            Err(e) => logger.log(&e, true, false, false).await,
+        }
+    }
+    // Show how the retried failures fared.
+    if let Some(ref retry) = args.retry {
+        retry.print_delta(&logger, args.dry_run).await;
+    }
+    // Write the JSON report if the user asked for one.
+    if let (Some(path), Some(dir_report)) = (&args.json_report, &dir_report) {
+        if let Err(e) = report::write(path, &summary, &skipped, dir_report, &slow_ops, &phases, symlink_counts.as_ref()).await {
+            logger.log(&format!("Failed to write JSON report {}: {}", path.display(), e), true, false, false).await;
+        }
+    }
+    // Record the run in the history unless the user opted out.
+    let mut run_id = None;
+    if !args.no_history {
+        if let Some(state_dir) = history::state_dir(args.state_dir.as_ref()) {
+            let record = history::RunRecord::new(&args, &summary);
+            match history::save(&state_dir, &record) {
+                Ok(_) => run_id = Some(record.run_id.clone()),
+                Err(e) => logger.log(&format!("Failed to record run history in {}: {}", state_dir.display(), e), true, false, false).await,
+            }
+            // keep the failures so they can be retried later
+            let failed_paths = deleter.lock().await.get_failed_paths().await;
+            if !failed_paths.is_empty() {
+                match history::save_failures(&state_dir, &record.run_id, &failed_paths) {
+                    Ok(_) => logger.log(&format!("Retry the failed paths with: nukem --retry-failed {}", record.run_id), false, false, false).await,
+                    Err(e) => logger.log(&format!("Failed to save the failed paths in {}: {}", state_dir.display(), e), true, false, false).await,
+                }
+                let failures = deleter.lock().await.get_failures().await;
+                match history::save_failure_details(&state_dir, &record.run_id, &failures) {
+                    Ok(path) => logger.log(&format!("Details of the failures: {}", path.display()), false, false, false).await,
+                    Err(e) => logger.log(&format!("Failed to save the details of the failures in {}: {}", state_dir.display(), e), true, false, false).await,
+                }
+            }
+        }
+    }
+    // Send each owner their share of the run, unless nothing was actually deleted.
+    if args.mail_owners {
+        let owner_counts = deleter.lock().await.owner_counts.clone();
+        match owner_counts {
+            Some(_) if args.dry_run => logger.log("Not mailing the owners in a dry run", false, false, false).await,
+            Some(owner_counts) => {
+                let targets = args.paths.iter().map(|path| path.display().to_string()).collect::<Vec<_>>().join(" ");
+                owners::mail_owners(&owner_counts.totals().await, &targets, &logger, args.size_unit).await;
+            }
+            None => {}
+        }
+    }
+    if interrupted {
+        logger.log("The run was stopped early. Run the same command again to resume it", false, false, false).await;
+    }
+    // Hand the totals to the calling script.
+    if let Some(fd) = args.summary_fd {
+        if let Err(e) = report::write_summary(fd, &summary, &skipped, run_id.as_deref()).await {
+            logger.log(&format!("Failed to write the summary to file descriptor {}: {}", fd, e), true, false, false).await;
+        }
+    }
+    // Make sure the final report has been written before exiting.
+    logger.flush().await;
+    // A terminated run exits like one killed by SIGTERM, once what it holds is released.
+    if interrupted && termination.as_ref().is_some_and(Termination::signalled) {
+        drop(_lock);
+        drop(_cgroup);
+        std::process::exit(termination::EXIT_STATUS);
+    }
+    // A run stopped by its own failures fails too.
+    if interrupted {
+        return Err(format!("The run stopped after {} failed deletions (--max-errors)", summary.failed).into());
+    }
+    // A run that was cut short by its budget fails, so the calling script notices.
+    if let Some(ref budget) = budget {
+        if let Some(totals) = budget.exhausted().await {
+            return Err(format!("{}. Check what is left before running again", totals).into());
+        }
+    }
 
     Ok(())
 }
 
+/// Runs a subcommand.
+///
+/// # Arguments
+///
+/// * `command` - The subcommand to run.
+/// * `args` - A reference to the parsed command-line arguments.
+///
+/// # Returns
+///
+/// * `Result<(), BoxedError>` - Ok if successful, Err otherwise.
+fn run_command(command: &Command, args: &Args) -> Result<(), BoxedError> {
+    let state_dir = history::state_dir(args.state_dir.as_ref()).ok_or("Unable to determine the state directory. Use --state-dir")?;
+    match command {
+        Command::History => history::print_history(&history::list(&state_dir), args.size_unit),
+        Command::Show { run_id } => history::print_record(&history::load(&state_dir, run_id)?, args.size_unit),
+        Command::Bench { dir, files } => {
+            let threads = ThreadInfo::compute_thread_count(args)?.total_thread_count;
+            println!("Measuring metadata rates in '{}' with {} worker threads...", dir.display(), threads);
+            let calibration = forecast::bench(dir, threads, *files)?;
+            forecast::print_calibration(&calibration);
+            let path = forecast::save(&state_dir, &calibration).map_err(|e| format!("Unable to save the calibration: {}", e))?;
+            println!("Calibration saved to {}", path.display());
+        }
+        Command::Purge { manifest, root, key_file } => purge::run(manifest, root, &state_dir, key_file.as_ref(), args.dry_run)?,
+        Command::VerifyCertificate { certificate, key_file } => purge::verify_certificate(certificate, &state_dir, key_file.as_ref())?,
+        // the pipeline makes plans, as a dry run
+        Command::Plan { .. } => unreachable!("'nukem plan' runs as a dry run"),
+        Command::Apply { plan } => plan::apply(plan, args.dry_run, args.size_unit)?,
+        Command::Restore { manifest } => undo::restore(manifest, args.dry_run)?,
+        Command::Audit { command: AuditCommand::Verify { file, key_file } } => {
+            // the key is only needed for signed logs, so a missing default key is fine for the others
+            let key = match key_file {
+                Some(_) => Some(purge::load_key(&state_dir, key_file.as_ref(), false)?),
+                None => purge::load_key(&state_dir, None, false).ok(),
+            };
+            let verification = audit::verify(file, key.as_deref())?;
+            println!(
+                "The audit log {} is intact: {} {} records{}",
+                file.display(), verification.records, if verification.signed { "signed" } else { "hash-chained" },
+                if verification.finished { "" } else { ", but it has no END record, so the purge was cut short or records were cut off the end" }
+            );
+        }
+    }
+    Ok(())
+}
+
 /// Initializes the logger.
 ///
 /// # Arguments
@@ -121,7 +611,22 @@ async fn main() -> Result<(), BoxedError> {
 ///
 /// * `Result<Arc<Logger>, BoxedError>` - Ok with `Logger` if successful.
 async fn initialize_logger(args: &Args, buffer_size: usize) -> Result<Arc<Logger>, BoxedError> {
-    Ok(Logger::new(args.resolve_logfile_name(), args.verbose, buffer_size))
+    // connect to the syslog server first, so one that can't be reached stops the run at once
+    let syslog = match args.syslog {
+        Some(ref target) => Some(Syslog::connect(target.clone(), args.syslog_ca.clone(), args.syslog_facility, args.dry_run)?),
+        None => None,
+    };
+    Ok(Logger::new(args.resolve_logfile_name(), args.verbose, buffer_size, syslog))
+}
+
+/// Where a run puts what it removes, and what it records of it.
+struct Outputs {
+    journal: Option<Journal>,
+    trash: Option<Trash>,
+    undo: Option<Undo>,
+    archive: Option<Archive>,
+    plan: Option<Plan>,
+    ledger: Option<Ledger>,
 }
 
 /// Sets up the deleter and shared state.
@@ -129,18 +634,64 @@ async fn initialize_logger(args: &Args, buffer_size: usize) -> Result<Arc<Logger
 /// # Arguments
 ///
 /// * `args` - A reference to the parsed command-line arguments.
+/// * `filters` - The filters shared with the crawler.
+/// * `excludes` - The exclude patterns shared with the crawler.
+/// * `anchors` - The anchors of the target patterns, shared with the crawler.
+/// * `outputs` - The journal, trash, staging folder, archive, plan and ledger the run was given.
+/// * `termination` - Whether the run has to stop, on SIGTERM or at '--max-errors'.
+/// * `budget` - What the run may delete, if '--max-delete' was specified.
 ///
 /// # Returns
 ///
-/// * `(Arc<Mutex<Deleter>>, Arc<Mutex<usize>>, Arc<Mutex<usize>>, Arc<Mutex<usize>>, Arc<Mutex<usize>>, Arc<Mutex<usize>>)`
-fn setup_deleter(args: &Args) -> (Arc<Mutex<Deleter>>, Arc<Mutex<usize>>, Arc<Mutex<usize>>, Arc<Mutex<usize>>, Arc<Mutex<usize>>, Arc<Mutex<usize>>) {
-    let deleter = Arc::new(Mutex::new(Deleter::new(args.dry_run)));
-    let total_directories = Arc::new(Mutex::new(0));
-    let total_files_symlinks = Arc::new(Mutex::new(0));
-    let total_crawling_ops = Arc::new(Mutex::new(0));
-    let total_stat_ops = Arc::new(Mutex::new(0));
-    let total_deletion_ops = Arc::new(Mutex::new(0));
-    (deleter, total_directories, total_files_symlinks, total_crawling_ops, total_stat_ops, total_deletion_ops)
+/// * `(Arc<Mutex<Deleter>>, Counters)` - The deleter and the totals of the run.
+fn setup_deleter(args: &Args, filters: &Arc<Filters>, excludes: &Arc<Excludes>, anchors: &Arc<Anchors>, outputs: Outputs, termination: Option<Termination>, budget: Option<Budget>) -> (Arc<Mutex<Deleter>>, Counters) {
+    let Outputs { journal, trash, undo, archive, plan, ledger } = outputs;
+    let live_sockets = args.live_sockets.map(|policy| Arc::new(LiveSockets::load(policy)));
+    let dir_report = args.json_report.as_ref().map(|_| DirReport::new());
+    let ext_counts = if args.ext.is_empty() { None } else { Some(ExtensionCounts::new(Arc::clone(filters))) };
+    let owner_counts = if args.owner_report { Some(OwnerCounts::new()) } else { None };
+    let symlink_counts = if args.symlink_report { Some(SymlinkCounts::new()) } else { None };
+    let in_use = if args.skip_in_use { Some(Arc::new(InUse::load())) } else { None };
+    let compressor = if args.compresses() { Some(args.compressor) } else { None };
+    let move_to = if args.moves() { args.move_to.clone() } else { None };
+    let tier_counts = if args.tier.is_empty() { None } else { Some(TierCounts::new(filters.tiers().clone())) };
+    let deleter = Deleter::new(DeleterOptions {
+        dry_run: args.dry_run,
+        check_open_handles: args.check_open_handles,
+        live_sockets,
+        dir_report,
+        slowest: args.slowest.unwrap_or(DEFAULT_SLOWEST),
+        op_timeout: args.op_timeout,
+        track_activity: args.dashboard,
+        ext_counts,
+        owner_counts,
+        excludes: Arc::clone(excludes),
+        compressor,
+        move_to,
+        tier_counts,
+        empty_files_only: args.empty_files_only,
+        prune_empty_dirs: args.prune_empty_dirs,
+        types: args.file_type,
+        accessed_before: args.not_accessed_in.map(filters::access_cutoff),
+        skip_hardlinked: args.skip_hardlinked,
+        symlink_counts,
+        in_use,
+        journal,
+        clear_immutable: args.clear_immutable,
+        trash,
+        undo,
+        archive,
+        termination,
+        one_file_system: args.one_file_system,
+        anchors: Arc::clone(anchors),
+        changed_before: args.min_age.map(filters::access_cutoff),
+        plan,
+        ledger,
+        budget,
+        max_errors: args.max_errors,
+        only_mine: args.only_mine.then(filters::invoking_uid),
+    });
+    (Arc::new(Mutex::new(deleter)), Counters::default())
 }
 
 /// Spawns a deleter task.
@@ -148,33 +699,35 @@ fn setup_deleter(args: &Args) -> (Arc<Mutex<Deleter>>, Arc<Mutex<usize>>, Arc<Mu
 /// # Arguments
 ///
 /// * `deleter` - A reference to the `Arc<Mutex<Deleter>>`.
-/// * `receiver` - A reference to the `Arc<Mutex<mpsc::Receiver<PathBuf>>>`.
+/// * `receiver` - A reference to the `SharedReceiver`.
 /// * `logger` - A reference to the `Arc<Logger`>.
 /// * `verbose` - A boolean indicating whether to enable verbose logging.
 /// * `total_deletion_ops` - A reference to the `Arc<Mutex<usize>>`.
-/// * `total_directories` - A reference to the `Arc<Mutex<usize>>`.
 /// * `thread_info` - A reference to the ThreadInfo struct.
-/// * `_is_file` - A boolean indicating whether the task is for files or directories (unused).
+/// * `passes` - The crawler passes that send paths to the queue.
 ///
 /// # Returns
 ///
 /// * `tokio::task::JoinHandle<Result<(), BoxedError>>>`
 fn spawn_deleter_task(
-    deleter: &Arc<Mutex<Deleter>>, receiver: Arc<Mutex<mpsc::Receiver<PathBuf>>>, logger: Arc<Logger>,
-    verbose: bool, total_deletion_ops: Arc<Mutex<usize>>, total_directories: Arc<Mutex<usize>>,
-    thread_info: ThreadInfo, _is_file: bool
+    deleter: &Arc<Mutex<Deleter>>, receiver: SharedReceiver, logger: Arc<Logger>,
+    verbose: bool, total_deletion_ops: Arc<Mutex<usize>>,
+    thread_info: ThreadInfo, passes: Vec<Completion>
 ) -> tokio::task::JoinHandle<Result<(), BoxedError>> {
     let deleter_clone = Arc::clone(deleter);
     let receiver_clone = Arc::clone(&receiver);
     let logger_clone = Arc::clone(&logger);
     let total_deletion_ops_clone = Arc::clone(&total_deletion_ops);
-    let total_directories_clone = Arc::clone(&total_directories);
     let thread_info_clone = thread_info.clone();
 
     tokio::spawn(async move {
-        deleter_clone.lock().await.delete_all(
+        // the deleter's state is shared between clones, so only hold the lock long enough to
+        // take a clone. Holding it for the whole run would serialize the file and directory
+        // deleters and keep the run's counters out of reach until they finish.
+        let deleter = deleter_clone.lock().await.clone();
+        deleter.delete_all(
             receiver_clone, thread_info_clone.total_thread_count, logger_clone, verbose,
-            total_deletion_ops_clone, total_directories_clone
+            total_deletion_ops_clone, passes
         ).await
     })
 }
@@ -0,0 +1,233 @@
+//! This module adds up what was deleted per file owner, for chargeback on shared storage.
+//!
+//! With '--owner-report', every deleted file and symlink is counted under the user that owned it,
+//! and the final report lists each owner with their number of files and bytes, largest first.
+//! Files within a deleted directory are counted too, since the crawler hands them to the deleters
+//! one by one instead of leaving them to the directory's removal, and a directory that is removed
+//! before its files got their turn is taken apart file by file so each is still counted
+//! (whoever unlinks a file counts it, so none is counted twice). With '--mail-owners', each owner
+//! is also sent their own summary after a real run (not a dry run) through the local 'sendmail',
+//! addressed to their user name so the mail system's aliases decide where it ends up.
+
+use std::collections::HashMap;
+use std::ffi::{CStr, OsStr};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::sync::Mutex;
+use crate::args::SizeUnit;
+use crate::logger::Logger;
+use crate::utility::format_size;
+
+/// What was deleted for one owner.
+#[derive(Debug, Clone, Default)]
+pub struct OwnerTotals {
+    pub uid: u32,
+    pub name: Option<String>,
+    pub files: u64,
+    pub bytes: u64,
+}
+
+/// Shared per-owner totals. Cloning it shares the underlying counts.
+#[derive(Clone, Default)]
+pub struct OwnerCounts {
+    counts: Arc<Mutex<HashMap<u32, (u64, u64)>>>,
+}
+
+impl OwnerCounts {
+    /// Creates an empty count.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - A new instance of OwnerCounts.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a deleted file or symlink under its owner.
+    ///
+    /// # Arguments
+    ///
+    /// * `uid` - The user ID that owned the object.
+    /// * `bytes` - The size of the object.
+    pub async fn record(&self, uid: u32, bytes: u64) {
+        let mut counts = self.counts.lock().await;
+        let entry = counts.entry(uid).or_insert((0, 0));
+        entry.0 += 1;
+        entry.1 += bytes;
+    }
+
+    /// Retrieves the totals of every owner, most bytes first.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<OwnerTotals>` - The totals of each owner, with their user name if it is known.
+    pub async fn totals(&self) -> Vec<OwnerTotals> {
+        let mut totals: Vec<OwnerTotals> = self.counts.lock().await.iter()
+            .map(|(&uid, &(files, bytes))| OwnerTotals { uid, name: user_name(uid), files, bytes })
+            .collect();
+        totals.sort_by(|a, b| b.bytes.cmp(&a.bytes).then(a.uid.cmp(&b.uid)));
+        totals
+    }
+}
+
+/// Looks up the user name of a user ID.
+///
+/// # Arguments
+///
+/// * `uid` - The user ID.
+///
+/// # Returns
+///
+/// * `Option<String>` - The user name, or None if the ID has no account.
+pub fn user_name(uid: u32) -> Option<String> {
+    account(uid, |passwd| unsafe { CStr::from_ptr(passwd.pw_name) }.to_string_lossy().into_owned())
+}
+
+/// Looks up the home directory of a user ID.
+///
+/// # Arguments
+///
+/// * `uid` - The user ID.
+///
+/// # Returns
+///
+/// * `Option<PathBuf>` - The home directory, or None if the ID has no account.
+pub fn home_dir(uid: u32) -> Option<PathBuf> {
+    account(uid, |passwd| PathBuf::from(OsStr::from_bytes(unsafe { CStr::from_ptr(passwd.pw_dir) }.to_bytes())))
+}
+
+/// Reads a field of the account of a user ID from the password database.
+///
+/// # Arguments
+///
+/// * `uid` - The user ID.
+/// * `field` - Reads the field from the entry, which is only valid during the call.
+///
+/// # Returns
+///
+/// * `Option<T>` - The field, or None if the ID has no account.
+fn account<T>(uid: u32, field: impl Fn(&libc::passwd) -> T) -> Option<T> {
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buffer = vec![0 as libc::c_char; 16384];
+    let mut result = std::ptr::null_mut();
+    let status = unsafe { libc::getpwuid_r(uid, &mut passwd, buffer.as_mut_ptr(), buffer.len(), &mut result) };
+    if status != 0 || result.is_null() {
+        return None;
+    }
+    Some(field(&passwd))
+}
+
+/// Logs the per-owner table of the final report.
+///
+/// # Arguments
+///
+/// * `totals` - The totals of each owner.
+/// * `logger` - An instance of the Logger.
+/// * `size_unit` - The unit used to display sizes.
+pub async fn print_table(totals: &[OwnerTotals], logger: &Arc<Logger>, size_unit: SizeUnit) {
+    logger.log("Deleted files by owner:", false, false, false).await;
+    for owner in totals {
+        logger.log(&format!(
+            "    {} ({}): {} files, {}",
+            owner.name.as_deref().unwrap_or("unknown"), owner.uid, owner.files, format_size(owner.bytes, size_unit)
+        ), false, false, false).await;
+    }
+}
+
+/// Mails each owner a summary of what was deleted of theirs. Owners without an account are
+/// skipped, and a failure to mail one owner doesn't stop the others.
+///
+/// # Arguments
+///
+/// * `totals` - The totals of each owner.
+/// * `targets` - The target patterns of the run.
+/// * `logger` - An instance of the Logger.
+/// * `size_unit` - The unit used to display sizes.
+pub async fn mail_owners(totals: &[OwnerTotals], targets: &str, logger: &Arc<Logger>, size_unit: SizeUnit) {
+    let host = host_name();
+    let mut sent = 0;
+    for owner in totals {
+        let name = match owner.name {
+            Some(ref name) => name,
+            None => {
+                logger.log(&format!("Not mailing user ID {}: it has no account", owner.uid), true, false, false).await;
+                continue;
+            }
+        };
+        let message = format!(
+            "To: {}\nSubject: {} of your files were deleted on {}\n\n\
+             A cleanup run on {} deleted {} files and symlinks owned by you ({}).\n\
+             Targets: {}\n",
+            name, owner.files, host, host, owner.files, format_size(owner.bytes, size_unit), targets
+        );
+        match send(&message).await {
+            Ok(()) => sent += 1,
+            Err(e) => logger.log(&format!("Failed to mail the summary to {}: {}", name, e), true, false, false).await,
+        }
+    }
+    logger.log(&format!("Mailed a summary to {} of {} owners", sent, totals.len()), false, false, false).await;
+}
+
+/// Hands a message to the local 'sendmail', which takes the recipients from its headers.
+///
+/// # Arguments
+///
+/// * `message` - The message, headers included.
+///
+/// # Returns
+///
+/// * `Result<(), String>` - Ok if sendmail accepted the message, Err with a message otherwise.
+async fn send(message: &str) -> Result<(), String> {
+    let mut child = Command::new(sendmail_program())
+        .args(["-oi", "-t"])
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("unable to run sendmail: {}", e))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(message.as_bytes()).await.map_err(|e| e.to_string())?;
+    }
+    match child.wait().await {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(format!("sendmail exited with {}", status)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Finds the sendmail program. It usually lives in /usr/sbin, which isn't always on the PATH.
+///
+/// # Returns
+///
+/// * `&'static str` - The program to run.
+pub fn sendmail_program() -> &'static str {
+    ["/usr/sbin/sendmail", "/usr/lib/sendmail"].iter().copied().find(|path| Path::new(path).exists()).unwrap_or("sendmail")
+}
+
+/// Checks that sendmail is installed, so the run doesn't end without mailing anyone.
+///
+/// # Returns
+///
+/// * `Result<(), String>` - Ok if sendmail was found, Err with a message otherwise.
+pub fn check_available() -> Result<(), String> {
+    let program = sendmail_program();
+    let found = program.starts_with('/') || std::env::var_os("PATH")
+        .is_some_and(|paths| std::env::split_paths(&paths).any(|dir| dir.join(program).is_file()));
+    if found { Ok(()) } else { Err("--mail-owners needs 'sendmail', which could not be found".into()) }
+}
+
+/// Looks up the name of this host.
+///
+/// # Returns
+///
+/// * `String` - The host name, or 'localhost' if it is unknown.
+pub fn host_name() -> String {
+    let mut buffer = [0 as libc::c_char; 256];
+    if unsafe { libc::gethostname(buffer.as_mut_ptr(), buffer.len()) } != 0 {
+        return "localhost".to_string();
+    }
+    buffer[buffer.len() - 1] = 0;
+    unsafe { CStr::from_ptr(buffer.as_ptr()) }.to_string_lossy().into_owned()
+}
@@ -0,0 +1,243 @@
+//! This module decides what the target patterns given on the command line stand for, so that the
+//! validator, the crawlers and the pre-run checks all expand them the same way.
+//!
+//! On top of the wildcards of the glob crate (`*`, `?`, `[0-9]`, `[!a-z]` and `**` as a whole
+//! path component), a pattern can hold `{a,b}` alternatives, which may be nested and may be empty
+//! (`*.{log,log.[0-9]}`, `core{,.[0-9]*}`). As in a shell, braces are expanded first and every
+//! alternative is then matched as a pattern of its own. Braces without a comma, and braces within
+//! a character class, are taken literally. A pattern ending in `/**` stands for everything below
+//! the directory, but not the directory itself.
+
+use std::path::{Path, PathBuf};
+use glob::Pattern;
+
+// Largest number of alternatives a single pattern may expand to.
+const MAX_ALTERNATIVES: usize = 1024;
+
+/// Checks whether a pattern has glob wildcards, after its braces were expanded.
+///
+/// # Arguments
+///
+/// * `text` - The pattern or path component.
+///
+/// # Returns
+///
+/// * `bool` - True if it has a '*', '?' or '['.
+pub fn has_wildcard(text: &str) -> bool {
+    text.contains(['*', '?', '['])
+}
+
+/// Expands a target pattern into the glob patterns it stands for.
+///
+/// # Arguments
+///
+/// * `pattern` - The pattern supplied by the user.
+///
+/// # Returns
+///
+/// * `Result<Vec<String>, String>` - Ok with the alternatives in order and without duplicates,
+///   Err with a message if the braces don't pair up or an alternative isn't a valid pattern.
+pub fn expand(pattern: &Path) -> Result<Vec<String>, String> {
+    let mut alternatives: Vec<String> = Vec::new();
+    for alternative in expand_braces(&pattern.to_string_lossy())? {
+        // everything below the directory, like '**' in gitignore, instead of just its subdirectories
+        let alternative = match alternative.strip_suffix("/**") {
+            Some(dir) => format!("{}/**/*", dir),
+            None if alternative == "**" => "**/*".to_string(),
+            None => alternative,
+        };
+        if has_wildcard(&alternative) {
+            Pattern::new(&alternative).map_err(|e| format!("'{}' is not a valid pattern: {}", alternative, e))?;
+        }
+        if !alternatives.contains(&alternative) {
+            alternatives.push(alternative);
+        }
+    }
+    Ok(alternatives)
+}
+
+/// Expands every target pattern. Invalid patterns are kept as they are, since the validator
+/// rejects them before anything is expanded.
+///
+/// # Arguments
+///
+/// * `patterns` - The patterns supplied by the user.
+///
+/// # Returns
+///
+/// * `Vec<String>` - The glob patterns they stand for.
+pub fn expand_all(patterns: &[PathBuf]) -> Vec<String> {
+    patterns.iter()
+        .flat_map(|pattern| expand(pattern).unwrap_or_else(|_| vec![pattern.to_string_lossy().into_owned()]))
+        .collect()
+}
+
+/// Expands the `{a,b}` alternatives of a pattern.
+///
+/// # Arguments
+///
+/// * `pattern` - The pattern.
+///
+/// # Returns
+///
+/// * `Result<Vec<String>, String>` - Ok with the expansions, Err with a message otherwise.
+fn expand_braces(pattern: &str) -> Result<Vec<String>, String> {
+    let chars: Vec<char> = pattern.chars().collect();
+    let open = match find_group(&chars) {
+        Some(open) => open,
+        None => return Ok(vec![pattern.to_string()]),
+    };
+    let (close, commas) = match_group(&chars, open).ok_or_else(|| format!("'{}' has an unmatched '{{'", pattern))?;
+    let prefix: String = chars[..open].iter().collect();
+    let suffixes = expand_braces(&chars[close + 1..].iter().collect::<String>())?;
+
+    let mut parts = Vec::new();
+    if commas.is_empty() {
+        // '{a}' isn't a group, but what it holds may be
+        for inner in expand_braces(&chars[open + 1..close].iter().collect::<String>())? {
+            parts.push(format!("{{{}}}", inner));
+        }
+    } else {
+        let mut start = open + 1;
+        for end in commas.iter().copied().chain(std::iter::once(close)) {
+            parts.extend(expand_braces(&chars[start..end].iter().collect::<String>())?);
+            start = end + 1;
+        }
+    }
+
+    if parts.len().saturating_mul(suffixes.len()) > MAX_ALTERNATIVES {
+        return Err(format!("'{}' expands to more than {} patterns", pattern, MAX_ALTERNATIVES));
+    }
+    Ok(parts.iter()
+        .flat_map(|part| suffixes.iter().map(|suffix| format!("{}{}{}", prefix, part, suffix)).collect::<Vec<_>>())
+        .collect())
+}
+
+/// Finds the next '{' outside of a character class.
+///
+/// # Arguments
+///
+/// * `chars` - The pattern.
+///
+/// # Returns
+///
+/// * `Option<usize>` - The position of the '{', or None if there is none.
+fn find_group(chars: &[char]) -> Option<usize> {
+    let mut pos = 0;
+    while pos < chars.len() {
+        match chars[pos] {
+            '{' => return Some(pos),
+            '[' => pos = class_end(chars, pos).unwrap_or(pos) + 1,
+            _ => pos += 1,
+        }
+    }
+    None
+}
+
+/// Finds the '}' that closes a group, and the commas that separate its alternatives.
+///
+/// # Arguments
+///
+/// * `chars` - The pattern.
+/// * `open` - The position of the '{'.
+///
+/// # Returns
+///
+/// * `Option<(usize, Vec<usize>)>` - The position of the '}' and of the commas that aren't
+///   within a nested group, or None if the group isn't closed.
+fn match_group(chars: &[char], open: usize) -> Option<(usize, Vec<usize>)> {
+    let mut depth = 0;
+    let mut commas = Vec::new();
+    let mut pos = open;
+    while pos < chars.len() {
+        match chars[pos] {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some((pos, commas));
+                }
+            }
+            ',' if depth == 1 => commas.push(pos),
+            '[' => pos = class_end(chars, pos).unwrap_or(pos),
+            _ => {}
+        }
+        pos += 1;
+    }
+    None
+}
+
+/// Finds the ']' that closes a character class, by the rules of the glob crate: a ']' right
+/// after the '[' or '[!' belongs to the class.
+///
+/// # Arguments
+///
+/// * `chars` - The pattern.
+/// * `open` - The position of the '['.
+///
+/// # Returns
+///
+/// * `Option<usize>` - The position of the ']', or None if the '[' doesn't start a class.
+fn class_end(chars: &[char], open: usize) -> Option<usize> {
+    let mut pos = open + 1;
+    if chars.get(pos) == Some(&'!') {
+        pos += 1;
+    }
+    // the first character is always part of the class
+    pos += 1;
+    (pos..chars.len()).find(|&pos| chars[pos] == ']')
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use super::{MAX_ALTERNATIVES, expand};
+
+    fn expanded(pattern: &str) -> Vec<String> {
+        expand(Path::new(pattern)).unwrap()
+    }
+
+    #[test]
+    fn expands_nested_and_empty_alternatives() {
+        assert_eq!(expanded("/d/{a,{b,c}}"), ["/d/a", "/d/b", "/d/c"]);
+        assert_eq!(expanded("/d/*.{log,log.[0-9]}"), ["/d/*.log", "/d/*.log.[0-9]"]);
+        assert_eq!(expanded("core{,.[0-9]*}"), ["core", "core.[0-9]*"]);
+        assert_eq!(expanded("a{,x}b"), ["ab", "axb"]);
+        assert_eq!(expanded("{a,b}{1,2}"), ["a1", "a2", "b1", "b2"]);
+        assert_eq!(expanded("{a,a,b}"), ["a", "b"]);
+    }
+
+    #[test]
+    fn takes_braces_without_a_comma_or_within_a_class_literally() {
+        assert_eq!(expanded("{a}"), ["{a}"]);
+        assert_eq!(expanded("{{a,b}}"), ["{a}", "{b}"]);
+        assert_eq!(expanded("x[{]y"), ["x[{]y"]);
+        assert_eq!(expanded("x[{,]{a,b}"), ["x[{,]a", "x[{,]b"]);
+        assert_eq!(expanded("{x[},]y,z}"), ["x[},]y", "z"]);
+        assert_eq!(expanded("[]{]{a,b}"), ["[]{]a", "[]{]b"]);
+    }
+
+    #[test]
+    fn rejects_unmatched_braces_and_invalid_alternatives() {
+        assert_eq!(expand(Path::new("/d/{a,b")), Err("'/d/{a,b' has an unmatched '{'".into()));
+        assert_eq!(expand(Path::new("{a,{b}")), Err("'{a,{b}' has an unmatched '{'".into()));
+        assert!(expand(Path::new("/d/{a,***}")).unwrap_err().starts_with("'/d/***' is not a valid pattern"));
+    }
+
+    #[test]
+    fn rejects_patterns_with_too_many_alternatives() {
+        // ten groups of two are exactly the limit, eleven are over it
+        assert_eq!(expanded(&"{a,b}".repeat(10)).len(), MAX_ALTERNATIVES);
+        let pattern = "{a,b}".repeat(11);
+        assert_eq!(expand(Path::new(&pattern)), Err(format!("'{}' expands to more than {} patterns", pattern, MAX_ALTERNATIVES)));
+    }
+
+    #[test]
+    fn stands_for_everything_below_a_directory_with_a_trailing_double_star() {
+        assert_eq!(expanded("/d/**"), ["/d/**/*"]);
+        assert_eq!(expanded("**"), ["**/*"]);
+        assert_eq!(expanded("/d/{a,b}/**"), ["/d/a/**/*", "/d/b/**/*"]);
+        assert_eq!(expanded("/d/**/*.log"), ["/d/**/*.log"]);
+        assert_eq!(expanded("/d/x"), ["/d/x"]);
+    }
+}
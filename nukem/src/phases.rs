@@ -0,0 +1,128 @@
+//! This module keeps track of when each phase of a run started and ended.
+//!
+//! Discovery and deletion run concurrently, so the phases overlap: the deleters start before the
+//! crawlers and keep going until the queues are drained. Each phase is reported with its start
+//! and end relative to the start of the run, which shows where the time went (e.g. that most of
+//! a run on an NFS filer was spent crawling).
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+/// The phases of a run, in the order they begin.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Phase {
+    /// Preparation before any paths are crawled, such as taking the lock and sampling free space.
+    Setup,
+    /// Expanding the wildcard patterns into paths.
+    Expansion,
+    /// Crawling the matched paths.
+    Crawl,
+    /// Deleting what the crawlers found.
+    Delete,
+    /// Checking that every object found was accounted for.
+    Verify,
+    /// Summarizing and reporting the results.
+    Finalize,
+}
+
+impl Phase {
+    /// All phases, in the order they appear in reports.
+    pub const ALL: [Phase; 6] = [
+        Phase::Setup,
+        Phase::Expansion,
+        Phase::Crawl,
+        Phase::Delete,
+        Phase::Verify,
+        Phase::Finalize,
+    ];
+
+    /// Returns the human-readable label used in reports.
+    ///
+    /// # Returns
+    ///
+    /// * `&'static str` - The label for the phase.
+    pub fn label(&self) -> &'static str {
+        match self {
+            Phase::Setup => "setup",
+            Phase::Expansion => "glob expansion",
+            Phase::Crawl => "crawl",
+            Phase::Delete => "delete",
+            Phase::Verify => "verify",
+            Phase::Finalize => "finalize",
+        }
+    }
+}
+
+/// A phase with its start and end, relative to the start of the run.
+#[derive(Debug, Clone, Copy)]
+pub struct PhaseSpan {
+    pub phase: Phase,
+    pub start: Duration,
+    pub end: Duration,
+}
+
+/// Shared record of the phases of a run. Cloning it shares the underlying record, so the Crawler
+/// and the main task can both record phases.
+#[derive(Clone)]
+pub struct PhaseTimes {
+    start: Instant,
+    spans: Arc<Mutex<HashMap<Phase, (Instant, Instant)>>>,
+}
+
+impl PhaseTimes {
+    /// Creates an empty record.
+    ///
+    /// # Arguments
+    ///
+    /// * `start` - The start time of the application.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - A new instance of PhaseTimes.
+    pub fn new(start: Instant) -> Self {
+        Self { start, spans: Arc::new(Mutex::new(HashMap::new())) }
+    }
+
+    /// Records part of a phase. A phase that is recorded more than once (for instance by both
+    /// crawler passes) spans from the earliest start to the latest end.
+    ///
+    /// # Arguments
+    ///
+    /// * `phase` - The phase.
+    /// * `from` - When this part of the phase started.
+    /// * `to` - When this part of the phase ended.
+    pub async fn record(&self, phase: Phase, from: Instant, to: Instant) {
+        let mut spans = self.spans.lock().await;
+        let span = spans.entry(phase).or_insert((from, to));
+        span.0 = span.0.min(from);
+        span.1 = span.1.max(to);
+    }
+
+    /// Records part of a phase that ends now.
+    ///
+    /// # Arguments
+    ///
+    /// * `phase` - The phase.
+    /// * `from` - When this part of the phase started.
+    pub async fn record_until_now(&self, phase: Phase, from: Instant) {
+        self.record(phase, from, Instant::now()).await;
+    }
+
+    /// Retrieves the recorded phases in the order they appear in reports.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<PhaseSpan>` - The recorded phases.
+    pub async fn spans(&self) -> Vec<PhaseSpan> {
+        let spans = self.spans.lock().await;
+        Phase::ALL.iter()
+            .filter_map(|phase| spans.get(phase).map(|&(from, to)| PhaseSpan {
+                phase: *phase,
+                start: from.saturating_duration_since(self.start),
+                end: to.saturating_duration_since(self.start),
+            }))
+            .collect()
+    }
+}
@@ -0,0 +1,435 @@
+//! This module writes the plans of 'nukem plan' and carries them out for 'nukem apply'.
+//!
+//! Change management often has to approve a concrete list of what will be destroyed, not a
+//! command line whose outcome depends on what the targets hold when it runs. 'nukem plan' is a
+//! dry run that records every object it would delete, each file and symlink on its own and each
+//! directory once everything within it is listed, along with why it was picked. 'nukem apply'
+//! then deletes exactly those objects and nothing else: files and symlinks are only unlinked if
+//! their device, inode, size and modification time are still those of the plan, and directories
+//! are only removed once they are empty, deepest first, so anything added since the plan was
+//! made keeps its directory. Whatever changed is kept and reported.
+//!
+//! A plan is a text file. It starts with a header line, the command that made it and its targets,
+//! followed by one line per object and an end line with the totals. Fields are separated by tabs,
+//! shown as spaces here:
+//!
+//! ```text
+//! # nukem plan 1
+//! # made 2026-10-14 09:30:00 +0000
+//! # command nukem --min-size 1M plan -o cleanup.plan /data/*.log
+//! # target  /data/*.log
+//! # kind  size  device  inode  mtime  reason  path
+//! file  1048576  2049  131  1760000000.000000000  target '/data/*.log'; passed the filters  /data/a.log
+//! # end  1  1048576
+//! ```
+//!
+//! Paths and reasons are escaped like the undo manifest. A plan without its end line, such as
+//! one cut short by SIGTERM, can't be applied.
+
+use std::collections::HashSet;
+use std::fs::{self, File, Metadata, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use chrono::Local;
+use glob::{MatchOptions, Pattern};
+use tokio::sync::Mutex;
+use crate::args::SizeUnit;
+use crate::identity::Identity;
+use crate::patterns;
+use crate::safefs::Anchors;
+use crate::undo::{escape, unescape};
+use crate::utility::{format_size, glob_options};
+
+const HEADER: &str = "# nukem plan 1";
+const COLUMNS: &str = "# kind\tsize\tdevice\tinode\tmtime\treason\tpath";
+const TARGET: &str = "# target\t";
+const END: &str = "# end\t";
+
+/// The kinds of objects in a plan.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    File,
+    Symlink,
+    Dir,
+}
+
+impl Kind {
+    /// Finds the kind of an object.
+    ///
+    /// # Arguments
+    ///
+    /// * `metadata` - The metadata of the object, read without following symlinks.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The kind.
+    fn of(metadata: &Metadata) -> Self {
+        if metadata.is_dir() {
+            Kind::Dir
+        } else if metadata.file_type().is_symlink() {
+            Kind::Symlink
+        } else {
+            Kind::File
+        }
+    }
+
+    /// Retrieves the name of the kind in a plan.
+    ///
+    /// # Returns
+    ///
+    /// * `&'static str` - The name.
+    fn name(self) -> &'static str {
+        match self {
+            Kind::File => "file",
+            Kind::Symlink => "symlink",
+            Kind::Dir => "dir",
+        }
+    }
+}
+
+/// The open plan and what has been written to it.
+struct State {
+    writer: BufWriter<File>,
+    objects: u64,
+    bytes: u64,
+    // a path can be reached through more than one target pattern, but is listed once
+    listed: HashSet<PathBuf>,
+    // the first write that failed; the plan can't be applied after it
+    error: Option<io::Error>,
+}
+
+/// Shared writer of the plan of a 'nukem plan' run. Cloning it shares the underlying file, so
+/// every worker records into the same plan.
+#[derive(Clone)]
+pub struct Plan {
+    path: PathBuf,
+    // the expanded target patterns, to tell why an object is in the plan
+    targets: Arc<Vec<(String, Pattern)>>,
+    // wildcards match within a path component, as they do when the targets are expanded
+    options: MatchOptions,
+    filtered: bool,
+    state: Arc<Mutex<State>>,
+}
+
+impl Plan {
+    /// Creates the plan and writes its header. An existing file is never replaced.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The file to write.
+    /// * `targets` - The target patterns of the run.
+    /// * `filtered` - A boolean indicating whether filters select the files.
+    /// * `ignore_case` - Whether '--ignore-case' was specified.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, String>` - Ok with the plan, Err with a message if it can't be written.
+    pub fn create(path: &Path, targets: &[PathBuf], filtered: bool, ignore_case: bool) -> Result<Self, String> {
+        let fail = |e: io::Error| format!("Unable to write the plan {}: {}", path.display(), e);
+        let file = OpenOptions::new().write(true).create_new(true).mode(0o600).open(path).map_err(fail)?;
+        let mut writer = BufWriter::new(file);
+        let command: Vec<String> = std::env::args().collect();
+        let mut header = format!("{}\n# made {}\n# command {}\n", HEADER, Local::now().format("%Y-%m-%d %H:%M:%S %z"), escape(Path::new(&command.join(" "))));
+        for target in targets {
+            header.push_str(&format!("{}{}\n", TARGET, escape(target)));
+        }
+        header.push_str(COLUMNS);
+        writeln!(writer, "{}", header).map_err(fail)?;
+        let targets = patterns::expand_all(targets).into_iter()
+            .map(|target| {
+                let pattern = Pattern::new(&target).unwrap_or_else(|_| Pattern::new(&Pattern::escape(&target)).unwrap());
+                (target, pattern)
+            })
+            .collect();
+        Ok(Self {
+            path: path.to_path_buf(),
+            targets: Arc::new(targets),
+            options: MatchOptions { require_literal_separator: true, ..glob_options(ignore_case) },
+            filtered,
+            state: Arc::new(Mutex::new(State { writer, objects: 0, bytes: 0, listed: HashSet::new(), error: None })),
+        })
+    }
+
+    /// Retrieves the file of the plan.
+    ///
+    /// # Returns
+    ///
+    /// * `&Path` - The file, to be passed to 'nukem apply'.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Records an object the run would delete.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The object.
+    /// * `metadata` - The metadata of the object, read without following symlinks.
+    pub async fn record(&self, path: &Path, metadata: &Metadata) {
+        let kind = Kind::of(metadata);
+        let size = if kind == Kind::Dir { 0 } else { metadata.len() };
+        let line = format!(
+            "{}\t{}\t{}\t{}\t{}.{:09}\t{}\t{}\n",
+            kind.name(), size, metadata.dev(), metadata.ino(), metadata.mtime(), metadata.mtime_nsec(),
+            escape(Path::new(&self.reason(path, kind))), escape(path)
+        );
+        let mut state = self.state.lock().await;
+        if state.error.is_some() || !state.listed.insert(path.to_path_buf()) {
+            return;
+        }
+        match state.writer.write_all(line.as_bytes()) {
+            Ok(()) => {
+                state.objects += 1;
+                state.bytes += size;
+            }
+            Err(e) => state.error = Some(e),
+        }
+    }
+
+    /// Ends the plan with its totals and makes sure it reached the disk. A plan that isn't
+    /// complete gets no end line, so it can't be applied.
+    ///
+    /// # Arguments
+    ///
+    /// * `complete` - A boolean indicating whether the run went through all of its targets.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(u64, u64), String>` - Ok with the number of objects and their bytes, Err with a
+    ///   message if the plan is incomplete.
+    pub async fn finish(&self, complete: bool) -> Result<(u64, u64), String> {
+        let mut state = self.state.lock().await;
+        if let Some(e) = state.error.take() {
+            return Err(format!("The plan {} is incomplete after {} objects and can't be applied: {}", self.path.display(), state.objects, e));
+        }
+        let end = format!("{}{}\t{}\n", END, state.objects, state.bytes);
+        let written = if complete { state.writer.write_all(end.as_bytes()) } else { Ok(()) };
+        written.and_then(|()| state.writer.flush()).and_then(|()| state.writer.get_ref().sync_all())
+            .map_err(|e| format!("The plan {} is incomplete and can't be applied: {}", self.path.display(), e))?;
+        if !complete {
+            return Err(format!("The plan {} is incomplete because the run was stopped, and can't be applied", self.path.display()));
+        }
+        Ok((state.objects, state.bytes))
+    }
+
+    /// Tells why an object is in the plan.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The object.
+    /// * `kind` - Its kind.
+    ///
+    /// # Returns
+    ///
+    /// * `String` - The target it matched or was found in, and how the filters picked it.
+    fn reason(&self, path: &Path, kind: Kind) -> String {
+        let found = path.ancestors().find_map(|ancestor| {
+            self.targets.iter().find(|(_, pattern)| pattern.matches_path_with(ancestor, self.options)).map(|(target, _)| (ancestor, target))
+        });
+        let mut reason = match found {
+            Some((ancestor, target)) if ancestor == path => format!("target '{}'", target),
+            Some((ancestor, target)) => format!("in '{}' (target '{}')", ancestor.display(), target),
+            None => "within the targets".to_string(),
+        };
+        if self.filtered {
+            reason.push_str(if kind == Kind::Dir { "; everything within it is in the plan" } else { "; passed the filters" });
+        }
+        reason
+    }
+}
+
+/// An object listed in a plan.
+struct Entry {
+    kind: Kind,
+    size: u64,
+    identity: Identity,
+    mtime: (i64, i64),
+    path: PathBuf,
+}
+
+impl Entry {
+    /// Tells how an object differs from what the plan recorded.
+    ///
+    /// # Arguments
+    ///
+    /// * `metadata` - The metadata of the object now, read without following symlinks.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<&'static str>` - What changed, or None if it is still the object of the plan.
+    fn difference(&self, metadata: &Metadata) -> Option<&'static str> {
+        if Kind::of(metadata) != self.kind {
+            return Some("it is another kind of object now");
+        }
+        if Identity::of(metadata) != self.identity {
+            return Some("it was replaced");
+        }
+        // removing the objects within a directory changes its modification time
+        if self.kind == Kind::Dir {
+            return None;
+        }
+        if metadata.len() != self.size {
+            return Some("its size changed");
+        }
+        if (metadata.mtime(), metadata.mtime_nsec()) != self.mtime {
+            return Some("it was modified");
+        }
+        None
+    }
+}
+
+/// Deletes the objects of a plan that haven't changed since it was made, and nothing else.
+///
+/// # Arguments
+///
+/// * `plan` - The plan, as written by 'nukem plan'.
+/// * `dry_run` - A boolean indicating whether to only report what would be deleted.
+/// * `unit` - The unit for the size in the summary.
+///
+/// # Returns
+///
+/// * `Result<(), String>` - Ok if everything in the plan is gone, Err with a message otherwise.
+pub fn apply(plan: &Path, dry_run: bool, unit: SizeUnit) -> Result<(), String> {
+    let (targets, entries) = read_plan(plan)?;
+    let anchors = Anchors::new(&targets);
+    let (mut files, mut dirs): (Vec<&Entry>, Vec<&Entry>) = entries.iter().partition(|entry| entry.kind != Kind::Dir);
+    // a directory can only be removed once what is within it is gone
+    dirs.sort_by_key(|entry| std::cmp::Reverse(entry.path.components().count()));
+    files.append(&mut dirs);
+    let (mut deleted, mut bytes, mut gone, mut changed, mut failed) = (0u64, 0u64, 0u64, 0u64, 0u64);
+    for entry in files {
+        let metadata = match fs::symlink_metadata(&entry.path) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                gone += 1;
+                continue;
+            }
+            Err(e) => {
+                eprintln!("Unable to check {}: {}", entry.path.display(), e);
+                failed += 1;
+                continue;
+            }
+        };
+        if let Some(difference) = entry.difference(&metadata) {
+            eprintln!("Kept {}: {} since the plan was made", entry.path.display(), difference);
+            changed += 1;
+            continue;
+        }
+        if dry_run {
+            println!("Would delete {}", entry.path.display());
+            deleted += 1;
+            bytes += entry.size;
+            continue;
+        }
+        let removed = if entry.kind == Kind::Dir { anchors.remove_dir(&entry.path) } else { anchors.unlink(&entry.path) };
+        match removed {
+            Ok(()) => {
+                println!("Deleted {}", entry.path.display());
+                deleted += 1;
+                bytes += entry.size;
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => gone += 1,
+            Err(e) if matches!(e.raw_os_error(), Some(libc::ENOTEMPTY) | Some(libc::EEXIST)) => {
+                eprintln!("Kept {}: it holds objects that aren't in the plan", entry.path.display());
+                changed += 1;
+            }
+            Err(e) => {
+                eprintln!("Unable to delete {}: {}", entry.path.display(), e);
+                failed += 1;
+            }
+        }
+    }
+    println!(
+        "{} {} of {} objects ({}), {} already gone, {} kept because they changed since the plan was made, {} failed",
+        if dry_run { "Would delete" } else { "Deleted" }, deleted, entries.len(), format_size(bytes, unit), gone, changed, failed
+    );
+    if changed > 0 || failed > 0 {
+        return Err(format!("Not everything in the plan {} was deleted. Make a new plan to review what is left", plan.display()));
+    }
+    Ok(())
+}
+
+/// Reads the targets and the objects of a plan.
+///
+/// # Arguments
+///
+/// * `plan` - The plan.
+///
+/// # Returns
+///
+/// * `Result<(Vec<PathBuf>, Vec<Entry>), String>` - Ok with the target patterns and the objects,
+///   Err with a message if the file isn't a complete plan.
+fn read_plan(plan: &Path) -> Result<(Vec<PathBuf>, Vec<Entry>), String> {
+    let fail = |e: io::Error| format!("Unable to read the plan {}: {}", plan.display(), e);
+    let mut lines = BufReader::new(File::open(plan).map_err(fail)?).lines();
+    if lines.next().transpose().map_err(fail)?.as_deref() != Some(HEADER) {
+        return Err(format!("{} is not a plan", plan.display()));
+    }
+    let (mut targets, mut entries, mut end) = (Vec::new(), Vec::new(), None);
+    for (index, line) in lines.enumerate() {
+        let line = line.map_err(fail)?;
+        let malformed = || format!("Line {} of the plan {} is malformed", index + 2, plan.display());
+        if end.is_some() {
+            return Err(format!("The plan {} goes on after its end line", plan.display()));
+        }
+        if let Some(target) = line.strip_prefix(TARGET) {
+            targets.push(unescape(target));
+            continue;
+        }
+        if let Some(totals) = line.strip_prefix(END) {
+            end = Some(totals.split('\t').next().and_then(|objects| objects.parse::<usize>().ok()).ok_or_else(malformed)?);
+            continue;
+        }
+        if line.starts_with('#') || line.is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        let entry = match fields[..] {
+            [kind, size, device, inode, mtime, _, path] => parse_entry(kind, size, device, inode, mtime, path),
+            _ => None,
+        };
+        let entry = entry.ok_or_else(malformed)?;
+        if !entry.path.is_absolute() {
+            return Err(format!("Line {} of the plan {} has a relative path", index + 2, plan.display()));
+        }
+        entries.push(entry);
+    }
+    match end {
+        Some(objects) if objects == entries.len() => Ok((targets, entries)),
+        Some(objects) => Err(format!("The plan {} lists {} objects, but its end line counts {}", plan.display(), entries.len(), objects)),
+        None => Err(format!("The plan {} has no end line. It is incomplete and can't be applied", plan.display())),
+    }
+}
+
+/// Parses the fields of an object in a plan.
+///
+/// # Arguments
+///
+/// * `kind` - The kind of the object.
+/// * `size` - Its size.
+/// * `device` - Its device.
+/// * `inode` - Its inode.
+/// * `mtime` - Its modification time, as seconds and nanoseconds.
+/// * `path` - Its escaped path.
+///
+/// # Returns
+///
+/// * `Option<Entry>` - The object, or None if a field is invalid.
+fn parse_entry(kind: &str, size: &str, device: &str, inode: &str, mtime: &str, path: &str) -> Option<Entry> {
+    let kind = match kind {
+        "file" => Kind::File,
+        "symlink" => Kind::Symlink,
+        "dir" => Kind::Dir,
+        _ => return None,
+    };
+    let (seconds, nanoseconds) = mtime.split_once('.')?;
+    Some(Entry {
+        kind,
+        size: size.parse().ok()?,
+        identity: Identity { device: device.parse().ok()?, inode: inode.parse().ok()? },
+        mtime: (seconds.parse().ok()?, nanoseconds.parse().ok()?),
+        path: unescape(path),
+    })
+}
@@ -0,0 +1,126 @@
+//! This module asks for confirmation on the console before objects are deleted, like 'rm -i'.
+//!
+//! With '-i' every file, symlink and directory is confirmed, and with '-I' only each match of
+//! the target patterns, a directory then going with everything within it. The questions are asked
+//! while crawling, so only what was confirmed reaches the deleters, and a directory is only asked
+//! about once everything within it was confirmed. Declining anything within a directory keeps the
+//! directory. The questions go through the logger, which prints nothing else until the answer
+//! arrives, and the crawl is made sequential so they come in the order of the tree.
+
+use std::path::Path;
+use std::sync::Arc;
+use crate::args::Args;
+use crate::logger::Logger;
+
+/// Which objects are confirmed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    /// Every object, with '-i'.
+    Each,
+    /// Only the matches of the target patterns, with '-I'.
+    TopLevel,
+}
+
+/// Asks the questions of '-i' and '-I' on the console.
+#[derive(Clone)]
+pub struct Prompter {
+    scope: Scope,
+    logger: Arc<Logger>,
+}
+
+impl Prompter {
+    /// Creates the prompter.
+    ///
+    /// # Arguments
+    ///
+    /// * `args` - The command-line arguments.
+    /// * `logger` - An instance of the Logger, which asks the questions.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<Self>` - The prompter, or None if neither '-i' nor '-I' was specified.
+    pub fn from_args(args: &Args, logger: &Arc<Logger>) -> Option<Self> {
+        let scope = if args.interactive {
+            Scope::Each
+        } else if args.interactive_top {
+            Scope::TopLevel
+        } else {
+            return None;
+        };
+        Some(Self { scope, logger: Arc::clone(logger) })
+    }
+
+    /// Checks whether every object is confirmed, so the crawler has to send each file on its own.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True with '-i'.
+    pub fn confirms_each(&self) -> bool {
+        self.scope == Scope::Each
+    }
+
+    /// Asks whether a file or symlink may be deleted.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The file or symlink.
+    /// * `is_root` - Whether it is a match of a target pattern.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if it was confirmed, or doesn't have to be.
+    pub async fn confirm_file(&self, path: &Path, is_root: bool) -> bool {
+        if !is_root && self.scope == Scope::TopLevel {
+            return true;
+        }
+        self.ask(&format!("nukem: delete {:?}?", path)).await
+    }
+
+    /// Asks whether a whole directory may be deleted, before it is crawled. Only '-I' asks then.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The directory.
+    /// * `is_root` - Whether it is a match of a target pattern.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if it was confirmed, or doesn't have to be.
+    pub async fn confirm_tree(&self, path: &Path, is_root: bool) -> bool {
+        if !is_root || self.scope == Scope::Each {
+            return true;
+        }
+        self.ask(&format!("nukem: delete directory {:?} and everything within it?", path)).await
+    }
+
+    /// Asks whether a directory may be deleted, once everything within it was confirmed. Only
+    /// '-i' asks then.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The directory.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if it was confirmed, or doesn't have to be.
+    pub async fn confirm_dir(&self, path: &Path) -> bool {
+        if self.scope == Scope::TopLevel {
+            return true;
+        }
+        self.ask(&format!("nukem: delete directory {:?}?", path)).await
+    }
+
+    /// Asks a question and reads the answer.
+    ///
+    /// # Arguments
+    ///
+    /// * `question` - The question.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if the answer was 'y' or 'yes'. Anything else, or no answer, declines.
+    async fn ask(&self, question: &str) -> bool {
+        let answer = self.logger.prompt(&format!("{} [y/N]", question)).await;
+        matches!(answer.to_ascii_lowercase().as_str(), "y" | "yes")
+    }
+}
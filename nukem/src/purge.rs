@@ -0,0 +1,355 @@
+//! This module implements the `purge` subcommand, which deletes exactly what a manifest lists,
+//! for erasure requests that have to be carried out and proven.
+//!
+//! The manifest is a CSV file with one entry per line: either a path relative to the purge root,
+//! or an identifier (such as a customer ID) followed by such a path. Paths may contain wildcards.
+//! Empty lines and lines starting with '#' are ignored. The whole manifest is checked before
+//! anything is deleted, and entries that point outside the root are refused.
+//!
+//! Every step is written to a hash-chained audit log under `<state dir>/purges` (see the `audit`
+//! module), and the purge doesn't start unless the log can be created. After deleting an object, the purge checks that it is really
+//! gone. When every entry has been handled, a completion certificate is written next to the audit
+//! log. It records the SHA-256 digests of the manifest and the audit log along with the totals,
+//! and is signed with HMAC-SHA256 using a key that only the operator holds, so it can later be
+//! checked with the `verify-certificate` subcommand. The key defaults to `<state dir>/purge.key`,
+//! which is generated on first use.
+
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Component, Path, PathBuf};
+use chrono::Local;
+use crate::audit::{self, AuditLog};
+use crate::owners;
+use crate::sha256::{self, Sha256};
+use crate::utility::glob_options;
+
+/// One line of a manifest.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub line: usize,
+    pub identifier: Option<String>,
+    pub pattern: PathBuf,
+}
+
+/// The totals of a purge.
+#[derive(Debug, Clone, Default)]
+pub struct Totals {
+    pub entries: u64,
+    pub objects: u64,
+    pub files: u64,
+    pub bytes: u64,
+    pub not_found: u64,
+    pub failed: u64,
+    pub verified: u64,
+}
+
+/// Reads a manifest and checks every entry.
+///
+/// # Arguments
+///
+/// * `text` - The contents of the manifest.
+///
+/// # Returns
+///
+/// * `Result<Vec<Entry>, String>` - Ok with the entries, Err with every invalid line otherwise.
+pub fn parse_manifest(text: &str) -> Result<Vec<Entry>, String> {
+    let mut entries = Vec::new();
+    let mut errors = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let unquote = |field: &str| field.trim().trim_matches('"').to_string();
+        let (identifier, pattern) = match line.split_once(',') {
+            Some((identifier, pattern)) => (Some(unquote(identifier)).filter(|id| !id.is_empty()), unquote(pattern)),
+            None => (None, unquote(line)),
+        };
+        let pattern = PathBuf::from(pattern);
+        if pattern.as_os_str().is_empty() {
+            errors.push(format!("line {}: no path", index + 1));
+        } else if !pattern.components().all(|component| matches!(component, Component::Normal(_) | Component::CurDir)) {
+            errors.push(format!("line {}: '{}' must be a path within the root, without '..'", index + 1, pattern.display()));
+        } else {
+            entries.push(Entry { line: index + 1, identifier, pattern });
+        }
+    }
+    if !errors.is_empty() {
+        return Err(format!("Invalid manifest:\n    {}", errors.join("\n    ")));
+    }
+    Ok(entries)
+}
+
+/// Carries out a purge and writes its audit log and, unless it is a dry run, its certificate.
+///
+/// # Arguments
+///
+/// * `manifest` - The manifest file.
+/// * `root` - The directory the manifest's paths are relative to.
+/// * `state_dir` - The state directory, where the audit log and certificate are kept.
+/// * `key_file` - The signing key, or None for the default key in the state directory.
+/// * `dry_run` - If true, only record what would be deleted.
+///
+/// # Returns
+///
+/// * `Result<(), String>` - Ok if every entry was purged and verified, Err with a message otherwise.
+pub fn run(manifest: &Path, root: &Path, state_dir: &Path, key_file: Option<&PathBuf>, dry_run: bool) -> Result<(), String> {
+    let text = fs::read(manifest).map_err(|e| format!("Unable to read the manifest {}: {}", manifest.display(), e))?;
+    let manifest = fs::canonicalize(manifest).map_err(|e| format!("Unable to open the manifest {}: {}", manifest.display(), e))?;
+    let entries = parse_manifest(&String::from_utf8_lossy(&text))?;
+    let root = fs::canonicalize(root).map_err(|e| format!("Unable to open the purge root {}: {}", root.display(), e))?;
+    if !root.is_dir() {
+        return Err(format!("The purge root {} is not a directory", root.display()));
+    }
+    // the key is loaded up front, so a real purge can't end without a certificate
+    let key = if dry_run { None } else { Some(load_key(state_dir, key_file, true)?) };
+
+    let purges_dir = state_dir.join("purges");
+    // the certificate names the audit log by its absolute path, so it can be checked from anywhere
+    let purges_dir = fs::create_dir_all(&purges_dir).and_then(|()| fs::canonicalize(&purges_dir))
+        .map_err(|e| format!("Unable to create {}: {}", purges_dir.display(), e))?;
+    let now = Local::now();
+    let purge_id = format!("{}-{}", now.format("%Y%m%d-%H%M%S"), std::process::id());
+    let mut audit = AuditLog::create(purges_dir.join(format!("{}.audit", purge_id)), key.clone())
+        .map_err(|e| format!("Unable to create the audit log, so nothing was purged: {}", e))?;
+    let fail = |e: io::Error| format!("Unable to write the audit log, so the purge was stopped: {}", e);
+
+    let manifest_digest = digest(&text);
+    audit.record(if dry_run { "BEGIN DRY RUN" } else { "BEGIN" }, None, &root, &format!(
+        "purge {} of {} entries from {} (sha256 {})", purge_id, entries.len(), manifest.display(), manifest_digest
+    )).map_err(fail)?;
+    println!("Purging {} manifest entries from '{}'{}", entries.len(), root.display(), if dry_run { " (dry run)" } else { "" });
+
+    let mut totals = Totals { entries: entries.len() as u64, ..Totals::default() };
+    let mut handled = HashSet::new();
+    for entry in &entries {
+        let identifier = entry.identifier.as_deref();
+        let matches = resolve(&root, &entry.pattern);
+        if matches.is_empty() {
+            totals.not_found += 1;
+            audit.record("NOT FOUND", identifier, &root.join(&entry.pattern), &format!("manifest line {}", entry.line)).map_err(fail)?;
+            continue;
+        }
+        for path in matches {
+            // an object listed by several entries is purged once
+            if !handled.insert(path.clone()) {
+                continue;
+            }
+            purge_object(&root, &path, identifier, dry_run, &mut audit, &mut totals).map_err(fail)?;
+        }
+    }
+
+    let complete = totals.failed == 0;
+    audit.record("END", None, &root, &format!(
+        "{} objects ({} files, {} bytes), {} verified, {} entries not found, {} failed",
+        totals.objects, totals.files, totals.bytes, totals.verified, totals.not_found, totals.failed
+    )).map_err(fail)?;
+    audit.sync().map_err(fail)?;
+    println!(
+        "{} {} objects ({} files, {} bytes), {} entries not found, {} failed",
+        if dry_run { "Would purge" } else { "Purged" }, totals.objects, totals.files, totals.bytes, totals.not_found, totals.failed
+    );
+    println!("Audit log: {}", audit.path.display());
+
+    if let Some(key) = key {
+        let audit_digest = fs::read(&audit.path).map(|log| digest(&log)).map_err(|e| format!("Unable to read back the audit log: {}", e))?;
+        let body = format!(
+            "purge_id: {}\ncompleted: {}\nhost: {}\nroot: {}\nmanifest: {}\nmanifest_sha256: {}\nentries: {}\nobjects: {}\nfiles: {}\nbytes: {}\nnot_found: {}\nfailed: {}\nverified: {}\naudit_log: {}\naudit_sha256: {}\noutcome: {}\n",
+            purge_id, Local::now().to_rfc3339(), owners::host_name(), root.display(), manifest.display(), manifest_digest,
+            totals.entries, totals.objects, totals.files, totals.bytes, totals.not_found, totals.failed, totals.verified,
+            audit.path.display(), audit_digest, if complete { "complete" } else { "incomplete" }
+        );
+        let certificate = format!("{}signature: hmac-sha256 {}\n", body, sha256::to_hex(&sha256::hmac(&key, body.as_bytes())));
+        let path = purges_dir.join(format!("{}.cert", purge_id));
+        fs::write(&path, certificate).map_err(|e| format!("Unable to write the certificate: {}", e))?;
+        println!("Certificate: {}", path.display());
+    }
+    if complete { Ok(()) } else { Err(format!("The purge is incomplete: {} objects could not be purged", totals.failed)) }
+}
+
+/// Finds the objects a manifest path refers to. Paths with wildcards are expanded, and other
+/// paths are taken as they are if they exist.
+///
+/// # Arguments
+///
+/// * `root` - The purge root.
+/// * `pattern` - The path from the manifest.
+///
+/// # Returns
+///
+/// * `Vec<PathBuf>` - The existing objects.
+fn resolve(root: &Path, pattern: &Path) -> Vec<PathBuf> {
+    let full = root.join(pattern);
+    let text = full.to_string_lossy();
+    if text.contains(['*', '?', '[']) {
+        glob::glob_with(&text, glob_options(false)).map(|paths| paths.filter_map(Result::ok).collect()).unwrap_or_default()
+    } else if fs::symlink_metadata(&full).is_ok() {
+        vec![full]
+    } else {
+        Vec::new()
+    }
+}
+
+/// Deletes one object, checks that it is gone, and records both in the audit log.
+///
+/// # Arguments
+///
+/// * `root` - The canonical purge root.
+/// * `path` - The object.
+/// * `identifier` - The identifier of the manifest entry, if any.
+/// * `dry_run` - If true, only record what would be deleted.
+/// * `audit` - The audit log.
+/// * `totals` - The totals to add the object to.
+///
+/// # Returns
+///
+/// * `io::Result<()>` - Ok unless the audit log could not be written.
+fn purge_object(root: &Path, path: &Path, identifier: Option<&str>, dry_run: bool, audit: &mut AuditLog, totals: &mut Totals) -> io::Result<()> {
+    // a symlinked directory on the way could lead outside the root, so the parent is resolved
+    let within_root = path.parent().and_then(|parent| fs::canonicalize(parent).ok()).is_some_and(|parent| parent.starts_with(root));
+    if !within_root {
+        totals.failed += 1;
+        return audit.record("REFUSED", identifier, path, "outside the purge root");
+    }
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            totals.failed += 1;
+            return audit.record("FAILED", identifier, path, &e.to_string());
+        }
+    };
+    let (files, bytes) = if metadata.is_dir() { measure(path) } else { (1, metadata.len()) };
+    let detail = format!("{} files, {} bytes", files, bytes);
+    if dry_run {
+        totals.objects += 1;
+        totals.files += files;
+        totals.bytes += bytes;
+        return audit.record("WOULD DELETE", identifier, path, &detail);
+    }
+    let removed = if metadata.is_dir() { fs::remove_dir_all(path) } else { fs::remove_file(path) };
+    if let Err(e) = removed {
+        totals.failed += 1;
+        return audit.record("FAILED", identifier, path, &e.to_string());
+    }
+    totals.objects += 1;
+    totals.files += files;
+    totals.bytes += bytes;
+    audit.record("DELETED", identifier, path, &detail)?;
+    match fs::symlink_metadata(path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            totals.verified += 1;
+            audit.record("VERIFIED", identifier, path, "no longer exists")
+        }
+        _ => {
+            totals.failed += 1;
+            audit.record("STILL PRESENT", identifier, path, "the object exists again after its deletion")
+        }
+    }
+}
+
+/// Counts the files and bytes within a directory, without following symlinks.
+///
+/// # Arguments
+///
+/// * `dir` - The directory.
+///
+/// # Returns
+///
+/// * `(u64, u64)` - The number of files and symlinks, and their total size.
+fn measure(dir: &Path) -> (u64, u64) {
+    let mut totals = (0, 0);
+    for entry in fs::read_dir(dir).into_iter().flatten().filter_map(Result::ok) {
+        match entry.metadata() {
+            Ok(metadata) if metadata.is_dir() => {
+                let (files, bytes) = measure(&entry.path());
+                totals.0 += files;
+                totals.1 += bytes;
+            }
+            Ok(metadata) => {
+                totals.0 += 1;
+                totals.1 += metadata.len();
+            }
+            Err(_) => {}
+        }
+    }
+    totals
+}
+
+/// Checks the signature of a certificate and that its audit log is unchanged and intact.
+///
+/// # Arguments
+///
+/// * `certificate` - The certificate file.
+/// * `state_dir` - The state directory, where the default key is kept.
+/// * `key_file` - The signing key, or None for the default key.
+///
+/// # Returns
+///
+/// * `Result<(), String>` - Ok if the certificate is authentic, Err with what is wrong otherwise.
+pub fn verify_certificate(certificate: &Path, state_dir: &Path, key_file: Option<&PathBuf>) -> Result<(), String> {
+    let text = fs::read_to_string(certificate).map_err(|e| format!("Unable to read the certificate {}: {}", certificate.display(), e))?;
+    let (body, signature) = text.rsplit_once("signature: hmac-sha256 ").ok_or("The certificate is not signed")?;
+    let key = load_key(state_dir, key_file, false)?;
+    if sha256::to_hex(&sha256::hmac(&key, body.as_bytes())) != signature.trim() {
+        return Err("The signature does not match: the certificate was altered or signed with another key".into());
+    }
+    let field = |name: &str| body.lines().find_map(|line| line.strip_prefix(name)?.strip_prefix(": ")).unwrap_or_default();
+    println!("The signature is valid: purge {} of {} ended {} ({})", field("purge_id"), field("root"), field("completed"), field("outcome"));
+    let audit_log = field("audit_log");
+    match fs::read(audit_log) {
+        Ok(log) if digest(&log) == field("audit_sha256") => {
+            let verification = audit::verify(Path::new(audit_log), Some(&key))?;
+            println!("The audit log {} is unchanged, and its {} records are intact", audit_log, verification.records);
+        }
+        Ok(_) => return Err(format!("The audit log {} was changed after the certificate was issued", audit_log)),
+        Err(e) => println!("The audit log {} could not be checked: {}", audit_log, e),
+    }
+    Ok(())
+}
+
+/// Loads the signing key. The default key is generated on first use, readable only by its owner.
+///
+/// # Arguments
+///
+/// * `state_dir` - The state directory, where the default key is kept.
+/// * `key_file` - The key given with '--key-file', if any.
+/// * `generate` - If true, generate the default key when there is none yet.
+///
+/// # Returns
+///
+/// * `Result<Vec<u8>, String>` - Ok with the key, Err with a message if it can't be loaded.
+pub fn load_key(state_dir: &Path, key_file: Option<&PathBuf>, generate: bool) -> Result<Vec<u8>, String> {
+    let path = key_file.cloned().unwrap_or_else(|| state_dir.join("purge.key"));
+    if generate && key_file.is_none() && !path.exists() {
+        let mut random = [0u8; 32];
+        File::open("/dev/urandom").and_then(|mut urandom| urandom.read_exact(&mut random))
+            .and_then(|()| fs::create_dir_all(state_dir))
+            .and_then(|()| OpenOptions::new().write(true).create_new(true).mode(0o600).open(&path))
+            .and_then(|mut file| writeln!(file, "{}", sha256::to_hex(&random)))
+            .map_err(|e| format!("Unable to generate the signing key {}: {}", path.display(), e))?;
+        println!("Generated the signing key {}", path.display());
+    }
+    let key = fs::read_to_string(&path).map_err(|e| format!("Unable to read the signing key {}: {}", path.display(), e))?;
+    let key = key.trim();
+    if key.is_empty() {
+        return Err(format!("The signing key {} is empty", path.display()));
+    }
+    Ok(key.as_bytes().to_vec())
+}
+
+/// Computes the SHA-256 digest of some data.
+///
+/// # Arguments
+///
+/// * `data` - The data.
+///
+/// # Returns
+///
+/// * `String` - The digest in hexadecimal.
+fn digest(data: &[u8]) -> String {
+    let mut digest = Sha256::new();
+    digest.update(data);
+    sha256::to_hex(&digest.finish())
+}
@@ -0,0 +1,358 @@
+//! This module lets the user refine a dry run interactively before anything is deleted.
+//!
+//! With '--dry-run --refine', the targets are scanned once and the scan is kept in memory. The
+//! user is then shown what would be deleted and can add excludes or tighten the filters at a
+//! prompt. Each change is checked like the command-line options and re-evaluated against the
+//! cached scan, without touching the filesystem again. Once the user approves the plan, it
+//! becomes a real run with the refined options; that run crawls the targets again, so anything
+//! that changed since the scan is judged on its current state.
+
+use std::ffi::OsStr;
+use std::fs::{self, Metadata};
+use std::io::{self, BufRead, IsTerminal};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::task;
+use crate::args::{parse_size, Args, SizeUnit};
+use crate::console::Takeover;
+use crate::excludes::{self, Excludes};
+use crate::expression::Expression;
+use crate::filters::{Filters, PermFilter};
+use crate::logger::Logger;
+use crate::utility::{expand_paths, format_size, shared_size};
+use crate::validator::Validator;
+
+/// How a refinement session ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    /// The user approved the plan, so the run continues for real.
+    Apply,
+    /// The user gave up, so nothing is deleted.
+    Quit,
+}
+
+/// A filesystem object from the cached scan.
+struct Node {
+    path: PathBuf,
+    metadata: Metadata,
+    children: Vec<Node>,
+}
+
+/// What the current options would do with the cached scan.
+#[derive(Default)]
+struct Plan {
+    files: u64,
+    dirs: u64,
+    bytes: u64,
+    kept: u64,
+    // the objects that would be removed, in scan order
+    removed: Vec<PathBuf>,
+}
+
+/// Scans the targets and runs the prompt until the user applies the plan or quits. The options
+/// the user adds are written back to the arguments.
+///
+/// # Arguments
+///
+/// * `args` - Command-line arguments, updated with the refinements.
+/// * `logger` - An instance of the Logger.
+///
+/// # Returns
+///
+/// * `Result<Decision, Box<dyn std::error::Error + Send + Sync>>` - Ok with how the session
+///   ended, Err if the prompt can't be used.
+pub async fn run(args: &mut Args, logger: &Arc<Logger>) -> Result<Decision, Box<dyn std::error::Error + Send + Sync>> {
+    if !io::stdin().is_terminal() {
+        return Err("--refine needs an interactive terminal".into());
+    }
+    let roots = expand_paths(&args.paths, args.ignore_case);
+    logger.log("Scanning the targets for refinement...", false, false, false).await;
+    logger.flush().await;
+    let cached = task::spawn_blocking(move || roots.into_iter().filter_map(scan).collect::<Vec<Node>>()).await?;
+
+    // the session has the terminal to itself until it ends
+    let session = logger.console().take_over().ok_or("--refine needs the terminal, but something else is using it")?;
+    let mut candidate = args.clone();
+    let mut plan = evaluate(&cached, &candidate)?;
+    print_plan(&plan, candidate.size_unit, &session);
+    print_help(&session);
+    loop {
+        session.text("refine> ");
+        let line = task::spawn_blocking(|| {
+            let mut line = String::new();
+            io::stdin().lock().read_line(&mut line).map(|read| (read > 0).then_some(line))
+        }).await??;
+        // end of input counts as giving up
+        let line = match line {
+            Some(line) => line,
+            None => return Ok(Decision::Quit),
+        };
+        let (command, value) = match line.trim().split_once(char::is_whitespace) {
+            Some((command, value)) => (command, value.trim()),
+            None => (line.trim(), ""),
+        };
+        let mut refined = candidate.clone();
+        let applied = match command {
+            "" => continue,
+            "show" => {
+                let count = value.parse().unwrap_or(20);
+                for path in plan.removed.iter().take(count) {
+                    session.line(&format!("    {}", path.display()));
+                }
+                if plan.removed.len() > count {
+                    session.line(&format!("    ... and {} more", plan.removed.len() - count));
+                }
+                continue;
+            }
+            "apply" => {
+                drop(session);
+                *args = candidate;
+                log_options(args, logger).await;
+                return Ok(Decision::Apply);
+            }
+            "quit" | "exit" => return Ok(Decision::Quit),
+            "exclude" => {
+                refined.exclude.push(value.to_string());
+                Ok(())
+            }
+            "include" => {
+                refined.include.push(value.to_string());
+                Ok(())
+            }
+            "min-size" => parse_size(value).map(|size| refined.min_size = Some(size)),
+            "max-size" => parse_size(value).map(|size| refined.max_size = Some(size)),
+            "name" => {
+                refined.name_regex.push(value.to_string());
+                Ok(())
+            }
+            "ext" => {
+                refined.ext.extend(value.split(',').map(|ext| ext.trim().to_string()));
+                Ok(())
+            }
+            "perm" => PermFilter::parse(value).map(|perm| refined.perm = Some(perm)),
+            "where" => Expression::parse(value, refined.ignore_case).map(|_| refined.where_expr = Some(value.to_string())),
+            "user" => {
+                refined.user.push(value.to_string());
+                Ok(())
+            }
+            "group" => {
+                refined.group.push(value.to_string());
+                Ok(())
+            }
+            _ => {
+                print_help(&session);
+                continue;
+            }
+        };
+        match applied.and_then(|()| Validator::validate(&mut refined)).and_then(|()| evaluate(&cached, &refined)) {
+            Ok(refined_plan) => {
+                candidate = refined;
+                plan = refined_plan;
+                print_plan(&plan, candidate.size_unit, &session);
+            }
+            Err(e) => session.line(&e),
+        }
+    }
+}
+
+/// Scans the targets and works out what the options would delete, without the prompt, for
+/// '--confirm-over'.
+///
+/// # Arguments
+///
+/// * `args` - Command-line arguments.
+///
+/// # Returns
+///
+/// * `Result<(u64, u64), Box<dyn std::error::Error + Send + Sync>>` - Ok with the number of
+///   objects that would be deleted and their size, Err if the options are invalid.
+pub async fn prescan(args: &Args) -> Result<(u64, u64), Box<dyn std::error::Error + Send + Sync>> {
+    let roots = expand_paths(&args.paths, args.ignore_case);
+    let cached = task::spawn_blocking(move || roots.into_iter().filter_map(scan).collect::<Vec<Node>>()).await?;
+    let plan = evaluate(&cached, args)?;
+    Ok((plan.files + plan.dirs, plan.bytes))
+}
+
+/// Reads an object and everything below it, without following symlinks.
+///
+/// # Arguments
+///
+/// * `path` - The object.
+///
+/// # Returns
+///
+/// * `Option<Node>` - The object, or None if it could not be read.
+fn scan(path: PathBuf) -> Option<Node> {
+    let metadata = fs::symlink_metadata(&path).ok()?;
+    let children = if metadata.is_dir() {
+        fs::read_dir(&path).map(|entries| entries.filter_map(Result::ok).filter_map(|entry| scan(entry.path())).collect()).unwrap_or_default()
+    } else {
+        Vec::new()
+    };
+    Some(Node { path, metadata, children })
+}
+
+/// Works out what the options would do with the cached scan, the way the crawler would decide.
+///
+/// # Arguments
+///
+/// * `scan` - The cached scan.
+/// * `args` - The options to evaluate.
+///
+/// # Returns
+///
+/// * `Result<Plan, String>` - Ok with the plan, Err with a message if the options are invalid.
+fn evaluate(scan: &[Node], args: &Args) -> Result<Plan, String> {
+    let filters = Filters::from_args(args);
+    let excludes = Excludes::from_args(args)?;
+    let mut plan = Plan::default();
+    for node in scan {
+        judge(node, &filters, &excludes, false, true, &mut plan);
+    }
+    Ok(plan)
+}
+
+/// Decides whether an object would be removed, along with everything below it.
+///
+/// # Arguments
+///
+/// * `node` - The object.
+/// * `filters` - The filters.
+/// * `excludes` - The excludes.
+/// * `within_match` - True if a parent directory matched the name filters.
+/// * `is_root` - True if the object is a target rather than something found within one.
+/// * `plan` - The plan to add the object to.
+///
+/// # Returns
+///
+/// * `bool` - True if the object would be removed completely.
+fn judge(node: &Node, filters: &Filters, excludes: &Excludes, within_match: bool, is_root: bool, plan: &mut Plan) -> bool {
+    let metadata = &node.metadata;
+    let is_dir = metadata.is_dir();
+    if excludes.is_excluded(&node.path, is_dir) || (!is_root && filters.skips_hidden() && filters.is_hidden(&node.path)) {
+        plan.kept += 1;
+        return false;
+    }
+    let name_matched = within_match || filters.matches_name(&node.path);
+    if is_dir {
+        // the crawler layers the rules of a '.nukemignore' file the same way
+        let layered;
+        let excludes = if node.children.iter().any(|child| child.path.file_name() == Some(OsStr::new(excludes::IGNORE_FILE))) {
+            let text = fs::read_to_string(node.path.join(excludes::IGNORE_FILE)).map_err(|e| e.to_string());
+            match text.and_then(|text| excludes.layer(&node.path, &text)) {
+                Ok(excludes) => {
+                    layered = excludes;
+                    &layered
+                }
+                Err(_) => {
+                    plan.kept += 1;
+                    return false;
+                }
+            }
+        } else {
+            excludes
+        };
+        let filtering = filters.applies_within(name_matched) || !excludes.is_empty();
+        let mut complete = true;
+        for child in &node.children {
+            // without filters, everything within a directory goes with it
+            complete &= if filtering || child.metadata.is_dir() {
+                judge(child, filters, excludes, name_matched, false, plan)
+            } else {
+                count_removed(child, plan);
+                true
+            };
+        }
+        if !complete || !filters.selects_dir(&node.path, metadata, name_matched) || filters.rejects_dir(metadata).is_some() || filters.keeps_directories() {
+            plan.kept += 1;
+            return false;
+        }
+        plan.dirs += 1;
+        plan.removed.push(node.path.clone());
+        true
+    } else if metadata.is_file() || metadata.file_type().is_symlink() {
+        if filters.rejects(metadata).is_some() || !filters.matches(&node.path, metadata, name_matched) {
+            plan.kept += 1;
+            return false;
+        }
+        count_removed(node, plan);
+        true
+    } else {
+        // special files only go along with their directory when nothing is filtered
+        plan.kept += 1;
+        false
+    }
+}
+
+/// Adds a file, symlink or special file that goes along with its directory to the plan.
+///
+/// # Arguments
+///
+/// * `node` - The object.
+/// * `plan` - The plan to add it to.
+fn count_removed(node: &Node, plan: &mut Plan) {
+    plan.files += 1;
+    plan.bytes += shared_size(&node.metadata);
+    plan.removed.push(node.path.clone());
+}
+
+/// Prints what the current options would do.
+///
+/// # Arguments
+///
+/// * `plan` - The plan.
+/// * `size_unit` - The unit used to display sizes.
+/// * `session` - The hold of the session on the console.
+fn print_plan(plan: &Plan, size_unit: SizeUnit, session: &Takeover) {
+    session.line(&format!(
+        "Would delete {} files and symlinks ({}) and {} directories, keeping {} objects",
+        plan.files, format_size(plan.bytes, size_unit), plan.dirs, plan.kept
+    ));
+}
+
+/// Prints the commands the prompt understands.
+///
+/// # Arguments
+///
+/// * `session` - The hold of the session on the console.
+fn print_help(session: &Takeover) {
+    session.line("Commands:");
+    session.line("    exclude <PATTERN>    keep paths matching a glob pattern, like --exclude");
+    session.line("    include <PATTERN>    only delete paths matching a glob pattern, like --include");
+    session.line("    min-size <SIZE>      only delete files at least this large");
+    session.line("    max-size <SIZE>      only delete files at most this large");
+    session.line("    name <REGEX>         only delete objects whose name matches, like --name-regex");
+    session.line("    ext <EXT,...>        only delete files with these extensions");
+    session.line("    perm <MODE>          only delete files with matching permissions, like --perm");
+    session.line("    where <EXPRESSION>   only delete files matching an expression, like --where");
+    session.line("    user <NAME>          only delete files owned by this user");
+    session.line("    group <NAME>         only delete files owned by this group");
+    session.line("    show [N]             list the first N objects that would be deleted (default 20)");
+    session.line("    apply                delete for real with these options");
+    session.line("    quit                 exit without deleting anything");
+}
+
+/// Logs the refined options, so the approved run can be repeated without the prompt.
+///
+/// # Arguments
+///
+/// * `args` - The refined arguments.
+/// * `logger` - An instance of the Logger.
+async fn log_options(args: &Args, logger: &Arc<Logger>) {
+    let mut options = Vec::new();
+    options.extend(args.exclude.iter().map(|pattern| format!("--exclude '{}'", pattern)));
+    options.extend(args.include.iter().map(|pattern| format!("--include '{}'", pattern)));
+    options.extend(args.min_size.map(|size| format!("--min-size {}", size)));
+    options.extend(args.max_size.map(|size| format!("--max-size {}", size)));
+    options.extend(args.name_regex.iter().map(|regex| format!("--name-regex '{}'", regex)));
+    if !args.ext.is_empty() {
+        options.push(format!("--ext {}", args.ext.join(",")));
+    }
+    options.extend(args.perm.map(|perm| format!("--perm {}", perm)));
+    options.extend(args.where_expr.as_ref().map(|text| format!("--where '{}'", text)));
+    options.extend(args.user.iter().map(|name| format!("--user {}", name)));
+    options.extend(args.group.iter().map(|name| format!("--group {}", name)));
+    if !options.is_empty() {
+        logger.log(&format!("Applying the refined plan. Filter options: {}", options.join(" ")), false, false, false).await;
+    }
+}
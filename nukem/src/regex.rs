@@ -0,0 +1,553 @@
+//! This module provides a small regular expression engine for matching file names.
+//!
+//! It supports the common syntax: literals, `.`, character classes (`[a-z_]`, `[^0-9]`, `\d`,
+//! `\w`, `\s` and their negations), the anchors `^` and `$`, groups with alternation (`(a|b)`,
+//! `(?:a|b)`) and the quantifiers `*`, `+`, `?`, `{n}`, `{n,}` and `{n,m}`. A leading `(?i)` makes
+//! the whole expression case-insensitive. Expressions are compiled to an NFA that is simulated one
+//! character at a time, so matching takes linear time in the length of the name, whatever the
+//! expression looks like.
+
+// Largest count allowed in a `{n,m}` quantifier.
+const MAX_REPEAT: u32 = 1000;
+// Largest number of instructions a compiled expression may have.
+const MAX_PROGRAM: usize = 10_000;
+
+/// A compiled regular expression.
+#[derive(Debug, Clone)]
+pub struct Regex {
+    program: Vec<Inst>,
+    ignore_case: bool,
+}
+
+/// A set of characters, given as inclusive ranges.
+#[derive(Debug, Clone)]
+struct Class {
+    ranges: Vec<(char, char)>,
+    negated: bool,
+}
+
+/// The parsed form of an expression.
+#[derive(Debug)]
+enum Node {
+    Empty,
+    Literal(char),
+    Any,
+    Class(Class),
+    Start,
+    End,
+    Concat(Vec<Node>),
+    Alternate(Vec<Node>),
+    Repeat { node: Box<Node>, min: u32, max: Option<u32> },
+}
+
+/// An instruction of the compiled NFA.
+#[derive(Debug, Clone)]
+enum Inst {
+    Literal(char),
+    Any,
+    Class(Class),
+    Start,
+    End,
+    Split(usize, usize),
+    Jump(usize),
+    Match,
+}
+
+impl Regex {
+    /// Compiles an expression.
+    ///
+    /// # Arguments
+    ///
+    /// * `pattern` - The regular expression.
+    /// * `ignore_case` - Whether to match case-insensitively even without a leading `(?i)`.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, String>` - Ok with the compiled expression, Err with a message if it is invalid.
+    pub fn with_case(pattern: &str, ignore_case: bool) -> Result<Self, String> {
+        let (ignore_case, rest) = match pattern.strip_prefix("(?i)") {
+            Some(rest) => (true, rest),
+            None => (ignore_case, pattern),
+        };
+        let mut parser = Parser { chars: rest.chars().collect(), pos: 0 };
+        let node = parser.parse_alternation()?;
+        if parser.pos < parser.chars.len() {
+            return Err(format!("unmatched ')' at position {}", parser.pos));
+        }
+        let mut program = Vec::new();
+        compile(&node, &mut program)?;
+        program.push(Inst::Match);
+        Ok(Self { program, ignore_case })
+    }
+
+    /// Checks whether the expression matches anywhere within a string.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The string to search.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if there is a match.
+    pub fn is_match(&self, text: &str) -> bool {
+        let chars: Vec<char> = text.chars().collect();
+        let len = chars.len();
+        // each position gets its own stamp, so a state is only added once per position
+        let mut seen = vec![0; self.program.len()];
+        let mut current = Vec::new();
+        if self.add_thread(&mut current, &mut seen, 1, 0, 0, len) {
+            return true;
+        }
+        for (pos, &c) in chars.iter().enumerate() {
+            let stamp = pos + 2;
+            let mut next = Vec::new();
+            for &pc in &current {
+                if self.step(pc, c) && self.add_thread(&mut next, &mut seen, stamp, pc + 1, pos + 1, len) {
+                    return true;
+                }
+            }
+            // the expression isn't anchored, so a match may also start at the next position
+            if self.add_thread(&mut next, &mut seen, stamp, 0, pos + 1, len) {
+                return true;
+            }
+            current = next;
+        }
+        false
+    }
+
+    /// Follows the empty transitions from a state and adds every state that consumes a
+    /// character to the list.
+    ///
+    /// # Arguments
+    ///
+    /// * `list` - The states waiting for the next character.
+    /// * `seen` - The stamp of the position each state was last added at.
+    /// * `stamp` - The stamp of the current position.
+    /// * `pc` - The state to start from.
+    /// * `pos` - The current position within the string.
+    /// * `len` - The length of the string.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if the match state was reached.
+    fn add_thread(&self, list: &mut Vec<usize>, seen: &mut [usize], stamp: usize, pc: usize, pos: usize, len: usize) -> bool {
+        let mut pending = vec![pc];
+        while let Some(pc) = pending.pop() {
+            if seen[pc] == stamp {
+                continue;
+            }
+            seen[pc] = stamp;
+            match self.program[pc] {
+                Inst::Split(first, second) => {
+                    pending.push(second);
+                    pending.push(first);
+                }
+                Inst::Jump(target) => pending.push(target),
+                Inst::Start if pos == 0 => pending.push(pc + 1),
+                Inst::End if pos == len => pending.push(pc + 1),
+                Inst::Start | Inst::End => {}
+                Inst::Match => return true,
+                _ => list.push(pc),
+            }
+        }
+        false
+    }
+
+    /// Checks whether the state at `pc` accepts a character.
+    ///
+    /// # Arguments
+    ///
+    /// * `pc` - The state.
+    /// * `c` - The character.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if the character is accepted.
+    fn step(&self, pc: usize, c: char) -> bool {
+        match self.program[pc] {
+            Inst::Literal(literal) => literal == c || (self.ignore_case && fold(literal) == fold(c)),
+            Inst::Any => true,
+            Inst::Class(ref class) => {
+                class.contains(c) || (self.ignore_case && (class.contains(fold(c)) || class.contains(upper(c))))
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Class {
+    /// Checks whether the class contains a character.
+    ///
+    /// # Arguments
+    ///
+    /// * `c` - The character.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if the character belongs to the class.
+    fn contains(&self, c: char) -> bool {
+        self.ranges.iter().any(|&(low, high)| low <= c && c <= high) != self.negated
+    }
+
+    /// Builds the class for a `\d`, `\w` or `\s` escape (or their upper-case negations).
+    ///
+    /// # Arguments
+    ///
+    /// * `c` - The letter following the backslash.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<Self>` - The class, or None if the letter doesn't name one.
+    fn perl(c: char) -> Option<Self> {
+        let ranges = match c.to_ascii_lowercase() {
+            'd' => vec![('0', '9')],
+            'w' => vec![('0', '9'), ('A', 'Z'), ('_', '_'), ('a', 'z')],
+            's' => vec![('\t', '\r'), (' ', ' ')],
+            _ => return None,
+        };
+        Some(Self { ranges, negated: c.is_ascii_uppercase() })
+    }
+}
+
+/// Lowercases a character for case-insensitive comparison.
+fn fold(c: char) -> char {
+    c.to_lowercase().next().unwrap_or(c)
+}
+
+/// Uppercases a character for case-insensitive comparison.
+fn upper(c: char) -> char {
+    c.to_uppercase().next().unwrap_or(c)
+}
+
+/// A recursive descent parser for expressions.
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    /// Parses branches separated by `|`.
+    fn parse_alternation(&mut self) -> Result<Node, String> {
+        let mut branches = vec![self.parse_concat()?];
+        while self.peek() == Some('|') {
+            self.pos += 1;
+            branches.push(self.parse_concat()?);
+        }
+        Ok(if branches.len() == 1 { branches.remove(0) } else { Node::Alternate(branches) })
+    }
+
+    /// Parses a sequence of quantified atoms, up to the end of the branch.
+    fn parse_concat(&mut self) -> Result<Node, String> {
+        let mut items = Vec::new();
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            let atom = self.parse_atom()?;
+            items.push(self.parse_quantifiers(atom)?);
+        }
+        Ok(match items.len() {
+            0 => Node::Empty,
+            1 => items.remove(0),
+            _ => Node::Concat(items),
+        })
+    }
+
+    /// Parses a single atom: a character, a class, an anchor or a group.
+    fn parse_atom(&mut self) -> Result<Node, String> {
+        let start = self.pos;
+        let c = self.next().ok_or("unexpected end of expression")?;
+        match c {
+            '(' => {
+                if self.chars[self.pos..].starts_with(&['?', ':']) {
+                    self.pos += 2;
+                } else if self.peek() == Some('?') {
+                    return Err(format!("unsupported group syntax at position {}", start));
+                }
+                let node = self.parse_alternation()?;
+                if self.next() != Some(')') {
+                    return Err(format!("unclosed group opened at position {}", start));
+                }
+                Ok(node)
+            }
+            '.' => Ok(Node::Any),
+            '^' => Ok(Node::Start),
+            '$' => Ok(Node::End),
+            '[' => self.parse_class(start),
+            '\\' => match self.parse_escape(start)? {
+                Escape::Char(c) => Ok(Node::Literal(c)),
+                Escape::Class(class) => Ok(Node::Class(class)),
+            },
+            '*' | '+' | '?' | '{' => Err(format!("nothing to repeat at position {}", start)),
+            c => Ok(Node::Literal(c)),
+        }
+    }
+
+    /// Applies the quantifier following an atom, if there is one. A quantifier can't be repeated
+    /// itself, so `a**` is rejected rather than read as `(a*)*`.
+    fn parse_quantifiers(&mut self, node: Node) -> Result<Node, String> {
+        let start = self.pos;
+        let (min, max) = match self.peek() {
+            Some('*') => (0, None),
+            Some('+') => (1, None),
+            Some('?') => (0, Some(1)),
+            Some('{') => self.parse_counted(start)?,
+            _ => return Ok(node),
+        };
+        if self.pos == start {
+            self.pos += 1;
+        }
+        // a lazy quantifier matches the same names as a greedy one
+        if self.peek() == Some('?') {
+            self.pos += 1;
+        }
+        if let Some('*' | '+' | '?' | '{') = self.peek() {
+            return Err(format!("nothing to repeat at position {}", self.pos));
+        }
+        Ok(Node::Repeat { node: Box::new(node), min, max })
+    }
+
+    /// Parses a `{n}`, `{n,}` or `{n,m}` quantifier, leaving the position after the closing brace.
+    fn parse_counted(&mut self, start: usize) -> Result<(u32, Option<u32>), String> {
+        let close = self.chars[start..].iter().position(|&c| c == '}')
+            .ok_or(format!("unclosed repetition at position {}", start))?;
+        let body: String = self.chars[start + 1..start + close].iter().collect();
+        let invalid = || format!("invalid repetition '{{{}}}' at position {}", body, start);
+        let number = |value: &str| value.trim().parse::<u32>().ok().filter(|&n| n <= MAX_REPEAT);
+        let (min, max) = match body.split_once(',') {
+            None => {
+                let n = number(&body).ok_or_else(invalid)?;
+                (n, Some(n))
+            }
+            Some((min, max)) if max.trim().is_empty() => (number(min).ok_or_else(invalid)?, None),
+            Some((min, max)) => (number(min).ok_or_else(invalid)?, Some(number(max).ok_or_else(invalid)?)),
+        };
+        if max.is_some_and(|max| max < min) {
+            return Err(invalid());
+        }
+        self.pos = start + close + 1;
+        Ok((min, max))
+    }
+
+    /// Parses a bracketed character class. The opening bracket has already been consumed.
+    fn parse_class(&mut self, start: usize) -> Result<Node, String> {
+        let unclosed = || format!("unclosed character class at position {}", start);
+        let mut class = Class { ranges: Vec::new(), negated: false };
+        if self.peek() == Some('^') {
+            self.pos += 1;
+            class.negated = true;
+        }
+        let mut first = true;
+        loop {
+            let position = self.pos;
+            let c = self.next().ok_or_else(unclosed)?;
+            if c == ']' && !first {
+                break;
+            }
+            first = false;
+            let low = match c {
+                '\\' => match self.parse_escape(position)? {
+                    Escape::Char(c) => c,
+                    Escape::Class(perl) if !perl.negated => {
+                        class.ranges.extend(perl.ranges);
+                        continue;
+                    }
+                    Escape::Class(_) => return Err(format!("negated class escape inside brackets at position {}", position)),
+                },
+                c => c,
+            };
+            // a '-' right before the closing bracket is a literal
+            if self.peek() == Some('-') && self.chars.get(self.pos + 1).is_some_and(|&c| c != ']') {
+                self.pos += 1;
+                let position = self.pos;
+                let high = match self.next().ok_or_else(unclosed)? {
+                    '\\' => match self.parse_escape(position)? {
+                        Escape::Char(c) => c,
+                        Escape::Class(_) => return Err(format!("invalid range end at position {}", position)),
+                    },
+                    c => c,
+                };
+                if high < low {
+                    return Err(format!("invalid range '{}-{}' at position {}", low, high, position));
+                }
+                class.ranges.push((low, high));
+            } else {
+                class.ranges.push((low, low));
+            }
+        }
+        Ok(Node::Class(class))
+    }
+
+    /// Parses the character after a backslash.
+    fn parse_escape(&mut self, start: usize) -> Result<Escape, String> {
+        let c = self.next().ok_or(format!("trailing backslash at position {}", start))?;
+        if let Some(class) = Class::perl(c) {
+            return Ok(Escape::Class(class));
+        }
+        match c {
+            'n' => Ok(Escape::Char('\n')),
+            't' => Ok(Escape::Char('\t')),
+            'r' => Ok(Escape::Char('\r')),
+            c if c.is_ascii_alphanumeric() => Err(format!("unsupported escape '\\{}' at position {}", c, start)),
+            c => Ok(Escape::Char(c)),
+        }
+    }
+
+    /// Returns the next character without consuming it.
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    /// Consumes the next character.
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek();
+        self.pos += 1;
+        c
+    }
+}
+
+/// What a backslash escape stands for.
+enum Escape {
+    Char(char),
+    Class(Class),
+}
+
+/// Compiles a parsed expression into NFA instructions.
+///
+/// # Arguments
+///
+/// * `node` - The parsed expression.
+/// * `program` - The instructions compiled so far.
+///
+/// # Returns
+///
+/// * `Result<(), String>` - Ok if successful, Err if the expression is too large.
+fn compile(node: &Node, program: &mut Vec<Inst>) -> Result<(), String> {
+    if program.len() > MAX_PROGRAM {
+        return Err("expression is too large".into());
+    }
+    match node {
+        Node::Empty => {}
+        Node::Literal(c) => program.push(Inst::Literal(*c)),
+        Node::Any => program.push(Inst::Any),
+        Node::Class(class) => program.push(Inst::Class(class.clone())),
+        Node::Start => program.push(Inst::Start),
+        Node::End => program.push(Inst::End),
+        Node::Concat(items) => {
+            for item in items {
+                compile(item, program)?;
+            }
+        }
+        Node::Alternate(branches) => {
+            let mut jumps = Vec::new();
+            for (i, branch) in branches.iter().enumerate() {
+                if i + 1 == branches.len() {
+                    compile(branch, program)?;
+                } else {
+                    let split = program.len();
+                    program.push(Inst::Split(split + 1, 0));
+                    compile(branch, program)?;
+                    jumps.push(program.len());
+                    program.push(Inst::Jump(0));
+                    program[split] = Inst::Split(split + 1, program.len());
+                }
+            }
+            let end = program.len();
+            for jump in jumps {
+                program[jump] = Inst::Jump(end);
+            }
+        }
+        Node::Repeat { node, min, max } => {
+            for _ in 0..*min {
+                compile(node, program)?;
+            }
+            match max {
+                None => {
+                    let split = program.len();
+                    program.push(Inst::Split(split + 1, 0));
+                    compile(node, program)?;
+                    program.push(Inst::Jump(split));
+                    program[split] = Inst::Split(split + 1, program.len());
+                }
+                Some(max) => {
+                    let mut splits = Vec::new();
+                    for _ in *min..*max {
+                        splits.push(program.len());
+                        program.push(Inst::Split(0, 0));
+                        compile(node, program)?;
+                    }
+                    let end = program.len();
+                    for split in splits {
+                        program[split] = Inst::Split(split + 1, end);
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Regex;
+
+    fn matches(pattern: &str, text: &str) -> bool {
+        Regex::with_case(pattern, false).unwrap().is_match(text)
+    }
+
+    #[test]
+    fn matches_literals_anywhere_and_anchors() {
+        assert!(matches("log", "app.log.1"));
+        assert!(matches(r"\.log$", "app.log"));
+        assert!(!matches(r"\.log$", "app.log.1"));
+        assert!(matches("^core", "core.1234"));
+        assert!(!matches("^core", "libcore.so"));
+        assert!(matches("^$", ""));
+    }
+
+    #[test]
+    fn matches_classes_and_escapes() {
+        assert!(matches(r"^core\.\d+$", "core.1234"));
+        assert!(!matches(r"^core\.\d+$", "core.12a"));
+        assert!(matches("^[a-c_]+$", "ab_c"));
+        assert!(!matches("^[^0-9]+$", "abc1"));
+        assert!(matches(r"^\w+\s\W$", "tmp_1 !"));
+        assert!(matches("^a.c$", "abc"));
+        assert!(!matches("^a.c$", "ac"));
+    }
+
+    #[test]
+    fn matches_groups_and_quantifiers() {
+        assert!(matches("^(tmp|cache)$", "cache"));
+        assert!(!matches("^(tmp|cache)$", "caches"));
+        assert!(matches("^(?:ab)+$", "ababab"));
+        assert!(!matches("^(?:ab)+$", "aba"));
+        assert!(matches("^colou?r$", "color"));
+        assert!(matches("^a{2,3}$", "aaa"));
+        assert!(!matches("^a{2,3}$", "aaaa"));
+        assert!(matches("^a{2,}$", "aaaaa"));
+        assert!(!matches("^a{2}$", "a"));
+        assert!(matches("^a.*?b$", "axxb"));
+    }
+
+    #[test]
+    fn ignores_case_when_asked() {
+        assert!(!matches("^readme$", "README"));
+        assert!(matches("(?i)^readme$", "README"));
+        assert!(Regex::with_case("^readme$", true).unwrap().is_match("ReadMe"));
+        assert!(Regex::with_case("^[a-z]+$", true).unwrap().is_match("ABC"));
+    }
+
+    #[test]
+    fn takes_linear_time_on_nested_quantifiers() {
+        let text = format!("{}b", "a".repeat(5000));
+        assert!(!matches("^(a|aa)+$", &text));
+    }
+
+    #[test]
+    fn rejects_invalid_patterns() {
+        for pattern in ["a**", "a+*", "a{2}{3}", "*a", "a|+", "(a", "a)", "[a-", "a{3,2}", "a{1001}", "a{x}", r"a\"] {
+            assert!(Regex::with_case(pattern, false).is_err(), "{:?} was accepted", pattern);
+        }
+    }
+}
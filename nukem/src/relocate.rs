@@ -0,0 +1,410 @@
+//! This module moves files to another location for '--action move'.
+//!
+//! Each file or symlink is moved under the '--move-to' directory at its absolute path (so
+//! '/scratch/a/b.dat' ends up at '<dest>/scratch/a/b.dat'), creating the directories it needs.
+//! Within a filesystem the move is a link and an unlink. Across filesystems the contents are
+//! copied into a temporary file while being hashed, the copy is synced and read back, and the
+//! source is only removed once the SHA-256 of the copy matches the source and the source hasn't
+//! changed in the meantime. The copy keeps the permissions, times and extended attributes of the
+//! source, and its owner when running as root, and the directories created for it mirror the
+//! owner and permissions of the source directories. An existing file at the destination is never
+//! replaced, and a move that fails part way leaves nothing behind at the destination.
+
+use std::ffi::CString;
+use std::fs::{self, File, FileTimes};
+use std::io::{self, Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Component, Path, PathBuf};
+use crate::sha256::{self, Sha256};
+
+/// What happened to an object that was handed to the mover.
+#[derive(Debug)]
+pub enum Outcome {
+    /// The object was moved. True if it had to be copied to another filesystem.
+    Moved(bool),
+    /// The destination already exists, so the object was left alone.
+    DestinationExists,
+}
+
+/// Finds where an object ends up under the destination directory.
+///
+/// # Arguments
+///
+/// * `dest` - The destination directory.
+/// * `path` - The path of the object.
+///
+/// # Returns
+///
+/// * `PathBuf` - The path of the object under the destination.
+pub fn destination(dest: &Path, path: &Path) -> PathBuf {
+    let absolute = std::env::current_dir().map(|dir| dir.join(path)).unwrap_or_else(|_| path.to_path_buf());
+    let mut target = dest.to_path_buf();
+    for component in absolute.components() {
+        match component {
+            Component::Normal(part) => target.push(part),
+            Component::ParentDir if target != dest => {
+                target.pop();
+            }
+            _ => {}
+        }
+    }
+    target
+}
+
+/// Moves a file or symlink under the destination directory. If anything fails along the way,
+/// whatever was created at the destination is removed again and the source is left in place.
+///
+/// # Arguments
+///
+/// * `path` - The object to move.
+/// * `dest` - The destination directory.
+///
+/// # Returns
+///
+/// * `io::Result<Outcome>` - Ok with the outcome, Err if the object could not be moved. The
+///   source is left in place whenever an error is returned.
+pub fn move_path(path: &Path, dest: &Path) -> io::Result<Outcome> {
+    let before = fs::symlink_metadata(path)?;
+    let target = destination(dest, path);
+    if fs::symlink_metadata(&target).is_ok() {
+        return Ok(Outcome::DestinationExists);
+    }
+    let created = create_parents(dest, &target)?;
+    let result = place(path, &target, &before);
+    if result.is_err() {
+        // only empty directories can be removed, so directories other workers filled stay
+        for dir in created.iter().rev() {
+            let _ = fs::remove_dir(dir);
+        }
+    }
+    result
+}
+
+/// Creates the missing parents of a destination, giving each the owner and permissions of the
+/// directory it mirrors.
+///
+/// # Arguments
+///
+/// * `dest` - The destination directory.
+/// * `target` - Where the object is moved to.
+///
+/// # Returns
+///
+/// * `io::Result<Vec<PathBuf>>` - Ok with the directories that were created, outermost first.
+fn create_parents(dest: &Path, target: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut missing: Vec<&Path> = target.ancestors().skip(1)
+        .take_while(|dir| dir.starts_with(dest) && *dir != dest && fs::symlink_metadata(dir).is_err())
+        .collect();
+    missing.reverse();
+
+    let mut created = Vec::new();
+    for dir in missing {
+        match fs::create_dir(dir) {
+            Ok(()) => created.push(dir.to_path_buf()),
+            // another worker got there first
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => {
+                for dir in created.iter().rev() {
+                    let _ = fs::remove_dir(dir);
+                }
+                return Err(e);
+            }
+        }
+        let source = Path::new("/").join(dir.strip_prefix(dest).unwrap_or(dir));
+        if let Ok(metadata) = fs::metadata(&source) {
+            let _ = std::os::unix::fs::chown(dir, Some(metadata.uid()), Some(metadata.gid()));
+            // the owner keeps write access, or nothing could be moved into the directory
+            let _ = fs::set_permissions(dir, fs::Permissions::from_mode(metadata.mode() | 0o700));
+        }
+    }
+    Ok(created)
+}
+
+/// Puts an object at its destination and removes the source. Within a filesystem this is a link
+/// and an unlink, across filesystems a verified copy.
+///
+/// # Arguments
+///
+/// * `path` - The object to move.
+/// * `target` - Where the object is moved to. Its parent exists.
+/// * `before` - The metadata of the object before it was moved.
+///
+/// # Returns
+///
+/// * `io::Result<Outcome>` - Ok with the outcome, Err if the object could not be moved.
+fn place(path: &Path, target: &Path, before: &fs::Metadata) -> io::Result<Outcome> {
+    // a hard link never replaces an existing file, unlike a rename
+    match fs::hard_link(path, target) {
+        Ok(()) => return remove_source(path, target).map(|()| Outcome::Moved(false)),
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => return Ok(Outcome::DestinationExists),
+        Err(e) if e.kind() != io::ErrorKind::CrossesDevices => return Err(e),
+        Err(_) => {}
+    }
+
+    let mut partial = target.to_path_buf().into_os_string();
+    partial.push(".nukem-partial");
+    let partial = PathBuf::from(partial);
+    let copied = if before.file_type().is_symlink() {
+        copy_symlink(path, &partial)
+    } else {
+        copy_file(path, &partial, before)
+    };
+    let placed = copied.and_then(|()| match fs::hard_link(&partial, target) {
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Ok(false),
+        placed => placed.map(|()| true),
+    });
+    let _ = fs::remove_file(&partial);
+    if !placed? {
+        return Ok(Outcome::DestinationExists);
+    }
+    if let Some(parent) = target.parent() {
+        File::open(parent)?.sync_all()?;
+    }
+    remove_source(path, target).map(|()| Outcome::Moved(true))
+}
+
+/// Finds the absolute path an object is restored to. Its directory is resolved, but the object
+/// itself isn't, so a symlink is recorded as the link.
+///
+/// # Arguments
+///
+/// * `path` - The object.
+///
+/// # Returns
+///
+/// * `io::Result<PathBuf>` - Ok with the absolute path, Err if the directory can't be resolved.
+pub fn absolute(path: &Path) -> io::Result<PathBuf> {
+    let name = path.file_name().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "the path has no file name"))?;
+    let parent = path.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    Ok(parent.canonicalize()?.join(name))
+}
+
+/// Moves a file, symlink or directory tree to a new name, for '--trash' and '--undoable'.
+/// Within a filesystem it is renamed. Across filesystems it is copied with 'copy_tree' and
+/// removed once the copy is complete and synced. If the source can't be removed, the copy of a
+/// file or symlink is removed again, but the copy of a directory that could only be removed in
+/// part is kept, so everything that is gone from the source is still there in full.
+///
+/// # Arguments
+///
+/// * `path` - The object to move.
+/// * `target` - The new name, which must not exist yet. Its parent exists.
+///
+/// # Returns
+///
+/// * `io::Result<bool>` - Ok with true if the object had to be copied to another filesystem,
+///   Err if it could not be moved. Nothing is left at the target then, except for such a
+///   directory.
+pub fn move_tree(path: &Path, target: &Path) -> io::Result<bool> {
+    let before = fs::symlink_metadata(path)?;
+    match fs::rename(path, target) {
+        Ok(()) => return Ok(false),
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {}
+        Err(e) => return Err(e),
+    }
+    let parent = target.parent().unwrap_or(Path::new("."));
+    if let Err(e) = copy_tree(path, target).and_then(|()| File::open(parent)?.sync_all()) {
+        let _ = if fs::symlink_metadata(target).is_ok_and(|copy| copy.is_dir()) { fs::remove_dir_all(target) } else { fs::remove_file(target) };
+        return Err(e);
+    }
+    if before.is_dir() {
+        fs::remove_dir_all(path).map(|()| true)
+    } else {
+        remove_source(path, target).map(|()| true)
+    }
+}
+
+/// Copies an object to another filesystem for '--trash', verifying every file like a move
+/// does. A directory is copied with everything within it, and gets its permissions and times
+/// once its entries are in place. The source is left alone.
+///
+/// # Arguments
+///
+/// * `path` - The file, symlink or directory to copy.
+/// * `target` - The copy, which must not exist yet.
+///
+/// # Returns
+///
+/// * `io::Result<()>` - Ok if the whole object was copied, Err otherwise. Whatever was copied
+///   up to the error is left for the caller to remove.
+pub fn copy_tree(path: &Path, target: &Path) -> io::Result<()> {
+    let before = fs::symlink_metadata(path)?;
+    if before.file_type().is_symlink() {
+        return copy_symlink(path, target);
+    }
+    if before.is_file() {
+        return copy_file(path, target, &before);
+    }
+    if !before.is_dir() {
+        return Err(io::Error::other(format!("{} is a special file, which can't be copied", path.display())));
+    }
+    fs::create_dir(target)?;
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        copy_tree(&entry.path(), &target.join(entry.file_name()))?;
+    }
+    // only root can hand the directory to another owner, so a failure here is not an error
+    let _ = std::os::unix::fs::chown(target, Some(before.uid()), Some(before.gid()));
+    copy_xattrs(path, target)?;
+    let dir = File::open(target)?;
+    dir.set_times(FileTimes::new().set_accessed(before.accessed()?).set_modified(before.modified()?))?;
+    dir.sync_all()?;
+    fs::set_permissions(target, before.permissions())
+}
+
+/// Removes the source of a move once its destination is in place. If the source can't be
+/// removed, the destination is removed instead so the object isn't left in both places.
+///
+/// # Arguments
+///
+/// * `path` - The source.
+/// * `target` - The destination.
+///
+/// # Returns
+///
+/// * `io::Result<()>` - Ok if the source was removed, Err otherwise.
+fn remove_source(path: &Path, target: &Path) -> io::Result<()> {
+    fs::remove_file(path).inspect_err(|_| {
+        let _ = fs::remove_file(target);
+    })
+}
+
+/// Copies the extended attributes of an object, without following symlinks. Attributes that
+/// need privileges the process doesn't have (such as 'trusted.*') are left out.
+///
+/// # Arguments
+///
+/// * `path` - The source.
+/// * `partial` - The copy.
+///
+/// # Returns
+///
+/// * `io::Result<()>` - Ok if the attributes were copied, Err otherwise.
+fn copy_xattrs(path: &Path, partial: &Path) -> io::Result<()> {
+    let source = CString::new(path.as_os_str().as_bytes())?;
+    let copy = CString::new(partial.as_os_str().as_bytes())?;
+    let size = unsafe { libc::llistxattr(source.as_ptr(), std::ptr::null_mut(), 0) };
+    if size < 0 {
+        let e = io::Error::last_os_error();
+        return if e.raw_os_error() == Some(libc::ENOTSUP) { Ok(()) } else { Err(e) };
+    }
+    let mut names = vec![0u8; size as usize];
+    let size = unsafe { libc::llistxattr(source.as_ptr(), names.as_mut_ptr() as *mut libc::c_char, names.len()) };
+    if size < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    names.truncate(size as usize);
+
+    for name in names.split(|&byte| byte == 0).filter(|name| !name.is_empty()) {
+        let name = CString::new(name)?;
+        let size = unsafe { libc::lgetxattr(source.as_ptr(), name.as_ptr(), std::ptr::null_mut(), 0) };
+        if size < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut value = vec![0u8; size as usize];
+        let size = unsafe { libc::lgetxattr(source.as_ptr(), name.as_ptr(), value.as_mut_ptr() as *mut libc::c_void, value.len()) };
+        if size < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let status = unsafe { libc::lsetxattr(copy.as_ptr(), name.as_ptr(), value.as_ptr() as *const libc::c_void, size as usize, 0) };
+        if status != 0 {
+            let e = io::Error::last_os_error();
+            if e.raw_os_error() != Some(libc::EPERM) {
+                return Err(io::Error::new(e.kind(), format!("unable to copy extended attribute {:?}: {}", name, e)));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Recreates a symlink at the partial destination.
+///
+/// # Arguments
+///
+/// * `path` - The symlink.
+/// * `partial` - The temporary destination.
+///
+/// # Returns
+///
+/// * `io::Result<()>` - Ok if the link was recreated, Err otherwise.
+fn copy_symlink(path: &Path, partial: &Path) -> io::Result<()> {
+    let link = fs::read_link(path)?;
+    std::os::unix::fs::symlink(&link, partial)?;
+    // only root can hand the link to another owner, so a failure here is not an error
+    let metadata = fs::symlink_metadata(path)?;
+    let _ = std::os::unix::fs::lchown(partial, Some(metadata.uid()), Some(metadata.gid()));
+    copy_xattrs(path, partial)?;
+    if fs::read_link(partial)? != link {
+        return Err(io::Error::other("the copied symlink doesn't match the original"));
+    }
+    Ok(())
+}
+
+/// Copies a file to the partial destination and verifies the copy against the source.
+///
+/// # Arguments
+///
+/// * `path` - The file.
+/// * `partial` - The temporary destination.
+/// * `before` - The metadata of the file before it was copied.
+///
+/// # Returns
+///
+/// * `io::Result<()>` - Ok if the copy is complete and matches the source, Err otherwise.
+fn copy_file(path: &Path, partial: &Path, before: &fs::Metadata) -> io::Result<()> {
+    let mut source = File::open(path)?;
+    let mut output = File::create_new(partial)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 1 << 20];
+    loop {
+        let read = source.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        output.write_all(&buffer[..read])?;
+    }
+    output.sync_all()?;
+    let expected = hasher.finish();
+
+    let after = fs::symlink_metadata(path)?;
+    if after.len() != before.len() || after.mtime() != before.mtime() || after.mtime_nsec() != before.mtime_nsec() {
+        return Err(io::Error::other("the file changed while it was being copied"));
+    }
+    let actual = hash_file(partial)?;
+    if actual != expected {
+        return Err(io::Error::other(format!(
+            "checksum mismatch: source {} but copy {}", sha256::to_hex(&expected), sha256::to_hex(&actual)
+        )));
+    }
+
+    // only root can hand the file to another owner, so a failure here is not an error
+    let _ = std::os::unix::fs::chown(partial, Some(before.uid()), Some(before.gid()));
+    output.set_permissions(before.permissions())?;
+    copy_xattrs(path, partial)?;
+    output.set_times(FileTimes::new().set_accessed(before.accessed()?).set_modified(before.modified()?))?;
+    output.sync_all()
+}
+
+/// Computes the SHA-256 of a file.
+///
+/// # Arguments
+///
+/// * `path` - The file.
+///
+/// # Returns
+///
+/// * `io::Result<[u8; 32]>` - Ok with the digest, Err if the file could not be read.
+fn hash_file(path: &Path) -> io::Result<[u8; 32]> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 1 << 20];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            return Ok(hasher.finish());
+        }
+        hasher.update(&buffer[..read]);
+    }
+}
@@ -0,0 +1,283 @@
+//! This module writes a machine-readable JSON report of a run.
+//!
+//! Besides the totals shown in the final report, the JSON report ('--json-report') contains a
+//! nested map of every crawled directory with its entry counts, bytes, failures and the time
+//! spent crawling and deleting it. This makes it possible to find out afterwards which parts of a
+//! tree dominated the cost of a cleanup. Collecting the per-directory results costs an extra stat
+//! per entry and memory per directory, so it is only done when the report was requested.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, Write as _};
+use std::mem::ManuallyDrop;
+use std::os::fd::FromRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use crate::skipped::{SkipCounter, SkipReason};
+use crate::symlinks::SymlinkCounts;
+use crate::utility::RunSummary;
+use crate::slowops::SlowOps;
+use crate::phases::PhaseTimes;
+
+/// The results collected for a single directory.
+#[derive(Debug, Clone, Default)]
+pub struct DirStats {
+    pub files: u64,
+    pub dirs: u64,
+    pub bytes: u64,
+    pub failures: u64,
+    pub crawl_elapsed: Duration,
+    pub delete_elapsed: Duration,
+}
+
+/// Shared per-directory results. Cloning it shares the underlying map, so the Crawler and the
+/// Deleter can both record results.
+#[derive(Clone, Default)]
+pub struct DirReport {
+    dirs: Arc<Mutex<HashMap<PathBuf, DirStats>>>,
+}
+
+impl DirReport {
+    /// Creates an empty report.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - A new instance of the DirReport.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records what the crawler found in a directory.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The directory.
+    /// * `files` - The number of non-directory entries.
+    /// * `dirs` - The number of subdirectories.
+    /// * `bytes` - The combined size of the non-directory entries.
+    /// * `elapsed` - The time spent reading the directory, including its subdirectories.
+    pub async fn record_crawl(&self, path: &Path, files: u64, dirs: u64, bytes: u64, elapsed: Duration) {
+        let mut map = self.dirs.lock().await;
+        let stats = map.entry(path.to_path_buf()).or_default();
+        stats.files = files;
+        stats.dirs = dirs;
+        stats.bytes = bytes;
+        stats.crawl_elapsed = elapsed;
+    }
+
+    /// Records the time spent deleting a directory.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The directory.
+    /// * `elapsed` - The time spent deleting it.
+    pub async fn record_delete(&self, path: &Path, elapsed: Duration) {
+        self.dirs.lock().await.entry(path.to_path_buf()).or_default().delete_elapsed = elapsed;
+    }
+
+    /// Records a failed deletion. Failures of directories are counted on the directory itself,
+    /// failures of files on the directory that holds them.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path that could not be deleted.
+    pub async fn record_failure(&self, path: &Path) {
+        let mut map = self.dirs.lock().await;
+        let key = match path.parent() {
+            Some(parent) if !map.contains_key(path) => parent.to_path_buf(),
+            _ => path.to_path_buf(),
+        };
+        map.entry(key).or_default().failures += 1;
+    }
+}
+
+/// A directory in the nested map. Directories that were not crawled themselves (such as the
+/// parent of a pattern) only carry children.
+#[derive(Default)]
+struct Node {
+    stats: Option<DirStats>,
+    children: BTreeMap<String, Node>,
+}
+
+impl Node {
+    /// Serializes the node and its children as a JSON object.
+    ///
+    /// # Arguments
+    ///
+    /// * `out` - The string to append to.
+    fn write_json(&self, out: &mut String) {
+        out.push('{');
+        if let Some(ref stats) = self.stats {
+            let _ = write!(
+                out,
+                "\"files\":{},\"dirs\":{},\"bytes\":{},\"failures\":{},\"crawl_secs\":{:.6},\"delete_secs\":{:.6},",
+                stats.files, stats.dirs, stats.bytes, stats.failures,
+                stats.crawl_elapsed.as_secs_f64(), stats.delete_elapsed.as_secs_f64()
+            );
+        }
+        out.push_str("\"children\":");
+        Self::write_children(&self.children, out);
+        out.push('}');
+    }
+
+    /// Serializes a set of directories as a JSON object keyed by directory name.
+    ///
+    /// # Arguments
+    ///
+    /// * `children` - The directories to serialize.
+    /// * `out` - The string to append to.
+    fn write_children(children: &BTreeMap<String, Node>, out: &mut String) {
+        out.push('{');
+        for (index, (name, child)) in children.iter().enumerate() {
+            if index > 0 {
+                out.push(',');
+            }
+            out.push_str(&json_string(name));
+            out.push(':');
+            child.write_json(out);
+        }
+        out.push('}');
+    }
+}
+
+/// Writes the JSON report.
+///
+/// # Arguments
+///
+/// * `path` - The file to write the report to.
+/// * `summary` - The totals of the run.
+/// * `skipped` - The shared skip counter, for the per-reason breakdown.
+/// * `dir_report` - The per-directory results.
+/// * `slow_ops` - The slowest operations of the run.
+/// * `phases` - The start and end of each phase of the run, relative to the start of the run.
+/// * `symlink_counts` - The symlink counts, if '--symlink-report' was specified.
+///
+/// # Returns
+///
+/// * `io::Result<()>` - Ok if the report was written.
+pub async fn write(path: &Path, summary: &RunSummary, skipped: &SkipCounter, dir_report: &DirReport, slow_ops: &SlowOps, phases: &PhaseTimes, symlink_counts: Option<&SymlinkCounts>) -> io::Result<()> {
+    let mut out = String::from("{");
+    write_totals(&mut out, summary, skipped).await;
+    out.push(',');
+
+    if let Some(symlink_counts) = symlink_counts {
+        let (found, deleted) = (symlink_counts.found().await, symlink_counts.deleted().await);
+        let _ = write!(
+            out,
+            "\"symlinks\":{{\"found\":{{\"valid\":{},\"dangling\":{}}},\"deleted\":{{\"valid\":{},\"dangling\":{}}}}},",
+            found.valid, found.dangling, deleted.valid, deleted.dangling
+        );
+    }
+
+    out.push_str("\"slowest_operations\":[");
+    for (index, op) in slow_ops.slowest().await.iter().enumerate() {
+        if index > 0 {
+            out.push(',');
+        }
+        let _ = write!(out, "{{\"op\":{},\"path\":{},\"secs\":{:.6}}}",
+            json_string(op.kind.label()), json_string(&op.path.to_string_lossy()), op.elapsed.as_secs_f64());
+    }
+    out.push_str("],");
+
+    out.push_str("\"phases\":[");
+    for (index, span) in phases.spans().await.iter().enumerate() {
+        if index > 0 {
+            out.push(',');
+        }
+        let _ = write!(out, "{{\"phase\":{},\"start_secs\":{:.6},\"end_secs\":{:.6}}}",
+            json_string(span.phase.label()), span.start.as_secs_f64(), span.end.as_secs_f64());
+    }
+    out.push_str("],");
+
+    // nest the directories by path component
+    let mut root = Node::default();
+    for (dir, stats) in dir_report.dirs.lock().await.iter() {
+        let mut node = &mut root;
+        for component in dir.components() {
+            node = node.children.entry(component.as_os_str().to_string_lossy().into_owned()).or_default();
+        }
+        node.stats = Some(stats.clone());
+    }
+    out.push_str("\"directories\":");
+    Node::write_children(&root.children, &mut out);
+    out.push_str("}\n");
+
+    tokio::fs::write(path, out).await
+}
+
+/// Writes the totals of a run as a single line of JSON to a file descriptor the caller opened,
+/// for '--summary-fd'. The descriptor is left open.
+///
+/// # Arguments
+///
+/// * `fd` - The file descriptor.
+/// * `summary` - The totals of the run.
+/// * `skipped` - The shared skip counter, for the per-reason breakdown.
+/// * `run_id` - The ID of the run in the run history, if it was recorded.
+///
+/// # Returns
+///
+/// * `io::Result<()>` - Ok if the summary was written.
+pub async fn write_summary(fd: i32, summary: &RunSummary, skipped: &SkipCounter, run_id: Option<&str>) -> io::Result<()> {
+    let mut out = String::from("{");
+    write_totals(&mut out, summary, skipped).await;
+    let _ = writeln!(out, ",\"run_id\":{}}}", run_id.map(json_string).unwrap_or_else(|| "null".to_string()));
+    // the descriptor belongs to whoever started nukem, so it must not be closed here
+    let mut file = ManuallyDrop::new(unsafe { File::from_raw_fd(fd) });
+    file.write_all(out.as_bytes()).and_then(|()| file.flush())
+}
+
+/// Writes the totals of a run and the skips by reason as JSON members, without the braces
+/// around them.
+///
+/// # Arguments
+///
+/// * `out` - The JSON being written.
+/// * `summary` - The totals of the run.
+/// * `skipped` - The shared skip counter.
+async fn write_totals(out: &mut String, summary: &RunSummary, skipped: &SkipCounter) {
+    let _ = write!(
+        out,
+        "\"dry_run\":{},\"found\":{},\"deleted\":{},\"failed\":{},\"skipped\":{},\"total_size\":{},\"elapsed_secs\":{:.6},\"accounting_ok\":{},\"interrupted\":{},",
+        summary.dry_run, summary.found, summary.deleted, summary.failed, summary.skipped,
+        summary.total_size, summary.elapsed.as_secs_f64(), summary.accounting_ok, summary.interrupted
+    );
+    out.push_str("\"skipped_by_reason\":{");
+    for (index, reason) in SkipReason::ALL.iter().enumerate() {
+        if index > 0 {
+            out.push(',');
+        }
+        let _ = write!(out, "{}:{}", json_string(reason.label()), skipped.get(*reason).await);
+    }
+    out.push('}');
+}
+
+/// Quotes and escapes a string for use in JSON.
+///
+/// # Arguments
+///
+/// * `value` - The string to quote.
+///
+/// # Returns
+///
+/// * `String` - The JSON string literal.
+pub fn json_string(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => { let _ = write!(out, "\\u{:04x}", c as u32); }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
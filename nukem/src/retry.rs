@@ -0,0 +1,81 @@
+//! This module re-runs the failures of a previous run.
+//!
+//! With '--retry-failed', the paths that could not be deleted in an earlier run (given by its run
+//! ID or by a file with one path per line) become the targets of the new run. Paths that are
+//! already gone are left out, and paths below another retried directory are covered by that
+//! directory. After the run a delta report shows how many of the retried paths are now deleted
+//! and how many are still there. The remaining failures are saved again, so a retry can itself be
+//! retried.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use glob::Pattern;
+use crate::history;
+use crate::logger::Logger;
+
+/// The failures of a previous run that are being retried.
+#[derive(Debug, Clone, Default)]
+pub struct RetrySet {
+    /// The run ID or file the failures were loaded from.
+    pub source: String,
+    /// The failed paths that still exist.
+    pub targets: Vec<PathBuf>,
+    /// The number of failed paths that were gone before the retry.
+    pub already_gone: usize,
+}
+
+impl RetrySet {
+    /// Loads the failures of a previous run.
+    ///
+    /// # Arguments
+    ///
+    /// * `state_dir` - The state directory, if known.
+    /// * `source` - The run ID, or a file with one path per line.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, String>` - Ok with the paths to retry, Err with a message if the list can't be read.
+    pub fn load(state_dir: Option<&Path>, source: &str) -> Result<Self, String> {
+        let mut failed = history::load_failures(state_dir, source)?;
+        failed.sort();
+        failed.dedup();
+
+        let mut targets: Vec<PathBuf> = Vec::new();
+        let mut already_gone = 0;
+        for path in failed {
+            if path.symlink_metadata().is_err() {
+                already_gone += 1;
+            } else if !targets.last().is_some_and(|parent| path.starts_with(parent)) {
+                // sorted order puts a directory right before everything below it
+                targets.push(path);
+            }
+        }
+        Ok(Self { source: source.to_string(), targets, already_gone })
+    }
+
+    /// Converts the targets to the patterns the crawler expects, escaping any characters that
+    /// would otherwise be read as wildcards.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<PathBuf>` - The escaped targets.
+    pub fn patterns(&self) -> Vec<PathBuf> {
+        self.targets.iter().map(|path| PathBuf::from(Pattern::escape(&path.to_string_lossy()))).collect()
+    }
+
+    /// Logs how many of the retried paths were dealt with.
+    ///
+    /// # Arguments
+    ///
+    /// * `logger` - An instance of the Logger.
+    /// * `dry_run` - A boolean indicating whether this was a dry run.
+    pub async fn print_delta(&self, logger: &Arc<Logger>, dry_run: bool) {
+        let still_present = self.targets.iter().filter(|path| path.symlink_metadata().is_ok()).count();
+        let suffix = if dry_run { " (dry run, nothing was removed)" } else { "" };
+        logger.log(&format!("Retry of '{}'{}:", self.source, suffix), false, false, false).await;
+        logger.log(&format!("    Retried paths: {}", self.targets.len()), false, false, false).await;
+        logger.log(&format!("    Now deleted: {}", self.targets.len() - still_present), false, false, false).await;
+        logger.log(&format!("    Still present: {}", still_present), false, false, false).await;
+        logger.log(&format!("    Already gone before the retry: {}", self.already_gone), false, false, false).await;
+    }
+}
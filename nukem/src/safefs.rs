@@ -0,0 +1,403 @@
+//! This module removes objects without following symlinks that were swapped into their paths.
+//!
+//! Between the crawl and the removal, whoever can write to a directory within the targets can
+//! replace a subdirectory with a symlink to somewhere else, such as '/etc'. Resolving the full path
+//! again would then unlink whatever the symlink points to. Instead, each target pattern has an
+//! anchor, the directory above its first wildcard (or above the path itself if it has none), which
+//! is opened the usual way, since the user named it. Every directory below the anchor is opened
+//! relative to the one above it with O_NOFOLLOW and O_DIRECTORY, so a symlink or a file in its
+//! place fails the removal instead of being followed, and objects are unlinked with 'unlinkat'
+//! relative to the directory they were found in. Directory trees are taken apart the same way,
+//! and the crawler reads each directory through the descriptor it opened.
+//!
+//! The most recently opened directories are kept open, so the objects of one directory don't each
+//! walk down from the anchor again; a directory that is removed is forgotten. Paths below none of
+//! the anchors are resolved as they are. Compressing, moving, stashing and archiving objects still
+//! work on their paths.
+
+use std::collections::VecDeque;
+use std::ffi::{CString, OsStr};
+use std::fs;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use tokio::task;
+use crate::patterns;
+use crate::utility::freed_size;
+
+// Flags for opening an anchor, which may be reached through symlinks.
+const ANCHOR_FLAGS: libc::c_int = libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC;
+// Flags for opening a directory below an anchor.
+const DIR_FLAGS: libc::c_int = ANCHOR_FLAGS | libc::O_NOFOLLOW;
+
+/// The anchors of the target patterns, and the directories opened below them.
+pub struct Anchors {
+    // the anchors, deepest first
+    anchors: Vec<PathBuf>,
+    // the most recently opened directories, oldest first
+    open: Mutex<VecDeque<(PathBuf, Arc<OwnedFd>)>>,
+}
+
+impl Anchors {
+    // Number of directories kept open.
+    const OPEN_DIRS: usize = 128;
+
+    /// Finds the anchors of the target patterns.
+    ///
+    /// # Arguments
+    ///
+    /// * `patterns` - The target patterns.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The anchors.
+    pub fn new(patterns: &[PathBuf]) -> Self {
+        let mut anchors: Vec<PathBuf> = patterns::expand_all(patterns).iter().map(|pattern| anchor(Path::new(pattern))).collect();
+        anchors.sort_by_key(|anchor| std::cmp::Reverse(anchor.components().count()));
+        anchors.dedup();
+        Self {
+            anchors,
+            open: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Retrieves the anchors.
+    ///
+    /// # Returns
+    ///
+    /// * `&[PathBuf]` - The anchors, deepest first.
+    pub fn roots(&self) -> &[PathBuf] {
+        &self.anchors
+    }
+
+    /// Reads a directory through a descriptor opened below its anchor.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The directory.
+    ///
+    /// # Returns
+    ///
+    /// * `io::Result<tokio::fs::ReadDir>` - Ok with the entries, Err if the directory can't be
+    ///   opened, such as when a symlink took the place of one of the directories on the way.
+    pub async fn read_dir(self: &Arc<Self>, path: &Path) -> io::Result<tokio::fs::ReadDir> {
+        let (anchors, dir) = (Arc::clone(self), path.to_path_buf());
+        let fd = task::spawn_blocking(move || anchors.open_dir(&dir)).await??;
+        tokio::fs::read_dir(listing(&fd, path)).await
+    }
+
+    /// Unlinks a file or symlink relative to its directory.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The file or symlink.
+    ///
+    /// # Returns
+    ///
+    /// * `io::Result<()>` - Ok if it was unlinked, Err otherwise.
+    pub async fn remove_file(self: &Arc<Self>, path: &Path) -> io::Result<()> {
+        let (anchors, file) = (Arc::clone(self), path.to_path_buf());
+        task::spawn_blocking(move || anchors.unlink(&file)).await?
+    }
+
+    /// Unlinks a file or symlink relative to its directory, on the calling thread.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The file or symlink.
+    ///
+    /// # Returns
+    ///
+    /// * `io::Result<()>` - Ok if it was unlinked, Err otherwise.
+    pub fn unlink(&self, path: &Path) -> io::Result<()> {
+        let (dir, name) = self.open_parent(path)?;
+        unlink_at(&dir, name, 0)
+    }
+
+    /// Removes an empty directory relative to its parent.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The directory.
+    ///
+    /// # Returns
+    ///
+    /// * `io::Result<()>` - Ok if it was removed, Err otherwise.
+    pub fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        let (dir, name) = self.open_parent(path)?;
+        unlink_at(&dir, name, libc::AT_REMOVEDIR)?;
+        self.forget(path);
+        Ok(())
+    }
+
+    /// Removes a directory tree like 'remove_dir_all', but through descriptors, and notes the
+    /// owner and the space freed of every file and symlink it unlinks. Objects within the tree
+    /// that another worker removed first are passed over, and a directory that turned into a
+    /// symlink since it was listed is unlinked as one. Given a device, the removal never descends
+    /// into a directory on another one, and given an owner, it stops at the first object of
+    /// another user.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The directory.
+    /// * `removed` - Where the owner and size of each unlinked object are added.
+    /// * `device` - The device the tree has to be on, or None to remove it across filesystems.
+    /// * `owner` - The user everything within the tree has to be owned by, or None for anyone.
+    ///
+    /// # Returns
+    ///
+    /// * `io::Result<()>` - Ok if the tree was removed, Err with the first error otherwise. The
+    ///   objects unlinked before the error are in `removed` either way.
+    pub fn remove_tree(&self, path: &Path, removed: &mut Vec<(u32, u64)>, device: Option<u64>, owner: Option<u32>) -> io::Result<()> {
+        let (parent, name) = self.open_parent(path)?;
+        let dir = open_at(Some(&parent), name, DIR_FLAGS).map_err(|e| moved(path, e))?;
+        let result = empty_tree(&dir, path, removed, device, owner).and_then(|()| unlink_at(&parent, name, libc::AT_REMOVEDIR));
+        self.forget(path);
+        result
+    }
+
+    /// Opens a directory, starting from the deepest one kept open on its way below its anchor.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The directory.
+    ///
+    /// # Returns
+    ///
+    /// * `io::Result<Arc<OwnedFd>>` - Ok with the descriptor, Err if it can't be opened.
+    fn open_dir(&self, path: &Path) -> io::Result<Arc<OwnedFd>> {
+        let path = normalize(path);
+        let Some(anchor) = self.anchors.iter().find(|anchor| path.starts_with(anchor)) else {
+            return open_at(None, path.as_os_str(), ANCHOR_FLAGS).map(Arc::new);
+        };
+        let start = self.open.lock().unwrap().iter()
+            .filter(|(dir, _)| path.starts_with(dir) && dir.starts_with(anchor))
+            .max_by_key(|(dir, _)| dir.components().count())
+            .map(|(dir, fd)| (dir.clone(), Arc::clone(fd)));
+        let (mut current, mut fd) = match start {
+            Some(start) => start,
+            None => {
+                let fd = Arc::new(open_at(None, anchor.as_os_str(), ANCHOR_FLAGS)?);
+                self.keep(anchor, &fd);
+                (anchor.clone(), fd)
+            }
+        };
+        if current == path {
+            return Ok(fd);
+        }
+        for component in path.strip_prefix(&current).unwrap_or(Path::new("")).components() {
+            current.push(component);
+            fd = Arc::new(open_at(Some(&fd), component.as_os_str(), DIR_FLAGS).map_err(|e| moved(&current, e))?);
+        }
+        self.keep(&path, &fd);
+        Ok(fd)
+    }
+
+    /// Opens the directory an object is in.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The object.
+    ///
+    /// # Returns
+    ///
+    /// * `io::Result<(Arc<OwnedFd>, &OsStr)>` - Ok with the directory and the name of the object
+    ///   within it, Err if the directory can't be opened.
+    fn open_parent<'a>(&self, path: &'a Path) -> io::Result<(Arc<OwnedFd>, &'a OsStr)> {
+        let name = path.file_name().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("{} has no name", path.display())))?;
+        let parent = path.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        Ok((self.open_dir(parent)?, name))
+    }
+
+    /// Keeps a directory open, closing the oldest one kept if there are too many.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The directory.
+    /// * `fd` - Its descriptor.
+    fn keep(&self, path: &Path, fd: &Arc<OwnedFd>) {
+        let mut open = self.open.lock().unwrap();
+        if open.len() >= Self::OPEN_DIRS {
+            open.pop_front();
+        }
+        open.push_back((path.to_path_buf(), Arc::clone(fd)));
+    }
+
+    /// Closes a removed directory, and any directory within it that was kept open.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The directory.
+    fn forget(&self, path: &Path) {
+        let path = normalize(path);
+        self.open.lock().unwrap().retain(|(dir, _)| !dir.starts_with(&path));
+    }
+}
+
+/// Finds the anchor of a target pattern: the directory above its first wildcard, or above the
+/// path itself if it has none.
+///
+/// # Arguments
+///
+/// * `pattern` - The pattern, with its braces expanded.
+///
+/// # Returns
+///
+/// * `PathBuf` - The anchor.
+fn anchor(pattern: &Path) -> PathBuf {
+    let pattern = normalize(pattern);
+    let components: Vec<Component> = pattern.components().collect();
+    let literal = components.iter().position(|component| patterns::has_wildcard(&component.as_os_str().to_string_lossy()))
+        .unwrap_or(components.len())
+        .min(components.len().saturating_sub(1));
+    let anchor: PathBuf = components[..literal].iter().collect();
+    if anchor.as_os_str().is_empty() { PathBuf::from(".") } else { anchor }
+}
+
+/// Drops the leading '.' of a relative path, so the same directory is always written the same way.
+///
+/// # Arguments
+///
+/// * `path` - The path.
+///
+/// # Returns
+///
+/// * `PathBuf` - The path without '.' components, or '.' for the current directory.
+fn normalize(path: &Path) -> PathBuf {
+    let path: PathBuf = path.components().filter(|component| *component != Component::CurDir).collect();
+    if path.as_os_str().is_empty() { PathBuf::from(".") } else { path }
+}
+
+/// Unlinks everything within a directory, deepest first.
+///
+/// # Arguments
+///
+/// * `dir` - The directory.
+/// * `path` - Its path, for the messages.
+/// * `removed` - Where the owner and size of each unlinked object are added.
+/// * `device` - The device the tree has to be on, or None to remove it across filesystems.
+/// * `owner` - The user everything within the tree has to be owned by, or None for anyone.
+///
+/// # Returns
+///
+/// * `io::Result<()>` - Ok if the directory is empty now, Err with the first error otherwise.
+fn empty_tree(dir: &OwnedFd, path: &Path, removed: &mut Vec<(u32, u64)>, device: Option<u64>, owner: Option<u32>) -> io::Result<()> {
+    let gone = |e: &io::Error| e.kind() == io::ErrorKind::NotFound;
+    for entry in fs::read_dir(listing(dir, path))? {
+        let entry = entry?;
+        let name = entry.file_name();
+        // read relative to the directory, without following symlinks
+        let metadata = match entry.metadata() {
+            Ok(metadata) => metadata,
+            Err(e) if gone(&e) => continue,
+            Err(e) => return Err(e),
+        };
+        if owner.is_some_and(|owner| owner != metadata.uid()) {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, format!("{} is owned by user {}", path.join(&name).display(), metadata.uid())));
+        }
+        if metadata.is_dir() {
+            let entry_path = path.join(&name);
+            if device.is_some_and(|device| device != metadata.dev()) {
+                return Err(io::Error::other(format!("{} is on another filesystem", entry_path.display())));
+            }
+            match open_at(Some(dir), &name, DIR_FLAGS) {
+                Ok(subdir) => {
+                    match empty_tree(&subdir, &entry_path, removed, device, owner).and_then(|()| unlink_at(dir, &name, libc::AT_REMOVEDIR)) {
+                        Err(e) if !gone(&e) => return Err(e),
+                        _ => continue,
+                    }
+                }
+                Err(e) if gone(&e) => continue,
+                // not a directory anymore, so it goes like a file
+                Err(e) if matches!(e.raw_os_error(), Some(libc::ELOOP) | Some(libc::ENOTDIR)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        match unlink_at(dir, &name, 0) {
+            Ok(()) => removed.push((metadata.uid(), freed_size(&metadata, metadata.nlink()))),
+            Err(e) if gone(&e) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Gives the path a directory is listed through. With '/proc', that's its descriptor, so the
+/// entries are those of the directory that was opened, and their metadata is read relative to it.
+///
+/// # Arguments
+///
+/// * `fd` - The descriptor of the directory.
+/// * `path` - The path of the directory, used if '/proc' isn't mounted.
+///
+/// # Returns
+///
+/// * `PathBuf` - The path to list.
+fn listing(fd: &OwnedFd, path: &Path) -> PathBuf {
+    static PROC_FDS: OnceLock<bool> = OnceLock::new();
+    if *PROC_FDS.get_or_init(|| Path::new("/proc/self/fd").is_dir()) {
+        PathBuf::from(format!("/proc/self/fd/{}", fd.as_raw_fd()))
+    } else {
+        path.to_path_buf()
+    }
+}
+
+/// Explains why a directory below an anchor couldn't be opened, if it isn't a directory anymore.
+///
+/// # Arguments
+///
+/// * `path` - The directory.
+/// * `e` - The error of 'openat'.
+///
+/// # Returns
+///
+/// * `io::Error` - The error, with its message for a symlink or file in place of the directory.
+fn moved(path: &Path, e: io::Error) -> io::Error {
+    match e.raw_os_error() {
+        Some(libc::ELOOP) => io::Error::other(format!("{} was replaced by a symlink, which is not followed", path.display())),
+        Some(libc::ENOTDIR) => io::Error::other(format!("{} is not a directory anymore", path.display())),
+        _ => e,
+    }
+}
+
+/// Opens a directory with 'openat'.
+///
+/// # Arguments
+///
+/// * `dir` - The directory the name is relative to, or None for the current directory.
+/// * `name` - The name or path.
+/// * `flags` - The flags to open it with.
+///
+/// # Returns
+///
+/// * `io::Result<OwnedFd>` - Ok with the descriptor, Err otherwise.
+fn open_at(dir: Option<&OwnedFd>, name: &OsStr, flags: libc::c_int) -> io::Result<OwnedFd> {
+    let name = CString::new(name.as_bytes()).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let fd = unsafe { libc::openat(dir.map_or(libc::AT_FDCWD, |dir| dir.as_raw_fd()), name.as_ptr(), flags) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+/// Unlinks an object with 'unlinkat'.
+///
+/// # Arguments
+///
+/// * `dir` - The directory the object is in.
+/// * `name` - The name of the object.
+/// * `flags` - 0 for a file or symlink, AT_REMOVEDIR for an empty directory.
+///
+/// # Returns
+///
+/// * `io::Result<()>` - Ok if it was unlinked, Err otherwise.
+fn unlink_at(dir: &OwnedFd, name: &OsStr, flags: libc::c_int) -> io::Result<()> {
+    let name = CString::new(name.as_bytes()).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    if unsafe { libc::unlinkat(dir.as_raw_fd(), name.as_ptr(), flags) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
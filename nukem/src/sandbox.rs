@@ -0,0 +1,284 @@
+//! This module confines a run with Landlock for '--sandbox', so that whatever goes wrong in the
+//! traversal, nukem can't remove or write anything outside of where it was pointed.
+//!
+//! Once the arguments are parsed, and before the runtime starts its threads, the process gives
+//! up every right to change the filesystem except for these: removing objects below the root of
+//! each target pattern, the directory above its first wildcard (or above the path itself if it
+//! has none), and writing to the directories the run writes to, which
+//! are those of the logfile, the state directory, the reports, the journal, the plan, the lock
+//! file, and the staging folder, trash, archive or destination of the action. '--action compress'
+//! and a compress tier may also write within the targets. Reading stays allowed everywhere, and
+//! so does writing to '/dev/null' and '/dev/tty'. A dry run may remove nothing at all. The
+//! restriction can't be lifted, and holds for the programs the run starts as well, such as the
+//! compressors and alert commands.
+//!
+//! Landlock grants removing an object through rights on the directory holding it, so the run may
+//! remove whatever the patterns match within their roots, such as the files '/var/log/*.log'
+//! matches within '/var/log', but never a root itself. A root that another pattern matches is
+//! emptied and left in place, counted as "target kept by --sandbox". A pattern without wildcards,
+//! such as '/tmp/build', has the directory above it as its root, so the run may remove anything
+//! within '/tmp' that way; prefer a pattern such as '/tmp/build/*' to keep the rights narrow.
+//!
+//! Landlock came with Linux 5.13 and has to be among the enabled security modules. Moving objects
+//! out of the targets, as '--action move', '--trash', '--undoable' and move and trash tiers do,
+//! needs its second version, from Linux 5.19, and truncating files is only controlled from Linux
+//! 6.2 on. A kernel that can't sandbox the run fails it instead of going on unconfined.
+
+use std::ffi::CString;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use crate::args::Args;
+use crate::history;
+use crate::safefs::Anchors;
+use crate::trash;
+use crate::undo;
+
+// The rights of Landlock's first version that change the filesystem.
+const WRITE_FILE: u64 = 1 << 1;
+const REMOVE_DIR: u64 = 1 << 4;
+const REMOVE_FILE: u64 = 1 << 5;
+const MAKE_CHAR: u64 = 1 << 6;
+const MAKE_DIR: u64 = 1 << 7;
+const MAKE_REG: u64 = 1 << 8;
+const MAKE_SOCK: u64 = 1 << 9;
+const MAKE_FIFO: u64 = 1 << 10;
+const MAKE_BLOCK: u64 = 1 << 11;
+const MAKE_SYM: u64 = 1 << 12;
+// Moving an object to another directory, from the second version on.
+const REFER: u64 = 1 << 13;
+// Truncating a file, from the third version on.
+const TRUNCATE: u64 = 1 << 14;
+
+const CREATE_RULESET_VERSION: u32 = 1;
+const RULE_PATH_BENEATH: libc::c_int = 1;
+
+#[repr(C)]
+struct RulesetAttr {
+    handled_access_fs: u64,
+}
+
+#[repr(C, packed)]
+struct PathBeneathAttr {
+    allowed_access: u64,
+    parent_fd: i32,
+}
+
+/// What a sandboxed run may still change.
+pub struct Sandbox {
+    /// The version of Landlock the kernel provides.
+    pub abi: i32,
+    /// The roots of the targets, which objects may be removed within.
+    pub removable: Vec<PathBuf>,
+    /// The directories that may be written to.
+    pub writable: Vec<PathBuf>,
+}
+
+/// Confines the process to what the run has to change. Has to be called before any other thread
+/// is started, since the threads that already run stay unconfined.
+///
+/// # Arguments
+///
+/// * `args` - Command-line arguments.
+///
+/// # Returns
+///
+/// * `Result<Sandbox, String>` - Ok with what the run may still change, Err with a message if the
+///   kernel can't confine it or a directory can't be opened.
+pub fn restrict(args: &Args) -> Result<Sandbox, String> {
+    let abi = unsafe { libc::syscall(libc::SYS_landlock_create_ruleset, std::ptr::null::<RulesetAttr>(), 0usize, CREATE_RULESET_VERSION) } as i32;
+    if abi < 1 {
+        return Err(format!("--sandbox needs Landlock, which this kernel doesn't provide: {}", io::Error::last_os_error()));
+    }
+    let moves = args.moves() || args.trashes() || args.undoable;
+    if moves && !args.dry_run && abi < 2 {
+        return Err("--sandbox can only move objects out of the targets with Landlock 2 (Linux 5.19) or later".into());
+    }
+    let refer = if abi >= 2 { REFER } else { 0 };
+    let truncate = if abi >= 3 { TRUNCATE } else { 0 };
+    let modify = WRITE_FILE | REMOVE_DIR | REMOVE_FILE | MAKE_DIR | MAKE_REG | MAKE_SOCK | MAKE_FIFO | MAKE_SYM | refer | truncate;
+    let handled = modify | MAKE_CHAR | MAKE_BLOCK;
+
+    let removable = if args.dry_run { Vec::new() } else { target_roots(args) };
+    let mut removal = REMOVE_FILE | REMOVE_DIR;
+    if args.compresses() {
+        removal |= WRITE_FILE | MAKE_REG | truncate;
+    }
+    if moves {
+        removal |= refer;
+    }
+    let writable = writable_dirs(args)?;
+
+    let attr = RulesetAttr { handled_access_fs: handled };
+    let ruleset = unsafe { libc::syscall(libc::SYS_landlock_create_ruleset, &attr as *const RulesetAttr, std::mem::size_of::<RulesetAttr>(), 0u32) };
+    if ruleset < 0 {
+        return Err(format!("Unable to create the Landlock ruleset: {}", io::Error::last_os_error()));
+    }
+    let ruleset = unsafe { OwnedFd::from_raw_fd(ruleset as i32) };
+    for dir in &removable {
+        allow(&ruleset, dir, removal).map_err(|e| format!("Unable to let the sandbox remove objects below {}: {}", dir.display(), e))?;
+    }
+    for dir in &writable {
+        allow(&ruleset, dir, modify).map_err(|e| format!("Unable to let the sandbox write to {}: {}", dir.display(), e))?;
+    }
+    // only writing applies to a file, and the devices may not exist in a container
+    for device in ["/dev/null", "/dev/tty"] {
+        let _ = allow(&ruleset, Path::new(device), WRITE_FILE);
+    }
+    if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0 {
+        return Err(format!("Unable to set no_new_privs for the sandbox: {}", io::Error::last_os_error()));
+    }
+    if unsafe { libc::syscall(libc::SYS_landlock_restrict_self, ruleset.as_raw_fd(), 0u32) } != 0 {
+        return Err(format!("Unable to enter the sandbox: {}", io::Error::last_os_error()));
+    }
+    Ok(Sandbox { abi, removable, writable })
+}
+
+/// Finds the roots of the target patterns, the directories above their first wildcard (or above
+/// the path itself if it has none), which are the only places the run may remove objects from.
+///
+/// # Arguments
+///
+/// * `args` - Command-line arguments.
+///
+/// # Returns
+///
+/// * `Vec<PathBuf>` - The roots that exist, resolved.
+pub fn target_roots(args: &Args) -> Vec<PathBuf> {
+    let mut roots: Vec<PathBuf> = Anchors::new(&args.paths).roots().iter()
+        .filter_map(|root| root.canonicalize().ok())
+        .filter(|root| root.is_dir())
+        .collect();
+    roots.sort();
+    roots.dedup();
+    roots
+}
+
+/// Finds the directories the run writes to, creating the state and staging directories that
+/// don't exist yet, since only existing directories can be let in.
+///
+/// # Arguments
+///
+/// * `args` - Command-line arguments.
+///
+/// # Returns
+///
+/// * `Result<Vec<PathBuf>, String>` - Ok with the directories, Err with a message if one can't be
+///   created.
+fn writable_dirs(args: &Args) -> Result<Vec<PathBuf>, String> {
+    let mut dirs = Vec::new();
+    let mut created = Vec::new();
+    dirs.extend(args.logfile_path.clone());
+    if !args.no_history || args.cooldown.is_some() {
+        created.extend(history::state_dir(args.state_dir.as_ref()));
+    }
+    let files = [&args.json_report, &args.event_journal, &args.plan, &args.lock_file, &args.archive_to];
+    dirs.extend(files.iter().filter_map(|file| file.as_deref()).map(parent));
+    if !args.dry_run {
+        if args.undoable {
+            created.extend(undo::base_dir(args));
+        }
+        if args.trashes() {
+            created.extend(trash::home_trash());
+        }
+        if args.moves() {
+            dirs.extend(args.move_to.clone());
+        }
+    }
+    for dir in &created {
+        std::fs::create_dir_all(dir).map_err(|e| format!("Unable to create {} for the sandbox: {}", dir.display(), e))?;
+    }
+    dirs.extend(created);
+    dirs.sort();
+    dirs.dedup();
+    Ok(dirs)
+}
+
+/// Finds the directory a file is written in.
+///
+/// # Arguments
+///
+/// * `file` - The file.
+///
+/// # Returns
+///
+/// * `PathBuf` - Its directory, or '.' if it has none.
+fn parent(file: &Path) -> PathBuf {
+    match file.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
+}
+
+/// Grants rights below a directory, or on a file.
+///
+/// # Arguments
+///
+/// * `ruleset` - The ruleset.
+/// * `path` - The directory or file.
+/// * `access` - The rights.
+///
+/// # Returns
+///
+/// * `io::Result<()>` - Ok if the rule was added, Err if the path can't be opened or the rule
+///   isn't accepted.
+fn allow(ruleset: &OwnedFd, path: &Path, access: u64) -> io::Result<()> {
+    let name = CString::new(path.as_os_str().as_bytes()).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let fd = unsafe { libc::open(name.as_ptr(), libc::O_PATH | libc::O_CLOEXEC) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+    let rule = PathBeneathAttr { allowed_access: access, parent_fd: fd.as_raw_fd() };
+    if unsafe { libc::syscall(libc::SYS_landlock_add_rule, ruleset.as_raw_fd(), RULE_PATH_BENEATH, &rule as *const PathBeneathAttr, 0u32) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::ErrorKind;
+    use clap::Parser;
+    use crate::args::Args;
+    use super::restrict;
+
+    #[test]
+    fn removes_pattern_matches_within_their_root_and_nothing_outside_it() {
+        let base = std::env::temp_dir().join(format!("nukem-sandbox-{}", std::process::id()));
+        let (root, outside) = (base.join("logs"), base.join("other"));
+        fs::create_dir_all(root.join("old")).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        for file in [root.join("app.log"), root.join("old/app.log"), outside.join("app.log")] {
+            fs::write(file, b"log").unwrap();
+        }
+
+        let pattern = root.join("*.log");
+        let args = Args::try_parse_from(["nukem", "--sandbox", "--no-history", pattern.to_str().unwrap()]).unwrap();
+        // Landlock confines the thread that enters it, so the other tests stay unconfined
+        let (root_in, outside_in) = (root.clone(), outside.clone());
+        let confined: Result<(), String> = std::thread::spawn(move || {
+            let sandbox = restrict(&args)?;
+            assert_eq!(sandbox.removable, [root_in.canonicalize().unwrap()]);
+            fs::remove_file(root_in.join("app.log")).unwrap();
+            fs::remove_file(root_in.join("old/app.log")).unwrap();
+            fs::remove_dir(root_in.join("old")).unwrap();
+            assert_eq!(fs::remove_file(outside_in.join("app.log")).unwrap_err().kind(), ErrorKind::PermissionDenied);
+            assert_eq!(fs::remove_dir(&root_in).unwrap_err().kind(), ErrorKind::PermissionDenied);
+            Ok(())
+        }).join().unwrap();
+
+        match confined {
+            Err(e) if e.starts_with("--sandbox needs Landlock") => eprintln!("skipping the sandbox test: {}", e),
+            result => {
+                result.unwrap();
+                assert!(!root.join("app.log").exists());
+                assert!(outside.join("app.log").exists());
+                assert!(root.is_dir());
+            }
+        }
+        fs::remove_dir_all(&base).unwrap();
+    }
+}
@@ -0,0 +1,207 @@
+//! This module keeps track of filesystem objects that were found but intentionally not deleted,
+//! broken down by the reason they were skipped. It lets the final report reconcile the number of
+//! objects found with the number of objects deleted.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/// The reasons an object can be skipped instead of deleted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SkipReason {
+    /// The object did not match the user's filters.
+    Filtered,
+    /// The object is owned by a user or group that wasn't asked for.
+    Owner,
+    /// The object's permissions don't match '--perm'.
+    Permissions,
+    /// The object was read more recently than '--not-accessed-in' allows.
+    RecentlyAccessed,
+    /// The object was created after the time given to '--created-before'.
+    RecentlyCreated,
+    /// The filesystem doesn't record when the object was created, so '--created-before' can't tell.
+    UnknownBirthTime,
+    /// The object was modified or changed more recently than '--min-age' allows.
+    MinAge,
+    /// The path, or a directory above it, was deleted by an earlier run within '--cooldown'.
+    Cooldown,
+    /// The file has other hard links and '--skip-hardlinked' was specified.
+    Hardlinked,
+    /// The object matched an exclude pattern.
+    Excluded,
+    /// The object is hidden and '--skip-hidden' was specified.
+    Hidden,
+    /// The object is protected from deletion.
+    Protected,
+    /// The object is a socket, FIFO, or device node.
+    SpecialFile,
+    /// The object lives on a different filesystem than its root.
+    OtherFilesystem,
+    /// The object disappeared before it could be deleted, usually with its parent directory.
+    Vanished,
+    /// Something else took the place of the object between the crawl and its deletion.
+    Replaced,
+    /// The object was matched through a symlink that leads outside the pattern's root.
+    OutsideRoot,
+    /// The directory contains a Unix socket bound by a running process.
+    LiveSocket,
+    /// The file, or a file within the directory, is open in another process.
+    InUse,
+    /// The object, or the directory holding it, is immutable or append-only.
+    Immutable,
+    /// The object can't be compressed, e.g. because it is a symlink or already compressed.
+    NotCompressible,
+    /// The directory is kept because the action only applies to files.
+    KeptDirectory,
+    /// The file is younger than every '--tier'.
+    BelowTiers,
+    /// The file was modified since it was crawled and would now go to another '--tier'.
+    TierChanged,
+    /// Something already exists where the object would be moved to.
+    DestinationExists,
+    /// The directory was empty when it was crawled, but something was added to it since.
+    NoLongerEmpty,
+    /// Deleting the object, or something within the directory, was declined at the prompt.
+    Declined,
+    /// An operation on the object took longer than '--op-timeout'.
+    Stuck,
+    /// The run reached '--max-delete' before it got to the object.
+    OverBudget,
+    /// The object is the root of a target pattern, which '--sandbox' only lets the run empty.
+    SandboxedTarget,
+}
+
+impl SkipReason {
+    /// All skip reasons, in the order they appear in reports.
+    pub const ALL: [SkipReason; 30] = [
+        SkipReason::Filtered,
+        SkipReason::Owner,
+        SkipReason::Permissions,
+        SkipReason::RecentlyAccessed,
+        SkipReason::RecentlyCreated,
+        SkipReason::UnknownBirthTime,
+        SkipReason::MinAge,
+        SkipReason::Cooldown,
+        SkipReason::Hardlinked,
+        SkipReason::Excluded,
+        SkipReason::Hidden,
+        SkipReason::Protected,
+        SkipReason::SpecialFile,
+        SkipReason::OtherFilesystem,
+        SkipReason::Vanished,
+        SkipReason::Replaced,
+        SkipReason::OutsideRoot,
+        SkipReason::LiveSocket,
+        SkipReason::InUse,
+        SkipReason::Immutable,
+        SkipReason::NotCompressible,
+        SkipReason::KeptDirectory,
+        SkipReason::BelowTiers,
+        SkipReason::TierChanged,
+        SkipReason::DestinationExists,
+        SkipReason::NoLongerEmpty,
+        SkipReason::Declined,
+        SkipReason::Stuck,
+        SkipReason::OverBudget,
+        SkipReason::SandboxedTarget,
+    ];
+
+    /// Returns the human-readable label used in reports.
+    ///
+    /// # Returns
+    ///
+    /// * `&'static str` - The label for the skip reason.
+    pub fn label(&self) -> &'static str {
+        match self {
+            SkipReason::Filtered => "filtered out",
+            SkipReason::Owner => "other owner",
+            SkipReason::Permissions => "other permissions",
+            SkipReason::RecentlyAccessed => "accessed recently",
+            SkipReason::RecentlyCreated => "created recently",
+            SkipReason::UnknownBirthTime => "creation time unknown",
+            SkipReason::MinAge => "protected by min-age",
+            SkipReason::Cooldown => "in cooldown",
+            SkipReason::Hardlinked => "hard linked elsewhere",
+            SkipReason::Excluded => "excluded",
+            SkipReason::Hidden => "hidden",
+            SkipReason::Protected => "protected",
+            SkipReason::SpecialFile => "special file",
+            SkipReason::OtherFilesystem => "other filesystem",
+            SkipReason::Vanished => "vanished",
+            SkipReason::Replaced => "replaced",
+            SkipReason::OutsideRoot => "outside the target root",
+            SkipReason::LiveSocket => "live socket",
+            SkipReason::InUse => "in use",
+            SkipReason::Immutable => "immutable or append-only",
+            SkipReason::NotCompressible => "not compressible",
+            SkipReason::KeptDirectory => "directory kept",
+            SkipReason::BelowTiers => "younger than every --tier",
+            SkipReason::TierChanged => "changed tier since the crawl",
+            SkipReason::DestinationExists => "destination exists",
+            SkipReason::NoLongerEmpty => "no longer empty",
+            SkipReason::Declined => "declined at the prompt",
+            SkipReason::Stuck => "stuck",
+            SkipReason::OverBudget => "over --max-delete",
+            SkipReason::SandboxedTarget => "target kept by --sandbox",
+        }
+    }
+}
+
+/// Shared counter of skipped objects per reason. Cloning it shares the underlying counts, so the
+/// Crawler and the Deleter can both record skips.
+#[derive(Clone, Default)]
+pub struct SkipCounter {
+    counts: Arc<Mutex<HashMap<SkipReason, u64>>>,
+}
+
+impl SkipCounter {
+    /// Creates a new, empty `SkipCounter`.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - A new instance of the SkipCounter.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records one skipped object.
+    ///
+    /// # Arguments
+    ///
+    /// * `reason` - The reason the object was skipped.
+    pub async fn add(&self, reason: SkipReason) {
+        *self.counts.lock().await.entry(reason).or_insert(0) += 1;
+    }
+
+    /// Records a number of objects skipped for the same reason.
+    ///
+    /// # Arguments
+    ///
+    /// * `reason` - Why the objects were skipped.
+    /// * `count` - How many were skipped.
+    pub async fn add_many(&self, reason: SkipReason, count: u64) {
+        *self.counts.lock().await.entry(reason).or_insert(0) += count;
+    }
+
+    /// Retrieves the number of objects skipped for a reason.
+    ///
+    /// # Arguments
+    ///
+    /// * `reason` - The skip reason to look up.
+    ///
+    /// # Returns
+    ///
+    /// * `u64` - The number of objects skipped for that reason.
+    pub async fn get(&self, reason: SkipReason) -> u64 {
+        *self.counts.lock().await.get(&reason).unwrap_or(&0)
+    }
+
+    /// Retrieves the total number of skipped objects across all reasons.
+    ///
+    /// # Returns
+    ///
+    /// * `u64` - The total number of skipped objects.
+    pub async fn total(&self) -> u64 {
+        self.counts.lock().await.values().sum()
+    }
+}
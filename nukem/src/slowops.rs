@@ -0,0 +1,170 @@
+//! This module keeps track of the slowest individual filesystem operations of a run.
+//!
+//! A handful of pathological directories or a stuck NFS export can dominate the runtime of a
+//! cleanup without showing up in the totals. The crawler and the deleter time each metadata and
+//! deletion call, and the slowest ones ('--slowest', 10 by default) are listed in the JSON
+//! report, and in the final report with '--verbose' or when '--slowest' was given.
+//!
+//! With '--op-timeout', a stat, opendir or unlink that takes longer than the limit is given up on,
+//! so a single hung call on a dead NFS server doesn't hold up its worker for the rest of the run.
+//! The worker moves on, the path is recorded as stuck and listed in the final report. The call
+//! itself can't be cancelled, it keeps a blocking thread until the kernel returns, and may still
+//! succeed later. Removing a directory tree is a single call whose duration depends on the size
+//! of the tree, so it has no limit.
+
+use std::future::Future;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+/// How many of the slowest operations are kept when '--slowest' isn't given.
+pub const DEFAULT_SLOWEST: usize = 10;
+
+/// The kinds of operations that are timed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OpKind {
+    /// Reading the metadata of a path.
+    Stat,
+    /// Opening a directory for reading.
+    OpenDir,
+    /// Removing a file or symlink.
+    RemoveFile,
+    /// Removing a directory and whatever is left inside it.
+    RemoveDir,
+    /// Compressing a file in place.
+    Compress,
+    /// Moving a file or symlink to the '--move-to' directory.
+    Move,
+    /// Moving a file, symlink or directory to the trash.
+    Trash,
+    /// Moving a file, symlink or directory to the staging folder of '--undoable'.
+    Stage,
+    /// Adding a file, symlink or directory to the '--archive-to' archive and removing it.
+    Archive,
+}
+
+impl OpKind {
+    /// Returns the human-readable label used in reports.
+    ///
+    /// # Returns
+    ///
+    /// * `&'static str` - The label for the operation.
+    pub fn label(&self) -> &'static str {
+        match self {
+            OpKind::Stat => "stat",
+            OpKind::OpenDir => "opendir",
+            OpKind::RemoveFile => "unlink",
+            OpKind::RemoveDir => "remove tree",
+            OpKind::Compress => "compress",
+            OpKind::Move => "move",
+            OpKind::Trash => "trash",
+            OpKind::Stage => "stage",
+            OpKind::Archive => "archive",
+        }
+    }
+}
+
+/// A single timed operation.
+#[derive(Debug, Clone)]
+pub struct SlowOp {
+    pub elapsed: Duration,
+    pub kind: OpKind,
+    pub path: PathBuf,
+}
+
+/// Shared list of the slowest operations, slowest first. Cloning it shares the underlying list,
+/// so the Crawler and the Deleter can both record operations.
+#[derive(Clone)]
+pub struct SlowOps {
+    limit: usize,
+    ops: Arc<Mutex<Vec<SlowOp>>>,
+    timeout: Option<Duration>,
+    stuck: Arc<Mutex<Vec<SlowOp>>>,
+}
+
+impl SlowOps {
+    /// Creates an empty list.
+    ///
+    /// # Arguments
+    ///
+    /// * `limit` - The number of operations to keep. Zero disables tracking.
+    /// * `timeout` - How long an operation may take before it is given up on, if '--op-timeout'
+    ///   was specified.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - A new instance of SlowOps.
+    pub fn new(limit: usize, timeout: Option<Duration>) -> Self {
+        Self { limit, ops: Arc::new(Mutex::new(Vec::with_capacity(limit + 1))), timeout, stuck: Arc::new(Mutex::new(Vec::new())) }
+    }
+
+    /// Runs an operation within the '--op-timeout' limit. An operation that exceeds it is
+    /// recorded as stuck, once per path and kind of operation.
+    ///
+    /// # Arguments
+    ///
+    /// * `kind` - The kind of operation.
+    /// * `path` - The path it operates on.
+    /// * `op` - The operation.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<io::Result<T>>` - The result of the operation, or None if it got stuck.
+    pub async fn limit<T>(&self, kind: OpKind, path: &Path, op: impl Future<Output = io::Result<T>>) -> Option<io::Result<T>> {
+        let timeout = match self.timeout {
+            Some(timeout) => timeout,
+            None => return Some(op.await),
+        };
+        match tokio::time::timeout(timeout, op).await {
+            Ok(result) => Some(result),
+            Err(_) => {
+                let mut stuck = self.stuck.lock().await;
+                if !stuck.iter().any(|op| op.kind == kind && op.path == path) {
+                    stuck.push(SlowOp { elapsed: timeout, kind, path: path.to_path_buf() });
+                }
+                None
+            }
+        }
+    }
+
+    /// Retrieves the operations that got stuck.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<SlowOp>` - The stuck operations in path order, each with the limit it exceeded.
+    pub async fn stuck(&self) -> Vec<SlowOp> {
+        let mut stuck = self.stuck.lock().await.clone();
+        stuck.sort_by(|a, b| a.path.cmp(&b.path));
+        stuck
+    }
+
+    /// Records an operation if it is among the slowest seen so far.
+    ///
+    /// # Arguments
+    ///
+    /// * `kind` - The kind of operation.
+    /// * `path` - The path it operated on.
+    /// * `elapsed` - How long it took.
+    pub async fn record(&self, kind: OpKind, path: &Path, elapsed: Duration) {
+        if self.limit == 0 {
+            return;
+        }
+        let mut ops = self.ops.lock().await;
+        if ops.len() < self.limit || ops.last().is_some_and(|op| elapsed > op.elapsed) {
+            ops.push(SlowOp { elapsed, kind, path: path.to_path_buf() });
+            ops.sort_by_key(|op| std::cmp::Reverse(op.elapsed));
+            ops.truncate(self.limit);
+        }
+    }
+
+    /// Retrieves the slowest operations, slowest first.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<SlowOp>` - The slowest operations.
+    pub async fn slowest(&self) -> Vec<SlowOp> {
+        self.ops.lock().await.clone()
+    }
+}
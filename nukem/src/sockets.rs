@@ -0,0 +1,101 @@
+//! This module finds live Unix domain sockets within directories that are about to be deleted.
+//!
+//! Runtime directories of services often hold the sockets that clients use to reach a daemon.
+//! Removing such a directory leaves the daemon running but unreachable. On Linux, the sockets
+//! bound by running processes are listed in /proc/net/unix, which is read once when the run
+//! starts. Depending on '--live-sockets', a directory containing one of them is either deleted
+//! with a warning or skipped. On other platforms no live sockets are found.
+
+use std::path::{Path, PathBuf};
+use crate::args::LiveSocketPolicy;
+
+/// A Unix domain socket bound by a running process.
+#[derive(Debug, Clone)]
+pub struct LiveSocket {
+    pub path: PathBuf,
+    pub listening: bool,
+}
+
+/// The live sockets found when the run started, and what to do about them.
+#[derive(Debug)]
+pub struct LiveSockets {
+    pub policy: LiveSocketPolicy,
+    sockets: Vec<LiveSocket>,
+}
+
+impl LiveSockets {
+    // Socket flag set on sockets that accept connections (__SO_ACCEPTCON).
+    #[cfg(target_os = "linux")]
+    const ACCEPTCON: u32 = 0x0001_0000;
+
+    /// Reads the sockets bound by running processes.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - What to do with directories that contain a live socket.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The live sockets, canonicalized so they can be compared with deletion targets.
+    #[cfg(target_os = "linux")]
+    pub fn load(policy: LiveSocketPolicy) -> Self {
+        let table = std::fs::read_to_string("/proc/net/unix").unwrap_or_default();
+        // columns: Num RefCount Protocol Flags Type St Inode Path
+        let mut sockets: Vec<LiveSocket> = table
+            .lines()
+            .skip(1)
+            .filter_map(|line| {
+                let fields: Vec<&str> = line.split_whitespace().collect();
+                let flags = u32::from_str_radix(fields.get(3)?, 16).ok()?;
+                // abstract sockets start with '@', and relative paths are relative to the binding
+                // process, so only absolute paths can be resolved
+                let path = Path::new(fields.get(7)?);
+                if !path.is_absolute() {
+                    return None;
+                }
+                let path = path.canonicalize().ok()?;
+                Some(LiveSocket { path, listening: flags & Self::ACCEPTCON != 0 })
+            })
+            .collect();
+        // several sockets can be bound to the same path, so keep one entry per path, listening first
+        sockets.sort_by(|a, b| a.path.cmp(&b.path).then(b.listening.cmp(&a.listening)));
+        sockets.dedup_by(|a, b| a.path == b.path);
+        Self { policy, sockets }
+    }
+
+    /// Reads the sockets bound by running processes.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - What to do with directories that contain a live socket.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - Always empty, live sockets can only be found on Linux.
+    #[cfg(not(target_os = "linux"))]
+    pub fn load(policy: LiveSocketPolicy) -> Self {
+        Self { policy, sockets: Vec::new() }
+    }
+
+    /// Checks whether any live sockets were found at all.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if there are no live sockets.
+    pub fn is_empty(&self) -> bool {
+        self.sockets.is_empty()
+    }
+
+    /// Finds the live sockets at or below a directory.
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - The canonicalized directory.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<&LiveSocket>` - The live sockets within the directory.
+    pub fn within(&self, dir: &Path) -> Vec<&LiveSocket> {
+        self.sockets.iter().filter(|socket| socket.path.starts_with(dir)).collect()
+    }
+}
@@ -0,0 +1,114 @@
+//! This module keeps the symlink counts for '--symlink-report'.
+//!
+//! The crawler and deleter counters lump files and symlinks together, since both are unlinked
+//! the same way. With '--symlink-report' the symlinks among them are counted on their own as
+//! well, split by whether their target exists. Telling the two apart takes a stat that follows
+//! the link, which is why it is only done when asked for. A link to a directory is a symlink
+//! here, since it is never followed.
+
+use std::path::Path;
+use std::sync::Arc;
+use tokio::fs;
+use tokio::sync::Mutex;
+
+/// The number of valid and dangling symlinks among some objects.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LinkTotals {
+    pub valid: u64,
+    pub dangling: u64,
+}
+
+impl LinkTotals {
+    /// Retrieves the number of symlinks.
+    ///
+    /// # Returns
+    ///
+    /// * `u64` - The valid and the dangling symlinks together.
+    pub fn total(&self) -> u64 {
+        self.valid + self.dangling
+    }
+}
+
+/// Shared symlink counts of a run. Cloning it shares the underlying counts, so the Crawler and
+/// the Deleter can both record symlinks.
+#[derive(Clone, Default)]
+pub struct SymlinkCounts {
+    found: Arc<Mutex<LinkTotals>>,
+    deleted: Arc<Mutex<LinkTotals>>,
+}
+
+impl SymlinkCounts {
+    /// Creates empty counts.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - A new instance of SymlinkCounts.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Checks whether a symlink points to something that exists.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The symlink.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if the target exists, false if the link is dangling or forms a loop.
+    pub async fn is_valid(path: &Path) -> bool {
+        fs::metadata(path).await.is_ok()
+    }
+
+    /// Records a symlink found by the crawler.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The symlink.
+    pub async fn record_found(&self, path: &Path) {
+        let valid = Self::is_valid(path).await;
+        Self::add(&self.found, valid).await;
+    }
+
+    /// Records a symlink that was deleted, or moved with '--action move'.
+    ///
+    /// # Arguments
+    ///
+    /// * `valid` - Whether its target existed, checked before it was removed.
+    pub async fn record_deleted(&self, valid: bool) {
+        Self::add(&self.deleted, valid).await;
+    }
+
+    /// Retrieves the symlinks found by the crawler.
+    ///
+    /// # Returns
+    ///
+    /// * `LinkTotals` - The valid and dangling symlinks found.
+    pub async fn found(&self) -> LinkTotals {
+        *self.found.lock().await
+    }
+
+    /// Retrieves the symlinks that were deleted (or would be in a dry run).
+    ///
+    /// # Returns
+    ///
+    /// * `LinkTotals` - The valid and dangling symlinks deleted.
+    pub async fn deleted(&self) -> LinkTotals {
+        *self.deleted.lock().await
+    }
+
+    /// Adds a symlink to a set of totals.
+    ///
+    /// # Arguments
+    ///
+    /// * `totals` - The totals to add to.
+    /// * `valid` - Whether the target of the symlink exists.
+    async fn add(totals: &Mutex<LinkTotals>, valid: bool) {
+        let mut totals = totals.lock().await;
+        if valid {
+            totals.valid += 1;
+        } else {
+            totals.dangling += 1;
+        }
+    }
+}
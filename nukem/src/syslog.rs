@@ -0,0 +1,399 @@
+//! This module ships log messages to a remote syslog server for '--syslog', so hosts without
+//! local log collection still have a central record of what nukem did.
+//!
+//! Every message the logger writes is sent as an RFC 5424 syslog message, framed by octet
+//! counting (RFC 6587), over plain TCP ('tcp://host:port') or TLS ('tls://host:port', RFC 5425).
+//! TLS is provided by the 'openssl s_client' program, which verifies the server certificate
+//! against the system trust store or '--syslog-ca', and the host name of the URL. Each message
+//! carries structured data: the registered 'origin' element with the software and its version,
+//! 'meta' with a sequence number that shows when messages went missing, and 'nukem@32473' with
+//! whether the run is a dry run and the UID it runs as.
+//!
+//! The connection is opened before the run starts, so an unreachable server or a certificate
+//! that doesn't verify fails the run at once. If the connection is lost during the run, it is
+//! reopened; the messages logged until that succeeds are dropped, and their number is reported
+//! to the server once it is back.
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{IpAddr, TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+use chrono::{Local, SecondsFormat};
+use crate::args::SyslogFacility;
+use crate::environment;
+
+// How long connecting to the server, including the TLS handshake, may take.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+// How long a message may take to be sent before the connection is given up.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+// How long to wait before reconnecting after the connection was lost.
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+// The SD-ID of the element with nukem's own parameters. 32473 is the enterprise number
+// reserved for documentation (RFC 5612).
+const SD_ID: &str = "nukem@32473";
+
+/// The transport used to reach the syslog server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    /// Plain TCP.
+    Tcp,
+    /// TLS, through 'openssl s_client'.
+    Tls,
+}
+
+/// A syslog server given with '--syslog'.
+#[derive(Debug, Clone)]
+pub struct Target {
+    pub transport: Transport,
+    pub host: String,
+    pub port: u16,
+}
+
+impl std::fmt::Display for Target {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let scheme = match self.transport {
+            Transport::Tcp => "tcp",
+            Transport::Tls => "tls",
+        };
+        if self.host.contains(':') {
+            write!(f, "{}://[{}]:{}", scheme, self.host, self.port)
+        } else {
+            write!(f, "{}://{}:{}", scheme, self.host, self.port)
+        }
+    }
+}
+
+/// Parses a syslog server such as 'tcp://logs.example.com:514' or 'tls://[2001:db8::1]'. Without
+/// a port, TCP uses 514 and TLS uses 6514.
+///
+/// # Arguments
+///
+/// * `value` - The URL supplied on the command line.
+///
+/// # Returns
+///
+/// * `Result<Target, String>` - Ok with the server, Err with a message if the URL is invalid.
+pub fn parse_target(value: &str) -> Result<Target, String> {
+    let (transport, rest) = if let Some(rest) = value.strip_prefix("tcp://") {
+        (Transport::Tcp, rest)
+    } else if let Some(rest) = value.strip_prefix("tls://") {
+        (Transport::Tls, rest)
+    } else {
+        return Err(format!("Use a tcp:// or tls:// URL for the syslog server: {}", value));
+    };
+    let rest = rest.strip_suffix('/').unwrap_or(rest);
+    // an IPv6 address is bracketed, so its colons aren't taken for the port
+    let (host, port) = if let Some(bracketed) = rest.strip_prefix('[') {
+        let (host, after) = bracketed.split_once(']').ok_or_else(|| format!("Missing ']' in the syslog URL: {}", value))?;
+        (host, after.strip_prefix(':'))
+    } else {
+        match rest.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (rest, None),
+        }
+    };
+    if host.is_empty() || host.contains('/') {
+        return Err(format!("Missing host in the syslog URL: {}", value));
+    }
+    let port = match port {
+        Some(port) => port.parse().map_err(|_| format!("Invalid port in the syslog URL: {}", value))?,
+        None if transport == Transport::Tls => 6514,
+        None => 514,
+    };
+    Ok(Target { transport, host: host.to_string(), port })
+}
+
+/// How important a message is, as the syslog severity it is sent with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// An error.
+    Error = 3,
+    /// A line of the reports.
+    Notice = 5,
+    /// A message only logged with '-v'.
+    Info = 6,
+}
+
+impl Severity {
+    // The MSGID of messages of this severity.
+    fn msgid(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Notice => "report",
+            Severity::Info => "info",
+        }
+    }
+}
+
+/// An open connection to the server.
+enum Connection {
+    Tcp(TcpStream),
+    // 'openssl s_client', with the messages written to its input
+    Tls(Child),
+}
+
+impl Connection {
+    /// Writes a framed message.
+    fn write(&mut self, frame: &[u8]) -> io::Result<()> {
+        match self {
+            Connection::Tcp(stream) => stream.write_all(frame),
+            Connection::Tls(child) => child.stdin.as_mut().ok_or(io::ErrorKind::BrokenPipe)?.write_all(frame),
+        }
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        // openssl sends what it has read and closes the session once its input ends
+        if let Connection::Tls(child) = self {
+            drop(child.stdin.take());
+            let deadline = Instant::now() + WRITE_TIMEOUT;
+            while matches!(child.try_wait(), Ok(None)) && Instant::now() < deadline {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+/// The syslog sink. It is owned by the logging task, which sends every line through it.
+pub struct Syslog {
+    target: Target,
+    ca_file: Option<PathBuf>,
+    facility: u8,
+    hostname: String,
+    structured_data: String,
+    sequence: u64,
+    connection: Option<Connection>,
+    retry_at: Option<Instant>,
+    dropped: u64,
+}
+
+impl Syslog {
+    /// Connects to the syslog server.
+    ///
+    /// # Arguments
+    ///
+    /// * `target` - The server.
+    /// * `ca_file` - The CA certificates a TLS server is verified with, or None for the system
+    ///   trust store.
+    /// * `facility` - The facility messages are sent with.
+    /// * `dry_run` - A boolean indicating whether the run is a dry run, recorded with every message.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, String>` - Ok with the sink, Err with a message if the server can't be reached.
+    pub fn connect(target: Target, ca_file: Option<PathBuf>, facility: SyslogFacility, dry_run: bool) -> Result<Self, String> {
+        // HOSTNAME is printable US-ASCII without spaces, at most 255 characters
+        let mut hostname: String = environment::hostname().chars().filter(|c| c.is_ascii_graphic()).take(255).collect();
+        if hostname.is_empty() {
+            hostname = "-".to_string();
+        }
+        let structured_data = format!(
+            "[origin software=\"nukem\" swVersion=\"{}\"][{} dryRun=\"{}\" uid=\"{}\"]",
+            escape(env!("CARGO_PKG_VERSION")), SD_ID, dry_run, unsafe { libc::geteuid() }
+        );
+        let connection = open(&target, ca_file.as_ref()).map_err(|e| format!("Unable to connect to the syslog server {}: {}", target, e))?;
+        Ok(Self {
+            target,
+            ca_file,
+            facility: facility.code(),
+            hostname,
+            structured_data,
+            sequence: 0,
+            connection: Some(connection),
+            retry_at: None,
+            dropped: 0,
+        })
+    }
+
+    /// Sends a message. If the connection was lost, it is reopened right away, and if that fails,
+    /// again once the retry interval is over; until then messages are dropped.
+    ///
+    /// # Arguments
+    ///
+    /// * `severity` - How important the message is.
+    /// * `message` - The message, without the prefixes added for the console.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<String>` - A message to print if the connection was lost just now.
+    pub fn send(&mut self, severity: Severity, message: &str) -> Option<String> {
+        if self.connection.is_none() && !self.reconnect() {
+            self.dropped += 1;
+            return None;
+        }
+        if self.dropped > 0 {
+            let dropped = std::mem::take(&mut self.dropped);
+            let notice = format!("Messages not sent while the connection to the syslog server was down: {}", dropped);
+            if let Err(e) = self.write(Severity::Error, &notice) {
+                self.dropped = dropped + 1;
+                return Some(e);
+            }
+        }
+        self.write(severity, message).err().inspect(|_| self.dropped += 1)
+    }
+
+    /// Reopens a lost connection, unless the last attempt was too recent.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if the connection is open again.
+    fn reconnect(&mut self) -> bool {
+        if self.retry_at.is_some_and(|retry_at| Instant::now() < retry_at) {
+            return false;
+        }
+        match open(&self.target, self.ca_file.as_ref()) {
+            Ok(connection) => {
+                self.connection = Some(connection);
+                self.retry_at = None;
+                true
+            }
+            Err(_) => {
+                self.retry_at = Some(Instant::now() + RETRY_INTERVAL);
+                false
+            }
+        }
+    }
+
+    /// Formats and writes a message, giving up the connection if the write fails.
+    ///
+    /// # Arguments
+    ///
+    /// * `severity` - How important the message is.
+    /// * `message` - The message.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), String>` - Ok if the message was sent, Err with a message if the connection
+    ///   was lost.
+    fn write(&mut self, severity: Severity, message: &str) -> Result<(), String> {
+        let Some(ref mut connection) = self.connection else {
+            return Err(format!("Not connected to the syslog server {}", self.target));
+        };
+        self.sequence += 1;
+        let record = format!(
+            "<{}>1 {} {} nukem {} {} {}[meta sequenceId=\"{}\"] {}",
+            self.facility * 8 + severity as u8,
+            Local::now().to_rfc3339_opts(SecondsFormat::Micros, false),
+            self.hostname, std::process::id(), severity.msgid(),
+            self.structured_data, self.sequence, message
+        );
+        let frame = format!("{} {}", record.len(), record);
+        connection.write(frame.as_bytes()).map_err(|e| {
+            // the next message tries to reconnect at once, later ones wait for the interval
+            self.connection = None;
+            self.retry_at = None;
+            format!("Lost the connection to the syslog server {}: {}", self.target, e)
+        })
+    }
+}
+
+/// Opens a connection to the server.
+///
+/// # Arguments
+///
+/// * `target` - The server.
+/// * `ca_file` - The CA certificates a TLS server is verified with.
+///
+/// # Returns
+///
+/// * `io::Result<Connection>` - Ok with the connection, Err if it couldn't be opened.
+fn open(target: &Target, ca_file: Option<&PathBuf>) -> io::Result<Connection> {
+    match target.transport {
+        Transport::Tcp => {
+            let mut last_error = io::Error::new(io::ErrorKind::NotFound, "the host name doesn't resolve");
+            for address in (target.host.as_str(), target.port).to_socket_addrs()? {
+                match TcpStream::connect_timeout(&address, CONNECT_TIMEOUT) {
+                    Ok(stream) => {
+                        stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+                        return Ok(Connection::Tcp(stream));
+                    }
+                    Err(e) => last_error = e,
+                }
+            }
+            Err(last_error)
+        }
+        Transport::Tls => open_tls(target, ca_file),
+    }
+}
+
+/// Starts 'openssl s_client' and waits for it to complete the TLS handshake.
+///
+/// # Arguments
+///
+/// * `target` - The server.
+/// * `ca_file` - The CA certificates the server is verified with.
+///
+/// # Returns
+///
+/// * `io::Result<Connection>` - Ok with the connection, Err if openssl couldn't be started or
+///   the handshake or the verification of the server failed.
+fn open_tls(target: &Target, ca_file: Option<&PathBuf>) -> io::Result<Connection> {
+    let address = if target.host.contains(':') { format!("[{}]:{}", target.host, target.port) } else { format!("{}:{}", target.host, target.port) };
+    let mut command = Command::new("openssl");
+    command.args(["s_client", "-brief", "-verify_return_error", "-connect", &address]);
+    // a name is sent for SNI and checked against the certificate, an address is only checked
+    if target.host.parse::<IpAddr>().is_ok() {
+        command.args(["-verify_ip", &target.host]);
+    } else {
+        command.args(["-servername", &target.host, "-verify_hostname", &target.host]);
+    }
+    if let Some(ca_file) = ca_file {
+        command.arg("-CAfile").arg(ca_file);
+    }
+    let mut child = command.stdin(Stdio::piped()).stdout(Stdio::null()).stderr(Stdio::piped()).spawn()
+        .map_err(|e| io::Error::new(e.kind(), format!("unable to run openssl: {}", e)))?;
+    let stderr = child.stderr.take().expect("stderr is piped");
+
+    // openssl reports the handshake on stderr; it is read to the end so the pipe never fills
+    let (lines, handshake) = mpsc::channel();
+    std::thread::spawn(move || {
+        for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+            let _ = lines.send(line);
+        }
+    });
+    let deadline = Instant::now() + CONNECT_TIMEOUT;
+    let mut last_line = String::new();
+    loop {
+        match handshake.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok(line) if line.starts_with("CONNECTION ESTABLISHED") => return Ok(Connection::Tls(child)),
+            // the reason a certificate was refused comes before the error that ends the handshake
+            Ok(line) if !last_line.starts_with("verify error") => last_line = line,
+            Ok(_) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                let _ = child.wait();
+                return Err(io::Error::other(if last_line.is_empty() { "openssl ended without a connection".to_string() } else { last_line }));
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "the TLS handshake timed out"));
+            }
+        }
+    }
+}
+
+/// Escapes a parameter value of the structured data: '"', '\' and ']' are preceded by a
+/// backslash.
+///
+/// # Arguments
+///
+/// * `value` - The value.
+///
+/// # Returns
+///
+/// * `String` - The escaped value.
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '"' | '\\' | ']') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
@@ -0,0 +1,129 @@
+//! This module lets a run that is sent SIGTERM stop cleanly and still leave its record behind.
+//!
+//! Batch schedulers such as SLURM and PBS send SIGTERM when a job reaches its walltime, and
+//! SIGKILL a little later. Cleanup jobs run from them would otherwise die without a report. Once
+//! SIGTERM arrives, the crawlers stop descending, each deleter worker finishes the object it is
+//! working on and stops, and the run goes on to its final report, the JSON report and the run
+//! history, which marks it as interrupted. Running the same command again picks up where it
+//! stopped, since whatever was deleted is gone. Everything has to be written within the grace
+//! period ('--term-grace', 10s by default); if it isn't, the process exits anyway, with the
+//! status 143 (128 + SIGTERM) it also exits with otherwise.
+//!
+//! A run with '--max-errors' stops the same way once that many deletions failed, such as when a
+//! whole subtree turns out to be on a read-only mount, and exits with an error instead.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+use crate::console::Stream;
+use crate::logger::Logger;
+
+/// The exit status of a run that was terminated.
+pub const EXIT_STATUS: i32 = 128 + libc::SIGTERM;
+
+/// Whether the run has to stop, because SIGTERM was received or '--max-errors' was reached.
+/// Cloning it shares the same state.
+#[derive(Clone)]
+pub struct Termination {
+    requested: watch::Receiver<bool>,
+    sender: Arc<watch::Sender<bool>>,
+    signalled: Arc<AtomicBool>,
+}
+
+impl Termination {
+    /// Creates the termination state of a run that only stops on its own, at '--max-errors'.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The termination state.
+    pub fn new() -> Self {
+        let (sender, requested) = watch::channel(false);
+        Self {
+            requested,
+            sender: Arc::new(sender),
+            signalled: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Starts listening for SIGTERM.
+    ///
+    /// # Arguments
+    ///
+    /// * `grace` - How long the run may take to write its records once SIGTERM arrived.
+    /// * `logger` - An instance of the Logger.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, String>` - Ok with the termination state, Err if the signal can't be caught.
+    pub fn listen(grace: Duration, logger: Arc<Logger>) -> Result<Self, String> {
+        let mut terminate = signal(SignalKind::terminate()).map_err(|e| format!("Unable to catch SIGTERM: {}", e))?;
+        let termination = Self::new();
+        let (sender, signalled) = (Arc::clone(&termination.sender), Arc::clone(&termination.signalled));
+        tokio::spawn(async move {
+            if terminate.recv().await.is_none() {
+                return;
+            }
+            signalled.store(true, Ordering::SeqCst);
+            sender.send_replace(true);
+            logger.log(&format!("Received SIGTERM, stopping and writing the partial report within {:?}", grace), true, false, false).await;
+            // a plain thread, so the deadline holds even if the runtime is stuck
+            let console = Arc::clone(logger.console());
+            std::thread::spawn(move || {
+                std::thread::sleep(grace);
+                console.line(Stream::Stderr, &format!("The grace period of {:?} after SIGTERM is over, exiting without a complete report", grace));
+                std::process::exit(EXIT_STATUS);
+            });
+        });
+        Ok(termination)
+    }
+
+    /// Stops the run from within, the way SIGTERM does.
+    pub fn stop(&self) {
+        self.sender.send_replace(true);
+    }
+
+    /// Checks whether the run was stopped by SIGTERM rather than from within.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if SIGTERM was received.
+    pub fn signalled(&self) -> bool {
+        self.signalled.load(Ordering::SeqCst)
+    }
+
+    /// Checks whether the run has to stop.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if SIGTERM was received or the run was stopped from within.
+    pub fn requested(&self) -> bool {
+        *self.requested.borrow()
+    }
+
+    /// Waits until the run has to stop.
+    pub async fn wait(&self) {
+        let mut requested = self.requested.clone();
+        // the state holds the sender, so this only returns once the run has to stop
+        let _ = requested.wait_for(|requested| *requested).await;
+    }
+}
+
+impl Default for Termination {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Waits until the run has to stop, or forever if nothing can stop it.
+///
+/// # Arguments
+///
+/// * `termination` - The termination state, unless '--term-grace' is 0 and '--max-errors' wasn't specified.
+pub async fn wait(termination: Option<&Termination>) {
+    match termination {
+        Some(termination) => termination.wait().await,
+        None => std::future::pending().await,
+    }
+}
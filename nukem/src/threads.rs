@@ -1,25 +1,28 @@
 //! This module computes the thread count used by crawler and deleter workers
 
 use crate::args::Args;
-use num_cpus;
 
 /// Holds information about the number of CPU cores and total thread count.
 #[derive(Clone)]
 pub struct ThreadInfo {
     pub core_count: usize,
     pub total_thread_count: usize,
+    // the ratio the count was derived from, or None if '-t' gave it
+    ratio: Option<(usize, bool)>,
+    logger_threads: usize,
 }
 
 impl ThreadInfo {
     // Threads per CPU. Set this low for testing but higher for better performance.
-    const DEFAULT_THREAD_RATIO: usize = 10;
+    pub const DEFAULT_THREAD_RATIO: usize = 10;
 
     /// Determines the number of threads to use.
     ///
     /// There are two methods:
     /// 1. User specifies number of threads through the command-line '-t' option.
     /// 2. System automatically computes number of threads to use, based on
-    ///    number of CPU cores in the system multiplied by the DEFAULT_THREAD_RATIO constant.
+    ///    number of CPU cores in the system multiplied by the '--thread-ratio' option, or by the
+    ///    DEFAULT_THREAD_RATIO constant if it wasn't given.
     ///
     /// # Arguments
     ///
@@ -31,15 +34,54 @@ impl ThreadInfo {
     pub fn compute_thread_count(args: &Args) -> Result<ThreadInfo, String> {
         let core_count = num_cpus::get();
         // If the user specified '-t' then use that value. Otherwise, automatically
-        // compute by multiplying the core_count by the DEFAULT_THREAD_RATIO constant.
-        let total_thread_count = match args.threads {
-            Some(t) => t,
-            None => core_count * Self::DEFAULT_THREAD_RATIO,
+        // compute by multiplying the core_count by the thread ratio.
+        let (total_thread_count, ratio) = match args.threads {
+            Some(t) => (t, None),
+            None => {
+                let ratio = args.thread_ratio.unwrap_or(Self::DEFAULT_THREAD_RATIO);
+                (core_count * ratio, Some((ratio, args.thread_ratio.is_some())))
+            }
         };
 
         Ok(ThreadInfo {
             core_count,
             total_thread_count,
+            ratio,
+            logger_threads: 0,
         })
     }
+
+    /// Adds the threads dedicated to the logger to the total.
+    ///
+    /// # Arguments
+    ///
+    /// * `count` - The number of logger threads.
+    pub fn add_logger_threads(&mut self, count: usize) {
+        self.logger_threads += count;
+        self.total_thread_count += count;
+    }
+
+    /// Explains how the thread count was derived, for '--explain-threads'.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<String>` - One line per step, ending with the final count.
+    pub fn explain(&self) -> Vec<String> {
+        let workers = self.total_thread_count - self.logger_threads;
+        let mut lines = vec![format!("CPU cores: {}", self.core_count)];
+        match self.ratio {
+            Some((ratio, given)) => {
+                lines.push(format!("Thread ratio: {} ({})", ratio, if given { "--thread-ratio" } else { "default" }));
+                lines.push(format!("Worker threads: {} x {} = {}", self.core_count, ratio, workers));
+            }
+            None => lines.push(format!("Worker threads: {} (-t, the CPU cores and thread ratio are not used)", workers)),
+        }
+        lines.push(format!("Logger threads: {} (--logger-threads)", self.logger_threads));
+        lines.push(format!("Threads: {} + {} = {}", workers, self.logger_threads, self.total_thread_count));
+        lines.push(format!(
+            "The file deleter and the directory deleter each run {} workers, {} in total",
+            self.total_thread_count, 2 * self.total_thread_count
+        ));
+        lines
+    }
 }
@@ -0,0 +1,323 @@
+//! This module lets one run act on files differently by their age, e.g. compress what is older
+//! than 30 days, trash what is older than 90 and delete what is older than 180.
+//!
+//! Each '--tier AGE=ACTION' names an action for the files that weren't modified within AGE, by
+//! their modification time like find's '-mtime'. A file goes to the oldest tier its age reaches,
+//! so with the tiers above a file of 100 days is trashed and one of 200 days is deleted, and a
+//! file younger than every tier is kept and counted as "younger than every --tier". The crawler
+//! picks the tier of each file and sends it along with the file (see identity::Found), and the
+//! deleter acts on it with the action of that tier. A file that was modified since the crawl and
+//! would now go to another tier is kept, counted as "changed tier since the crawl".
+//!
+//! The actions are those of '--action compress', '--action move' (to '--move-to'), '--trash' and
+//! plain deletion, and each one is carried out the same way as on its own. Since a directory
+//! could hold files of several tiers, tiers only act on files and symlinks and every directory is
+//! kept, like with '--action compress'. The final report counts the files and their size, before
+//! they were acted on, per tier.
+
+use std::fmt;
+use std::fs::Metadata;
+use std::os::unix::fs::MetadataExt;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use crate::args::parse_age;
+use crate::filters::access_cutoff;
+
+/// What a tier does with its files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TierAction {
+    /// Compress them in place with '--compressor'.
+    Compress,
+    /// Move them under '--move-to'.
+    Move,
+    /// Move them to the desktop trash.
+    Trash,
+    /// Delete them.
+    Delete,
+}
+
+impl TierAction {
+    /// Returns the name the action is given on the command line.
+    ///
+    /// # Returns
+    ///
+    /// * `&'static str` - The name of the action.
+    pub fn label(self) -> &'static str {
+        match self {
+            TierAction::Compress => "compress",
+            TierAction::Move => "move",
+            TierAction::Trash => "trash",
+            TierAction::Delete => "delete",
+        }
+    }
+}
+
+/// A '--tier': the files that weren't modified within an age, and what is done with them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tier {
+    pub age: Duration,
+    pub action: TierAction,
+}
+
+impl Tier {
+    /// Parses a tier such as `30d=compress`. The age has the units of '--min-age', and the action
+    /// is one of `compress`, `move`, `trash` and `delete`, in any case.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The tier as given on the command line.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, String>` - Ok with the tier, Err with a message if it is invalid.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let (age, action) = text.split_once('=').ok_or_else(|| format!("Invalid tier '{}'. Use AGE=ACTION, e.g. 30d=compress", text))?;
+        let age = parse_age(age).map_err(|e| format!("Invalid tier '{}': {}", text, e))?;
+        let action = match action.trim().to_ascii_lowercase().as_str() {
+            "compress" => TierAction::Compress,
+            "move" => TierAction::Move,
+            "trash" => TierAction::Trash,
+            "delete" => TierAction::Delete,
+            _ => return Err(format!("Invalid tier '{}'. The action must be compress, move, trash or delete", text)),
+        };
+        Ok(Self { age, action })
+    }
+}
+
+impl fmt::Display for Tier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", age_label(self.age), self.action.label())
+    }
+}
+
+/// The tiers of a run, oldest first, each with the modification time its files must be older than.
+#[derive(Debug, Clone, Default)]
+pub struct Tiers {
+    tiers: Vec<(i64, Tier)>,
+}
+
+impl Tiers {
+    /// Orders the tiers, oldest first, and works out their cutoffs from the current time.
+    ///
+    /// # Arguments
+    ///
+    /// * `tiers` - The tiers as given on the command line.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The tiers.
+    pub fn new(tiers: &[Tier]) -> Self {
+        let mut tiers: Vec<(i64, Tier)> = tiers.iter().map(|tier| (access_cutoff(tier.age), *tier)).collect();
+        tiers.sort_by_key(|(_, tier)| std::cmp::Reverse(tier.age));
+        Self { tiers }
+    }
+
+    /// Checks whether any tier was given.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if '--tier' was specified.
+    pub fn is_empty(&self) -> bool {
+        self.tiers.is_empty()
+    }
+
+    /// Picks the tier of a file by its modification time.
+    ///
+    /// # Arguments
+    ///
+    /// * `metadata` - The metadata of the file, read without following symlinks.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<usize>` - The index of the oldest tier the file's age reaches, or None if it is
+    ///   younger than every tier.
+    pub fn pick(&self, metadata: &Metadata) -> Option<usize> {
+        self.pick_at(metadata.mtime())
+    }
+
+    /// Picks the tier of a file modified at the given time.
+    ///
+    /// # Arguments
+    ///
+    /// * `modified` - The modification time, in seconds since the epoch.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<usize>` - The index of the oldest tier the file's age reaches, or None.
+    fn pick_at(&self, modified: i64) -> Option<usize> {
+        self.tiers.iter().position(|(cutoff, _)| modified < *cutoff)
+    }
+
+    /// Looks up a tier picked by `pick`.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - The index of the tier.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<Tier>` - The tier, or None if there is no such tier.
+    pub fn get(&self, index: usize) -> Option<Tier> {
+        self.tiers.get(index).map(|(_, tier)| *tier)
+    }
+
+    /// Lists the tiers, oldest first.
+    ///
+    /// # Returns
+    ///
+    /// * `impl Iterator<Item = Tier>` - The tiers.
+    pub fn iter(&self) -> impl Iterator<Item = Tier> + '_ {
+        self.tiers.iter().map(|(_, tier)| *tier)
+    }
+}
+
+/// Checks that no two tiers have the same age, which would leave it open which one a file goes to.
+///
+/// # Arguments
+///
+/// * `tiers` - The tiers as given on the command line.
+///
+/// # Returns
+///
+/// * `Result<(), String>` - Ok if every tier has an age of its own, Err with a message otherwise.
+pub fn check(tiers: &[Tier]) -> Result<(), String> {
+    for (i, tier) in tiers.iter().enumerate() {
+        if let Some(other) = tiers[..i].iter().find(|other| other.age == tier.age) {
+            return Err(format!("--tier {} and --tier {} have the same age", other, tier));
+        }
+    }
+    Ok(())
+}
+
+/// Shared counts of the files acted on per tier. Cloning it shares the underlying counts, so
+/// every deleter worker adds to the same ones.
+#[derive(Clone)]
+pub struct TierCounts {
+    tiers: Tiers,
+    counts: Arc<Mutex<Vec<(u64, u64)>>>,
+}
+
+impl TierCounts {
+    /// Creates the counts, all zero.
+    ///
+    /// # Arguments
+    ///
+    /// * `tiers` - The tiers of the run.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The counts.
+    pub fn new(tiers: Tiers) -> Self {
+        let counts = vec![(0, 0); tiers.tiers.len()];
+        Self { tiers, counts: Arc::new(Mutex::new(counts)) }
+    }
+
+    /// Returns the tiers the files are counted by.
+    ///
+    /// # Returns
+    ///
+    /// * `&Tiers` - The tiers.
+    pub fn tiers(&self) -> &Tiers {
+        &self.tiers
+    }
+
+    /// Counts a file its tier acted on.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - The index of the tier.
+    /// * `bytes` - The size of the file before it was acted on.
+    pub async fn record(&self, index: usize, bytes: u64) {
+        if let Some((files, size)) = self.counts.lock().await.get_mut(index) {
+            *files += 1;
+            *size += bytes;
+        }
+    }
+
+    /// Lists the tiers, oldest first, with the number of files each one acted on and their size.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<(Tier, u64, u64)>` - The tier, its files and their size in bytes.
+    pub async fn totals(&self) -> Vec<(Tier, u64, u64)> {
+        let counts = self.counts.lock().await;
+        self.tiers.iter().zip(counts.iter()).map(|(tier, (files, size))| (tier, *files, *size)).collect()
+    }
+}
+
+/// Writes an age in the largest unit of '--min-age' that it is a whole number of.
+///
+/// # Arguments
+///
+/// * `age` - The age.
+///
+/// # Returns
+///
+/// * `String` - The age, e.g. `30d` or `36h`.
+fn age_label(age: Duration) -> String {
+    let secs = age.as_secs();
+    for (unit, length) in [("w", 604_800), ("d", 86_400), ("h", 3600), ("m", 60)] {
+        if secs > 0 && secs.is_multiple_of(length) {
+            return format!("{}{}", secs / length, unit);
+        }
+    }
+    format!("{}s", secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::{check, Tier, TierAction, Tiers};
+
+    const DAY: u64 = 86_400;
+
+    fn tier(days: u64, action: TierAction) -> Tier {
+        Tier { age: Duration::from_secs(days * DAY), action }
+    }
+
+    #[test]
+    fn parses_an_age_and_an_action() {
+        assert_eq!(Tier::parse("30d=compress"), Ok(tier(30, TierAction::Compress)));
+        assert_eq!(Tier::parse("90=Trash"), Ok(tier(90, TierAction::Trash)));
+        assert_eq!(Tier::parse("2w=move"), Ok(tier(14, TierAction::Move)));
+        assert_eq!(Tier::parse("12h=DELETE"), Ok(Tier { age: Duration::from_secs(12 * 3600), action: TierAction::Delete }));
+    }
+
+    #[test]
+    fn rejects_invalid_tiers() {
+        assert_eq!(Tier::parse("30d"), Err("Invalid tier '30d'. Use AGE=ACTION, e.g. 30d=compress".into()));
+        assert_eq!(Tier::parse("30d=shred"), Err("Invalid tier '30d=shred'. The action must be compress, move, trash or delete".into()));
+        assert!(Tier::parse("soon=delete").unwrap_err().starts_with("Invalid tier 'soon=delete': "));
+    }
+
+    #[test]
+    fn writes_tiers_in_the_largest_whole_unit() {
+        assert_eq!(tier(30, TierAction::Compress).to_string(), "30d=compress");
+        assert_eq!(tier(14, TierAction::Delete).to_string(), "2w=delete");
+        assert_eq!(Tier::parse("1.5d=trash").map(|tier| tier.to_string()), Ok("36h=trash".into()));
+        assert_eq!(Tier::parse("90s=move").map(|tier| tier.to_string()), Ok("90s=move".into()));
+    }
+
+    #[test]
+    fn picks_the_oldest_tier_a_file_reaches() {
+        let tiers = Tiers::new(&[tier(30, TierAction::Compress), tier(180, TierAction::Delete), tier(90, TierAction::Trash)]);
+        assert_eq!(tiers.iter().map(|tier| tier.action).collect::<Vec<_>>(), [TierAction::Delete, TierAction::Trash, TierAction::Compress]);
+        let now = chrono::Utc::now().timestamp();
+        let days_ago = |days: u64| now - (days * DAY) as i64;
+        assert_eq!(tiers.pick_at(days_ago(200)).and_then(|index| tiers.get(index)), Some(tier(180, TierAction::Delete)));
+        assert_eq!(tiers.pick_at(days_ago(100)).and_then(|index| tiers.get(index)), Some(tier(90, TierAction::Trash)));
+        assert_eq!(tiers.pick_at(days_ago(31)).and_then(|index| tiers.get(index)), Some(tier(30, TierAction::Compress)));
+        assert_eq!(tiers.pick_at(days_ago(29)), None);
+        assert_eq!(tiers.pick_at(now), None);
+    }
+
+    #[test]
+    fn rejects_two_tiers_of_the_same_age() {
+        assert_eq!(check(&[tier(30, TierAction::Compress), tier(90, TierAction::Delete)]), Ok(()));
+        assert_eq!(
+            check(&[tier(30, TierAction::Compress), tier(90, TierAction::Trash), tier(30, TierAction::Delete)]),
+            Err("--tier 30d=compress and --tier 30d=delete have the same age".into())
+        );
+    }
+}
@@ -0,0 +1,189 @@
+//! This module moves objects to the desktop trash for '--trash', following the freedesktop.org
+//! Trash specification.
+//!
+//! Everything goes to the home trash, '$XDG_DATA_HOME/Trash' (or '~/.local/share/Trash'), so file
+//! managers can list and restore it. Each object is moved into 'files/' under a name that isn't
+//! taken yet, and 'info/<name>.trashinfo' records its original absolute path (percent-encoded) and
+//! the local time it was trashed. The info file is created first and exclusively, which is what
+//! reserves the name when several workers, or other programs, trash objects of the same name.
+//!
+//! Within the filesystem of the trash the object is renamed, so a directory tree moves as a
+//! whole. An object on another filesystem is copied into the trash instead, verifying every file
+//! like '--action move' does, and removed once the copy is complete. Per-filesystem trash
+//! directories ('$topdir/.Trash-$uid') aren't used, so trashing a large tree from another
+//! filesystem costs a full copy.
+
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, DirBuilder, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use crate::owners;
+use crate::relocate;
+
+/// The home trash that objects are moved to.
+#[derive(Debug, Clone)]
+pub struct Trash {
+    dir: PathBuf,
+}
+
+impl Trash {
+    /// Finds the home trash and creates its directories if they don't exist yet.
+    ///
+    /// # Arguments
+    ///
+    /// * `create` - A boolean indicating whether to create the directories. A dry run leaves
+    ///   the trash alone.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, String>` - Ok with the trash, Err with a message if it can't be used.
+    pub fn open(create: bool) -> Result<Self, String> {
+        let dir = home_trash().ok_or("Unable to find the trash: neither $XDG_DATA_HOME nor the home directory is known")?;
+        if create {
+            for sub in ["files", "info"] {
+                DirBuilder::new().recursive(true).mode(0o700).create(dir.join(sub))
+                    .map_err(|e| format!("Unable to use the trash {}: {}", dir.display(), e))?;
+            }
+        }
+        Ok(Self { dir })
+    }
+
+    /// Retrieves the directory of the trash.
+    ///
+    /// # Returns
+    ///
+    /// * `&Path` - The trash, holding 'files' and 'info'.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Moves a file, symlink or directory tree to the trash. If anything fails along the way,
+    /// the object is left where it was and nothing is left behind in the trash, except for a
+    /// directory copied from another filesystem that could only be removed in part: it stays in
+    /// the trash in full.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The object.
+    ///
+    /// # Returns
+    ///
+    /// * `io::Result<bool>` - Ok with true if the object had to be copied from another
+    ///   filesystem, Err if it could not be trashed.
+    pub fn put(&self, path: &Path) -> io::Result<bool> {
+        fs::symlink_metadata(path)?;
+        let original = relocate::absolute(path)?;
+        let (info, target) = self.reserve(&original)?;
+        relocate::move_tree(path, &target).inspect_err(|_| {
+            if fs::symlink_metadata(&target).is_err() {
+                let _ = fs::remove_file(&info);
+            }
+        })
+    }
+
+    /// Reserves a name in the trash by creating its info file, adding a number to the name of
+    /// the object until one is free.
+    ///
+    /// # Arguments
+    ///
+    /// * `original` - The absolute path of the object.
+    ///
+    /// # Returns
+    ///
+    /// * `io::Result<(PathBuf, PathBuf)>` - Ok with the info file and where the object goes in
+    ///   'files', Err if the info file couldn't be written.
+    fn reserve(&self, original: &Path) -> io::Result<(PathBuf, PathBuf)> {
+        let name = original.file_name().unwrap_or(OsStr::new("unnamed"));
+        let date = chrono::Local::now().format("%Y-%m-%dT%H:%M:%S");
+        let contents = format!("[Trash Info]\nPath={}\nDeletionDate={}\n", encode(original), date);
+        for number in 1u64.. {
+            let candidate = numbered(name, number);
+            let target = self.dir.join("files").join(&candidate);
+            // an object left in 'files' without its info file still holds the name
+            if fs::symlink_metadata(&target).is_ok() {
+                continue;
+            }
+            let mut info_name = candidate;
+            info_name.push(".trashinfo");
+            let info = self.dir.join("info").join(info_name);
+            let mut file = match OpenOptions::new().write(true).create_new(true).mode(0o600).open(&info) {
+                Ok(file) => file,
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            };
+            if let Err(e) = file.write_all(contents.as_bytes()).and_then(|()| file.sync_all()) {
+                let _ = fs::remove_file(&info);
+                return Err(e);
+            }
+            return Ok((info, target));
+        }
+        Err(io::Error::other("no free name left in the trash"))
+    }
+}
+
+/// Finds the home trash.
+///
+/// # Returns
+///
+/// * `Option<PathBuf>` - '$XDG_DATA_HOME/Trash', or 'Trash' under '~/.local/share' if that isn't
+///   set, or None if there is no home directory either.
+pub fn home_trash() -> Option<PathBuf> {
+    // the specification ignores relative values
+    let data = std::env::var_os("XDG_DATA_HOME").map(PathBuf::from).filter(|dir| dir.is_absolute());
+    let data = data.or_else(|| {
+        let home = std::env::var_os("HOME").map(PathBuf::from).filter(|dir| dir.is_absolute());
+        home.or_else(|| owners::home_dir(unsafe { libc::geteuid() })).map(|home| home.join(".local/share"))
+    })?;
+    Some(data.join("Trash"))
+}
+
+/// Adds a number to a name in the trash, in front of its extension: the second 'report.pdf' is
+/// 'report.2.pdf'.
+///
+/// # Arguments
+///
+/// * `name` - The name of the object.
+/// * `number` - How many objects of that name came before, plus one.
+///
+/// # Returns
+///
+/// * `OsString` - The name, unchanged for the first one.
+fn numbered(name: &OsStr, number: u64) -> OsString {
+    if number == 1 {
+        return name.to_os_string();
+    }
+    let bytes = name.as_bytes();
+    // a leading dot starts a hidden name, not an extension
+    let (stem, extension) = match bytes.iter().rposition(|&byte| byte == b'.') {
+        Some(dot) if dot > 0 => bytes.split_at(dot),
+        _ => (bytes, &b""[..]),
+    };
+    let mut numbered = stem.to_vec();
+    numbered.extend_from_slice(format!(".{}", number).as_bytes());
+    numbered.extend_from_slice(extension);
+    OsString::from_vec(numbered)
+}
+
+/// Percent-encodes a path for the 'Path' key of an info file, leaving the unreserved characters
+/// of RFC 2396 and the slashes alone.
+///
+/// # Arguments
+///
+/// * `path` - The absolute path.
+///
+/// # Returns
+///
+/// * `String` - The encoded path.
+fn encode(path: &Path) -> String {
+    let mut encoded = String::new();
+    for &byte in path.as_os_str().as_bytes() {
+        if byte.is_ascii_alphanumeric() || b"-_.!~*'()/".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
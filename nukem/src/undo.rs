@@ -0,0 +1,338 @@
+//! This module stages deleted objects for '--undoable' and puts them back for 'nukem restore'.
+//!
+//! Instead of being removed, every object is moved into a staging folder of the run,
+//! '<undo dir>/<run id>/', under a sequence number, and a line in '<undo dir>/<run id>.manifest'
+//! records where it came from and where it went. The line is written and flushed before the
+//! object is moved, so a run that is cut short leaves no staged object without its line. The undo
+//! directory defaults to the 'undo' folder of the state directory and can be changed with
+//! '--undo-dir'. Within its filesystem an object is renamed, so a directory tree moves as a whole;
+//! an object on another filesystem is copied, verifying every file like '--action move' does.
+//!
+//! The manifest starts with a '#' header, followed by one tab-separated line per object:
+//!
+//! ```text
+//! number  the sequence number of the object
+//! kind    'file', 'symlink' or 'dir'
+//! size    size in bytes (0 for directories)
+//! staged  where the object was moved to
+//! path    where it was deleted from
+//! ```
+//!
+//! Both paths are absolute, with '%', tabs, newlines, carriage returns and bytes that aren't UTF-8
+//! written as '%XX'. Nothing frees the space of staged objects until the staging folder is removed.
+//! 'nukem restore <manifest>' moves every staged object back, the last one first, and refuses to
+//! replace anything that took its place in the meantime. It can be run again once such conflicts
+//! are cleared, and removes the staging folder and the manifest once everything is back.
+
+use std::fs::{self, DirBuilder, File, Metadata, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use chrono::Local;
+use tokio::sync::Mutex;
+use crate::args::Args;
+use crate::history;
+use crate::relocate;
+
+// The first line of every manifest, followed by the names of the columns.
+const HEADER: &str = "# nukem undo manifest 1";
+const COLUMNS: &str = "# number\tkind\tsize\tstaged\tpath";
+
+/// The open manifest and what has been written to it.
+struct State {
+    // None in a dry run, which stages nothing
+    writer: Option<BufWriter<File>>,
+    entries: u64,
+    // the first write that failed; nothing is staged after it
+    error: Option<String>,
+}
+
+/// Shared staging folder and manifest of a run. Cloning it shares the underlying manifest, so
+/// every worker records into the same one.
+#[derive(Clone)]
+pub struct Undo {
+    dir: PathBuf,
+    manifest: PathBuf,
+    state: Arc<Mutex<State>>,
+}
+
+impl Undo {
+    /// Creates the staging folder and the manifest of the run.
+    ///
+    /// # Arguments
+    ///
+    /// * `base` - The undo directory.
+    /// * `create` - A boolean indicating whether to create them. A dry run leaves the undo
+    ///   directory alone.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, String>` - Ok with the staging folder, Err with a message if it can't be created.
+    pub fn create(base: &Path, create: bool) -> Result<Self, String> {
+        let fail = |e: io::Error| format!("Unable to use the undo directory {}: {}", base.display(), e);
+        let run_id = format!("{}-{}", Local::now().format("%Y%m%d-%H%M%S"), std::process::id());
+        let base = if base.is_absolute() { base.to_path_buf() } else { std::env::current_dir().map_err(fail)?.join(base) };
+        let dir = base.join(&run_id);
+        let manifest = base.join(format!("{}.manifest", run_id));
+        let writer = if create {
+            DirBuilder::new().recursive(true).mode(0o700).create(&dir).map_err(fail)?;
+            let file = OpenOptions::new().write(true).create_new(true).mode(0o600).open(&manifest).map_err(fail)?;
+            let mut writer = BufWriter::new(file);
+            writeln!(writer, "{}\n{}", HEADER, COLUMNS).and_then(|()| writer.flush()).map_err(fail)?;
+            Some(writer)
+        } else {
+            None
+        };
+        Ok(Self { dir, manifest, state: Arc::new(Mutex::new(State { writer, entries: 0, error: None })) })
+    }
+
+    /// Retrieves the staging folder of the run.
+    ///
+    /// # Returns
+    ///
+    /// * `&Path` - The folder staged objects are moved to.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Retrieves the manifest of the run.
+    ///
+    /// # Returns
+    ///
+    /// * `&Path` - The manifest, to be passed to 'nukem restore'.
+    pub fn manifest(&self) -> &Path {
+        &self.manifest
+    }
+
+    /// Records an object in the manifest and moves it into the staging folder. If the manifest
+    /// can't be written, the object is left where it was.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The object.
+    /// * `metadata` - The metadata of the object, read without following symlinks.
+    ///
+    /// # Returns
+    ///
+    /// * `io::Result<bool>` - Ok with true if the object had to be copied to another filesystem,
+    ///   Err if it could not be staged.
+    pub async fn stage(&self, path: &Path, metadata: &Metadata) -> io::Result<bool> {
+        let original = relocate::absolute(path)?;
+        let staged = {
+            let mut state = self.state.lock().await;
+            if let Some(ref e) = state.error {
+                return Err(io::Error::other(e.clone()));
+            }
+            let number = state.entries + 1;
+            let staged = self.dir.join(number.to_string());
+            let (kind, size) = if metadata.is_dir() {
+                ("dir", 0)
+            } else if metadata.file_type().is_symlink() {
+                ("symlink", metadata.len())
+            } else {
+                ("file", metadata.len())
+            };
+            let line = format!("{}\t{}\t{}\t{}\t{}\n", number, kind, size, escape(&staged), escape(&original));
+            let writer = state.writer.as_mut().ok_or_else(|| io::Error::other("nothing is staged in a dry run"))?;
+            // the line has to be on disk before the object is moved, or a crash could lose it
+            if let Err(e) = writer.write_all(line.as_bytes()).and_then(|()| writer.flush()).and_then(|()| writer.get_ref().sync_data()) {
+                state.error = Some(format!("the manifest {} can't be written: {}", self.manifest.display(), e));
+                return Err(e);
+            }
+            state.entries = number;
+            staged
+        };
+        let object = path.to_path_buf();
+        tokio::task::spawn_blocking(move || relocate::move_tree(&object, &staged)).await?
+    }
+
+    /// Makes sure the manifest reached the disk.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<u64, String>` - Ok with the number of objects recorded, Err with a message if
+    ///   the manifest is incomplete.
+    pub async fn finish(&self) -> Result<u64, String> {
+        let mut state = self.state.lock().await;
+        if let Some(e) = state.error.take() {
+            return Err(format!("The undo manifest is incomplete after {} objects: {}", state.entries, e));
+        }
+        if let Some(ref mut writer) = state.writer {
+            writer.flush().and_then(|()| writer.get_ref().sync_all())
+                .map_err(|e| format!("The undo manifest {} is incomplete: {}", self.manifest.display(), e))?;
+        }
+        Ok(state.entries)
+    }
+}
+
+/// Finds the undo directory.
+///
+/// # Arguments
+///
+/// * `args` - Command-line arguments.
+///
+/// # Returns
+///
+/// * `Option<PathBuf>` - '--undo-dir', or the 'undo' folder of the state directory, or None if
+///   neither is known.
+pub fn base_dir(args: &Args) -> Option<PathBuf> {
+    args.undo_dir.clone().or_else(|| history::state_dir(args.state_dir.as_ref()).map(|dir| dir.join("undo")))
+}
+
+/// One object recorded in a manifest.
+struct Entry {
+    number: u64,
+    staged: PathBuf,
+    original: PathBuf,
+}
+
+/// Puts the objects recorded in a manifest back where they were deleted from.
+///
+/// # Arguments
+///
+/// * `manifest` - The manifest, as written by an '--undoable' run.
+/// * `dry_run` - A boolean indicating whether to only report what would be restored.
+///
+/// # Returns
+///
+/// * `Result<(), String>` - Ok if everything that was staged is back, Err with a message otherwise.
+pub fn restore(manifest: &Path, dry_run: bool) -> Result<(), String> {
+    let entries = read_manifest(manifest)?;
+    let (mut restored, mut missing, mut conflicts, mut failed) = (0u64, 0u64, 0u64, 0u64);
+    for entry in entries.iter().rev() {
+        // the run stopped before the object was moved, or an earlier restore put it back
+        if fs::symlink_metadata(&entry.staged).is_err() {
+            missing += 1;
+            continue;
+        }
+        if fs::symlink_metadata(&entry.original).is_ok() {
+            eprintln!("Kept #{}: {} exists again", entry.number, entry.original.display());
+            conflicts += 1;
+            continue;
+        }
+        if dry_run {
+            println!("Would restore {}", entry.original.display());
+            restored += 1;
+            continue;
+        }
+        let parent = entry.original.parent().unwrap_or(Path::new("/"));
+        match fs::create_dir_all(parent).and_then(|()| relocate::move_tree(&entry.staged, &entry.original)) {
+            Ok(_) => {
+                println!("Restored {}", entry.original.display());
+                restored += 1;
+            }
+            Err(e) => {
+                eprintln!("Unable to restore #{} to {}: {}", entry.number, entry.original.display(), e);
+                failed += 1;
+            }
+        }
+    }
+    println!(
+        "{} {} of {} objects, {} not staged or already restored, {} kept because their path is taken, {} failed",
+        if dry_run { "Would restore" } else { "Restored" }, restored, entries.len(), missing, conflicts, failed
+    );
+    if conflicts > 0 || failed > 0 {
+        return Err(format!("Not everything was restored. Run 'nukem restore {}' again once the paths are free", manifest.display()));
+    }
+    if !dry_run {
+        let dir = manifest.with_extension("");
+        // only an empty folder can be removed, so nothing staged is ever lost here
+        if fs::remove_dir(&dir).is_ok() || fs::symlink_metadata(&dir).is_err() {
+            let _ = fs::remove_file(manifest);
+        }
+    }
+    Ok(())
+}
+
+/// Reads the entries of a manifest.
+///
+/// # Arguments
+///
+/// * `manifest` - The manifest.
+///
+/// # Returns
+///
+/// * `Result<Vec<Entry>, String>` - Ok with the entries in the order they were staged, Err with a
+///   message if the file isn't a valid manifest.
+fn read_manifest(manifest: &Path) -> Result<Vec<Entry>, String> {
+    let fail = |e: io::Error| format!("Unable to read the undo manifest {}: {}", manifest.display(), e);
+    let mut lines = BufReader::new(File::open(manifest).map_err(fail)?).lines();
+    if lines.next().transpose().map_err(fail)?.as_deref() != Some(HEADER) {
+        return Err(format!("{} is not an undo manifest", manifest.display()));
+    }
+    let mut entries = Vec::new();
+    for (index, line) in lines.enumerate() {
+        let line = line.map_err(fail)?;
+        if line.starts_with('#') || line.is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        let entry = match fields[..] {
+            [number, _, _, staged, original] => number.parse().ok().map(|number| Entry { number, staged: unescape(staged), original: unescape(original) }),
+            _ => None,
+        };
+        let entry = entry.ok_or_else(|| format!("Line {} of the undo manifest {} is malformed", index + 2, manifest.display()))?;
+        if !entry.staged.is_absolute() || !entry.original.is_absolute() {
+            return Err(format!("Line {} of the undo manifest {} has a relative path", index + 2, manifest.display()));
+        }
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// Escapes a path for a manifest or a plan.
+///
+/// # Arguments
+///
+/// * `path` - The path.
+///
+/// # Returns
+///
+/// * `String` - The path, with '%', tabs, newlines, carriage returns and bytes that aren't UTF-8
+///   written as '%XX'.
+pub fn escape(path: &Path) -> String {
+    let mut escaped = String::new();
+    for chunk in path.as_os_str().as_bytes().utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '%' | '\t' | '\n' | '\r' => escaped.push_str(&format!("%{:02X}", c as u8)),
+                c => escaped.push(c),
+            }
+        }
+        for &byte in chunk.invalid() {
+            escaped.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    escaped
+}
+
+/// Reverses 'escape'.
+///
+/// # Arguments
+///
+/// * `field` - The escaped path.
+///
+/// # Returns
+///
+/// * `PathBuf` - The path.
+pub fn unescape(field: &str) -> PathBuf {
+    let bytes = field.as_bytes();
+    let mut path = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|hex| std::str::from_utf8(hex).ok()).and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                path.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                path.push(byte);
+                i += 1;
+            }
+        }
+    }
+    PathBuf::from(std::ffi::OsString::from_vec(path))
+}
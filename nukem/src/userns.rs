@@ -0,0 +1,185 @@
+//! This module runs nukem inside an unprivileged user namespace, for cleaning up the storage of
+//! rootless containers.
+//!
+//! Rootless container engines (podman, rootless docker, buildah) store image layers and overlay
+//! upper directories owned by the user's subordinate UIDs and GIDs from /etc/subuid and
+//! /etc/subgid. The user can't delete most of those files directly and gets EPERM. With
+//! '--userns', nukem starts itself again in a new user namespace where UID 0 is the user and
+//! UIDs 1 and up are the subordinate range, the same mapping the container engines use, so it is
+//! root over those files within the namespace. The mappings are set up by the setuid helpers
+//! newuidmap and newgidmap from shadow-utils, which only grant the ranges listed for the user.
+
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::os::unix::io::FromRawFd;
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+use std::thread;
+use crate::owners;
+
+// Set in the environment of the nukem started within the namespace, so it doesn't start another.
+const INSIDE: &str = "NUKEM_USERNS";
+
+/// A subordinate ID range from /etc/subuid or /etc/subgid.
+#[derive(Debug, Clone, Copy)]
+struct IdRange {
+    start: u32,
+    count: u32,
+}
+
+/// Checks whether this process was started within the namespace by '--userns'.
+///
+/// # Returns
+///
+/// * `bool` - True within the namespace.
+pub fn is_inside() -> bool {
+    std::env::var_os(INSIDE).is_some()
+}
+
+/// Runs nukem again, with the same arguments, within a new user namespace and waits for it.
+///
+/// # Returns
+///
+/// * `Result<i32, String>` - Ok with the exit code of the run, Err with a message if the
+///   namespace could not be set up.
+pub fn run_inside() -> Result<i32, String> {
+    let uid = unsafe { libc::getuid() };
+    let gid = unsafe { libc::getgid() };
+    if uid == 0 {
+        return Err("--userns is for unprivileged users. Run without it as root".into());
+    }
+    let name = owners::user_name(uid).ok_or_else(|| format!("User ID {} has no account, so it has no subordinate IDs", uid))?;
+    let uids = subordinate_ids("/etc/subuid", &name, uid)?;
+    let gids = subordinate_ids("/etc/subgid", &name, uid)?;
+    for helper in ["newuidmap", "newgidmap"] {
+        if !on_path(helper) {
+            return Err(format!("--userns needs '{}' from shadow-utils (the uidmap package), which could not be found", helper));
+        }
+    }
+
+    // the child reports its PID once it is in the namespace, then waits until the maps are written
+    let (pid_read, pid_write) = pipe()?;
+    let (go_read, go_write) = pipe()?;
+    let mapper = thread::spawn(move || -> Result<(), String> {
+        let mut pid_pipe = unsafe { File::from_raw_fd(pid_read) };
+        let mut go_pipe = unsafe { File::from_raw_fd(go_write) };
+        let mut pid = [0u8; 4];
+        // the child didn't get as far as the namespace, and the spawn reports why
+        if pid_pipe.read_exact(&mut pid).is_err() {
+            return Ok(());
+        }
+        let pid = i32::from_ne_bytes(pid).to_string();
+        let mapped = map_ids("newuidmap", &pid, uid, uids).and_then(|()| map_ids("newgidmap", &pid, gid, gids));
+        // the child gives up on anything but a 1
+        let _ = go_pipe.write_all(&[mapped.is_ok() as u8]);
+        mapped
+    });
+
+    let mut command = Command::new(std::env::current_exe().map_err(|e| format!("Unable to find the nukem executable: {}", e))?);
+    command.args(std::env::args_os().skip(1)).env(INSIDE, "1");
+    unsafe {
+        command.pre_exec(move || {
+            // only async-signal-safe calls between fork and exec
+            if libc::unshare(libc::CLONE_NEWUSER) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let pid = libc::getpid().to_ne_bytes();
+            if libc::write(pid_write, pid.as_ptr() as *const libc::c_void, pid.len()) != pid.len() as isize {
+                return Err(io::Error::last_os_error());
+            }
+            let mut go = 0u8;
+            if libc::read(go_read, &mut go as *mut u8 as *mut libc::c_void, 1) != 1 || go != 1 {
+                return Err(io::Error::from_raw_os_error(libc::EPERM));
+            }
+            Ok(())
+        });
+    }
+    let child = command.spawn();
+    // the child's ends are closed in this process, so the mapper sees the end of the pipe if the child failed
+    unsafe {
+        libc::close(pid_write);
+        libc::close(go_read);
+    }
+    let mapped = mapper.join().map_err(|_| "The ID mapping thread panicked".to_string())?;
+    let mut child = child.map_err(|e| match mapped {
+        Err(ref mapping_error) => mapping_error.clone(),
+        Ok(()) => format!("Unable to enter a user namespace: {}. Unprivileged user namespaces may be disabled (kernel.unprivileged_userns_clone or user.max_user_namespaces)", e),
+    })?;
+    let status = child.wait().map_err(|e| format!("Lost track of the run within the user namespace: {}", e))?;
+    Ok(status.code().unwrap_or(1))
+}
+
+/// Looks up the subordinate IDs of a user.
+///
+/// # Arguments
+///
+/// * `file` - /etc/subuid or /etc/subgid.
+/// * `name` - The user name.
+/// * `uid` - The user ID, which may be listed instead of the name.
+///
+/// # Returns
+///
+/// * `Result<IdRange, String>` - Ok with the first range listed for the user, Err otherwise.
+fn subordinate_ids(file: &str, name: &str, uid: u32) -> Result<IdRange, String> {
+    let text = fs::read_to_string(file).map_err(|e| format!("Unable to read {}: {}", file, e))?;
+    text.lines()
+        .filter_map(|line| {
+            let mut fields = line.trim().splitn(3, ':');
+            let owner = fields.next()?;
+            let range = IdRange { start: fields.next()?.parse().ok()?, count: fields.next()?.parse().ok()? };
+            (owner == name || owner == uid.to_string()).then_some(range)
+        })
+        .find(|range| range.count > 0)
+        .ok_or_else(|| format!("{} lists no subordinate IDs for '{}'. Add a range, e.g. with 'usermod --add-subuids'", file, name))
+}
+
+/// Writes the ID map of the namespace with one of the setuid helpers: 0 becomes the user's own
+/// ID, and 1 and up the subordinate range.
+///
+/// # Arguments
+///
+/// * `helper` - newuidmap or newgidmap.
+/// * `pid` - The PID of the process within the namespace.
+/// * `own` - The user's own UID or GID.
+/// * `range` - The subordinate range.
+///
+/// # Returns
+///
+/// * `Result<(), String>` - Ok if the map was written, Err with a message otherwise.
+fn map_ids(helper: &str, pid: &str, own: u32, range: IdRange) -> Result<(), String> {
+    let output = Command::new(helper)
+        .args([pid, "0", &own.to_string(), "1", "1", &range.start.to_string(), &range.count.to_string()])
+        .output()
+        .map_err(|e| format!("Unable to run {}: {}", helper, e))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(format!("{} failed: {}", helper, String::from_utf8_lossy(&output.stderr).trim()))
+    }
+}
+
+/// Creates a pipe whose ends are closed on exec.
+///
+/// # Returns
+///
+/// * `Result<(i32, i32), String>` - Ok with the read and write ends.
+fn pipe() -> Result<(i32, i32), String> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+        return Err(format!("Unable to create a pipe: {}", io::Error::last_os_error()));
+    }
+    Ok((fds[0], fds[1]))
+}
+
+/// Checks whether a program is on the PATH.
+///
+/// # Arguments
+///
+/// * `program` - The program name.
+///
+/// # Returns
+///
+/// * `bool` - True if it was found.
+fn on_path(program: &str) -> bool {
+    std::env::var_os("PATH").is_some_and(|paths| std::env::split_paths(&paths).any(|dir| dir.join(program).is_file()))
+}
@@ -1,15 +1,77 @@
 //! This module provides various utilitarian functions used across the application.
 
 use std::sync::Arc;
-use crate::args::Args;
+use crate::args::{Action, Args, SizeUnit};
 use crate::logger::Logger;
 use crate::threads::ThreadInfo;
 use crate::deleter::Deleter;
+use crate::watchdog::Watchdog;
+use crate::skipped::SkipReason;
+use crate::handles::OpenHandle;
+use crate::freespace::SpaceProbe;
+use crate::phases::{Phase, PhaseTimes};
+use crate::owners;
+use crate::filters;
+use crate::patterns;
+use crate::regex::Regex;
+use crate::symlinks::LinkTotals;
+use crate::environment;
+use crate::immutable::Protection;
+use crate::slowops::SlowOp;
+use crate::trash;
+use crate::undo;
+use crate::tiers::Tiers;
+use crate::identity::Found;
+use glob::{glob_with, MatchOptions};
 use std::time::Duration;
 use tokio::sync::{mpsc, Mutex};
 use std::path::PathBuf;
+use std::fs::Metadata;
+use std::os::unix::fs::MetadataExt;
 use std::time::Instant;
 
+/// Totals of a run, returned by `finalize` so they can be recorded in the run history.
+#[derive(Debug, Clone)]
+pub struct RunSummary {
+    pub found: u64,
+    pub deleted: u64,
+    pub failed: u64,
+    pub skipped: u64,
+    pub total_size: u64,
+    pub elapsed: Duration,
+    pub accounting_ok: bool,
+    // the run stopped on SIGTERM or at --max-errors, before handling everything it found
+    pub interrupted: bool,
+    pub dry_run: bool,
+}
+
+/// The totals the crawlers and deleters add up while the run is in progress.
+#[derive(Clone, Default)]
+pub struct Counters {
+    pub directories: Arc<Mutex<usize>>,
+    pub files_symlinks: Arc<Mutex<usize>>,
+    pub crawling_ops: Arc<Mutex<usize>>,
+    pub stat_ops: Arc<Mutex<usize>>,
+    pub deletion_ops: Arc<Mutex<usize>>,
+}
+
+/// What the final report is made of, besides the deleter's own counts.
+pub struct ReportSources {
+    pub start: Instant,
+    pub counters: Counters,
+    pub widest_dirs: Arc<Mutex<Vec<(usize, PathBuf)>>>,
+    pub space_probe: Option<SpaceProbe>,
+    pub watchdog: Option<Arc<Watchdog>>,
+    pub phases: PhaseTimes,
+    // the widest directories, phase timings and worker restarts are only listed with --verbose,
+    // and the slowest operations also when --slowest asked for them
+    pub verbose: bool,
+    pub list_slowest: bool,
+}
+
+/// The receiving end of a queue, shared by the workers that consume it.
+pub type SharedReceiver = Arc<Mutex<mpsc::Receiver<Found>>>;
+
 /// Sets up channels for inter-task communication.
 ///
 /// # Arguments
@@ -18,8 +80,8 @@ use std::time::Instant;
 ///
 /// # Returns
 ///
-/// * `(mpsc::Sender<PathBuf>, mpsc::Sender<PathBuf>, Arc<Mutex<mpsc::Receiver<PathBuf>>>, Arc<Mutex<mpsc::Receiver<PathBuf>>>)`
-pub fn setup_channels(args: &Args) -> (mpsc::Sender<PathBuf>, mpsc::Sender<PathBuf>, Arc<Mutex<mpsc::Receiver<PathBuf>>>, Arc<Mutex<mpsc::Receiver<PathBuf>>>) {
+/// * `(mpsc::Sender<Found>, mpsc::Sender<Found>, SharedReceiver, SharedReceiver)`
+pub fn setup_channels(args: &Args) -> (mpsc::Sender<Found>, mpsc::Sender<Found>, SharedReceiver, SharedReceiver) {
     // communication channels use the buffer_size specified by the '-b' commandline option
     let (file_sender, file_receiver) = mpsc::channel(args.buffer_size);
     let (dir_sender, dir_receiver) = mpsc::channel(args.buffer_size);
@@ -28,6 +90,203 @@ pub fn setup_channels(args: &Args) -> (mpsc::Sender<PathBuf>, mpsc::Sender<PathB
     (file_sender, dir_sender, file_receiver, dir_receiver)
 }
 
+/// Formats a byte count for display using the unit selected with '--size-unit'.
+///
+/// # Arguments
+///
+/// * `bytes` - The size in bytes.
+/// * `unit` - The unit to display the size in.
+///
+/// # Returns
+///
+/// * `String` - The formatted size, for example "12.34 MiB".
+pub fn format_size(bytes: u64, unit: SizeUnit) -> String {
+    const KIB: f64 = 1024.0;
+    const MIB: f64 = KIB * 1024.0;
+    const GIB: f64 = MIB * 1024.0;
+
+    // resolve 'auto' to the largest unit that keeps the value at or above one
+    let unit = match unit {
+        SizeUnit::Auto if bytes as f64 >= GIB => SizeUnit::GiB,
+        SizeUnit::Auto if bytes as f64 >= MIB => SizeUnit::MiB,
+        SizeUnit::Auto if bytes as f64 >= KIB => SizeUnit::KiB,
+        SizeUnit::Auto => SizeUnit::Bytes,
+        other => other,
+    };
+
+    match unit {
+        SizeUnit::KiB => format!("{:.2} KiB", bytes as f64 / KIB),
+        SizeUnit::MiB => format!("{:.2} MiB", bytes as f64 / MIB),
+        SizeUnit::GiB => format!("{:.2} GiB", bytes as f64 / GIB),
+        _ => format!("{} B", bytes),
+    }
+}
+
+/// Works out the share of a file's space that one of its names accounts for. A file with several
+/// hard links only frees its space once the last of them is gone, so each link counts for an
+/// equal part of it.
+///
+/// # Arguments
+///
+/// * `metadata` - The metadata of the object, read without following symlinks.
+///
+/// # Returns
+///
+/// * `u64` - The size divided by the number of links, or the size of a directory.
+pub fn shared_size(metadata: &Metadata) -> u64 {
+    if metadata.is_dir() { metadata.len() } else { metadata.len() / metadata.nlink().max(1) }
+}
+
+/// Works out how much space unlinking a file frees. Only the last hard link frees anything; the
+/// earlier ones leave the data in place.
+///
+/// # Arguments
+///
+/// * `metadata` - The metadata of the object, read without following symlinks.
+/// * `links` - The links the file has left, counting this one. A link count read right before
+///   the unlink is one, unless other links were unlinked since it was read.
+///
+/// # Returns
+///
+/// * `u64` - The size if this is the last link, zero otherwise.
+pub fn freed_size(metadata: &Metadata, links: u64) -> u64 {
+    if !metadata.is_dir() && links > 1 { 0 } else { metadata.len() }
+}
+
+/// Builds the options used for every wildcard pattern, so that validation, crawling and the
+/// pre-run checks all agree on what a pattern matches. This and the two functions after it are
+/// where '--ignore-case' takes effect, for wildcards, '--name-regex' and '--ext' alike.
+///
+/// # Arguments
+///
+/// * `ignore_case` - Whether '--ignore-case' was specified.
+///
+/// # Returns
+///
+/// * `MatchOptions` - The glob match options.
+pub fn glob_options(ignore_case: bool) -> MatchOptions {
+    MatchOptions { case_sensitive: !ignore_case, ..MatchOptions::new() }
+}
+
+/// Compiles a '--name-regex' expression.
+///
+/// # Arguments
+///
+/// * `pattern` - The regular expression.
+/// * `ignore_case` - Whether '--ignore-case' was specified. A leading '(?i)' ignores case either way.
+///
+/// # Returns
+///
+/// * `Result<Regex, String>` - Ok with the compiled expression, Err with a message if it is invalid.
+pub fn name_regex(pattern: &str, ignore_case: bool) -> Result<Regex, String> {
+    Regex::with_case(pattern, ignore_case)
+}
+
+/// Checks whether a file name ends with an extension given to '--ext'.
+///
+/// # Arguments
+///
+/// * `name` - The file name.
+/// * `ext` - The extension, without the leading dot.
+/// * `ignore_case` - Whether '--ignore-case' was specified.
+///
+/// # Returns
+///
+/// * `bool` - True if the name has more than the extension and a '.' before it.
+pub fn has_extension(name: &str, ext: &str, ignore_case: bool) -> bool {
+    let (name, ext) = if ignore_case { (name.to_lowercase(), ext.to_lowercase()) } else { (name.to_string(), ext.to_string()) };
+    name.len() > ext.len() + 1 && name.ends_with(&ext) && name[..name.len() - ext.len()].ends_with('.')
+}
+
+/// Expands the glob patterns given on the command line into the paths they currently match.
+/// Paths without wildcards are returned as they are.
+///
+/// # Arguments
+///
+/// * `patterns` - The paths and patterns supplied by the user.
+/// * `ignore_case` - Whether to match wildcards case-insensitively.
+///
+/// # Returns
+///
+/// * `Vec<PathBuf>` - The matching paths.
+pub fn expand_paths(patterns: &[PathBuf], ignore_case: bool) -> Vec<PathBuf> {
+    patterns::expand_all(patterns)
+        .iter()
+        .filter_map(|pattern| glob_with(pattern, glob_options(ignore_case)).ok())
+        .flat_map(|paths| paths.filter_map(Result::ok))
+        .collect()
+}
+
+/// Reports the processes that hold files open within the deletion targets.
+///
+/// # Arguments
+///
+/// * `handles` - The open handles that were found.
+/// * `logger` - An instance of the `Logger`.
+pub async fn print_open_handles(handles: &[OpenHandle], logger: &Arc<Logger>) {
+    if handles.is_empty() {
+        logger.log("Open file handles within targets: None", false, false, false).await;
+        return;
+    }
+    logger.log(&format!("Open file handles within targets: {} (space is not freed until they are closed)", handles.len()), false, false, false).await;
+    for handle in handles {
+        logger.log(&format!("    pid {} ({}): {}", handle.pid, handle.command, handle.path.display()), false, false, false).await;
+    }
+}
+
+/// Lists the files and directories that were kept because they were in use.
+///
+/// # Arguments
+///
+/// * `kept` - The kept paths.
+/// * `logger` - An instance of the `Logger`.
+pub async fn print_in_use(kept: &[PathBuf], logger: &Arc<Logger>) {
+    if kept.is_empty() {
+        return;
+    }
+    logger.log(&format!("Kept because they are in use: {}", kept.len()), false, false, false).await;
+    for path in kept {
+        logger.log(&format!("    {}", path.display()), false, false, false).await;
+    }
+}
+
+/// Reports the objects that were kept because they are immutable or append-only, and how many had
+/// those attributes cleared by '--clear-immutable'.
+///
+/// # Arguments
+///
+/// * `kept` - The kept objects, with what protected each.
+/// * `cleared` - The number of objects whose attributes were cleared.
+/// * `logger` - An instance of the `Logger`.
+pub async fn print_immutable(kept: &[(PathBuf, Protection)], cleared: u64, logger: &Arc<Logger>) {
+    if cleared > 0 {
+        logger.log(&format!("Cleared the immutable or append-only attribute of: {} objects", cleared), false, false, false).await;
+    }
+    if kept.is_empty() {
+        return;
+    }
+    logger.log(&format!("Kept because they are immutable or append-only: {}", kept.len()), false, false, false).await;
+    for (path, protection) in kept {
+        logger.log(&format!("    {} ({})", path.display(), protection.describe()), false, false, false).await;
+    }
+}
+
+/// Lists the paths that were kept because an operation on them got stuck.
+///
+/// # Arguments
+///
+/// * `stuck` - The operations that exceeded '--op-timeout'.
+/// * `logger` - An instance of the `Logger`.
+pub async fn print_stuck(stuck: &[SlowOp], logger: &Arc<Logger>) {
+    if stuck.is_empty() {
+        return;
+    }
+    logger.log(&format!("Stuck paths (given up on after {:?}): {}", stuck[0].elapsed, stuck.len()), false, false, false).await;
+    for op in stuck {
+        logger.log(&format!("    {} ({})", op.path.display(), op.kind.label()), false, false, false).await;
+    }
+}
+
 /// Informational report that shows paths, threads, and workers.
 ///
 /// # Arguments
@@ -47,50 +306,200 @@ pub async fn print_info(args: &Args, logger: &Arc<Logger>, thread_info: &ThreadI
     let full_logfile_name = args.resolve_logfile_name().unwrap_or_else(|| "None".into());
     let buffer_size = args.buffer_size;
 
-    // print the report
+    // print the report, starting with what the run depends on besides the arguments
+    print_environment(args, logger).await;
     logger.log(&format!("Logfile path: {}", full_logfile_name), false, false, false).await;
     logger.log(&format!("Core count: {}", core_count), false, false, false).await;
     logger.log(&format!("Threads: {}", thread_count), false, false, false).await;
     logger.log(&format!("Worker tasks count: {}", worker_tasks_count), false, false, false).await;
     logger.log(&format!("Number of Buffers: {}", buffer_size), false, false, false).await;
+    match args.action {
+        Action::Compress => logger.log(&format!("Action: compress with {}", args.compressor.program()), false, false, false).await,
+        Action::Move => logger.log(&format!("Action: move to {}", args.move_to.as_ref().map(|dest| dest.display().to_string()).unwrap_or_default()), false, false, false).await,
+        Action::Delete if !args.tier.is_empty() => {
+            let tiers: Vec<String> = Tiers::new(&args.tier).iter().map(|tier| tier.to_string()).collect();
+            logger.log(&format!("Action: by age, {}", tiers.join(", ")), false, false, false).await;
+        }
+        Action::Delete if args.prune_empty_dirs => logger.log("Action: prune empty directories", false, false, false).await,
+        Action::Delete if args.archive_to.is_some() => logger.log(&format!("Action: archive to {}, then delete", args.archive_to.as_ref().map(|path| path.display().to_string()).unwrap_or_default()), false, false, false).await,
+        Action::Delete if args.undoable => logger.log(&format!("Action: delete, staging everything in {} so the run can be undone", undo::base_dir(args).map(|dir| dir.display().to_string()).unwrap_or_default()), false, false, false).await,
+        Action::Delete if args.trash => logger.log(&format!("Action: move to the trash {}", trash::home_trash().map(|dir| dir.display().to_string()).unwrap_or_default()), false, false, false).await,
+        Action::Delete => {}
+    }
+    if args.userns {
+        logger.log("Running in a user namespace with the subordinate UIDs and GIDs mapped", false, false, false).await;
+    }
+    if let Some(cpu) = args.cgroup_cpu {
+        logger.log(&format!("Cgroup CPU limit: {}% of one CPU", cpu), false, false, false).await;
+    }
+    if let Some(memory) = args.cgroup_memory {
+        logger.log(&format!("Cgroup memory limit: {}", format_size(memory, args.size_unit)), false, false, false).await;
+    }
+    if let Some(limit) = args.memory_limit {
+        logger.log(&format!("Memory limit: {}", format_size(limit, args.size_unit)), false, false, false).await;
+    }
+    if args.not_accessed_in.is_some() {
+        warn_noatime(&expand_paths(&args.paths, args.ignore_case), logger).await;
+    }
     logger.log("----------------------------------------------------------------", false, false, false).await;
     Ok(())
 }
 
+/// Logs the build of nukem and the machine it runs on, so a run can be reproduced elsewhere.
+///
+/// # Arguments
+///
+/// * `args` - Command-line arguments.
+/// * `logger` - An instance of the `Logger`.
+async fn print_environment(args: &Args, logger: &Arc<Logger>) {
+    let features = environment::features();
+    let filesystems = environment::target_filesystems(&args.paths)
+        .iter()
+        .map(|(mount, fs_type)| format!("{} ({})", mount.display(), if fs_type.is_empty() { "?" } else { fs_type }))
+        .collect::<Vec<_>>();
+    logger.log(&format!("Build: {}", environment::build()), false, false, false).await;
+    logger.log(&format!("Features: {}", if features.is_empty() { "none".to_string() } else { features.join(", ") }), false, false, false).await;
+    logger.log(&format!("Host: {}", environment::hostname()), false, false, false).await;
+    logger.log(&format!("System: {}", environment::system()), false, false, false).await;
+    logger.log(&format!("Effective user: {}", environment::effective_user()), false, false, false).await;
+    logger.log(&format!("Resource limits: {}", environment::rlimits().join(", ")), false, false, false).await;
+    if !filesystems.is_empty() {
+        logger.log(&format!("Target filesystems: {}", filesystems.join(", ")), false, false, false).await;
+    }
+}
+
+/// Warns about targets on filesystems mounted with 'noatime'. Files there keep the access time
+/// they were created with, so '--not-accessed-in' sees them as unread however often they are read.
+///
+/// # Arguments
+///
+/// * `roots` - The target paths, with patterns already expanded.
+/// * `logger` - An instance of the `Logger`.
+async fn warn_noatime(roots: &[PathBuf], logger: &Arc<Logger>) {
+    let mut devices = Vec::new();
+    for root in roots {
+        let device = match std::fs::metadata(root) {
+            Ok(metadata) => std::os::unix::fs::MetadataExt::dev(&metadata),
+            Err(_) => continue,
+        };
+        if devices.contains(&device) {
+            continue;
+        }
+        devices.push(device);
+        if filters::is_noatime(root) {
+            logger.log(&format!(
+                "{} is on a filesystem mounted noatime. Access times aren't updated there, so --not-accessed-in treats files as unread since they were created",
+                root.display()
+            ), true, false, false).await;
+        }
+    }
+}
+
 /// Prints a summary of the filesystem objects that the Crawler found.
 ///
 /// # Arguments
 ///
 /// * `total_directories` - Total number of directories found.
 /// * `total_files_symlinks` - Total number of files and symlinks found.
+/// * `widest_dirs` - The widest directories encountered, widest first, if they are listed.
+/// * `hidden_skipped` - Number of hidden objects kept because of '--skip-hidden'.
+/// * `symlinks_found` - The symlinks among the files and symlinks, if '--symlink-report' was specified.
 /// * `logger` - An instance of the `Logger`.
 pub async fn print_crawler_summary(
     total_directories: usize,
     total_files_symlinks: usize,
+    widest_dirs: Option<&[(usize, PathBuf)]>,
+    hidden_skipped: u64,
+    symlinks_found: Option<LinkTotals>,
     logger: &Arc<Logger>,
 ) {
     logger.log("----------------------------------------------------------------", false, false, false).await;
     logger.log(&format!("Total directories: {}", total_directories), false, false, false).await;
     logger.log(&format!("Total files and symlinks: {}", total_files_symlinks), false, false, false).await;
+    if let Some(symlinks) = symlinks_found {
+        print_symlinks(total_files_symlinks as u64, symlinks, logger).await;
+    }
+    if hidden_skipped > 0 {
+        logger.log(&format!("Hidden objects skipped: {}", hidden_skipped), false, false, false).await;
+    }
+    if let Some(widest_dirs) = widest_dirs.filter(|widest_dirs| !widest_dirs.is_empty()) {
+        logger.log("Widest directories:", false, false, false).await;
+        for (width, path) in widest_dirs {
+            logger.log(&format!("    {} entries: {}", width, path.display()), false, false, false).await;
+        }
+    }
 }
 
-/// Prints a final report of deletion statistics.
+/// Prints how many files and how many valid and dangling symlinks make up a count of files and
+/// symlinks.
+///
+/// # Arguments
+///
+/// * `files_symlinks` - The number of files and symlinks.
+/// * `symlinks` - The symlinks among them.
+/// * `logger` - An instance of the `Logger`.
+async fn print_symlinks(files_symlinks: u64, symlinks: LinkTotals, logger: &Arc<Logger>) {
+    logger.log(&format!("    files: {}", files_symlinks.saturating_sub(symlinks.total())), false, false, false).await;
+    logger.log(&format!(
+        "    symlinks: {} ({} valid, {} dangling)", symlinks.total(), symlinks.valid, symlinks.dangling
+    ), false, false, false).await;
+}
+
+/// Cross-checks the crawler and deleter counters. Every object the crawlers found must have been
+/// deleted, failed, or skipped. Any discrepancy is reported as an internal accounting error rather
+/// than silently printing numbers that don't add up. A run that was terminated leaves some of
+/// what it found unhandled, which is reported as such.
 ///
 /// # Arguments
 ///
+/// * `found` - Total number of objects found by the crawlers.
 /// * `deleter` - An instance of the `Deleter`.
 /// * `logger` - An instance of the `Logger`.
-/// * `elapsed` - The duration of the application run.
-/// * `total_operations` - The total number of metadata operations performed.
+/// * `interrupted` - A boolean indicating whether the run stopped on SIGTERM.
 ///
 /// # Returns
 ///
-/// * `Result<(), Box<dyn std::error::Error + Send + Sync>>` - Ok if successful, Err otherwise.
-pub async fn print_final_report(deleter: &Deleter, logger: &Arc<Logger>, elapsed: Duration, total_operations: usize) {
-    //outputs total size of deleted files in MB, which is more user-friendly
-    let total_size_in_megabytes = deleter.get_total_size().await as f64 / 1024.0 / 1024.0;
+/// * `bool` - True if the counters add up, false otherwise.
+pub async fn verify_accounting(found: usize, deleter: &Deleter, logger: &Arc<Logger>, interrupted: bool) -> bool {
+    let deleted = deleter.get_deleted_files().await + deleter.get_deleted_dirs().await;
+    let failed = deleter.get_failed_deletions().await;
+    let skipped = deleter.get_skipped().await;
+    let accounted = deleted + failed + skipped;
+
+    if interrupted && found as u64 > accounted {
+        logger.log(&format!(
+            "Interrupted with {} of the {} objects found still unhandled: deleted {} + failed {} + skipped {} = {}",
+            found as u64 - accounted, found, deleted, failed, skipped, accounted
+        ), false, false, false).await;
+        return false;
+    }
+    if found as u64 != accounted {
+        logger.log(&format!(
+            "Internal accounting error: found {} objects, but deleted {} + failed {} + skipped {} = {}",
+            found, deleted, failed, skipped, accounted
+        ), true, false, false).await;
+        return false;
+    }
+    true
+}
+
+/// Prints a final report of deletion statistics.
+///
+/// # Arguments
+///
+/// * `deleter` - An instance of the `Deleter`.
+/// * `logger` - An instance of the `Logger`.
+/// * `elapsed` - The duration of the application run.
+/// * `total_operations` - The total number of metadata operations performed.
+/// * `sources` - The free space captured before the run, the memory watchdog and the phases.
+/// * `size_unit` - The unit used to display sizes.
+pub async fn print_final_report(
+    deleter: &Deleter, logger: &Arc<Logger>, elapsed: Duration, total_operations: usize, sources: &ReportSources, size_unit: SizeUnit
+) {
+    // outputs total size of deleted files in the unit selected by the user
+    let total_size = format_size(deleter.get_total_size().await, size_unit);
     //keep track of the number of objects that couldn't be deleted
-    let failed_deletions = *deleter.failed_deletions.lock().await;
+    let failed_deletions = deleter.get_failed_deletions().await;
     // number of seconds elapsed since application began
     let elapsed_secs = elapsed.as_secs_f64();
     //compute operations per second
@@ -102,46 +511,257 @@ pub async fn print_final_report(deleter: &Deleter, logger: &Arc<Logger>, elapsed
 
     // print the report
     logger.log("----------------------------------------------------------------", false, false, false).await;
+    if let Some(ref tier_counts) = deleter.tier_counts {
+        logger.log(&format!(
+            "Files and symlinks acted on by tier: {} ({} copied across filesystems)",
+            deleter.get_deleted_files().await, deleter.get_copied_files().await
+        ), false, false, false).await;
+        for (tier, files, bytes) in tier_counts.totals().await {
+            logger.log(&format!("    {}: {} ({})", tier, files, format_size(bytes, size_unit)), false, false, false).await;
+        }
+    } else if deleter.compressor.is_some() {
+        logger.log(&format!("Compressed files: {}", deleter.get_deleted_files().await), false, false, false).await;
+    } else if deleter.move_to.is_some() {
+        logger.log(&format!(
+            "Moved files and symlinks: {} ({} copied to another filesystem)",
+            deleter.get_deleted_files().await, deleter.get_copied_files().await
+        ), false, false, false).await;
+    } else if deleter.trash.is_some() {
+        logger.log(&format!("Trashed directories: {}", deleter.get_deleted_dirs().await), false, false, false).await;
+        logger.log(&format!(
+            "Trashed files and symlinks: {} ({} copied from another filesystem)",
+            deleter.get_deleted_files().await, deleter.get_copied_files().await
+        ), false, false, false).await;
+    } else if deleter.archive.is_some() {
+        logger.log(&format!("Archived and deleted directories: {}", deleter.get_deleted_dirs().await), false, false, false).await;
+        logger.log(&format!("Archived and deleted files and symlinks: {}", deleter.get_deleted_files().await), false, false, false).await;
+    } else if deleter.undo.is_some() {
+        logger.log(&format!("Staged directories: {}", deleter.get_deleted_dirs().await), false, false, false).await;
+        logger.log(&format!(
+            "Staged files and symlinks: {} ({} copied to another filesystem)",
+            deleter.get_deleted_files().await, deleter.get_copied_files().await
+        ), false, false, false).await;
+    } else {
+        logger.log(&format!("Deleted directories: {}", deleter.get_deleted_dirs().await), false, false, false).await;
+        logger.log(&format!("Deleted files and symlinks: {}", deleter.get_deleted_files().await), false, false, false).await;
+    }
+    // compressed files are never symlinks, so there is nothing to split up unless other tiers
+    // acted on some
+    if let Some(symlink_counts) = deleter.symlink_counts.as_ref().filter(|_| deleter.compressor.is_none() || deleter.tier_counts.is_some()) {
+        print_symlinks(deleter.get_deleted_files().await, symlink_counts.deleted().await, logger).await;
+    }
+    if deleter.empty_files_only {
+        logger.log(&format!("Empty files reclaimed: {}", deleter.get_deleted_files().await), false, false, false).await;
+    }
+    if deleter.prune_empty_dirs {
+        logger.log(&format!("Empty directories pruned: {}", deleter.get_deleted_dirs().await), false, false, false).await;
+    }
+    // every file that was deleted had to be stale, so all of the size is reclaimed from stale files
+    if let Some(cutoff) = deleter.accessed_before {
+        let since = chrono::DateTime::from_timestamp(cutoff, 0).map(|time| time.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M").to_string()).unwrap_or_default();
+        logger.log(&format!(
+            "Reclaimed from files not accessed since {}: {} in {} files",
+            since, total_size, deleter.get_deleted_files().await
+        ), false, false, false).await;
+    }
+    logger.log(&format!("Skipped: {}", deleter.get_skipped().await), false, false, false).await;
+    for reason in SkipReason::ALL {
+        let count = deleter.skipped.get(reason).await;
+        if count > 0 {
+            logger.log(&format!("    {}: {}", reason.label(), count), false, false, false).await;
+        }
+    }
+    if let Some(ref in_use) = deleter.in_use {
+        print_in_use(&in_use.kept().await, logger).await;
+    }
+    print_immutable(&deleter.immutables.kept().await, deleter.immutables.cleared().await, logger).await;
+    print_stuck(&deleter.slow_ops.stuck().await, logger).await;
+    if let Some(ref ext_counts) = deleter.ext_counts {
+        logger.log("Deleted files by extension:", false, false, false).await;
+        for (ext, count) in ext_counts.counts().await {
+            logger.log(&format!("    .{}: {}", ext, count), false, false, false).await;
+        }
+    }
+    if let Some(ref owner_counts) = deleter.owner_counts {
+        owners::print_table(&owner_counts.totals().await, logger, size_unit).await;
+    }
     logger.log(&format!("Failed deletions: {}", failed_deletions), false, false, false).await;
-    logger.log(&format!("Deletion completed. Total size: {:.2} MB", total_size_in_megabytes), false, false, false).await;
+    if sources.verbose {
+        logger.log(&format!("Worker restarts: {}", deleter.get_worker_restarts().await), false, false, false).await;
+    }
+    // compressing frees the difference between the originals and their compressed copies, which
+    // are only kept when they are smaller
+    let computed = if deleter.tier_counts.is_some() {
+        // the compressed copies of a compress tier stay, like with '--action compress'
+        let compressed = deleter.get_compressed_size().await;
+        let saved = deleter.get_total_size().await.saturating_sub(compressed);
+        if deleter.dry_run || compressed == 0 {
+            logger.log(&format!("Tiers completed. Total size: {}", total_size), false, false, false).await;
+        } else {
+            logger.log(&format!(
+                "Tiers completed. Total size: {}, compressed size: {}, saved: {}",
+                total_size, format_size(compressed, size_unit), format_size(saved, size_unit)
+            ), false, false, false).await;
+        }
+        saved
+    } else if deleter.compressor.is_some() {
+        let original = deleter.get_total_size().await;
+        let compressed = deleter.get_compressed_size().await;
+        let saved = original.saturating_sub(compressed);
+        if deleter.dry_run {
+            logger.log(&format!("Compression completed. Original size: {}", total_size), false, false, false).await;
+        } else {
+            logger.log(&format!(
+                "Compression completed. Original size: {}, compressed size: {}, saved: {}",
+                total_size, format_size(compressed, size_unit), format_size(saved, size_unit)
+            ), false, false, false).await;
+        }
+        saved
+    } else if let Some(ref dest) = deleter.move_to {
+        logger.log(&format!("Move completed. Total size: {} moved to {}", total_size, dest.display()), false, false, false).await;
+        deleter.get_total_size().await
+    } else if let Some(ref trash) = deleter.trash {
+        // trashing within a filesystem frees nothing until the trash is emptied
+        logger.log(&format!("Trash completed. Total size: {} moved to {}", total_size, trash.dir().display()), false, false, false).await;
+        deleter.get_total_size().await
+    } else if let Some(ref archive) = deleter.archive {
+        logger.log(&format!("Deletion completed. Total size: {} archived to {}", total_size, archive.path().display()), false, false, false).await;
+        deleter.get_total_size().await
+    } else if let Some(ref undo) = deleter.undo {
+        // staging within a filesystem frees nothing until the staging folder is removed
+        logger.log(&format!("Deletion completed. Total size: {} staged in {}", total_size, undo.dir().display()), false, false, false).await;
+        deleter.get_total_size().await
+    } else {
+        logger.log(&format!("Deletion completed. Total size: {}", total_size), false, false, false).await;
+        deleter.get_total_size().await
+    };
+    if let Some(freed) = sources.space_probe.as_ref().and_then(SpaceProbe::freed) {
+        print_space_freed(freed, computed, logger, size_unit).await;
+    }
+    let partial_dirs = deleter.get_partial_dirs().await;
+    if !partial_dirs.is_empty() {
+        logger.log("Partially deleted directories:", false, false, false).await;
+        for (dir, remaining) in partial_dirs {
+            logger.log(&format!("    {}: {} entries remaining", dir.display(), remaining), false, false, false).await;
+        }
+    }
+    let slowest = deleter.slow_ops.slowest().await;
+    if sources.list_slowest && !slowest.is_empty() {
+        logger.log("Slowest operations:", false, false, false).await;
+        for op in slowest {
+            logger.log(&format!("    {:?} {}: {}", op.elapsed, op.kind.label(), op.path.display()), false, false, false).await;
+        }
+    }
+    // the phases overlap, so their shares of the run don't add up to 100%
+    if sources.verbose {
+        logger.log("Phase timings:", false, false, false).await;
+        for span in sources.phases.spans().await {
+            let length = span.end.saturating_sub(span.start);
+            let share = if elapsed.as_secs_f64() > 0.0 { 100.0 * length.as_secs_f64() / elapsed.as_secs_f64() } else { 0.0 };
+            logger.log(&format!(
+                "    {}: {:.3}s to {:.3}s ({:?}, {:.0}% of the run)",
+                span.phase.label(), span.start.as_secs_f64(), span.end.as_secs_f64(), length, share
+            ), false, false, false).await;
+        }
+    }
     logger.log(&format!("Execution time: {:?}", elapsed), false, false, false).await;
     logger.log(&format!("Metadata operations per second: {:.2} ops/s", ops_per_sec), false, false, false).await;
+    if let Some(ref watchdog) = sources.watchdog {
+        let peak_rss = *watchdog.peak_rss.lock().await;
+        let throttle_count = *watchdog.throttle_count.lock().await;
+        logger.log(&format!("Peak memory use: {} (throttled {} times)", format_size(peak_rss, size_unit), throttle_count), false, false, false).await;
+    }
     logger.log("--------------- Application Run Complete -----------------------", false, false, false).await;
 }
 
+/// Reports the free space actually gained on the target filesystems and flags it when it falls
+/// well short of the computed total.
+///
+/// # Arguments
+///
+/// * `freed` - The measured change in free space, in bytes.
+/// * `computed` - The total size of the deleted files, in bytes.
+/// * `logger` - An instance of the `Logger`.
+/// * `size_unit` - The unit used to display sizes.
+pub async fn print_space_freed(freed: i64, computed: u64, logger: &Arc<Logger>, size_unit: SizeUnit) {
+    // differences smaller than this are normal filesystem noise
+    const NOISE_BYTES: u64 = 16 * 1024 * 1024;
+
+    let freed_display = if freed < 0 {
+        format!("-{}", format_size(freed.unsigned_abs(), size_unit))
+    } else {
+        format_size(freed as u64, size_unit)
+    };
+    logger.log(&format!("Space actually freed: {}", freed_display), false, false, false).await;
+
+    // the computed total only includes files the deleter removed one by one, so freeing more
+    // than that is expected. Freeing much less is worth pointing out.
+    let shortfall = computed.saturating_sub(freed.max(0) as u64);
+    if shortfall > NOISE_BYTES && shortfall > computed / 10 {
+        logger.log(&format!(
+            "{} of the computed total was not freed. Likely causes: hardlinks to deleted files, \
+             files still held open (see --check-open-handles), or filesystem snapshots",
+            format_size(shortfall, size_unit)
+        ), true, false, false).await;
+    }
+}
+
 /// Finalizes the application by printing summaries and reports.
 ///
 /// # Arguments
 ///
 /// * `deleter` - A reference to the Deleter.
 /// * `logger` - A reference to the Logger.
-/// * `start` - The start time of the application.
-/// * `total_directories` - Total count of directories deleted.
-/// * `total_files_symlinks` - Total count of files and symlinks deleted.
-/// * `total_crawling_ops` - Total count of Crawler metadata operations.
-/// * `total_stat_ops` - Total count of filesystem stat metadata operations.
-/// * `total_deletion_ops` - Total count of deletion metadata operations.
+/// * `sources` - The totals of the run and what else the final report is made of.
+/// * `size_unit` - The unit used to display sizes.
+/// * `interrupted` - A boolean indicating whether the run stopped on SIGTERM.
+///
+/// # Returns
+///
+/// * `RunSummary` - The totals of the run.
 pub async fn finalize(
-    deleter: &Arc<Mutex<Deleter>>, logger: &Arc<Logger>, start: Instant, total_directories: Arc<Mutex<usize>>,
-    total_files_symlinks: Arc<Mutex<usize>>, total_crawling_ops: Arc<Mutex<usize>>, total_stat_ops: Arc<Mutex<usize>>,
-    total_deletion_ops: Arc<Mutex<usize>>
-) {
+    deleter: &Arc<Mutex<Deleter>>, logger: &Arc<Logger>, sources: ReportSources, size_unit: SizeUnit, interrupted: bool
+) -> RunSummary {
+    let finalize_start = Instant::now();
     // get values for variables
-    let total_directories = *total_directories.lock().await;
-    let total_files_symlinks = *total_files_symlinks.lock().await;
-    let total_crawling_ops = *total_crawling_ops.lock().await;
-    let total_stat_ops = *total_stat_ops.lock().await;
-    let total_deletion_ops = *total_deletion_ops.lock().await;
+    let total_directories = *sources.counters.directories.lock().await;
+    let total_files_symlinks = *sources.counters.files_symlinks.lock().await;
+    let total_crawling_ops = *sources.counters.crawling_ops.lock().await;
+    let total_stat_ops = *sources.counters.stat_ops.lock().await;
+    let total_deletion_ops = *sources.counters.deletion_ops.lock().await;
 
     // compute total number of metadata operations
     let total_operations = total_crawling_ops + total_stat_ops + total_deletion_ops;
     // print the summary of crawler activity
-    print_crawler_summary(total_directories, total_files_symlinks, &logger).await;
+    let widest_dirs = sources.widest_dirs.lock().await.clone();
     // wait for deleter tasks to finish, then shutdown the deleter workers
     let deleter = deleter.lock().await;
+    let hidden_skipped = deleter.skipped.get(SkipReason::Hidden).await;
+    let symlinks_found = match deleter.symlink_counts {
+        Some(ref symlink_counts) => Some(symlink_counts.found().await),
+        None => None,
+    };
+    print_crawler_summary(total_directories, total_files_symlinks, sources.verbose.then_some(&widest_dirs[..]), hidden_skipped, symlinks_found, logger).await;
     deleter.shutdown().await;
+    // make sure every object that was found is accounted for
+    let verify_start = Instant::now();
+    let accounting_ok = verify_accounting(total_directories + total_files_symlinks, &deleter, logger, interrupted).await;
+    sources.phases.record_until_now(Phase::Verify, verify_start).await;
     // calculate elapsed time of application run
-    let elapsed = start.elapsed();
+    let elapsed = sources.start.elapsed();
     // print final report
-    print_final_report(&*deleter, &logger, elapsed, total_operations).await;
+    sources.phases.record_until_now(Phase::Finalize, finalize_start).await;
+    print_final_report(&deleter, logger, elapsed, total_operations, &sources, size_unit).await;
+
+    RunSummary {
+        found: (total_directories + total_files_symlinks) as u64,
+        deleted: deleter.get_deleted_files().await + deleter.get_deleted_dirs().await,
+        failed: deleter.get_failed_deletions().await,
+        skipped: deleter.get_skipped().await,
+        total_size: deleter.get_total_size().await,
+        elapsed,
+        accounting_ok,
+        interrupted,
+        dry_run: deleter.dry_run,
+    }
 }
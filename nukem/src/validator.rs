@@ -1,8 +1,35 @@
 //! This module provides functions to validate data in various parts of the application
 
-use std::path::PathBuf;
-use glob::glob;
-use crate::args::Args;
+use std::io::IsTerminal;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use glob::glob_with;
+use crate::utility::{expand_paths, glob_options, name_regex};
+use crate::patterns;
+use crate::expression::Expression;
+use crate::args::{Action, Args, OverBudget};
+use crate::alerts;
+use crate::excludes::{Excludes, Includes};
+use crate::filters;
+use crate::owners;
+use crate::forecast;
+use crate::trash;
+use crate::undo;
+use crate::archive;
+use crate::tiers;
+use crate::syslog::Transport;
+use crate::watchdog::Watchdog;
+
+// Directories no run may delete, or delete anything containing them.
+#[cfg(not(windows))]
+const PROTECTED: &[&str] = &[
+    "/", "/bin", "/boot", "/dev", "/etc", "/home", "/lib", "/lib32", "/lib64", "/opt", "/proc",
+    "/root", "/run", "/sbin", "/srv", "/sys", "/usr", "/var",
+];
+#[cfg(windows)]
+const PROTECTED: &[&str] = &[
+    "C:\\", "C:\\Windows", "C:\\Program Files", "C:\\Program Files (x86)", "C:\\ProgramData", "C:\\Users",
+];
 
 /// General purpose validation module. If it needs to be validated, it happens here.
 pub struct Validator {}
@@ -18,10 +45,64 @@ impl Validator {
     ///
     /// * `Result<(), String>` - Ok if validation passes, Error if validation fails.
     pub fn validate(args: &mut Args) -> Result<(), String> {
-        Self::validate_paths(&args.paths)?;
+        Self::validate_paths(&args.paths, args.ignore_case)?;
+        Self::validate_protected(&args.paths, &args.protect, args.ignore_case)?;
+        if !args.i_know_what_i_am_doing {
+            Self::validate_not_root_or_home(&args.paths, args.ignore_case)?;
+        }
         Self::validate_logfile_path(&args.logfile_path)?;
+        if let Some(fd) = args.summary_fd {
+            Self::validate_summary_fd(fd)?;
+        }
         Self::validate_buffer_size(args.buffer_size)?;
         Self::validate_thread_count(args.threads)?;
+        Self::validate_thread_ratio(args.thread_ratio, args.logger_threads)?;
+        if let Some(limit) = args.memory_limit {
+            Watchdog::validate_limit(limit, args.size_unit)?;
+        }
+        if args.op_timeout.is_some_and(|timeout| timeout.is_zero()) {
+            return Err("--op-timeout must be longer than zero".into());
+        }
+        Self::validate_alerts(args)?;
+        Self::validate_syslog(args)?;
+        Self::validate_filters(args)?;
+        if args.created_before.is_some() {
+            Self::validate_birth_times(&args.paths, args.ignore_case)?;
+        }
+        Self::validate_action(args)?;
+        if let Some(ref plan) = args.plan {
+            Self::validate_plan(plan, args)?;
+        }
+        if (args.interactive || args.interactive_top) && !std::io::stdin().is_terminal() {
+            return Err("-i and -I need an interactive terminal to ask on".into());
+        }
+        if !args.max_delete.is_empty() && args.over_budget == OverBudget::Ask && !std::io::stdin().is_terminal() {
+            return Err("--over-budget ask needs an interactive terminal to ask on".into());
+        }
+        Self::validate_mail_owners(args)?;
+        if args.sandbox {
+            Self::validate_sandbox(args)?;
+        }
+        Ok(())
+    }
+
+    /// Validates that a sandboxed run doesn't need to write where the sandbox can't let it.
+    ///
+    /// # Arguments
+    ///
+    /// * `args` - Command-line arguments.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), String>` - Ok if the run can be sandboxed, Error otherwise.
+    fn validate_sandbox(args: &Args) -> Result<(), String> {
+        // sendmail writes to its queue, and the sandbox stops it from gaining privileges too
+        if args.mail_owners {
+            return Err("--sandbox can't be combined with --mail-owners, since the mail is queued outside the sandbox".into());
+        }
+        if args.cgroup {
+            return Err("--sandbox can't be combined with --cgroup, since the cgroup is created outside the sandbox".into());
+        }
         Ok(())
     }
 
@@ -30,34 +111,43 @@ impl Validator {
     /// # Arguments
     ///
     /// * `paths` - A reference to a list of file paths to validate.
+    /// * `ignore_case` - Whether wildcards are matched case-insensitively.
     ///
     /// # Returns
     ///
     /// * `Result<(), String>` - Ok if all paths are valid, Err with a message if any path is invalid.
-    fn validate_paths(paths: &[PathBuf]) -> Result<(), String> {
+    fn validate_paths(paths: &[PathBuf], ignore_case: bool) -> Result<(), String> {
         for path in paths {
-            // User supplied wildcard
-            if path.to_string_lossy().contains("*") || path.to_string_lossy().contains("?") {
-                // If the glob pattern resolves to a path that exists, continue. If the path does not exist, error
+            Self::validate_share_path(path)?;
+            let alternatives = patterns::expand(path).map_err(|err| format!("Failed to read glob pattern: {}", err))?;
+            // User supplied wildcards or alternatives
+            if alternatives.len() > 1 || alternatives.iter().any(|alternative| patterns::has_wildcard(alternative)) {
+                // If any alternative resolves to a path that exists, continue. If none does, error
                 let mut matched = false;
-                for entry in glob(&*path.to_string_lossy()).map_err(|err| format!("Failed to read glob pattern: {}", err))? {
-                    match entry {
-                        Ok(path) => {
-                            if !path.exists() {
+                for alternative in &alternatives {
+                    if !patterns::has_wildcard(alternative) {
+                        matched |= Path::new(alternative).symlink_metadata().is_ok();
+                        continue;
+                    }
+                    let mut entries = glob_with(alternative, glob_options(ignore_case)).map_err(|err| format!("Failed to read glob pattern: {}", err))?;
+                    match entries.next() {
+                        Some(Ok(path)) => {
+                            if path.symlink_metadata().is_err() {
                                 return Err(format!("Path '{}' does not exist", path.display()));
                             }
                             matched = true;
-                            break;
                         },
-                        Err(e) => return Err(format!("Failed to read glob pattern: {}", e)),
+                        Some(Err(e)) => return Err(format!("Failed to read glob pattern: {}", e)),
+                        None => {}
                     }
                 }
                 if !matched {
                     return Err(format!("No paths matched the provided glob pattern: {}", path.display()));
                 }
             } else {
-                // Path does not have wildcards. If the path exists, return OK. Otherwise, return error
-                if !path.exists() {
+                // Path does not have wildcards. If the path exists, return OK. Otherwise, return error.
+                // A dangling symlink exists as far as deletion is concerned.
+                if path.symlink_metadata().is_err() {
                     return Err(format!("Path '{}' does not exist", path.display()));
                 }
             }
@@ -65,6 +155,145 @@ impl Validator {
         Ok(())
     }
 
+    /// Validate that no target is a protected path or contains one. The built-in system
+    /// directories and the '--protect' paths are compared after the wildcards are expanded and
+    /// the paths resolved, so neither '/et*', '/tmp/../etc' nor a symlinked parent gets past it.
+    ///
+    /// # Arguments
+    ///
+    /// * `paths` - The target patterns.
+    /// * `protect` - The paths given with '--protect'.
+    /// * `ignore_case` - Whether wildcards are matched case-insensitively.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), String>` - Ok if no protected path would be deleted, Error otherwise.
+    fn validate_protected(paths: &[PathBuf], protect: &[PathBuf], ignore_case: bool) -> Result<(), String> {
+        let mut protected: Vec<PathBuf> = Vec::new();
+        for path in PROTECTED.iter().map(PathBuf::from).chain(protect.iter().cloned()) {
+            // '/lib' is often a symlink to '/usr/lib', so both names are protected
+            if let Ok(resolved) = path.canonicalize() {
+                protected.push(resolved);
+            }
+            // paths that don't exist yet are kept as given, relative to the working directory
+            match std::env::current_dir() {
+                Ok(dir) if path.is_relative() => protected.push(dir.join(path)),
+                _ => protected.push(path),
+            }
+        }
+        for target in expand_paths(paths, ignore_case) {
+            let resolved = Self::resolve_target(&target);
+            if let Some(path) = protected.iter().find(|path| path.starts_with(&resolved)) {
+                return Err(if *path == resolved {
+                    format!("Refusing to delete '{}': it is a protected path", target.display())
+                } else {
+                    format!("Refusing to delete '{}': it contains the protected path '{}'", target.display(), path.display())
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Validate that no target is the root of a mounted filesystem or the home directory of the
+    /// user running nukem, which can only be deleted with '--i-know-what-i-am-doing'. With sudo,
+    /// the home directory of the user who ran sudo counts too. Wildcards are expanded first, so
+    /// '/*' or '/home/*' is caught as well.
+    ///
+    /// # Arguments
+    ///
+    /// * `paths` - The target patterns.
+    /// * `ignore_case` - Whether wildcards are matched case-insensitively.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), String>` - Ok if no target is a mount point or home directory, Error otherwise.
+    fn validate_not_root_or_home(paths: &[PathBuf], ignore_case: bool) -> Result<(), String> {
+        let uids = [Some(unsafe { libc::getuid() }), std::env::var("SUDO_UID").ok().and_then(|uid| uid.parse().ok())];
+        let homes: Vec<PathBuf> = uids.iter().flatten().filter_map(|uid| owners::home_dir(*uid))
+            .chain(std::env::var_os("HOME").filter(|home| !home.is_empty()).map(PathBuf::from))
+            .filter_map(|home| home.canonicalize().ok())
+            .collect();
+        let hint = "Pass --i-know-what-i-am-doing to delete it anyway";
+        for target in expand_paths(paths, ignore_case) {
+            let resolved = Self::resolve_target(&target);
+            if homes.contains(&resolved) {
+                return Err(format!("Refusing to delete '{}': it is the home directory of the user running nukem. {}", target.display(), hint));
+            }
+            // a symlink to a mount point is only a link
+            let is_dir = resolved.symlink_metadata().is_ok_and(|metadata| metadata.is_dir());
+            if is_dir && forecast::filesystem_of(&resolved).is_some_and(|(mount, _)| mount == resolved) {
+                return Err(format!("Refusing to delete '{}': it is the root of a mounted filesystem. {}", target.display(), hint));
+            }
+        }
+        Ok(())
+    }
+
+    /// Resolves a target to an absolute path without '..', symlinks or '.' in it. Only its
+    /// directory is resolved: a symlink target is deleted as a link, not as what it points to.
+    ///
+    /// # Arguments
+    ///
+    /// * `target` - A path that matched one of the target patterns.
+    ///
+    /// # Returns
+    ///
+    /// * `PathBuf` - The resolved path, or the path as it is if it can't be resolved.
+    fn resolve_target(target: &Path) -> PathBuf {
+        let name = match target.file_name() {
+            Some(name) => name,
+            // '/', '.' and paths ending in '..' name a directory, never a symlink
+            None => return target.canonicalize().unwrap_or_else(|_| target.to_path_buf()),
+        };
+        let parent = target.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        match parent.canonicalize() {
+            Ok(parent) => parent.join(name),
+            Err(_) => target.to_path_buf(),
+        }
+    }
+
+    /// Reject SMB share targets that the platform can't open directly. Windows resolves
+    /// `\\server\share` UNC paths natively, but elsewhere the share has to be mounted first.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path to check.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), String>` - Ok if the path is not an unsupported share path, Error otherwise.
+    fn validate_share_path(path: &Path) -> Result<(), String> {
+        let path = path.to_string_lossy();
+        let is_unc = path.starts_with("\\\\") && !cfg!(windows);
+        if is_unc || path.starts_with("smb://") {
+            return Err(format!(
+                "SMB share '{}' can't be opened directly. Mount the share (e.g. mount -t cifs) and pass the mounted path.",
+                path
+            ));
+        }
+        Ok(())
+    }
+
+    /// Validate that the file descriptor of '--summary-fd' is open for writing, so a wrong number
+    /// is found out before anything is deleted rather than when the summary is due.
+    ///
+    /// # Arguments
+    ///
+    /// * `fd` - The file descriptor.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), String>` - Ok if the summary can be written to it, Error otherwise.
+    fn validate_summary_fd(fd: i32) -> Result<(), String> {
+        let flags = if fd < 0 { -1 } else { unsafe { libc::fcntl(fd, libc::F_GETFL) } };
+        if flags == -1 {
+            return Err(format!("--summary-fd {} is not an open file descriptor", fd));
+        }
+        if flags & libc::O_ACCMODE == libc::O_RDONLY {
+            return Err(format!("--summary-fd {} is only open for reading", fd));
+        }
+        Ok(())
+    }
+
     /// Validate the user-specified logfile path. The user should just specify the path to a
     /// directory, not a filename for the logfile. The filename is generated automatically, complete
     /// with date/time stamp.
@@ -104,7 +333,7 @@ impl Validator {
     fn validate_buffer_size(buffer_size: usize) -> Result<(), String> {
         // Buffer size must be between 100 and 2000. If the user made no specification of '-b' then
         // the default buffer size of 100 is used.
-        if buffer_size < 100 || buffer_size > 2000 {
+        if !(100..=2000).contains(&buffer_size) {
             return Err(format!("Invalid buffer size {}. The buffer size must be between 100 and 2000.", buffer_size));
         }
         Ok(())
@@ -125,10 +354,370 @@ impl Validator {
         // Thread count must be between 1 and 64. If the user made no specification of '-t' then the
         // application will automatically calculate the number of threads to spawn
         if let Some(t) = threads {
-            if t < 1 || t > 64 {
+            if !(1..=64).contains(&t) {
                 return Err(format!("Invalid thread count {}. The thread count must be between 1 and 64.", t));
             }
         }
         Ok(())
     }
+
+    /// Validate the user-supplied thread ratio and number of logger threads. Like the thread
+    /// count, the ratio is between 1 and 64, and at most 8 threads are added for the logger.
+    ///
+    /// # Arguments
+    ///
+    /// * `ratio` - The number of threads per CPU core, if specified.
+    /// * `logger_threads` - The number of threads added for the logger.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), String>` - Ok if both are within the allowed range, Error otherwise.
+    fn validate_thread_ratio(ratio: Option<usize>, logger_threads: usize) -> Result<(), String> {
+        if let Some(r) = ratio {
+            if !(1..=64).contains(&r) {
+                return Err(format!("Invalid thread ratio {}. The thread ratio must be between 1 and 64.", r));
+            }
+        }
+        if logger_threads > 8 {
+            return Err(format!("Invalid number of logger threads {}. At most 8 threads can be added for the logger.", logger_threads));
+        }
+        Ok(())
+    }
+
+    /// Validate that the owners can be mailed. A dry run doesn't mail anyone, so it doesn't need
+    /// sendmail.
+    ///
+    /// # Arguments
+    ///
+    /// * `args` - command-line arguments.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), String>` - Ok if the owners can be mailed or don't have to be, Error otherwise.
+    fn validate_mail_owners(args: &Args) -> Result<(), String> {
+        if args.mail_owners && !args.dry_run {
+            owners::check_available()?;
+        }
+        Ok(())
+    }
+
+    /// Validate the alert options. A threshold without an action (or the other way around) would
+    /// never do anything, and webhooks are limited to plain HTTP URLs.
+    ///
+    /// # Arguments
+    ///
+    /// * `args` - command-line arguments.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), String>` - Ok if the alert options are consistent, Error otherwise.
+    fn validate_alerts(args: &Args) -> Result<(), String> {
+        let has_threshold = args.alert_failures.is_some() || args.alert_elapsed.is_some();
+        let has_action = args.alert_exec.is_some() || args.alert_webhook.is_some();
+        if has_threshold && !has_action {
+            return Err("An alert threshold was given without --alert-exec or --alert-webhook".into());
+        }
+        if has_action && !has_threshold {
+            return Err("An alert action was given without --alert-failures or --alert-elapsed".into());
+        }
+        if let Some(ref url) = args.alert_webhook {
+            alerts::parse_http_url(url)?;
+        }
+        Ok(())
+    }
+
+    /// Validate the syslog options: CA certificates only apply to a TLS server, and have to be a
+    /// readable file.
+    ///
+    /// # Arguments
+    ///
+    /// * `args` - command-line arguments.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), String>` - Ok if the syslog options are consistent, Error otherwise.
+    fn validate_syslog(args: &Args) -> Result<(), String> {
+        if let (Some(ref target), Some(ref ca_file)) = (&args.syslog, &args.syslog_ca) {
+            if target.transport != Transport::Tls {
+                return Err(format!("--syslog-ca only applies to a tls:// syslog server, not {}", target));
+            }
+            std::fs::File::open(ca_file).map_err(|e| format!("Unable to read the CA certificates {}: {}", ca_file.display(), e))?;
+        }
+        Ok(())
+    }
+
+    /// Validate that the filters can be met at all.
+    ///
+    /// # Arguments
+    ///
+    /// * `args` - command-line arguments.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), String>` - Ok if the filters are consistent, Error otherwise.
+    fn validate_filters(args: &Args) -> Result<(), String> {
+        if let (Some(min), Some(max)) = (args.min_size, args.max_size) {
+            if min > max {
+                return Err(format!("--min-size ({} bytes) is larger than --max-size ({} bytes)", min, max));
+            }
+        }
+        Excludes::from_args(args)?;
+        Includes::from_args(args)?;
+        if args.ext.iter().any(|ext| ext.trim_start_matches('.').is_empty()) {
+            return Err("--ext contains an empty extension".into());
+        }
+        for name in &args.user {
+            filters::lookup_user(name)?;
+        }
+        for name in &args.group {
+            filters::lookup_group(name)?;
+        }
+        if let Some(ref text) = args.where_expr {
+            Expression::parse(text, args.ignore_case).map_err(|e| format!("Invalid --where expression: {}", e))?;
+        }
+        for pattern in &args.name_regex {
+            name_regex(pattern, args.ignore_case).map_err(|e| format!("Invalid --name-regex '{}': {}", pattern, e))?;
+        }
+        if args.negate && !filters::Filters::from_args(args).has_criteria() {
+            return Err("--not needs a name, size, extension, --where or content filter to negate".into());
+        }
+        Ok(())
+    }
+
+    /// Validate that the targets of '--created-before' are on filesystems that record when files
+    /// were created. Each filesystem is only checked once.
+    ///
+    /// # Arguments
+    ///
+    /// * `paths` - The patterns supplied by the user.
+    /// * `ignore_case` - Whether wildcards are matched case-insensitively.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), String>` - Ok if creation times can be read, Error naming the first target
+    ///   where they can't.
+    fn validate_birth_times(paths: &[PathBuf], ignore_case: bool) -> Result<(), String> {
+        let mut devices = Vec::new();
+        for target in expand_paths(paths, ignore_case) {
+            let metadata = match target.symlink_metadata() {
+                Ok(metadata) => metadata,
+                Err(_) => continue,
+            };
+            if devices.contains(&metadata.dev()) {
+                continue;
+            }
+            devices.push(metadata.dev());
+            if filters::birth_time(&metadata).is_none() {
+                let fs_type = forecast::filesystem_of(&target).map(|(_, fs_type)| fs_type).filter(|fs_type| !fs_type.is_empty());
+                return Err(format!(
+                    "--created-before can't be used: '{}' is on {} that doesn't record when files were created",
+                    target.display(), fs_type.map_or_else(|| "a filesystem".to_string(), |fs_type| format!("a {} filesystem", fs_type))
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Validate that the requested action can be carried out.
+    ///
+    /// # Arguments
+    ///
+    /// * `args` - command-line arguments.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), String>` - Ok if the action can be carried out, Error otherwise.
+    fn validate_action(args: &Args) -> Result<(), String> {
+        if args.compresses() && !args.dry_run {
+            args.compressor.check_available()?;
+        }
+        if !args.tier.is_empty() {
+            Self::validate_tiers(args)?;
+        }
+        match (args.moves(), &args.move_to) {
+            (true, Some(dest)) => Self::validate_move_to(dest, &args.paths, args.ignore_case),
+            (true, None) => Err("A move tier needs --move-to".into()),
+            (false, Some(_)) => Err("--move-to can only be used with --action move or a move tier".into()),
+            _ if args.prune_empty_dirs && args.action != Action::Delete => Err("--prune-empty-dirs can only be used with --action delete".into()),
+            _ if args.trash => Self::validate_trash(&args.paths, args.ignore_case),
+            _ if args.undoable => Self::validate_undo_dir(args),
+            _ => match args.archive_to {
+                Some(ref path) => Self::validate_archive_to(path, args),
+                None => Ok(()),
+            },
+        }
+    }
+
+    /// Validate that no two tiers have the same age, and that the trash a trash tier moves files
+    /// to is outside the targets.
+    ///
+    /// # Arguments
+    ///
+    /// * `args` - command-line arguments.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), String>` - Ok if the tiers can be carried out, Error otherwise.
+    fn validate_tiers(args: &Args) -> Result<(), String> {
+        tiers::check(&args.tier)?;
+        if args.trashes() {
+            Self::validate_trash(&args.paths, args.ignore_case)?;
+        }
+        Ok(())
+    }
+
+    /// Validate that the archive of '--archive-to' can be written by an installed compressor,
+    /// doesn't exist yet and is outside the targets, so it is never archived into itself.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The archive.
+    /// * `args` - command-line arguments.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), String>` - Ok if the archive can be written, Error otherwise.
+    fn validate_archive_to(path: &Path, args: &Args) -> Result<(), String> {
+        let compressor = archive::compressor_for(path).ok_or_else(|| format!("--archive-to '{}' must end in .tar.zst or .tar.gz", path.display()))?;
+        if !args.dry_run {
+            compressor.check_available().map_err(|_| format!("--archive-to needs '{}', which could not be run", compressor.program()))?;
+        }
+        if std::fs::symlink_metadata(path).is_ok() {
+            return Err(format!("--archive-to '{}' already exists", path.display()));
+        }
+        let parent = path.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        let parent = parent.canonicalize().map_err(|e| format!("--archive-to '{}' can't be used: {}", path.display(), e))?;
+        for pattern in patterns::expand_all(&args.paths) {
+            let matches = glob_with(&pattern, glob_options(args.ignore_case)).map_err(|e| e.to_string())?;
+            for target in matches.filter_map(Result::ok).filter_map(|path| path.canonicalize().ok()) {
+                if parent.starts_with(&target) {
+                    return Err(format!("--archive-to '{}' is inside the target '{}'", path.display(), target.display()));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Validate that 'nukem plan' only records deletions, and that its plan doesn't exist yet and
+    /// is outside the targets, so it is never listed in itself.
+    ///
+    /// # Arguments
+    ///
+    /// * `plan` - The file to write the plan to.
+    /// * `args` - command-line arguments.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), String>` - Ok if the plan can be written, Error otherwise.
+    fn validate_plan(plan: &Path, args: &Args) -> Result<(), String> {
+        if args.action != Action::Delete || !args.tier.is_empty() || args.prune_empty_dirs || args.trash || args.undoable || args.archive_to.is_some() {
+            return Err("'nukem plan' only plans deletions, and can't be combined with --action, --tier, --prune-empty-dirs, --trash, --undoable or --archive-to".into());
+        }
+        if args.retry.is_some() {
+            return Err("'nukem plan' takes its paths, and can't be combined with --retry-failed".into());
+        }
+        if std::fs::symlink_metadata(plan).is_ok() {
+            return Err(format!("The plan '{}' already exists", plan.display()));
+        }
+        let parent = plan.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        let parent = parent.canonicalize().map_err(|e| format!("The plan '{}' can't be written: {}", plan.display(), e))?;
+        for pattern in patterns::expand_all(&args.paths) {
+            let matches = glob_with(&pattern, glob_options(args.ignore_case)).map_err(|e| e.to_string())?;
+            for target in matches.filter_map(Result::ok).filter_map(|path| path.canonicalize().ok()) {
+                if parent.starts_with(&target) {
+                    return Err(format!("The plan '{}' is inside the target '{}'", plan.display(), target.display()));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Validate that the trash of '--trash' is neither inside a target nor holding one, so
+    /// trashed objects are never crawled again and the trash isn't trashed into itself.
+    ///
+    /// # Arguments
+    ///
+    /// * `paths` - The target patterns.
+    /// * `ignore_case` - Whether wildcards are matched case-insensitively.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), String>` - Ok if the trash can be used, Error otherwise.
+    fn validate_trash(paths: &[PathBuf], ignore_case: bool) -> Result<(), String> {
+        let trash = trash::home_trash().ok_or("--trash can't be used: neither $XDG_DATA_HOME nor the home directory is known")?;
+        let trash = trash.canonicalize().unwrap_or(trash);
+        for pattern in patterns::expand_all(paths) {
+            let matches = glob_with(&pattern, glob_options(ignore_case)).map_err(|e| e.to_string())?;
+            for target in matches.filter_map(Result::ok).filter_map(|path| path.canonicalize().ok()) {
+                if trash.starts_with(&target) {
+                    return Err(format!("--trash can't be used: the target '{}' holds the trash {}", target.display(), trash.display()));
+                }
+                if target.starts_with(&trash) {
+                    return Err(format!("--trash can't be used: the target '{}' is in the trash", target.display()));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Validate that the undo directory of '--undoable' is neither inside a target nor holding
+    /// one, so staged objects are never crawled again and no target is staged into itself.
+    ///
+    /// # Arguments
+    ///
+    /// * `args` - command-line arguments.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), String>` - Ok if the undo directory can be used, Error otherwise.
+    fn validate_undo_dir(args: &Args) -> Result<(), String> {
+        let dir = undo::base_dir(args).ok_or("--undoable can't be used: the state directory is unknown. Use --undo-dir")?;
+        let dir = std::path::absolute(&dir).map_err(|e| format!("--undo-dir '{}' can't be used: {}", dir.display(), e))?;
+        // the undo directory may not exist yet, so the part of it that does is resolved
+        let existing = dir.ancestors().find_map(|ancestor| ancestor.canonicalize().ok().map(|resolved| (ancestor, resolved)));
+        let dir = match existing {
+            Some((ancestor, resolved)) => resolved.join(dir.strip_prefix(ancestor).unwrap_or(Path::new(""))),
+            None => dir,
+        };
+        for pattern in patterns::expand_all(&args.paths) {
+            let matches = glob_with(&pattern, glob_options(args.ignore_case)).map_err(|e| e.to_string())?;
+            for target in matches.filter_map(Result::ok).filter_map(|path| path.canonicalize().ok()) {
+                if dir.starts_with(&target) {
+                    return Err(format!("--undoable can't be used: the undo directory {} is inside the target '{}'", dir.display(), target.display()));
+                }
+                if target.starts_with(&dir) {
+                    return Err(format!("--undoable can't be used: the target '{}' is in the undo directory", target.display()));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Validate that the destination of '--action move' is a directory outside the targets, so
+    /// the moved objects are never crawled again.
+    ///
+    /// # Arguments
+    ///
+    /// * `dest` - The destination directory.
+    /// * `paths` - The target patterns.
+    /// * `ignore_case` - Whether wildcards are matched case-insensitively.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), String>` - Ok if the destination can be used, Error otherwise.
+    fn validate_move_to(dest: &Path, paths: &[PathBuf], ignore_case: bool) -> Result<(), String> {
+        let dest = dest.canonicalize().map_err(|e| format!("--move-to '{}' can't be used: {}", dest.display(), e))?;
+        if !dest.is_dir() {
+            return Err(format!("--move-to '{}' is not a directory", dest.display()));
+        }
+        for pattern in patterns::expand_all(paths) {
+            let matches = glob_with(&pattern, glob_options(ignore_case)).map_err(|e| e.to_string())?;
+            for target in matches.filter_map(Result::ok).filter_map(|path| path.canonicalize().ok()) {
+                if dest.starts_with(&target) {
+                    return Err(format!("--move-to '{}' is inside the target '{}'", dest.display(), target.display()));
+                }
+            }
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,173 @@
+//! This module provides a memory watchdog for long-running deletions.
+//!
+//! When the user specifies '--memory-limit', a background task samples the resident set size of
+//! the process once per second. Once memory use reaches the high watermark, the watchdog raises a
+//! pressure flag that the Crawler checks before discovering new objects. Discovery stays paused
+//! until the deleters have drained enough work for memory use to fall below the low watermark.
+//!
+//! Throttling only helps while the queues hold work. If memory use stays high for a while after
+//! the deleters emptied them, it is held elsewhere and pausing discovery would only stall the run,
+//! so the watchdog resumes discovery and doesn't throttle again until memory use fell below the
+//! low watermark. A limit that the run already reaches at startup is rejected.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use tokio::sync::mpsc::WeakSender;
+use crate::args::SizeUnit;
+use crate::identity::Found;
+use crate::logger::Logger;
+use crate::utility::format_size;
+
+/// The `Watchdog` structure tracks memory use against the user-supplied limit.
+pub struct Watchdog {
+    pub limit: u64,
+    pub pressure: Arc<Mutex<bool>>,
+    pub peak_rss: Arc<Mutex<u64>>,
+    pub throttle_count: Arc<Mutex<u64>>,
+}
+
+impl Watchdog {
+    // How often the resident set size is sampled.
+    const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+    // Percentage of the limit at which discovery is throttled.
+    const HIGH_WATERMARK: u64 = 90;
+    // Percentage of the limit at which discovery resumes.
+    const LOW_WATERMARK: u64 = 75;
+    // How long memory use may stay high with nothing queued before throttling is given up.
+    const DRAINED_GRACE: Duration = Duration::from_secs(10);
+
+    /// Creates a new `Watchdog` instance.
+    ///
+    /// # Arguments
+    ///
+    /// * `limit` - The resident memory limit in bytes.
+    ///
+    /// # Returns
+    ///
+    /// * `Arc<Self>` - A pointer to the `Watchdog` instance.
+    pub fn new(limit: u64) -> Arc<Self> {
+        Arc::new(Self {
+            limit,
+            pressure: Arc::new(Mutex::new(false)),
+            peak_rss: Arc::new(Mutex::new(0)),
+            throttle_count: Arc::new(Mutex::new(0)),
+        })
+    }
+
+    /// Checks that a limit leaves room above the memory the run already uses, since discovery
+    /// would otherwise be throttled from the start.
+    ///
+    /// # Arguments
+    ///
+    /// * `limit` - The resident memory limit in bytes.
+    /// * `size_unit` - The unit used to display sizes.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), String>` - Ok if the limit is above the throttling point, Err otherwise.
+    pub fn validate_limit(limit: u64, size_unit: SizeUnit) -> Result<(), String> {
+        match Self::resident_set_size() {
+            Some(rss) if rss >= limit / 100 * Self::HIGH_WATERMARK => Err(format!(
+                "--memory-limit {} is too low: nukem already uses {}, and discovery is throttled at {}% of the limit",
+                format_size(limit, size_unit), format_size(rss, size_unit), Self::HIGH_WATERMARK
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Spawns the task that samples memory use for the remainder of the run.
+    ///
+    /// # Arguments
+    ///
+    /// * `queues` - The queues between the crawlers and the deleters, which throttling drains.
+    /// * `logger` - An instance of the Logger.
+    /// * `size_unit` - The unit used to display sizes.
+    pub fn spawn(self: &Arc<Self>, queues: Vec<WeakSender<Found>>, logger: Arc<Logger>, size_unit: SizeUnit) {
+        let watchdog = Arc::clone(self);
+        tokio::spawn(async move {
+            if Self::resident_set_size().is_none() {
+                logger.log("Memory watchdog disabled: resident set size is not available on this platform", true, false, false).await;
+                return;
+            }
+            let high = watchdog.limit / 100 * Self::HIGH_WATERMARK;
+            let low = watchdog.limit / 100 * Self::LOW_WATERMARK;
+            // throttling is given up once it no longer frees anything, until memory use drops
+            let mut armed = true;
+            let mut drained_since: Option<Instant> = None;
+            loop {
+                if let Some(rss) = Self::resident_set_size() {
+                    {
+                        let mut peak = watchdog.peak_rss.lock().await;
+                        *peak = (*peak).max(rss);
+                    }
+                    let mut pressure = watchdog.pressure.lock().await;
+                    if rss <= low {
+                        armed = true;
+                    }
+                    if !*pressure && armed && rss >= high {
+                        *pressure = true;
+                        drained_since = None;
+                        *watchdog.throttle_count.lock().await += 1;
+                        logger.log(&format!("Memory use {} is approaching the limit of {}. Throttling discovery",
+                            format_size(rss, size_unit), format_size(watchdog.limit, size_unit)), true, true, false).await;
+                    } else if *pressure && rss <= low {
+                        *pressure = false;
+                        logger.log(&format!("Memory use back to {}. Resuming discovery", format_size(rss, size_unit)), false, true, false).await;
+                    } else if *pressure && Self::drained(&queues) {
+                        let since = *drained_since.get_or_insert_with(Instant::now);
+                        if since.elapsed() >= Self::DRAINED_GRACE {
+                            *pressure = false;
+                            armed = false;
+                            logger.log(&format!(
+                                "Memory use stays at {} with nothing queued, so throttling doesn't free it. Resuming discovery without throttling until it falls below {}",
+                                format_size(rss, size_unit), format_size(low, size_unit)
+                            ), true, true, false).await;
+                        }
+                    } else {
+                        drained_since = None;
+                    }
+                }
+                tokio::time::sleep(Self::SAMPLE_INTERVAL).await;
+            }
+        });
+    }
+
+    /// Checks whether the deleters emptied every queue.
+    ///
+    /// # Arguments
+    ///
+    /// * `queues` - The queues between the crawlers and the deleters.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if nothing is queued, or the queues are closed.
+    fn drained(queues: &[WeakSender<Found>]) -> bool {
+        queues.iter().filter_map(WeakSender::upgrade).all(|queue| queue.capacity() == queue.max_capacity())
+    }
+
+    /// Waits until the pressure flag has been cleared. The Crawler calls this before discovering
+    /// each new object, so it returns immediately when memory use is healthy.
+    ///
+    /// # Arguments
+    ///
+    /// * `pressure` - The pressure flag raised by the watchdog.
+    pub async fn wait_for_headroom(pressure: &Arc<Mutex<bool>>) {
+        while *pressure.lock().await {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    /// Reads the resident set size of the current process.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<u64>` - The resident set size in bytes, or None if it cannot be determined.
+    fn resident_set_size() -> Option<u64> {
+        // the 'VmRSS' line of /proc/self/status reports the resident set size in kB
+        let status = std::fs::read_to_string("/proc/self/status").ok()?;
+        let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+        let kilobytes: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+        Some(kilobytes * 1024)
+    }
+}